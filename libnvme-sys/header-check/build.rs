@@ -31,6 +31,7 @@ fn main() {
         cfg.include(gate_dir.join(p));
     }
 
+    cfg.header("libdevinfo.h");
     cfg.header("libnvme.h");
 
     cfg.skip_struct(|name| match name {
@@ -48,7 +49,13 @@ fn main() {
         "nvme_log_req" => true,
         "nvme_nvm_lba_fmt" => true,
         "nvme_fw_commit_req" => true,
+        "nvme_get_feat_req" => true,
+        "nvme_id_req" => true,
         "nvme_format_req" => true,
+        "nvme_vuc_iter" => true,
+        "nvme_vuc_disc" => true,
+        "nvme_vuc_req" => true,
+        "nvme_wdc_e6_req" => true,
         "di_node" => true,
 
         // Skip over u128
@@ -105,7 +112,13 @@ fn main() {
         "nvme_log_req_t" => true,
         "nvme_nvm_lba_fmt_t" => true,
         "nvme_fw_commit_req_t" => true,
+        "nvme_get_feat_req_t" => true,
+        "nvme_id_req_t" => true,
         "nvme_format_req_t" => true,
+        "nvme_vuc_iter_t" => true,
+        "nvme_vuc_disc_t" => true,
+        "nvme_vuc_req_t" => true,
+        "nvme_wdc_e6_req_t" => true,
         "di_node_t" => true,
 
         // Skip over u128
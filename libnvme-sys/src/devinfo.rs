@@ -2,6 +2,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::ffi::{c_char, c_int};
+
 use super::opaque_type;
 
 opaque_type!(di_node, di_node_t);
+
+#[cfg_attr(target_os = "illumos", link(name = "devinfo"))]
+extern "C" {
+    pub fn di_instance(node: *mut di_node) -> c_int;
+    pub fn di_devfs_path(node: *mut di_node) -> *mut c_char;
+    pub fn di_devfs_path_free(path_buf: *mut c_char);
+}
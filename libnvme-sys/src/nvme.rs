@@ -143,6 +143,24 @@ pub const NVME_LOG_SIZE_K_FIXED: nvme_log_size_kind_t = 1;
 pub const NVME_LOG_SIZE_K_VAR: nvme_log_size_kind_t = 2;
 pub type nvme_log_size_kind_t = c_uint;
 
+pub const NVME_VUC_DISC_IO_NONE: nvme_vuc_disc_io_t = 0;
+pub const NVME_VUC_DISC_IO_INPUT: nvme_vuc_disc_io_t = 1;
+pub const NVME_VUC_DISC_IO_OUTPUT: nvme_vuc_disc_io_t = 2;
+pub type nvme_vuc_disc_io_t = c_uint;
+
+pub const NVME_VUC_DISC_IMPACT_NONE: nvme_vuc_disc_impact_t = 0;
+pub const NVME_VUC_DISC_IMPACT_DATA: nvme_vuc_disc_impact_t = 1;
+pub const NVME_VUC_DISC_IMPACT_NS: nvme_vuc_disc_impact_t = 2;
+pub type nvme_vuc_disc_impact_t = c_uint;
+
+pub const NVME_VUC_DISC_LOCK_NONE: nvme_vuc_disc_lock_t = 0;
+pub const NVME_VUC_DISC_LOCK_READ: nvme_vuc_disc_lock_t = 1;
+pub const NVME_VUC_DISC_LOCK_WRITE: nvme_vuc_disc_lock_t = 2;
+pub type nvme_vuc_disc_lock_t = c_uint;
+
+pub const NVME_CSI_NVM: nvme_csi_t = 0;
+pub type nvme_csi_t = c_uint;
+
 // TODO: These come from nvme.h and should probably be pulled out into a NVMe
 //  spec crate at some point.
 pub const NVME_FWC_SAVE: u32 = 0;
@@ -191,12 +209,18 @@ opaque_type!(nvme_format_req, nvme_format_req_t);
 opaque_type!(nvme_log_disc, nvme_log_disc_t);
 opaque_type!(nvme_log_req, nvme_log_req_t);
 opaque_type!(nvme_fw_commit_req, nvme_fw_commit_req_t);
+opaque_type!(nvme_get_feat_req, nvme_get_feat_req_t);
+opaque_type!(nvme_id_req, nvme_id_req_t);
+opaque_type!(nvme_vuc_iter, nvme_vuc_iter_t);
+opaque_type!(nvme_vuc_disc, nvme_vuc_disc_t);
+opaque_type!(nvme_vuc_req, nvme_vuc_req_t);
+opaque_type!(nvme_wdc_e6_req, nvme_wdc_e6_req_t);
 
 // Using "super" here rather than "crate" because `ctest2` does not support rust
 // 2018 edition.
 pub type nvme_identify_ctrl_t = super::identify::nvme_identify_ctrl;
 
-#[link(name = "nvme")]
+#[cfg_attr(target_os = "illumos", link(name = "nvme"))]
 extern "C" {
     // NVMe handle.
     pub fn nvme_init() -> *mut nvme_t;
@@ -240,6 +264,10 @@ extern "C" {
         outp: *mut *mut nvme_ctrl_t,
     ) -> bool;
     pub fn nvme_ctrl_fini(ctrl: *mut nvme_ctrl_t);
+    pub fn nvme_ctrl_devi(
+        ctrl: *mut nvme_ctrl_t,
+        devip: *mut *mut di_node,
+    ) -> bool;
 
     // NVMe Controller information. Information about a controller is a
     // separate lifetime than the controller itself.
@@ -311,6 +339,15 @@ extern "C" {
         info: *mut nvme_ns_info_t,
         fmtp: *mut *const nvme_nvm_lba_fmt,
     ) -> bool;
+    pub fn nvme_ns_info_level(
+        info: *mut nvme_ns_info_t,
+    ) -> nvme_ns_disc_level_t;
+    pub fn nvme_ns_info_size(
+        info: *mut nvme_ns_info_t,
+        sizep: *mut u64,
+    ) -> bool;
+    pub fn nvme_ns_info_cap(info: *mut nvme_ns_info_t, capp: *mut u64) -> bool;
+    pub fn nvme_ns_info_use(info: *mut nvme_ns_info_t, usep: *mut u64) -> bool;
 
     // Controller Locking.
     pub fn nvme_ctrl_lock(
@@ -352,6 +389,74 @@ extern "C" {
     pub fn nvme_log_disc_free(disc: *mut nvme_log_disc_t);
     pub fn nvme_log_req_fini(req: *mut nvme_log_req_t);
 
+    // NVMe Log Page Requests. These are used to request log pages that
+    // libnvme does not know about by name.
+    pub fn nvme_log_req_init(
+        ctrl: *mut nvme_ctrl_t,
+        reqp: *mut *mut nvme_log_req_t,
+    ) -> bool;
+    pub fn nvme_log_req_set_lid(req: *mut nvme_log_req_t, lid: u32) -> bool;
+    pub fn nvme_log_req_set_lsp(req: *mut nvme_log_req_t, lsp: u32) -> bool;
+    pub fn nvme_log_req_set_nsid(req: *mut nvme_log_req_t, nsid: u32) -> bool;
+    pub fn nvme_log_req_set_rae(req: *mut nvme_log_req_t, rae: bool) -> bool;
+    pub fn nvme_log_req_set_offset(req: *mut nvme_log_req_t, off: u64) -> bool;
+    pub fn nvme_log_req_set_lsi(req: *mut nvme_log_req_t, lsi: u32) -> bool;
+
+    // Identify requests.
+    pub fn nvme_id_req_init_by_cns(
+        ctrl: *mut nvme_ctrl_t,
+        csi: nvme_csi_t,
+        cns: u32,
+        reqp: *mut *mut nvme_id_req_t,
+    ) -> bool;
+    pub fn nvme_id_req_fini(req: *mut nvme_id_req_t);
+    pub fn nvme_id_req_set_nsid(req: *mut nvme_id_req_t, nsid: u32) -> bool;
+    pub fn nvme_id_req_set_ctrlid(req: *mut nvme_id_req_t, ctrlid: u32)
+        -> bool;
+    pub fn nvme_id_req_set_output(
+        req: *mut nvme_id_req_t,
+        buf: *mut c_void,
+        len: usize,
+    ) -> bool;
+    pub fn nvme_id_req_clear_output(req: *mut nvme_id_req_t) -> bool;
+    pub fn nvme_id_req_exec(req: *mut nvme_id_req_t) -> bool;
+
+    // Get Features requests.
+    pub fn nvme_get_feat_req_init(
+        ctrl: *mut nvme_ctrl_t,
+        reqp: *mut *mut nvme_get_feat_req_t,
+    ) -> bool;
+    pub fn nvme_get_feat_req_fini(req: *mut nvme_get_feat_req_t);
+    pub fn nvme_get_feat_req_set_fid(
+        req: *mut nvme_get_feat_req_t,
+        fid: u32,
+    ) -> bool;
+    pub fn nvme_get_feat_req_set_sel(
+        req: *mut nvme_get_feat_req_t,
+        sel: u32,
+    ) -> bool;
+    pub fn nvme_get_feat_req_set_nsid(
+        req: *mut nvme_get_feat_req_t,
+        nsid: u32,
+    ) -> bool;
+    pub fn nvme_get_feat_req_set_cdw11(
+        req: *mut nvme_get_feat_req_t,
+        cdw11: u32,
+    ) -> bool;
+    pub fn nvme_get_feat_req_set_output(
+        req: *mut nvme_get_feat_req_t,
+        buf: *mut c_void,
+        len: usize,
+    ) -> bool;
+    pub fn nvme_get_feat_req_clear_output(
+        req: *mut nvme_get_feat_req_t,
+    ) -> bool;
+    pub fn nvme_get_feat_req_exec(req: *mut nvme_get_feat_req_t) -> bool;
+    pub fn nvme_get_feat_req_get_cdw0(
+        req: *mut nvme_get_feat_req_t,
+        cdw0: *mut u32,
+    ) -> bool;
+
     // Firmware Download and Commit (Activation)
     pub fn nvme_fw_load(
         ctrl: *mut nvme_ctrl_t,
@@ -397,9 +502,86 @@ extern "C" {
     pub fn nvme_format_req_exec(req: *mut nvme_format_req_t) -> bool;
     pub fn nvme_format_req_fini(req: *mut nvme_format_req_t);
 
+    // Vendor Unique Command discovery.
+    pub fn nvme_vuc_discover_init(
+        ctrl: *mut nvme_ctrl_t,
+        flags: u32,
+        iterp: *mut *mut nvme_vuc_iter_t,
+    ) -> bool;
+    pub fn nvme_vuc_discover_step(
+        iter: *mut nvme_vuc_iter_t,
+        discp: *mut *const nvme_vuc_disc_t,
+    ) -> nvme_iter_t;
+    pub fn nvme_vuc_discover_fini(iter: *mut nvme_vuc_iter_t);
+    pub fn nvme_vuc_disc_name(disc: *const nvme_vuc_disc_t) -> *const c_char;
+    pub fn nvme_vuc_disc_desc(disc: *const nvme_vuc_disc_t) -> *const c_char;
+    pub fn nvme_vuc_disc_opcode(disc: *const nvme_vuc_disc_t) -> u32;
+    pub fn nvme_vuc_disc_dt(disc: *const nvme_vuc_disc_t)
+        -> nvme_vuc_disc_io_t;
+    pub fn nvme_vuc_disc_impact(
+        disc: *const nvme_vuc_disc_t,
+    ) -> nvme_vuc_disc_impact_t;
+    pub fn nvme_vuc_disc_lock(
+        disc: *const nvme_vuc_disc_t,
+    ) -> nvme_vuc_disc_lock_t;
+
+    // Vendor Unique Command execution.
+    pub fn nvme_vuc_req_init(
+        ctrl: *mut nvme_ctrl_t,
+        reqp: *mut *mut nvme_vuc_req_t,
+    ) -> bool;
+    pub fn nvme_vuc_req_fini(req: *mut nvme_vuc_req_t);
+    pub fn nvme_vuc_req_set_opcode(req: *mut nvme_vuc_req_t, opc: u32) -> bool;
+    pub fn nvme_vuc_req_set_nsid(req: *mut nvme_vuc_req_t, nsid: u32) -> bool;
+    pub fn nvme_vuc_req_set_cdw12(req: *mut nvme_vuc_req_t, cdw: u32) -> bool;
+    pub fn nvme_vuc_req_set_cdw13(req: *mut nvme_vuc_req_t, cdw: u32) -> bool;
+    pub fn nvme_vuc_req_set_cdw14(req: *mut nvme_vuc_req_t, cdw: u32) -> bool;
+    pub fn nvme_vuc_req_set_cdw15(req: *mut nvme_vuc_req_t, cdw: u32) -> bool;
+    pub fn nvme_vuc_req_set_timeout(req: *mut nvme_vuc_req_t, to: u32) -> bool;
+    pub fn nvme_vuc_req_set_impact(
+        req: *mut nvme_vuc_req_t,
+        impact: nvme_vuc_disc_impact_t,
+    ) -> bool;
+    pub fn nvme_vuc_req_set_input(
+        req: *mut nvme_vuc_req_t,
+        buf: *const c_void,
+        len: usize,
+    ) -> bool;
+    pub fn nvme_vuc_req_set_output(
+        req: *mut nvme_vuc_req_t,
+        buf: *mut c_void,
+        len: usize,
+    ) -> bool;
+    pub fn nvme_vuc_req_clear_output(req: *mut nvme_vuc_req_t) -> bool;
+    pub fn nvme_vuc_req_exec(req: *mut nvme_vuc_req_t) -> bool;
+    pub fn nvme_vuc_req_get_cdw0(
+        req: *mut nvme_vuc_req_t,
+        cdw0: *mut u32,
+    ) -> bool;
+
     // WDC resizing functions.  These are interfaces supported in the SN840,
     // SN650, SN655, etc.
     pub fn nvme_wdc_resize_set(ctrl: *mut nvme_ctrl_t, gb: u32) -> bool;
     pub fn nvme_wdc_resize_get(ctrl: *mut nvme_ctrl_t, gbp: *mut u32) -> bool;
 
+    // WDC diagnostic functions.
+    pub fn nvme_wdc_e6_req_init(
+        ctrl: *mut nvme_ctrl_t,
+        reqp: *mut *mut nvme_wdc_e6_req_t,
+    ) -> bool;
+    pub fn nvme_wdc_e6_req_fini(req: *mut nvme_wdc_e6_req_t);
+    pub fn nvme_wdc_e6_req_set_offset(
+        req: *mut nvme_wdc_e6_req_t,
+        off: u64,
+    ) -> bool;
+    pub fn nvme_wdc_e6_req_set_output(
+        req: *mut nvme_wdc_e6_req_t,
+        buf: *mut c_void,
+        len: usize,
+    ) -> bool;
+    pub fn nvme_wdc_e6_req_clear_output(req: *mut nvme_wdc_e6_req_t) -> bool;
+    pub fn nvme_wdc_e6_req_exec(req: *mut nvme_wdc_e6_req_t) -> bool;
+    pub fn nvme_wdc_assert_clear(ctrl: *mut nvme_ctrl_t) -> bool;
+    pub fn nvme_wdc_assert_inject(ctrl: *mut nvme_ctrl_t) -> bool;
+
 }
@@ -0,0 +1,86 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::Controller,
+    logpage::LogPageError,
+    nvmespec::ana::{ana_log_len, AnaLog, ANA_LID},
+};
+
+impl<'a> Controller<'a> {
+    /// Get the Asymmetric Namespace Access log, which reports the ANA state
+    /// of each ANA group and the namespaces that belong to it. Use
+    /// `AnaLog::namespace_states` to look up the state of a namespace.
+    pub fn ana_groups(&self) -> Result<AnaLog, LogPageError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        if id.id_mic.m_anar_sup() == 0 {
+            return Err(LogPageError::Unsupported("ANA reporting"));
+        }
+
+        // The log could hold every group with every namespace, which can be
+        // many megabytes, but is typically far smaller. Start with the header
+        // and re-read the log with more room until it all fits, which takes
+        // a read for each group with namespaces at most.
+        let max = ana_log_len(id.ap_nanagrpid, id.id_nn);
+        let req = self.log_request()?.set_lid(ANA_LID)?;
+        let mut buf = vec![0u8; AnaLog::required_len(&[])];
+        loop {
+            req.execute(&mut buf)?;
+            let len = AnaLog::required_len(&buf).min(max);
+            if len <= buf.len() {
+                return Ok(AnaLog::decode(&buf)?);
+            }
+            buf.resize(len, 0);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use libnvme_sys::identify::IdMic;
+
+    use super::*;
+    use crate::{
+        nvmespec::ana::AnaState,
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    #[test]
+    fn read_ana_log() {
+        let mut log = vec![0u8; 16];
+        log[0..8].copy_from_slice(&7u64.to_le_bytes());
+        log[8..10].copy_from_slice(&2u16.to_le_bytes());
+        for (id, state, nsids) in
+            [(1u32, 0x01, &[1u32, 2][..]), (2, 0x02, &[3])]
+        {
+            let mut desc = [0u8; 32];
+            desc[0..4].copy_from_slice(&id.to_le_bytes());
+            desc[4..8].copy_from_slice(&(nsids.len() as u32).to_le_bytes());
+            desc[16] = state;
+            log.extend(desc);
+            log.extend(nsids.iter().flat_map(|nsid| nsid.to_le_bytes()));
+        }
+
+        let nvme = Nvme::with_backend(
+            Sim::new().with_controller(
+                SimController::new(0)
+                    .with_identify(|id| {
+                        id.id_mic = IdMic::from_bits(1 << 3);
+                        id.ap_nanagrpid = 128;
+                        id.id_nn = 1 << 20;
+                    })
+                    .with_log_page(ANA_LID, log),
+            ),
+        );
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let ana = controller.ana_groups().unwrap();
+        assert_eq!(ana.change_count, 7);
+        assert_eq!(ana.groups.len(), 2);
+        let states = ana.namespace_states();
+        assert_eq!(states[&2], (1, AnaState::Optimized));
+        assert_eq!(states[&3], (2, AnaState::NonOptimized));
+    }
+}
@@ -0,0 +1,619 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driving controllers from async code.
+//!
+//! libnvme calls block, sometimes for a long time: a format or a firmware
+//! commit can take minutes. An [`AsyncNvme`] runs them on a dedicated pool of
+//! threads rather than on the async runtime's workers. Each
+//! [`AsyncController`] is opened on, and stays on, one thread of the pool,
+//! together with any lock it holds, so that its operations run one after
+//! another in the order they were submitted.
+//!
+//! Dropping one of the futures returned here cancels the operation as far as
+//! the operation allows: one that has not started yet is skipped, a firmware
+//! load stops before its next chunk, and waiting for a lock stops at once.
+//! A command that has already been sent to the controller, such as a format,
+//! runs to completion and its result is discarded.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use crate::{
+    backend::LockLevel,
+    controller::{
+        Controller, ControllerLocator, NvmeControllerError, TryLockResult,
+        WriteLockedController,
+    },
+    controller_info::ControllerInfo,
+    firmware::FirmwareLoadError,
+    owned::{
+        OwnedController, OwnedReadLockedController, OwnedWriteLockedController,
+    },
+    pool::{NvmePool, PoolError},
+};
+
+/// How long to wait before trying again for a lock that another handle
+/// holds, doubling up to [`LOCK_BACKOFF_MAX`] while it stays held.
+const LOCK_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const LOCK_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum AsyncError {
+    #[error(transparent)]
+    Pool(#[from] PoolError),
+    #[error(transparent)]
+    Controller(#[from] NvmeControllerError),
+    #[error(transparent)]
+    FirmwareLoad(#[from] FirmwareLoadError),
+    #[error("the worker thread for this controller has exited")]
+    WorkerGone,
+}
+
+type Job = Box<dyn FnOnce(&mut WorkerState) + Send>;
+
+static NEXT_CONTROLLER_ID: AtomicU64 = AtomicU64::new(0);
+
+enum Held {
+    // Only kept so that the lock is released when it is dropped.
+    Read(#[allow(dead_code)] OwnedReadLockedController),
+    Write(OwnedWriteLockedController),
+}
+
+/// A controller opened on a worker thread, and the lock held on it if any.
+struct Slot {
+    controller: OwnedController,
+    held: Option<Held>,
+}
+
+impl Slot {
+    fn write_locked(&self) -> &WriteLockedController<'static> {
+        match &self.held {
+            Some(Held::Write(guard)) => guard,
+            _ => unreachable!("write guard exists without the write lock"),
+        }
+    }
+}
+
+struct WorkerState {
+    pool: NvmePool,
+    slots: HashMap<u64, Slot>,
+}
+
+impl WorkerState {
+    fn slot(&mut self, id: u64) -> &mut Slot {
+        self.slots.get_mut(&id).expect("controller is open on its worker")
+    }
+}
+
+#[derive(Clone)]
+struct Worker {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Worker {
+    fn spawn(index: usize, pool: NvmePool) -> Self {
+        let (jobs, rx) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name(format!("libnvme-worker-{index}"))
+            .spawn(move || {
+                let mut state = WorkerState { pool, slots: HashMap::new() };
+                // The thread exits once the pool and every controller opened
+                // on it have been dropped.
+                while let Ok(job) = rx.recv() {
+                    job(&mut state);
+                }
+            })
+            .expect("failed to spawn libnvme worker thread");
+        Self { jobs }
+    }
+
+    /// Run `f` on the worker. `f` is handed the sender for its reply so that
+    /// it can tell whether the caller is still waiting, and undo anything it
+    /// would otherwise leave behind if not.
+    async fn submit<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut WorkerState, oneshot::Sender<R>) + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move |state| {
+            if !tx.is_closed() {
+                f(state, tx);
+            }
+        });
+        self.jobs.send(job).map_err(|_| AsyncError::WorkerGone)?;
+        rx.await.map_err(|_| AsyncError::WorkerGone)
+    }
+
+    /// Run `f` on the worker and return its result.
+    async fn call<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut WorkerState) -> R + Send + 'static,
+    {
+        self.submit(move |state, tx| {
+            let _ = tx.send(f(state));
+        })
+        .await
+    }
+
+    /// Queue `f` without waiting for it.
+    fn post<F>(&self, f: F)
+    where
+        F: FnOnce(&mut WorkerState) + Send + 'static,
+    {
+        // If the worker is gone, so is everything `f` would clean up.
+        let _ = self.jobs.send(Box::new(f));
+    }
+}
+
+/// A pool of threads on which controllers are driven for async callers.
+pub struct AsyncNvme {
+    workers: Vec<Worker>,
+    next: AtomicUsize,
+}
+
+impl AsyncNvme {
+    /// A pool of `threads` threads using the illumos libnvme.
+    pub fn new(threads: usize) -> Self {
+        Self::with_pool(NvmePool::new(), threads)
+    }
+
+    /// A pool of `threads` threads that each open their handle with `pool`.
+    pub fn with_pool(pool: NvmePool, threads: usize) -> Self {
+        assert!(threads > 0, "an AsyncNvme needs at least one thread");
+        Self {
+            workers: (0..threads)
+                .map(|i| Worker::spawn(i, pool.clone()))
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Open the controller identified by `locator` on one of the pool's
+    /// threads.
+    pub async fn controller(
+        &self,
+        locator: ControllerLocator,
+    ) -> Result<AsyncController, AsyncError> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let worker = self.workers[index % self.workers.len()].clone();
+        let id = NEXT_CONTROLLER_ID.fetch_add(1, Ordering::Relaxed);
+
+        let open = locator.clone();
+        worker
+            .submit(move |state, tx| match state.pool.controller(&open) {
+                Ok(controller) => {
+                    state.slots.insert(id, Slot { controller, held: None });
+                    if tx.send(Ok(())).is_err() {
+                        state.slots.remove(&id);
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                }
+            })
+            .await??;
+        Ok(AsyncController { worker, id, locator })
+    }
+}
+
+/// A controller whose operations run on a thread of an [`AsyncNvme`].
+///
+/// Operations that need the controller locked are made through the guard
+/// returned by [`AsyncController::write_lock`].
+pub struct AsyncController {
+    worker: Worker,
+    id: u64,
+    locator: ControllerLocator,
+}
+
+impl Drop for AsyncController {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.worker.post(move |state| {
+            state.slots.remove(&id);
+        });
+    }
+}
+
+impl AsyncController {
+    pub fn locator(&self) -> &ControllerLocator {
+        &self.locator
+    }
+
+    /// Run `f` with the controller on its worker thread, for operations that
+    /// have no dedicated method here.
+    pub async fn run<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Controller<'static>) -> R + Send + 'static,
+    {
+        let id = self.id;
+        self.worker.call(move |state| f(&state.slot(id).controller)).await
+    }
+
+    pub async fn get_info(&self) -> Result<ControllerInfo, AsyncError> {
+        Ok(self.run(|controller| controller.get_info()).await??)
+    }
+
+    /// Read the first `len` bytes of the log page with the given Log Page
+    /// Identifier.
+    pub async fn get_logpage(
+        &self,
+        lid: u32,
+        len: usize,
+    ) -> Result<Vec<u8>, AsyncError> {
+        let page = self
+            .run(move |controller| {
+                let mut buf = vec![0; len];
+                controller
+                    .log_request()?
+                    .set_lid(lid)?
+                    .execute(&mut buf)
+                    .map(|()| buf)
+            })
+            .await??;
+        Ok(page)
+    }
+
+    /// Take the read lock, waiting for as long as another handle holds the
+    /// write lock.
+    pub async fn read_lock(
+        &mut self,
+    ) -> Result<AsyncReadLockedController<'_>, AsyncError> {
+        self.lock(LockLevel::Read).await?;
+        Ok(AsyncReadLockedController { controller: Some(self) })
+    }
+
+    /// Take the write lock, waiting for as long as another handle holds any
+    /// lock on the controller.
+    pub async fn write_lock(
+        &mut self,
+    ) -> Result<AsyncWriteLockedController<'_>, AsyncError> {
+        self.lock(LockLevel::Write).await?;
+        Ok(AsyncWriteLockedController { controller: Some(self) })
+    }
+
+    /// Libnvme's blocking lock would tie up a worker thread, and every other
+    /// controller on it, for as long as the lock is contended. Instead try
+    /// the lock on the worker and wait here between attempts.
+    async fn lock(&self, level: LockLevel) -> Result<(), AsyncError> {
+        let mut backoff = LOCK_BACKOFF_MIN;
+        while !self.try_lock(level).await? {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(LOCK_BACKOFF_MAX);
+        }
+        Ok(())
+    }
+
+    async fn try_lock(&self, level: LockLevel) -> Result<bool, AsyncError> {
+        let id = self.id;
+        let taken = self
+            .worker
+            .submit(move |state, tx| {
+                let slot = state.slot(id);
+                let controller = slot.controller.clone();
+                let result = match level {
+                    LockLevel::Read => match controller.try_read_lock() {
+                        TryLockResult::Ok(guard) => Ok(Some(Held::Read(guard))),
+                        TryLockResult::Locked(_) => Ok(None),
+                        TryLockResult::Err(e) => Err(e),
+                    },
+                    LockLevel::Write => match controller.try_write_lock() {
+                        TryLockResult::Ok(guard) => {
+                            Ok(Some(Held::Write(guard)))
+                        }
+                        TryLockResult::Locked(_) => Ok(None),
+                        TryLockResult::Err(e) => Err(e),
+                    },
+                };
+                let reply = result.as_ref().map(Option::is_some);
+                let reply = reply.map_err(|e| e.clone());
+                // If the caller stopped waiting, the lock is dropped again
+                // here rather than being left held by nobody.
+                if tx.send(reply).is_ok() {
+                    if let Ok(held) = result {
+                        slot.held = held;
+                    }
+                }
+            })
+            .await??;
+        Ok(taken)
+    }
+
+    fn release(id: u64, state: &mut WorkerState) {
+        state.slot(id).held = None;
+    }
+
+    async fn unlock(&self) -> Result<(), AsyncError> {
+        let id = self.id;
+        self.worker.call(move |state| Self::release(id, state)).await
+    }
+
+    fn unlock_later(&self) {
+        let id = self.id;
+        self.worker.post(move |state| Self::release(id, state));
+    }
+}
+
+/// The read lock on an [`AsyncController`].
+///
+/// Dropping the guard queues the lock's release on the controller's worker;
+/// [`AsyncReadLockedController::unlock`] also waits for it.
+pub struct AsyncReadLockedController<'a> {
+    controller: Option<&'a mut AsyncController>,
+}
+
+impl Drop for AsyncReadLockedController<'_> {
+    fn drop(&mut self) {
+        if let Some(controller) = self.controller.take() {
+            controller.unlock_later();
+        }
+    }
+}
+
+impl AsyncReadLockedController<'_> {
+    pub async fn unlock(mut self) -> Result<(), AsyncError> {
+        let controller =
+            self.controller.take().expect("controller invariant violated");
+        controller.unlock().await
+    }
+}
+
+impl std::ops::Deref for AsyncReadLockedController<'_> {
+    type Target = AsyncController;
+
+    fn deref(&self) -> &Self::Target {
+        self.controller.as_ref().expect("controller is locked")
+    }
+}
+
+/// The write lock on an [`AsyncController`].
+///
+/// Dropping the guard queues the lock's release on the controller's worker;
+/// [`AsyncWriteLockedController::unlock`] also waits for it.
+pub struct AsyncWriteLockedController<'a> {
+    controller: Option<&'a mut AsyncController>,
+}
+
+impl Drop for AsyncWriteLockedController<'_> {
+    fn drop(&mut self) {
+        if let Some(controller) = self.controller.take() {
+            controller.unlock_later();
+        }
+    }
+}
+
+impl std::ops::Deref for AsyncWriteLockedController<'_> {
+    type Target = AsyncController;
+
+    fn deref(&self) -> &Self::Target {
+        self.controller.as_ref().expect("controller is locked")
+    }
+}
+
+impl AsyncWriteLockedController<'_> {
+    pub async fn unlock(mut self) -> Result<(), AsyncError> {
+        let controller =
+            self.controller.take().expect("controller invariant violated");
+        controller.unlock().await
+    }
+
+    /// Run `f` with the write locked controller on its worker thread, for
+    /// operations that have no dedicated method here.
+    pub async fn run_locked<R, F>(&self, f: F) -> Result<R, AsyncError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&WriteLockedController<'static>) -> R + Send + 'static,
+    {
+        let id = self.id;
+        self.worker.call(move |state| f(state.slot(id).write_locked())).await
+    }
+
+    /// Upload new firmware to the controller, as
+    /// [`WriteLockedController::firmware_load`].
+    ///
+    /// If the returned future is dropped, the upload stops before its next
+    /// chunk.
+    pub async fn firmware_load(&self, data: Vec<u8>) -> Result<(), AsyncError> {
+        let id = self.id;
+        self.worker
+            .submit(move |state, tx| {
+                let controller = state.slot(id).write_locked();
+                let result =
+                    controller.firmware_load_while(&data, || !tx.is_closed());
+                let _ = tx.send(result);
+            })
+            .await??;
+        Ok(())
+    }
+
+    /// Format the namespace `nsid`, or every namespace if it is the
+    /// broadcast NSID, with the LBA format `lbaf` and Secure Erase Setting
+    /// `ses`.
+    ///
+    /// Once the format has been sent to the controller it cannot be
+    /// cancelled; dropping the returned future only discards its result.
+    pub async fn format(
+        &self,
+        nsid: u32,
+        lbaf: u32,
+        ses: u32,
+    ) -> Result<(), AsyncError> {
+        self.run_locked(move |controller| {
+            controller
+                .format_request()?
+                .set_nsid(nsid)?
+                .set_lbaf(lbaf)?
+                .set_ses(ses)?
+                .execute()
+        })
+        .await??;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        firmware::{FirmwareCommitAction, NvmeSlot},
+        sim::{Sim, SimController, SimNamespace},
+        Nvme, NvmeErrorCode,
+    };
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    fn async_nvme(sim: &Sim, threads: usize) -> AsyncNvme {
+        let sim = sim.clone();
+        AsyncNvme::with_pool(
+            NvmePool::with_opener(move || Ok(Nvme::with_backend(sim.clone()))),
+            threads,
+        )
+    }
+
+    #[tokio::test]
+    async fn operations() {
+        let sim = Sim::new()
+            .with_controller(SimController::new(0))
+            .with_controller(SimController::new(1).with_namespaces(vec![
+                SimNamespace { blkdev: false, ..SimNamespace::new(1) },
+            ]));
+        let nvme = async_nvme(&sim, 2);
+
+        let mut controller =
+            nvme.controller(ControllerLocator::Instance(1)).await.unwrap();
+        let info = controller.get_info().await.unwrap();
+        assert_eq!(info.serial(), "SIM-1");
+        let smart = controller.get_logpage(0x2, 512).await.unwrap();
+        assert_eq!(u16::from_le_bytes([smart[1], smart[2]]), 300);
+
+        let locked = controller.write_lock().await.unwrap();
+        let load = locked.firmware_load(b"SIM2.0  ".repeat(8));
+        assert_send(&load);
+        load.await.unwrap();
+        locked
+            .run_locked(|c| {
+                c.firmware_commit_request()?
+                    .set_slot(NvmeSlot::try_from(2).unwrap())?
+                    .set_action(FirmwareCommitAction::SaveActivate)?
+                    .execute()
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        locked.format(1, 1, 0).await.unwrap();
+        locked.unlock().await.unwrap();
+
+        let state = sim
+            .controller(1, |c| {
+                (c.next_active_slot(), c.namespace(1).unwrap().lbaf)
+            })
+            .unwrap();
+        assert_eq!(state, (Some(2), 1));
+
+        // The lock has been released, so another handle can take it.
+        let other = Nvme::with_backend(sim.clone());
+        assert!(Controller::init_by_instance(&other, 1)
+            .unwrap()
+            .write_lock()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn lock_waits_for_other_holders() {
+        let sim = Sim::new().with_controller(SimController::new(0));
+        let nvme = async_nvme(&sim, 2);
+        let mut controller =
+            nvme.controller(ControllerLocator::Instance(0)).await.unwrap();
+
+        // Another handle on this thread holds the write lock.
+        let other = Nvme::with_backend(sim.clone());
+        let held = Controller::init_by_instance(&other, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        // Giving up on the wait leaves nothing locked behind.
+        let wait = controller.read_lock();
+        let waited = tokio::time::timeout(Duration::from_millis(50), wait);
+        assert!(waited.await.is_err());
+
+        let (locked, ()) = tokio::join!(controller.write_lock(), async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            drop(held);
+        });
+        let locked = locked.unwrap();
+        let err = Controller::init_by_instance(&other, 0)
+            .unwrap()
+            .read_lock()
+            .map_err(|(_, e)| e)
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), NvmeErrorCode::LockWouldBlock);
+        locked.unlock().await.unwrap();
+
+        // A dropped guard's lock is released before the controller's next
+        // operation.
+        drop(controller.read_lock().await.unwrap());
+        controller.get_info().await.unwrap();
+        assert!(Controller::init_by_instance(&other, 0)
+            .unwrap()
+            .write_lock()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelled_operations_do_not_run() {
+        let sim = Sim::new().with_controller(SimController::new(0));
+        let nvme = async_nvme(&sim, 1);
+        let mut controller =
+            nvme.controller(ControllerLocator::Instance(0)).await.unwrap();
+        let locked = controller.write_lock().await.unwrap();
+
+        // Keep the worker busy until the firmware load queued behind it has
+        // been abandoned.
+        let (unblock, blocked) = std::sync::mpsc::channel::<()>();
+        let mut busy = Box::pin(locked.run(move |_| blocked.recv().unwrap()));
+        let mut load = Box::pin(locked.firmware_load(vec![0; 4096]));
+        // Poll each future once, which queues its job on the worker.
+        tokio::select! {
+            biased;
+            _ = &mut busy => unreachable!("the worker is blocked"),
+            _ = &mut load => unreachable!("the worker is blocked"),
+            _ = std::future::ready(()) => {}
+        }
+        drop(load);
+        unblock.send(()).unwrap();
+        busy.await.unwrap();
+
+        // Nothing was staged, so there is nothing to commit.
+        let err = locked
+            .run_locked(|c| {
+                c.firmware_commit_request()?
+                    .set_slot(NvmeSlot::try_from(2).unwrap())?
+                    .set_action(FirmwareCommitAction::Save)?
+                    .execute()
+            })
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_ne!(err.device_status_code(), 0);
+    }
+}
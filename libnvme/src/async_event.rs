@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    feature::FeatureSelect,
+    logpage::LogPageError,
+    nvmespec::async_event::{
+        AsyncEventConfig, ChangedNamespaces, ASYNC_EVENT_CONFIG_FID,
+        CHANGED_NS_LEN, CHANGED_NS_LID,
+    },
+};
+
+impl<'a> Controller<'a> {
+    /// Get the namespaces that have changed since the Changed Namespace List
+    /// log was last read.
+    ///
+    /// Reading the log clears the namespace attribute changed event unless
+    /// `retain_event` is set, in which case the event is left outstanding
+    /// for another consumer, such as the kernel, to observe.
+    pub fn changed_namespaces(
+        &self,
+        retain_event: bool,
+    ) -> Result<ChangedNamespaces, LogPageError> {
+        let mut buf = vec![0u8; CHANGED_NS_LEN];
+        self.log_request()?
+            .set_lid(CHANGED_NS_LID)?
+            .set_rae(retain_event)?
+            .execute(&mut buf)?;
+        Ok(ChangedNamespaces::decode(&buf)?)
+    }
+
+    /// Get the asynchronous events the controller is configured to report.
+    pub fn async_event_config(
+        &self,
+        sel: FeatureSelect,
+    ) -> Result<AsyncEventConfig, NvmeControllerError> {
+        self.get_feature_request()?
+            .set_fid(ASYNC_EVENT_CONFIG_FID)?
+            .set_select(sel)?
+            .execute()
+            .map(AsyncEventConfig)
+    }
+
+    /// Get the asynchronous events the controller supports, derived from the
+    /// Optional Asynchronous Events Supported and Log Page Attributes fields
+    /// of Identify Controller.
+    pub fn supported_async_events(
+        &self,
+    ) -> Result<AsyncEventConfig, NvmeControllerError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        let (oaes, lpa) = (id.id_oaes, id.id_lpa);
+
+        // SMART / Health critical warnings are always supported.
+        let mut events = 0xff;
+        for (supported, bit) in [
+            (oaes.oaes_nsan(), 8),
+            (oaes.oaes_fwact(), 9),
+            (lpa.lp_telemetry(), 10),
+            (oaes.oaes_ansacn(), 11),
+            (oaes.oaes_plat(), 12),
+            (oaes.oaes_lbasi(), 13),
+            (oaes.oaes_egeal(), 14),
+        ] {
+            if supported != 0 {
+                events |= 1 << bit;
+            }
+        }
+        Ok(AsyncEventConfig(events))
+    }
+}
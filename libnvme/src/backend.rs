@@ -0,0 +1,254 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The interface between the safe API of this crate and whatever is actually
+//! talking to NVMe controllers.
+//!
+//! By default that is the illumos libnvme. Every `Controller`, `Namespace`
+//! and request builder is a thin layer over a `ControllerBackend`, so code
+//! written against them can also be run against the simulator (behind the
+//! `sim` feature) or any other implementation of these traits.
+//!
+//! Requests are described by plain structs that are handed to the backend in
+//! a single call when they are executed. The request builders check that each
+//! field is in range when it is set, with the same error codes libnvme uses,
+//! but checks that depend on the controller, such as libnvme rejecting a log
+//! page it knows the controller does not support, happen on execution.
+
+use crate::{
+    controller::NvmeControllerError,
+    controller_info::ControllerInfo,
+    feature::FeatureSelect,
+    firmware::FirmwareCommitAction,
+    logpage::LogPageName,
+    namespace::{NamespaceDiscoveryLevel, NamespaceInfo},
+    vuc::{VucCommand, VucImpact},
+    NvmeError, NvmeErrorCode,
+};
+
+/// The controllers found by `Backend::discover`.
+pub type ControllerIter<'a> = Box<
+    dyn Iterator<Item = Result<Box<dyn ControllerBackend>, NvmeError>> + 'a,
+>;
+
+/// A source of NVMe controllers.
+pub trait Backend {
+    /// Find every controller on the system.
+    fn discover(&self) -> Result<ControllerIter<'_>, NvmeError>;
+
+    /// Open the controller with the given driver instance number.
+    fn controller_by_instance(
+        &self,
+        instance: i32,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError>;
+
+    /// Open the controller at `path` in the device tree, in the form returned
+    /// by `di_devfs_path(3DEVINFO)`.
+    fn controller_by_devinfo_path(
+        &self,
+        path: &str,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError>;
+}
+
+/// The controller lock levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LockLevel {
+    Read,
+    Write,
+}
+
+/// The operations that can be performed on a single controller.
+///
+/// Namespaces are identified by their NSID rather than by a handle of their
+/// own. An implementation must keep alive whatever it needs itself, as it may
+/// outlive the `Backend` that opened it. It must also be `Send`, so that an
+/// [`OwnedController`](crate::owned::OwnedController), opened through a
+/// `Backend` of its own, can be handed to another thread; calls on it are
+/// never made from more than one thread at a time.
+pub trait ControllerBackend: Send {
+    /// Take a snapshot of information about the controller.
+    fn info(&self) -> Result<ControllerInfo, NvmeControllerError>;
+
+    /// The driver instance number of the controller.
+    fn instance(&self) -> Result<i32, NvmeControllerError>;
+
+    /// The path of the controller in the device tree, in the form returned by
+    /// `di_devfs_path(3DEVINFO)`.
+    fn devinfo_path(&self) -> Result<String, NvmeControllerError>;
+
+    /// Take the controller lock. When `block` is false and the lock cannot be
+    /// taken immediately this fails with `NvmeErrorCode::LockWouldBlock`.
+    fn lock(
+        &self,
+        level: LockLevel,
+        block: bool,
+    ) -> Result<(), NvmeControllerError>;
+
+    /// Release the controller lock.
+    fn unlock(&self);
+
+    /// The NSIDs of the namespaces at `level`.
+    fn namespaces(
+        &self,
+        level: NamespaceDiscoveryLevel,
+    ) -> Result<Vec<u32>, NvmeControllerError>;
+
+    /// Take a snapshot of information about a namespace.
+    fn namespace_info(
+        &self,
+        nsid: u32,
+    ) -> Result<NamespaceInfo, NvmeControllerError>;
+
+    fn blkdev_attach(&self, nsid: u32) -> Result<(), NvmeControllerError>;
+
+    fn blkdev_detach(&self, nsid: u32) -> Result<(), NvmeControllerError>;
+
+    /// Read the whole of a log page that libnvme knows by name.
+    fn named_log(
+        &self,
+        name: LogPageName,
+    ) -> Result<Vec<u8>, NvmeControllerError>;
+
+    /// Read `buf.len()` bytes of a log page.
+    fn get_log(
+        &self,
+        req: &LogRequest,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError>;
+
+    /// Issue an Identify command for the NVM command set with the given
+    /// Controller or Namespace Structure (CNS) value.
+    fn identify(
+        &self,
+        cns: u32,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError>;
+
+    /// Get a feature, writing any data structure it returns to `buf`, and
+    /// return Dword 0 of the completion queue entry.
+    fn get_feature(
+        &self,
+        req: &GetFeatureRequest,
+        buf: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError>;
+
+    /// Download part of a firmware image at `offset`.
+    fn firmware_load(
+        &self,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<(), NvmeControllerError>;
+
+    fn firmware_commit(
+        &self,
+        req: &FirmwareCommitRequest,
+    ) -> Result<(), NvmeControllerError>;
+
+    fn format(&self, req: &FormatRequest) -> Result<(), NvmeControllerError>;
+
+    /// The named vendor unique commands available on the controller.
+    fn vuc_commands(&self) -> Result<Vec<VucCommand>, NvmeControllerError>;
+
+    /// Execute a vendor unique command, returning Dword 0 of the completion
+    /// queue entry.
+    fn vuc(
+        &self,
+        req: &VucRequest,
+        input: Option<&[u8]>,
+        output: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError>;
+
+    /// The capacity of a WDC device in gigabytes.
+    fn wdc_resize_get(&self) -> Result<u32, NvmeControllerError>;
+
+    fn wdc_resize_set(&self, gb: u32) -> Result<(), NvmeControllerError>;
+
+    /// Read `buf.len()` bytes of the WDC E6 diagnostic dump.
+    fn wdc_e6_read(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError>;
+
+    fn wdc_assert_clear(&self) -> Result<(), NvmeControllerError>;
+
+    fn wdc_assert_inject(&self) -> Result<(), NvmeControllerError>;
+}
+
+/// Check the value a request field is being set to, failing with `code` if it
+/// is not `valid`.
+pub(crate) fn check_field(
+    valid: bool,
+    code: NvmeErrorCode,
+    field: &str,
+    value: u32,
+) -> Result<(), NvmeControllerError> {
+    if valid {
+        return Ok(());
+    }
+    Err(NvmeControllerError::new(
+        code,
+        format!("failed to set {field} to {value:#x}"),
+        format!("{field} {value:#x} is out of range"),
+    ))
+}
+
+/// A Get Log Page command. Fields that are `None` are left at libnvme's
+/// defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogRequest {
+    pub lid: Option<u32>,
+    pub lsp: Option<u32>,
+    pub lsi: Option<u32>,
+    pub nsid: Option<u32>,
+    pub rae: Option<bool>,
+    /// The byte offset into the log page to start reading from.
+    pub offset: u64,
+}
+
+/// A Get Features command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GetFeatureRequest {
+    pub fid: Option<u32>,
+    pub sel: Option<FeatureSelect>,
+    pub nsid: Option<u32>,
+    pub cdw11: Option<u32>,
+}
+
+/// A Firmware Commit command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirmwareCommitRequest {
+    pub slot: Option<u32>,
+    pub action: Option<FirmwareCommitAction>,
+    /// The Boot Partition ID, used by the boot partition commit actions.
+    pub bpid: Option<u32>,
+}
+
+/// A Format NVM command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FormatRequest {
+    pub lbaf: Option<u32>,
+    pub nsid: Option<u32>,
+    pub ses: Option<u32>,
+}
+
+/// A vendor unique admin command, without its data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VucRequest {
+    pub opcode: Option<u32>,
+    pub nsid: Option<u32>,
+    pub cdw12: Option<u32>,
+    pub cdw13: Option<u32>,
+    pub cdw14: Option<u32>,
+    pub cdw15: Option<u32>,
+    /// The command timeout in seconds.
+    pub timeout: Option<u32>,
+    pub impact: Option<VucImpact>,
+}
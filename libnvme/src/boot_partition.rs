@@ -0,0 +1,273 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading and writing the boot partitions of a controller.
+//!
+//! Note: writing a boot partition needs the Boot Partition Identifier of the
+//! Firmware Commit command and the boot partition commit actions, neither of
+//! which illumos supports today. Against illumos the commit fails with
+//! `NvmeErrorCode::FwCommitActionRange`, after the image has been downloaded
+//! to the controller, and the boot partition is left unchanged.
+
+use thiserror::Error;
+
+use crate::{
+    controller::{Controller, NvmeControllerError, WriteLockedController},
+    firmware::{FirmwareCommitAction, FirmwareLoadError},
+};
+
+/// Boot Partition log page identifier (1.3).
+const BOOT_PARTITION_LID: u32 = 0x15;
+/// The log page starts with a 16 byte header followed by the contents of the
+/// requested boot partition.
+const BOOT_PARTITION_HDR_LEN: usize = 16;
+/// Boot partition sizes are reported in 128 KiB units.
+const BOOT_PARTITION_SIZE_UNIT: u64 = 128 * 1024;
+/// Read the boot partition in the same sized chunks we use for firmware
+/// downloads.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BootPartitionError {
+    #[error("NVMe boot partitions must be 0 or 1 but got {0}")]
+    InvalidBootPartition(u8),
+    #[error("NVMe device does not support boot partitions")]
+    Unsupported,
+    #[error(
+        "boot partition image is {size} bytes but the boot partition is \
+        {capacity} bytes"
+    )]
+    ImageTooLarge { size: usize, capacity: u64 },
+    #[error("libnvme error: {0}")]
+    ControllerError(#[from] NvmeControllerError),
+    #[error("{0}")]
+    FirmwareLoad(#[from] FirmwareLoadError),
+}
+
+/// One of the two boot partitions an NVMe controller may have.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub struct BootPartitionId(u8);
+
+impl TryFrom<u8> for BootPartitionId {
+    type Error = BootPartitionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0..=1 => Ok(BootPartitionId(value)),
+            invalid => Err(BootPartitionError::InvalidBootPartition(invalid)),
+        }
+    }
+}
+
+impl From<BootPartitionId> for u8 {
+    fn from(value: BootPartitionId) -> Self {
+        value.0
+    }
+}
+
+/// Information about the boot partitions of an NVMe controller.
+#[derive(Debug, Copy, Clone)]
+pub struct BootPartitionInfo {
+    /// The size of each boot partition in bytes.
+    pub size: u64,
+    /// The boot partition the controller will boot from.
+    pub active: BootPartitionId,
+}
+
+impl BootPartitionInfo {
+    fn from_header(hdr: &[u8; BOOT_PARTITION_HDR_LEN]) -> Self {
+        // NVMe Spec: Bytes 07:04 are the Boot Partition Information (BPINFO)
+        // where bits 14:00 are the Boot Partition Size (BPSZ) and bit 31 is
+        // the Active Boot Partition ID (ABPID).
+        let bpinfo = u32::from_le_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]);
+        let bpsz = u64::from(bpinfo & 0x7fff);
+        Self {
+            size: bpsz * BOOT_PARTITION_SIZE_UNIT,
+            active: BootPartitionId((bpinfo >> 31) as u8),
+        }
+    }
+}
+
+impl<'a> Controller<'a> {
+    /// Get the size and active boot partition of the controller.
+    pub fn boot_partition_info(
+        &self,
+    ) -> Result<BootPartitionInfo, BootPartitionError> {
+        let req = self.log_request()?.set_lid(BOOT_PARTITION_LID)?;
+        let mut hdr = [0u8; BOOT_PARTITION_HDR_LEN];
+        req.execute(&mut hdr)?;
+
+        let info = BootPartitionInfo::from_header(&hdr);
+        if info.size == 0 {
+            return Err(BootPartitionError::Unsupported);
+        }
+        Ok(info)
+    }
+
+    /// Read the entire contents of a boot partition.
+    pub fn read_boot_partition(
+        &self,
+        id: BootPartitionId,
+    ) -> Result<Vec<u8>, BootPartitionError> {
+        let info = self.boot_partition_info()?;
+        let size =
+            usize::try_from(info.size).expect("32-bit systems unsupported");
+
+        // NVMe Spec: The Log Specific Parameter selects the Boot Partition
+        // Identifier (BPID) to read.
+        let req = self
+            .log_request()?
+            .set_lid(BOOT_PARTITION_LID)?
+            .set_lsp(u32::from(id.0))?;
+
+        let mut data = vec![0u8; size];
+        let mut offset = BOOT_PARTITION_HDR_LEN as u64;
+        for chunk in data.chunks_mut(CHUNK_SIZE) {
+            req.read_at(offset, chunk)?;
+            offset += chunk.len() as u64;
+        }
+
+        Ok(data)
+    }
+}
+
+impl<'ctrl> WriteLockedController<'ctrl> {
+    /// Replace the contents of a boot partition with `image`.
+    ///
+    /// The image is downloaded to the controller with the same chunked path
+    /// as `firmware_load` and then committed to the boot partition. This does
+    /// not change which boot partition is active; see
+    /// [`Self::activate_boot_partition`].
+    pub fn write_boot_partition(
+        &self,
+        id: BootPartitionId,
+        image: &[u8],
+    ) -> Result<(), BootPartitionError> {
+        let info = self.boot_partition_info()?;
+        if image.len() as u64 > info.size {
+            return Err(BootPartitionError::ImageTooLarge {
+                size: image.len(),
+                capacity: info.size,
+            });
+        }
+
+        self.firmware_load(image)?;
+        self.firmware_commit_request()?
+            .set_action(FirmwareCommitAction::ReplaceBootPartition)?
+            .set_boot_partition(id)?
+            .execute()?;
+
+        Ok(())
+    }
+
+    /// Make the controller boot from the given boot partition.
+    pub fn activate_boot_partition(
+        &self,
+        id: BootPartitionId,
+    ) -> Result<(), BootPartitionError> {
+        self.firmware_commit_request()?
+            .set_action(FirmwareCommitAction::ActivateBootPartition)?
+            .set_boot_partition(id)?
+            .execute()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    #[test]
+    fn read_both_boot_partitions() {
+        // One 128 KiB unit per partition, with boot partition 1 active.
+        let size = BOOT_PARTITION_SIZE_UNIT as usize;
+        let mut log = vec![0u8; BOOT_PARTITION_HDR_LEN + size];
+        log[4..8].copy_from_slice(&(1u32 | 1 << 31).to_le_bytes());
+        for (i, b) in log[BOOT_PARTITION_HDR_LEN..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let sim = Sim::new().with_controller(
+            SimController::new(0)
+                .with_log_page(BOOT_PARTITION_LID, log.clone()),
+        );
+        let nvme = Nvme::with_backend(sim);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+
+        let info = controller.boot_partition_info().unwrap();
+        assert_eq!(info.size, BOOT_PARTITION_SIZE_UNIT);
+        assert_eq!(u8::from(info.active), 1);
+        for id in [0, 1] {
+            let id = BootPartitionId::try_from(id).unwrap();
+            let data = controller.read_boot_partition(id).unwrap();
+            assert_eq!(data, log[BOOT_PARTITION_HDR_LEN..]);
+        }
+        assert!(matches!(
+            BootPartitionId::try_from(2),
+            Err(BootPartitionError::InvalidBootPartition(2))
+        ));
+    }
+
+    #[test]
+    fn write_and_activate() {
+        let sim = Sim::new()
+            .with_controller(SimController::new(0).with_boot_partitions(1, 0));
+        let nvme = Nvme::with_backend(sim.clone());
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        // More than one download chunk, but less than the whole partition.
+        let image = (0..CHUNK_SIZE + 64).map(|i| i as u8).collect::<Vec<_>>();
+        let bp1 = BootPartitionId::try_from(1).unwrap();
+        controller.write_boot_partition(bp1, &image).unwrap();
+        let data = controller.read_boot_partition(bp1).unwrap();
+        assert_eq!(data[..image.len()], image);
+        assert!(data[image.len()..].iter().all(|&b| b == 0));
+        let bp0 = BootPartitionId::try_from(0).unwrap();
+        let data = controller.read_boot_partition(bp0).unwrap();
+        assert!(data.iter().all(|&b| b == 0));
+
+        assert_eq!(
+            u8::from(controller.boot_partition_info().unwrap().active),
+            0
+        );
+        controller.activate_boot_partition(bp1).unwrap();
+        assert_eq!(
+            u8::from(controller.boot_partition_info().unwrap().active),
+            1
+        );
+        sim.controller(0, |c| {
+            assert_eq!(c.boot_partition(1).unwrap()[..image.len()], image);
+            assert_eq!(c.active_boot_partition(), 1);
+        });
+
+        let image = vec![0u8; BOOT_PARTITION_SIZE_UNIT as usize + 4];
+        assert!(matches!(
+            controller.write_boot_partition(bp0, &image),
+            Err(BootPartitionError::ImageTooLarge { size, capacity })
+                if size == image.len() && capacity == BOOT_PARTITION_SIZE_UNIT
+        ));
+    }
+
+    #[test]
+    fn unsupported_without_boot_partitions() {
+        let sim = Sim::new().with_controller(
+            SimController::new(0)
+                .with_log_page(BOOT_PARTITION_LID, vec![0; 16]),
+        );
+        let nvme = Nvme::with_backend(sim);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        assert!(matches!(
+            controller.boot_partition_info(),
+            Err(BootPartitionError::Unsupported)
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::Controller,
+    logpage::LogPageError,
+    nvmespec::command_effects::{
+        CommandEffectsLog, COMMAND_EFFECTS_LEN, COMMAND_EFFECTS_LID,
+    },
+};
+
+impl<'a> Controller<'a> {
+    /// Get the Commands Supported and Effects log, which describes which
+    /// admin and I/O commands the controller supports and what each of them
+    /// may change.
+    pub fn command_effects(&self) -> Result<CommandEffectsLog, LogPageError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        if id.id_lpa.lp_cmdeff() == 0 {
+            return Err(LogPageError::Unsupported(
+                "the commands supported and effects log",
+            ));
+        }
+
+        let mut buf = vec![0u8; COMMAND_EFFECTS_LEN];
+        self.log_request()?.set_lid(COMMAND_EFFECTS_LID)?.execute(&mut buf)?;
+        Ok(CommandEffectsLog::decode(&buf)?)
+    }
+}
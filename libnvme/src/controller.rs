@@ -2,38 +2,46 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{ffi::CStr, ops::Deref};
+use std::{
+    marker::PhantomData,
+    ops::Deref,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
 use thiserror::Error;
 
 use crate::{
+    backend::{
+        check_field, ControllerBackend, ControllerIter, FormatRequest,
+        LockLevel,
+    },
     controller_info::ControllerInfo,
-    error::{InternalError, LibraryError},
+    error::InternalError,
     namespace::{NamespaceDiscovery, NamespaceDiscoveryLevel},
-    util::FfiPtr,
     Nvme, NvmeError, NvmeErrorCode,
 };
 
-use libnvme_sys::nvme::*;
-
-enum ControllerLockLevel {
-    Read = NVME_LOCK_L_READ as isize,
-    Write = NVME_LOCK_L_WRITE as isize,
-}
-
-enum ControllerLockFlags {
-    Block = 0,
-    DontBlock = NVME_LOCK_F_DONT_BLOCK as isize,
-}
-
 pub enum TryLockResult<L, T, E> {
     Ok(L),
     Locked(T),
     Err(E),
 }
 
+/// An NVMe controller.
+///
+/// The lifetime ties a `Controller` to the [`Nvme`] it was found through;
+/// see [`OwnedController`](crate::owned::OwnedController) for a controller
+/// without one.
+///
+/// A `Controller` is neither `Send` nor `Sync`: it may share its library
+/// handle with the `Nvme` and with other controllers, which libnvme only
+/// allows one thread at a time to use.
 pub struct Controller<'a> {
-    pub(crate) inner: *mut nvme_ctrl_t,
-    _nvme: &'a Nvme,
+    // Shared by every handle made with `share`. Those held by owned handles
+    // may be on other threads; see `OwnedController`.
+    backend: Arc<Mutex<Box<dyn ControllerBackend>>>,
+    // `Nvme` is `!Send` and `!Sync`, so this makes `Controller` both too.
+    _nvme: PhantomData<&'a Nvme>,
 }
 
 impl<'a> Controller<'a> {
@@ -42,51 +50,90 @@ impl<'a> Controller<'a> {
         nvme: &'a Nvme,
         instance: i32,
     ) -> Result<Self, NvmeError> {
-        let mut controller = std::ptr::null_mut();
-        nvme.check_result(
-            unsafe {
-                nvme_ctrl_init_by_instance(nvme.0, instance, &mut controller)
-            },
-            || format!("failed to get controller for instance {instance}"),
-        )?;
+        let backend = nvme.backend.controller_by_instance(instance)?;
+        Ok(Self::from_backend(backend))
+    }
+
+    /// Initialize a `Controller` from its path in the device tree, with or
+    /// without the leading `/devices`.
+    pub fn init_by_devinfo_path(
+        nvme: &'a Nvme,
+        path: &str,
+    ) -> Result<Self, NvmeError> {
+        let path = path.strip_prefix("/devices").unwrap_or(path);
+        let backend = nvme.backend.controller_by_devinfo_path(path)?;
+        Ok(Self::from_backend(backend))
+    }
+
+    /// A way to open this controller again, for example from another thread.
+    pub fn locator(&self) -> Result<ControllerLocator, NvmeControllerError> {
+        self.instance().map(ControllerLocator::Instance)
+    }
+
+    /// The instance number of the controller's driver, as in `nvme0`.
+    pub fn instance(&self) -> Result<i32, NvmeControllerError> {
+        self.backend().instance()
+    }
+
+    /// The controller's path in the device tree, without the leading
+    /// `/devices`.
+    pub fn devinfo_path(&self) -> Result<String, NvmeControllerError> {
+        self.backend().devinfo_path()
+    }
+
+    pub(crate) fn from_backend(backend: Box<dyn ControllerBackend>) -> Self {
+        Self { backend: Arc::new(Mutex::new(backend)), _nvme: PhantomData }
+    }
+
+    /// The backend, for the duration of a single call on it.
+    pub(crate) fn backend(&self) -> MutexGuard<'_, Box<dyn ControllerBackend>> {
+        // A panic part way through a call leaves nothing half updated on our
+        // side, so there is no reason to refuse further calls.
+        self.backend.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        Ok(Self { inner: controller, _nvme: nvme })
+    /// Another handle onto the same controller, and so also onto any lock
+    /// held on it.
+    ///
+    /// Soundness: the caller picks the lifetime of the new handle. That is
+    /// only sound because a [`ControllerBackend`] keeps alive whatever it
+    /// needs itself rather than borrowing from the `Nvme` it was opened
+    /// through, so the lifetime on `Controller` is an API convention that
+    /// memory safety does not depend on.
+    /// [`OwnedController`](crate::owned::OwnedController) relies on this to
+    /// drop the lifetime. A backend that borrowed from its `Nvme` would make
+    /// this unsound.
+    pub(crate) fn share<'b>(&self) -> Controller<'b> {
+        Controller { backend: Arc::clone(&self.backend), _nvme: PhantomData }
     }
 
     pub fn get_info(&self) -> Result<ControllerInfo, NvmeControllerError> {
-        let mut ctrl_info: *mut nvme_ctrl_info_t = std::ptr::null_mut();
-        self.check_result(
-            unsafe { nvme_ctrl_info_snap(self.inner, &mut ctrl_info) },
-            || "failed to get controller info snapshot",
-        )
-        .map(|_| unsafe { ControllerInfo::from_raw(ctrl_info) })
+        self.backend().info()
     }
 
     fn lock_impl(
         self,
-        level: ControllerLockLevel,
-        flags: ControllerLockFlags,
+        level: LockLevel,
+        block: bool,
     ) -> Result<Self, (Self, NvmeControllerError)> {
-        if let Err(e) = self.check_result(
-            unsafe { nvme_ctrl_lock(self.inner, level as u32, flags as u32) },
-            || "failed to grab nvme controller lock",
-        ) {
-            return Err((self, e));
+        let result = self.backend().lock(level, block);
+        match result {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
         }
-        Ok(self)
     }
 
     pub fn read_lock(
         self,
     ) -> Result<ReadLockedController<'a>, (Self, NvmeControllerError)> {
-        self.lock_impl(ControllerLockLevel::Read, ControllerLockFlags::Block)
+        self.lock_impl(LockLevel::Read, true)
             .map(|c| ReadLockedController { controller: Some(c) })
     }
 
     pub fn write_lock(
         self,
     ) -> Result<WriteLockedController<'a>, (Self, NvmeControllerError)> {
-        self.lock_impl(ControllerLockLevel::Write, ControllerLockFlags::Block)
+        self.lock_impl(LockLevel::Write, true)
             .map(|c| WriteLockedController { controller: Some(c) })
     }
 
@@ -94,10 +141,7 @@ impl<'a> Controller<'a> {
         self,
     ) -> TryLockResult<ReadLockedController<'a>, Self, NvmeControllerError>
     {
-        match self.lock_impl(
-            ControllerLockLevel::Read,
-            ControllerLockFlags::DontBlock,
-        ) {
+        match self.lock_impl(LockLevel::Read, false) {
             Ok(c) => {
                 TryLockResult::Ok(ReadLockedController { controller: Some(c) })
             }
@@ -114,10 +158,7 @@ impl<'a> Controller<'a> {
         self,
     ) -> TryLockResult<WriteLockedController<'a>, Self, NvmeControllerError>
     {
-        match self.lock_impl(
-            ControllerLockLevel::Write,
-            ControllerLockFlags::DontBlock,
-        ) {
+        match self.lock_impl(LockLevel::Write, false) {
             Ok(c) => {
                 TryLockResult::Ok(WriteLockedController { controller: Some(c) })
             }
@@ -138,64 +179,42 @@ impl<'a> Controller<'a> {
     }
 }
 
-impl Drop for Controller<'_> {
-    fn drop(&mut self) {
-        unsafe { nvme_ctrl_fini(self.inner) }
-    }
+/// Identifies a controller independently of any open handle to it, so that it
+/// can be reopened elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControllerLocator {
+    /// The driver instance number.
+    Instance(i32),
+    /// The path in the device tree, as accepted by
+    /// [`Controller::init_by_devinfo_path`].
+    DevinfoPath(String),
 }
 
-pub struct ControllerDiscovery<'a> {
-    nvme: &'a Nvme,
-    iter: *mut nvme_ctrl_iter_t,
+impl ControllerLocator {
+    pub fn open<'a>(
+        &self,
+        nvme: &'a Nvme,
+    ) -> Result<Controller<'a>, NvmeError> {
+        match self {
+            Self::Instance(instance) => {
+                Controller::init_by_instance(nvme, *instance)
+            }
+            Self::DevinfoPath(path) => {
+                Controller::init_by_devinfo_path(nvme, path)
+            }
+        }
+    }
 }
 
-impl<'a> Drop for ControllerDiscovery<'a> {
-    fn drop(&mut self) {
-        unsafe { nvme_ctrl_discover_fini(self.iter) }
-    }
+pub struct ControllerDiscovery<'a> {
+    iter: ControllerIter<'a>,
 }
 
 impl<'a> ControllerDiscovery<'a> {
     pub(crate) fn new(nvme: &'a Nvme) -> Result<Self, NvmeError> {
-        let mut iter = std::ptr::null_mut();
-        nvme.check_result(
-            unsafe { nvme_ctrl_discover_init(nvme.0, &mut iter) },
-            || "failed to init nvme controller discovery",
-        )
-        .map(|_| ControllerDiscovery { nvme, iter })
-    }
-
-    fn internal_step(&self) -> Result<Option<Controller<'a>>, NvmeError> {
-        let mut nvme_ctr_disc: *const nvme_ctrl_disc_t = std::ptr::null_mut();
-        let state =
-            unsafe { nvme_ctrl_discover_step(self.iter, &mut nvme_ctr_disc) };
-        match state {
-            NVME_ITER_VALID => {
-                let di_node_t = unsafe { nvme_ctrl_disc_devi(nvme_ctr_disc) };
-                let mut nvme_ctrl: *mut nvme_ctrl_t = std::ptr::null_mut();
-                self.nvme
-                    .check_result(
-                        unsafe {
-                            nvme_ctrl_init(
-                                self.nvme.0,
-                                di_node_t,
-                                &mut nvme_ctrl,
-                            )
-                        },
-                        || "failed to init nvme controller",
-                    )
-                    .map(|_| {
-                        Some(Controller { inner: nvme_ctrl, _nvme: self.nvme })
-                    })
-            }
-            NVME_ITER_DONE => Ok(None),
-            NVME_ITER_ERROR => Err(self
-                .nvme
-                .fatal_context("failed to iterate nvme controllers")),
-            invalid => unreachable!(
-                "invalid nvme controller iteration state ({invalid})",
-            ),
-        }
+        let iter = nvme.backend.discover()?;
+        Ok(ControllerDiscovery { iter })
     }
 }
 
@@ -203,12 +222,13 @@ impl<'a> Iterator for ControllerDiscovery<'a> {
     type Item = Result<Controller<'a>, NvmeError>;
 
     fn next(&mut self) -> Option<Result<Controller<'a>, NvmeError>> {
-        self.internal_step().transpose()
+        self.iter.next().map(|backend| backend.map(Controller::from_backend))
     }
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 #[error("{error}")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NvmeControllerError {
     code: NvmeErrorCode,
     device_status_code_type: u32,
@@ -217,53 +237,51 @@ pub struct NvmeControllerError {
 }
 
 impl NvmeControllerError {
-    pub fn code(&self) -> NvmeErrorCode {
-        self.code
+    pub(crate) fn from_parts(
+        code: NvmeErrorCode,
+        device_status_code_type: u32,
+        device_status_code: u32,
+        error: InternalError,
+    ) -> Self {
+        Self { code, device_status_code_type, device_status_code, error }
     }
 
-    pub fn device_status_code_type(&self) -> u32 {
-        self.device_status_code_type
+    /// Create an error for a backend other than libnvme, such as the
+    /// simulator.
+    pub fn new<C: Into<String>, M: Into<String>>(
+        code: NvmeErrorCode,
+        context: C,
+        errmsg: M,
+    ) -> Self {
+        Self::from_parts(code, 0, 0, InternalError::new(context, errmsg))
     }
 
-    pub fn device_status_code(&self) -> u32 {
-        self.device_status_code
+    /// Create an error for a command the controller completed with the given
+    /// Status Code Type and Status Code.
+    pub fn device<C: Into<String>, M: Into<String>>(
+        device_status_code_type: u32,
+        device_status_code: u32,
+        context: C,
+        errmsg: M,
+    ) -> Self {
+        Self::from_parts(
+            NvmeErrorCode::Controller,
+            device_status_code_type,
+            device_status_code,
+            InternalError::new(context, errmsg),
+        )
     }
-}
 
-impl<'a> LibraryError for Controller<'a> {
-    type Error = NvmeControllerError;
-
-    fn get_errmsg(&self) -> String {
-        let errmsg = unsafe { nvme_ctrl_errmsg(self.inner) };
-        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
+    pub fn code(&self) -> NvmeErrorCode {
+        self.code
     }
 
-    fn get_syserr(&self) -> i32 {
-        unsafe { nvme_ctrl_syserr(self.inner) }
+    pub fn device_status_code_type(&self) -> u32 {
+        self.device_status_code_type
     }
 
-    fn current_error(
-        &self,
-        internal: crate::error::InternalError,
-    ) -> Self::Error {
-        let mut device_status_code_type = 0;
-        let mut device_status_code = 0;
-
-        let raw = unsafe { nvme_ctrl_err(self.inner) };
-        unsafe {
-            nvme_ctrl_deverr(
-                self.inner,
-                &mut device_status_code_type,
-                &mut device_status_code,
-            )
-        };
-
-        NvmeControllerError {
-            code: NvmeErrorCode::from_raw(raw),
-            device_status_code_type,
-            device_status_code,
-            error: internal,
-        }
+    pub fn device_status_code(&self) -> u32 {
+        self.device_status_code
     }
 }
 
@@ -274,7 +292,7 @@ pub struct ReadLockedController<'a> {
 impl<'a> Drop for ReadLockedController<'a> {
     fn drop(&mut self) {
         if let Some(controller) = self.controller.take() {
-            unsafe { nvme_ctrl_unlock(controller.inner) }
+            controller.backend().unlock();
         }
     }
 }
@@ -283,7 +301,7 @@ impl<'a> ReadLockedController<'a> {
     pub fn unlock(mut self) -> Controller<'a> {
         let controller =
             self.controller.take().expect("controller invariant violated");
-        unsafe { nvme_ctrl_unlock(controller.inner) };
+        controller.backend().unlock();
         controller
     }
 }
@@ -303,7 +321,7 @@ pub struct WriteLockedController<'a> {
 impl<'a> Drop for WriteLockedController<'a> {
     fn drop(&mut self) {
         if let Some(controller) = self.controller.take() {
-            unsafe { nvme_ctrl_unlock(controller.inner) }
+            controller.backend().unlock();
         }
     }
 }
@@ -312,22 +330,17 @@ impl<'a> WriteLockedController<'a> {
     pub fn unlock(mut self) -> Controller<'a> {
         let controller =
             self.controller.take().expect("controller invariant violated");
-        unsafe { nvme_ctrl_unlock(controller.inner) };
+        controller.backend().unlock();
         controller
     }
 
     pub fn format_request(
         &self,
     ) -> Result<FormatRequestBuilder<'_>, NvmeControllerError> {
-        let controller =
-            self.controller.as_ref().expect("controller is locked");
-        let mut req = std::ptr::null_mut();
-        controller
-            .check_result(
-                unsafe { nvme_format_req_init(controller.inner, &mut req) },
-                || "failed to create format request",
-            )
-            .map(|_| FormatRequestBuilder { req, controller: self })
+        Ok(FormatRequestBuilder {
+            req: FormatRequest::default(),
+            controller: self,
+        })
     }
 }
 
@@ -339,49 +352,41 @@ impl<'a> Deref for WriteLockedController<'a> {
     }
 }
 
+/// The highest LBA format index, with the extended formats of NVMe 2.0.
+const MAX_LBAF: u32 = 63;
+/// The highest Secure Erase Setting: a cryptographic erase.
+const MAX_SES: u32 = 2;
+
+/// A Format NVM request.
+///
+/// Each field is checked to be in range as it is set. Whether the controller
+/// supports the request is only checked once it is executed.
 pub struct FormatRequestBuilder<'ctrl> {
-    req: *mut nvme_format_req_t,
+    req: FormatRequest,
     controller: &'ctrl WriteLockedController<'ctrl>,
 }
 
-impl<'ctrl> Drop for FormatRequestBuilder<'ctrl> {
-    fn drop(&mut self) {
-        unsafe { nvme_format_req_fini(self.req) }
-    }
-}
-
 impl<'ctrl> FormatRequestBuilder<'ctrl> {
-    pub fn set_lbaf(self, lbaf: u32) -> Result<Self, NvmeControllerError> {
-        self.controller
-            .check_result(
-                unsafe { nvme_format_req_set_lbaf(self.req, lbaf) },
-                || format!("failed to set LBA format {lbaf} on format request"),
-            )
-            .map(|_| self)
+    pub fn set_lbaf(mut self, lbaf: u32) -> Result<Self, NvmeControllerError> {
+        let valid = lbaf <= MAX_LBAF;
+        check_field(valid, NvmeErrorCode::FormatLbafRange, "LBA format", lbaf)?;
+        self.req.lbaf = Some(lbaf);
+        Ok(self)
     }
 
-    pub fn set_nsid(self, nsid: u32) -> Result<Self, NvmeControllerError> {
-        self.controller
-            .check_result(
-                unsafe { nvme_format_req_set_nsid(self.req, nsid) },
-                || format!("failed to set nsid {nsid} on format request"),
-            )
-            .map(|_| self)
+    pub fn set_nsid(mut self, nsid: u32) -> Result<Self, NvmeControllerError> {
+        check_field(nsid != 0, NvmeErrorCode::NsRange, "NSID", nsid)?;
+        self.req.nsid = Some(nsid);
+        Ok(self)
     }
 
-    pub fn set_ses(self, ses: u32) -> Result<Self, NvmeControllerError> {
-        self.controller
-            .check_result(
-                unsafe { nvme_format_req_set_ses(self.req, ses) },
-                || format!("failed to set ses {ses} on format request"),
-            )
-            .map(|_| self)
+    pub fn set_ses(mut self, ses: u32) -> Result<Self, NvmeControllerError> {
+        check_field(ses <= MAX_SES, NvmeErrorCode::FormatSesRange, "SES", ses)?;
+        self.req.ses = Some(ses);
+        Ok(self)
     }
 
     pub fn execute(self) -> Result<(), NvmeControllerError> {
-        self.controller
-            .check_result(unsafe { nvme_format_req_exec(self.req) }, || {
-                "failed to execute format request"
-            })
+        self.controller.backend().format(&self.req)
     }
 }
@@ -2,37 +2,46 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{borrow::Cow, ffi::CStr, marker::PhantomData};
+use std::{borrow::Cow, ffi::c_char, marker::PhantomData};
 
 use libnvme_sys::nvme::*;
 use thiserror::Error;
 
-use crate::{
-    error::{InternalError, LibraryError},
-    lba::LbaFormat,
-    util::FfiPtr,
-};
+use crate::{error::InternalError, lba::LbaFormat};
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 #[error("{error}")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NvmeInfoError {
     code: NvmeInfoErrorCode,
     error: InternalError,
 }
 
 impl NvmeInfoError {
+    #[cfg(any(target_os = "illumos", not(feature = "sim")))]
     pub(crate) fn from_code_and_error(
         code: NvmeInfoErrorCode,
         error: InternalError,
     ) -> Self {
         Self { code, error }
     }
+    /// Create an error for a backend other than libnvme, such as the
+    /// simulator.
+    pub fn new<C: Into<String>, M: Into<String>>(
+        code: NvmeInfoErrorCode,
+        context: C,
+        errmsg: M,
+    ) -> Self {
+        Self { code, error: InternalError::new(context, errmsg) }
+    }
+
     pub fn code(&self) -> NvmeInfoErrorCode {
         self.code
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NvmeInfoErrorCode {
     Ok,
     Transport,
@@ -48,6 +57,7 @@ pub enum NvmeInfoErrorCode {
 }
 
 impl NvmeInfoErrorCode {
+    #[cfg(any(target_os = "illumos", not(feature = "sim")))]
     pub(crate) fn from_raw(raw: u32) -> Self {
         match raw {
             NVME_INFO_ERR_OK => NvmeInfoErrorCode::Ok,
@@ -66,108 +76,99 @@ impl NvmeInfoErrorCode {
 }
 
 pub(crate) struct ControllerInfoIdentify<'a> {
-    pub(crate) inner: *const nvme_identify_ctrl_t,
+    inner: *const nvme_identify_ctrl_t,
     // Note this type does not have a drop method as the data comes from the
     // `ControllerInfo` itself.
     _phantom: PhantomData<&'a ControllerInfo>,
 }
 
-pub struct ControllerInfo(*mut nvme_ctrl_info_t);
-
-impl FfiPtr for ControllerInfo {
-    type Ptr = *mut nvme_ctrl_info_t;
+impl<'a> ControllerInfoIdentify<'a> {
+    /// The Identify Controller data structure.
+    pub(crate) fn data(&self) -> &'a nvme_identify_ctrl_t {
+        // SAFETY: `inner` points into the `ControllerInfo` this borrows.
+        unsafe { &*self.inner }
+    }
 
-    unsafe fn from_raw(ptr: Self::Ptr) -> Self {
-        Self(ptr)
+    /// The Identify Controller data structure as the bytes the controller
+    /// returned.
+    #[cfg(feature = "trace")]
+    pub(crate) fn bytes(&self) -> &'a [u8] {
+        // SAFETY: as for `data`, and the structure is plain bytes.
+        unsafe {
+            std::slice::from_raw_parts(
+                self.inner.cast::<u8>(),
+                std::mem::size_of::<nvme_identify_ctrl_t>(),
+            )
+        }
     }
 }
 
-impl Drop for ControllerInfo {
-    fn drop(&mut self) {
-        unsafe { nvme_ctrl_info_free(self.0) }
-    }
+/// A snapshot of information about a controller, taken when it was
+/// requested.
+pub struct ControllerInfo {
+    identify: Box<nvme_identify_ctrl_t>,
+    model: String,
+    serial: String,
+    fwrev: String,
+    pci_vid: Result<u16, NvmeInfoError>,
+    lba_formats: Vec<Result<LbaFormat, NvmeInfoError>>,
+}
+
+/// Identify strings are space padded and not nul terminated.
+fn identify_string(field: &[c_char]) -> String {
+    let bytes: Vec<u8> = field.iter().map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).trim_end_matches(['\0', ' ']).to_string()
 }
 
 impl ControllerInfo {
+    /// Create a snapshot from the Identify Controller data structure along
+    /// with the information that does not come from it.
+    pub fn new(
+        identify: nvme_identify_ctrl_t,
+        pci_vid: Result<u16, NvmeInfoError>,
+        lba_formats: Vec<Result<LbaFormat, NvmeInfoError>>,
+    ) -> Self {
+        Self {
+            model: identify_string(&identify.id_model),
+            serial: identify_string(&identify.id_serial),
+            fwrev: identify_string(&identify.id_fwrev),
+            identify: Box::new(identify),
+            pci_vid,
+            lba_formats,
+        }
+    }
+
     // Private to the crate for now until it's determined to be useful to
     // consumers.
     pub(crate) fn get_controller_info_identify(
         &self,
     ) -> ControllerInfoIdentify<'_> {
-        ControllerInfoIdentify {
-            inner: unsafe { nvme_ctrl_info_identify(self.0) },
-            _phantom: PhantomData,
-        }
+        ControllerInfoIdentify { inner: &*self.identify, _phantom: PhantomData }
     }
 
     pub fn model(&self) -> Cow<'_, str> {
-        unsafe {
-            CStr::from_ptr(nvme_ctrl_info_model(self.0)).to_string_lossy()
-        }
+        Cow::Borrowed(&self.model)
     }
 
     pub fn serial(&self) -> Cow<'_, str> {
-        unsafe {
-            CStr::from_ptr(nvme_ctrl_info_serial(self.0)).to_string_lossy()
-        }
+        Cow::Borrowed(&self.serial)
     }
 
     pub fn fwrev(&self) -> Cow<'_, str> {
-        unsafe {
-            CStr::from_ptr(nvme_ctrl_info_fwrev(self.0)).to_string_lossy()
-        }
+        Cow::Borrowed(&self.fwrev)
     }
 
     pub fn num_namespaces(&self) -> u32 {
-        unsafe { nvme_ctrl_info_nns(self.0) }
+        self.identify.id_nn
     }
 
     pub fn pci_vid(&self) -> Result<u16, NvmeInfoError> {
-        let mut vid = 0;
-        self.check_result(
-            unsafe { nvme_ctrl_info_pci_vid(self.0, &mut vid) },
-            || "failed to get pci vid",
-        )
-        .map(|_| vid)
-    }
-
-    fn num_formats(&self) -> u32 {
-        unsafe { nvme_ctrl_info_nformats(self.0) }
-    }
-
-    fn nvm_lba_fmt(&self, index: u32) -> Result<LbaFormat<'_>, NvmeInfoError> {
-        let mut lba: *const nvme_nvm_lba_fmt_t = std::ptr::null_mut();
-        self.check_result(
-            unsafe { nvme_ctrl_info_format(self.0, index, &mut lba) },
-            || format!("failed to get lba fmt for index {index}"),
-        )
-        .map(|_| unsafe { LbaFormat::from_raw(lba) })
+        self.pci_vid.clone()
     }
 
     pub fn lba_formats(
         &self,
-    ) -> impl Iterator<Item = Result<LbaFormat<'_>, NvmeInfoError>> {
-        (0..self.num_formats()).map(|i| self.nvm_lba_fmt(i))
-    }
-}
-
-impl LibraryError for ControllerInfo {
-    type Error = NvmeInfoError;
-
-    fn get_errmsg(&self) -> String {
-        let errmsg = unsafe { nvme_ctrl_info_errmsg(self.0) };
-        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
-    }
-
-    fn get_syserr(&self) -> i32 {
-        unsafe { nvme_ctrl_info_syserr(self.0) }
-    }
-
-    fn current_error(&self, internal: InternalError) -> Self::Error {
-        let raw = unsafe { nvme_ctrl_info_err(self.0) };
-        NvmeInfoError {
-            code: NvmeInfoErrorCode::from_raw(raw),
-            error: internal,
-        }
+    ) -> impl Iterator<Item = Result<LbaFormat, NvmeInfoError>> + '_ {
+        self.lba_formats.iter().cloned()
     }
 }
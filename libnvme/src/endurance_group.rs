@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::Controller,
+    logpage::LogPageError,
+    nvmespec::endurance_group::{
+        decode_endurance_group_list, EnduranceGroupLog, NvmSet,
+        ENDURANCE_GROUP_LEN, ENDURANCE_GROUP_LID, ENDURANCE_GROUP_LIST_CNS,
+        ENDURANCE_GROUP_LIST_LEN, NVM_SET_LIST_CNS, NVM_SET_LIST_LEN,
+    },
+};
+
+/// The information reported for one endurance group.
+#[derive(Debug, Clone, Copy)]
+pub struct EnduranceGroup {
+    pub id: u16,
+    pub info: EnduranceGroupLog,
+}
+
+impl<'a> Controller<'a> {
+    /// Get the Endurance Group Information log for endurance group `id`.
+    pub fn endurance_group_log(
+        &self,
+        id: u16,
+    ) -> Result<EnduranceGroupLog, LogPageError> {
+        let mut buf = [0u8; ENDURANCE_GROUP_LEN];
+        self.log_request()?
+            .set_lid(ENDURANCE_GROUP_LID)?
+            .set_lsi(u32::from(id))?
+            .execute(&mut buf)?;
+        Ok(EnduranceGroupLog::decode(&buf)?)
+    }
+
+    /// Get the information for every endurance group on the controller.
+    ///
+    /// The groups are those in the Identify Endurance Group List, which holds
+    /// at most 2047 identifiers.
+    pub fn endurance_groups(
+        &self,
+    ) -> Result<Vec<EnduranceGroup>, LogPageError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        if id.ap_engidmax == 0 {
+            return Err(LogPageError::Unsupported("endurance groups"));
+        }
+
+        let mut buf = vec![0u8; ENDURANCE_GROUP_LIST_LEN];
+        self.identify(ENDURANCE_GROUP_LIST_CNS, &mut buf)?;
+        decode_endurance_group_list(&buf)?
+            .into_iter()
+            .map(|id| {
+                let info = self.endurance_group_log(id)?;
+                Ok(EnduranceGroup { id, info })
+            })
+            .collect()
+    }
+
+    /// Get the attributes of the NVM sets on the controller from the
+    /// Identify NVM Set List.
+    pub fn nvm_sets(&self) -> Result<Vec<NvmSet>, LogPageError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        if id.ap_nsetidmax == 0 {
+            return Err(LogPageError::Unsupported("NVM sets"));
+        }
+
+        let mut buf = vec![0u8; NVM_SET_LIST_LEN];
+        self.identify(NVM_SET_LIST_CNS, &mut buf)?;
+        Ok(NvmSet::decode_list(&buf)?)
+    }
+}
@@ -4,6 +4,8 @@
 
 use thiserror::Error;
 
+/// Reading the error state libnvme keeps in its handles.
+#[cfg(any(target_os = "illumos", not(feature = "sim")))]
 pub(crate) trait LibraryError {
     type Error;
 
@@ -13,7 +15,6 @@ pub(crate) trait LibraryError {
     fn fatal_context<C: Into<String>>(&self, context: C) -> Self::Error {
         let errmsg = self.get_errmsg();
         let syserr = self.get_syserr();
-        let syserr = std::io::Error::from_raw_os_error(syserr);
         self.current_error(InternalError {
             context: context.into(),
             syserr,
@@ -38,15 +39,31 @@ pub(crate) trait LibraryError {
     }
 }
 
-#[derive(Debug, Error)]
-#[error("{context}: {errmsg} [{syserr}]")]
+#[derive(Debug, Clone, Error)]
+#[error(
+    "{context}: {errmsg} [{}]",
+    std::io::Error::from_raw_os_error(*.syserr)
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InternalError {
     context: String,
-    syserr: std::io::Error,
+    syserr: i32,
     errmsg: String,
 }
 
+impl InternalError {
+    /// An error that did not come from libnvme itself, such as one produced
+    /// by a simulated backend.
+    pub(crate) fn new<C: Into<String>, M: Into<String>>(
+        context: C,
+        errmsg: M,
+    ) -> Self {
+        Self { context: context.into(), syserr: 0, errmsg: errmsg.into() }
+    }
+}
+
 // Add a blanket implementation that works on references as well
+#[cfg(any(target_os = "illumos", not(feature = "sim")))]
 impl<T: LibraryError> LibraryError for &T {
     type Error = T::Error;
 
@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    backend::{check_field, GetFeatureRequest},
+    controller::{Controller, NvmeControllerError},
+    NvmeErrorCode,
+};
+
+/// Which value of a feature Get Features returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FeatureSelect {
+    Current = 0,
+    Default = 1,
+    Saved = 2,
+    /// Whether the feature is saveable, namespace specific and changeable,
+    /// rather than its value.
+    SupportedCapabilities = 3,
+}
+
+/// A Get Features request for an arbitrary feature identified by its Feature
+/// Identifier (FID).
+///
+/// Each field is checked to be in range as it is set. Whether the controller
+/// supports the request is only checked once it is executed.
+pub struct GetFeatureRequestBuilder<'ctrl> {
+    req: GetFeatureRequest,
+    controller: &'ctrl Controller<'ctrl>,
+}
+
+impl<'ctrl> GetFeatureRequestBuilder<'ctrl> {
+    /// Set the Feature Identifier.
+    pub fn set_fid(mut self, fid: u32) -> Result<Self, NvmeControllerError> {
+        check_field(fid <= 0xff, NvmeErrorCode::FeatFidRange, "FID", fid)?;
+        self.req.fid = Some(fid);
+        Ok(self)
+    }
+
+    pub fn set_select(
+        mut self,
+        sel: FeatureSelect,
+    ) -> Result<Self, NvmeControllerError> {
+        self.req.sel = Some(sel);
+        Ok(self)
+    }
+
+    /// Set the namespace for namespace specific features.
+    pub fn set_nsid(mut self, nsid: u32) -> Result<Self, NvmeControllerError> {
+        self.req.nsid = Some(nsid);
+        Ok(self)
+    }
+
+    /// Set the feature specific Command Dword 11.
+    pub fn set_cdw11(
+        mut self,
+        cdw11: u32,
+    ) -> Result<Self, NvmeControllerError> {
+        self.req.cdw11 = Some(cdw11);
+        Ok(self)
+    }
+
+    /// Get a feature that is returned entirely in Dword 0 of the completion
+    /// queue entry.
+    pub fn execute(&self) -> Result<u32, NvmeControllerError> {
+        self.controller.backend().get_feature(&self.req, None)
+    }
+
+    /// Get a feature that also returns a data structure, which is written to
+    /// `buf`. Dword 0 of the completion queue entry is returned.
+    pub fn execute_with_output(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<u32, NvmeControllerError> {
+        self.controller.backend().get_feature(&self.req, Some(buf))
+    }
+}
+
+impl<'a> Controller<'a> {
+    /// Returns a new `GetFeatureRequestBuilder` that can be used to read an
+    /// arbitrary feature from the controller.
+    ///
+    /// Note: libnvme does not support Set Features today.
+    pub fn get_feature_request(
+        &self,
+    ) -> Result<GetFeatureRequestBuilder<'_>, NvmeControllerError> {
+        Ok(GetFeatureRequestBuilder {
+            req: GetFeatureRequest::default(),
+            controller: self,
+        })
+    }
+}
@@ -3,16 +3,17 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use core::slice;
-use std::mem::{self, MaybeUninit};
+use std::mem;
 
 use libnvme_sys::nvme::*;
 use thiserror::Error;
 
 use crate::{
+    backend::FirmwareCommitRequest,
+    boot_partition::BootPartitionId,
     controller::{Controller, NvmeControllerError, WriteLockedController},
     controller_info::ControllerInfoIdentify,
-    error::LibraryError,
-    logpage::{LogPageInfo, LogPageName},
+    logpage::LogPageName,
     NvmeError,
 };
 
@@ -61,8 +62,7 @@ impl FirmwareLogPage {
         logpage: nvme_fwslot_log_t,
     ) -> Self {
         let active_slot = logpage.bitfield1.fw_afi();
-        let slot1_is_read_only =
-            unsafe { (*identify.inner).id_frmw.fw_readonly() };
+        let slot1_is_read_only = identify.data().id_frmw.fw_readonly();
 
         // NVMe Spec: "If this field is 0h, then the controller does not
         // indicate the firmware slot that is going to be activated at the next
@@ -72,7 +72,7 @@ impl FirmwareLogPage {
             slot => Some(slot),
         };
 
-        let number_of_slots = unsafe { (*identify.inner).id_frmw.fw_nslot() };
+        let number_of_slots = identify.data().id_frmw.fw_nslot();
         let nslots = usize::from(number_of_slots);
         let mut firmware_slots = Vec::with_capacity(nslots);
         for slot in &logpage.fw_frs[..nslots] {
@@ -155,33 +155,20 @@ impl<'a> Controller<'a> {
         &self,
     ) -> Result<FirmwareLogPage, FirmwareLogPageError> {
         let expected_size = mem::size_of::<nvme_fwslot_log_t>();
-        let LogPageInfo { size, req, .. } =
-            self.get_logpage(LogPageName::Firmware)?;
+        let buf = self.backend().named_log(LogPageName::Firmware)?;
+        let size = buf.len();
         if size != expected_size {
             return Err(FirmwareLogPageError::UnexpectedSize {
                 size,
                 expected_size,
             });
         }
-        let mut buf = MaybeUninit::<nvme_fwslot_log_t>::zeroed();
-        self.check_result(
-            unsafe {
-                nvme_log_req_set_output(
-                    req.inner,
-                    buf.as_mut_ptr().cast(),
-                    size,
-                )
-            },
-            || format!("failed to set logpage req size to {size}"),
-        )?;
-
-        self.check_result(unsafe { nvme_log_req_exec(req.inner) }, || {
-            "failed to execute firmware log request"
-        })?;
-
-        // Our request was successful so we can assume this struct has been
-        // populated now.
-        let logpage = unsafe { buf.assume_init() };
+
+        // The buffer is exactly the size of the log page and has no alignment
+        // guarantees.
+        let logpage = unsafe {
+            std::ptr::read_unaligned(buf.as_ptr().cast::<nvme_fwslot_log_t>())
+        };
         let controller_info = self.get_info()?;
         let identify = controller_info.get_controller_info_identify();
         Ok(FirmwareLogPage::init(&identify, logpage))
@@ -196,6 +183,8 @@ pub enum FirmwareLoadError {
     NvmeController(#[from] NvmeControllerError),
     #[error("Supplied firmware is too large")]
     FirmwareImageTooLarge,
+    #[error("firmware load was cancelled after {loaded} bytes")]
+    Cancelled { loaded: u64 },
 }
 
 impl<'ctrl> WriteLockedController<'ctrl> {
@@ -205,17 +194,7 @@ impl<'ctrl> WriteLockedController<'ctrl> {
         data: &[u8],
         offset: u64,
     ) -> Result<(), NvmeControllerError> {
-        self.check_result(
-            unsafe {
-                nvme_fw_load(
-                    self.inner,
-                    data.as_ptr().cast(),
-                    data.len(),
-                    offset,
-                )
-            },
-            || "failed to load firmware",
-        )
+        self.backend().firmware_load(data, offset)
     }
 
     /// Upload new firmware to the NVMe controller.
@@ -223,6 +202,17 @@ impl<'ctrl> WriteLockedController<'ctrl> {
     /// Note this firmware needs to be commited to a slot via
     /// `FirmwareCommitRequestBuilder`.
     pub fn firmware_load(&self, data: &[u8]) -> Result<(), FirmwareLoadError> {
+        self.firmware_load_while(data, || true)
+    }
+
+    /// As [`Self::firmware_load`], but stopping with
+    /// [`FirmwareLoadError::Cancelled`] before any chunk for which `proceed`
+    /// returns false.
+    pub(crate) fn firmware_load_while(
+        &self,
+        data: &[u8],
+        mut proceed: impl FnMut() -> bool,
+    ) -> Result<(), FirmwareLoadError> {
         // We ideally would like to check that `data.len()` is using the right
         // Firmware Update Granularity (FWUG) but we have observed drives
         // where the provided vendor firmware size is not a multiple of the
@@ -236,6 +226,9 @@ impl<'ctrl> WriteLockedController<'ctrl> {
 
         let mut chunks = data.chunks(size);
         for chunk in &mut chunks {
+            if !proceed() {
+                return Err(FirmwareLoadError::Cancelled { loaded: offset });
+            }
             self.firmware_load_chunk(chunk, offset)?;
             // SAFETY:
             // - adding CHUNK_SIZE to the offset every time is okay since only
@@ -256,18 +249,16 @@ impl<'ctrl> WriteLockedController<'ctrl> {
     pub fn firmware_commit_request(
         &self,
     ) -> Result<FirmwareCommitRequestBuilder<'_>, NvmeControllerError> {
-        let mut req = std::ptr::null_mut();
-        self.check_result(
-            unsafe { nvme_fw_commit_req_init(self.inner, &mut req) },
-            || "failed to create firmware commit request",
-        )?;
-
-        Ok(FirmwareCommitRequestBuilder { req, controller: self })
+        Ok(FirmwareCommitRequestBuilder {
+            req: FirmwareCommitRequest::default(),
+            controller: self,
+        })
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FirmwareCommitAction {
     ///  Save image only.
     Save = NVME_FWC_SAVE,
@@ -279,61 +270,91 @@ pub enum FirmwareCommitAction {
     ///
     /// Note: illumos does not support this today.
     ActivateImmediately = NVME_FWC_ACTIVATE_IMMED,
+    /// Replace the boot partition set with
+    /// [`FirmwareCommitRequestBuilder::set_boot_partition`] with the
+    /// downloaded image (1.3).
+    ///
+    /// Note: illumos does not support this today.
+    ReplaceBootPartition = 6,
+    /// Mark the boot partition set with
+    /// [`FirmwareCommitRequestBuilder::set_boot_partition`] as active (1.3).
+    ///
+    /// Note: illumos does not support this today.
+    ActivateBootPartition = 7,
 }
 
 pub struct FirmwareCommitRequestBuilder<'ctrl> {
-    req: *mut nvme_fw_commit_req_t,
+    req: FirmwareCommitRequest,
     controller: &'ctrl WriteLockedController<'ctrl>,
 }
 
-impl<'ctrl> Drop for FirmwareCommitRequestBuilder<'ctrl> {
-    fn drop(&mut self) {
-        unsafe { nvme_fw_commit_req_fini(self.req) }
-    }
-}
-
 impl<'ctrl> FirmwareCommitRequestBuilder<'ctrl> {
     /// Set the NVMe slot the firmware is going to be commited to.
-    pub fn set_slot(self, slot: NvmeSlot) -> Result<Self, NvmeControllerError> {
-        self.controller
-            .check_result(
-                unsafe {
-                    nvme_fw_commit_req_set_slot(self.req, u32::from(slot.0))
-                },
-                || {
-                    format!(
-                        "failed to set firmware commit request slot to {}",
-                        slot.0
-                    )
-                },
-            )
-            .map(|_| self)
+    pub fn set_slot(
+        mut self,
+        slot: NvmeSlot,
+    ) -> Result<Self, NvmeControllerError> {
+        self.req.slot = Some(u32::from(slot.0));
+        Ok(self)
     }
 
     /// Set the commit action.
     pub fn set_action(
-        self,
+        mut self,
         action: FirmwareCommitAction,
     ) -> Result<Self, NvmeControllerError> {
-        self.controller
-            .check_result(
-                unsafe {
-                    nvme_fw_commit_req_set_action(self.req, action as u32)
-                },
-                || {
-                    format!(
-                    "failed to set firmware commit request action to {action:?}"
-                )
-                },
-            )
-            .map(|_| self)
+        self.req.action = Some(action);
+        Ok(self)
+    }
+
+    /// Set the boot partition that a boot partition commit action applies
+    /// to.
+    pub fn set_boot_partition(
+        mut self,
+        id: BootPartitionId,
+    ) -> Result<Self, NvmeControllerError> {
+        self.req.bpid = Some(u32::from(u8::from(id)));
+        Ok(self)
     }
 
     /// Execute a firmware commit request.
     pub fn execute(self) -> Result<(), NvmeControllerError> {
-        self.controller
-            .check_result(unsafe { nvme_fw_commit_req_exec(self.req) }, || {
-                "failed to execute firmware commit request"
+        self.controller.backend().firmware_commit(&self.req)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    #[test]
+    fn cancelled_load() {
+        let nvme = Nvme::with_backend(
+            Sim::new().with_controller(SimController::new(0)),
+        );
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        let image = vec![0u8; 3 * 64 * 1024];
+        let mut chunks = 0;
+        let err = controller
+            .firmware_load_while(&image, || {
+                chunks += 1;
+                chunks < 3
             })
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            FirmwareLoadError::Cancelled { loaded: 0x20000 }
+        ));
+
+        controller.firmware_load_while(&image, || true).unwrap();
     }
 }
@@ -0,0 +1,20 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::controller::{Controller, NvmeControllerError};
+
+impl<'a> Controller<'a> {
+    /// Issue an Identify command for the NVM command set with the given
+    /// Controller or Namespace Structure (CNS) value, writing the returned
+    /// data structure to `buf`.
+    ///
+    /// Note: libnvme only allows the CNS values that it knows about.
+    pub(crate) fn identify(
+        &self,
+        cns: u32,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.backend().identify(cns, buf)
+    }
+}
@@ -0,0 +1,1118 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The backend for the illumos libnvme.
+
+use std::{ffi::CStr, sync::Arc};
+
+use libnvme_sys::{
+    devinfo::{di_devfs_path, di_devfs_path_free, di_instance, di_node},
+    nvme::*,
+};
+
+use crate::{
+    backend::{
+        Backend, ControllerBackend, ControllerIter, FirmwareCommitRequest,
+        FormatRequest, GetFeatureRequest, LockLevel, LogRequest, VucRequest,
+    },
+    controller::NvmeControllerError,
+    controller_info::{ControllerInfo, NvmeInfoError, NvmeInfoErrorCode},
+    error::{InternalError, LibraryError},
+    lba::LbaFormat,
+    logpage::LogPageName,
+    namespace::{NamespaceDiscoveryLevel, NamespaceInfo, NamespaceSize},
+    vuc::VucCommand,
+    NvmeError, NvmeErrorCode, NvmeInitError,
+};
+
+/// The libnvme handle, which must outlive every controller opened from it.
+struct NvmeHandle(*mut nvme_t);
+
+impl Drop for NvmeHandle {
+    fn drop(&mut self) {
+        unsafe { nvme_fini(self.0) }
+    }
+}
+
+impl LibraryError for NvmeHandle {
+    type Error = NvmeError;
+
+    fn get_errmsg(&self) -> String {
+        let errmsg = unsafe { nvme_errmsg(self.0) };
+        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
+    }
+
+    fn get_syserr(&self) -> i32 {
+        unsafe { nvme_syserr(self.0) }
+    }
+
+    fn current_error(&self, internal: InternalError) -> Self::Error {
+        let code = NvmeErrorCode::from_raw(unsafe { nvme_err(self.0) });
+        assert_ne!(
+            code,
+            NvmeErrorCode::Ok,
+            "attempted to get current_error for a successful response"
+        );
+        NvmeError { code, error: internal }
+    }
+}
+
+pub(crate) struct Illumos {
+    hdl: Arc<NvmeHandle>,
+}
+
+impl Illumos {
+    pub(crate) fn init() -> Result<Self, NvmeInitError> {
+        let ptr = unsafe { nvme_init() };
+        if ptr.is_null() {
+            return Err(NvmeInitError);
+        }
+        // Shared with the controllers opened from it, which keep it alive;
+        // see the `Send` implementation for `Controller`.
+        #[allow(clippy::arc_with_non_send_sync)]
+        let hdl = Arc::new(NvmeHandle(ptr));
+        Ok(Self { hdl })
+    }
+}
+
+impl Backend for Illumos {
+    fn discover(&self) -> Result<ControllerIter<'_>, NvmeError> {
+        let mut iter = std::ptr::null_mut();
+        self.hdl.check_result(
+            unsafe { nvme_ctrl_discover_init(self.hdl.0, &mut iter) },
+            || "failed to init nvme controller discovery",
+        )?;
+        Ok(Box::new(ControllerDiscovery { hdl: Arc::clone(&self.hdl), iter }))
+    }
+
+    fn controller_by_instance(
+        &self,
+        instance: i32,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let mut ctrl = std::ptr::null_mut();
+        self.hdl.check_result(
+            unsafe {
+                nvme_ctrl_init_by_instance(self.hdl.0, instance, &mut ctrl)
+            },
+            || format!("failed to get controller for instance {instance}"),
+        )?;
+        Ok(Box::new(Controller { inner: ctrl, _hdl: Arc::clone(&self.hdl) }))
+    }
+
+    fn controller_by_devinfo_path(
+        &self,
+        path: &str,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let mut iter = std::ptr::null_mut();
+        self.hdl.check_result(
+            unsafe { nvme_ctrl_discover_init(self.hdl.0, &mut iter) },
+            || "failed to init nvme controller discovery",
+        )?;
+        let iter = Owned::new(iter, nvme_ctrl_discover_fini);
+
+        loop {
+            let mut disc: *const nvme_ctrl_disc_t = std::ptr::null();
+            match unsafe { nvme_ctrl_discover_step(iter.ptr, &mut disc) } {
+                NVME_ITER_VALID => {
+                    let devi = unsafe { nvme_ctrl_disc_devi(disc) };
+                    if devfs_path(devi).as_deref() != Some(path) {
+                        continue;
+                    }
+                    let mut ctrl = std::ptr::null_mut();
+                    self.hdl.check_result(
+                        unsafe { nvme_ctrl_init(self.hdl.0, devi, &mut ctrl) },
+                        || format!("failed to init nvme controller at {path}"),
+                    )?;
+                    return Ok(Box::new(Controller {
+                        inner: ctrl,
+                        _hdl: Arc::clone(&self.hdl),
+                    }));
+                }
+                NVME_ITER_DONE => {
+                    return Err(NvmeError::new(
+                        NvmeErrorCode::BadController,
+                        format!("failed to get controller at {path}"),
+                        "no nvme controller found at path",
+                    ))
+                }
+                NVME_ITER_ERROR => {
+                    return Err(self
+                        .hdl
+                        .fatal_context("failed to iterate nvme controllers"))
+                }
+                invalid => unreachable!(
+                    "invalid nvme controller iteration state ({invalid})",
+                ),
+            }
+        }
+    }
+}
+
+/// The path of a device tree node, without the leading `/devices`.
+fn devfs_path(node: *mut di_node) -> Option<String> {
+    let path = unsafe { di_devfs_path(node) };
+    if path.is_null() {
+        return None;
+    }
+    let owned = unsafe { CStr::from_ptr(path) }.to_string_lossy().into_owned();
+    unsafe { di_devfs_path_free(path) };
+    Some(owned)
+}
+
+struct ControllerDiscovery {
+    hdl: Arc<NvmeHandle>,
+    iter: *mut nvme_ctrl_iter_t,
+}
+
+impl Drop for ControllerDiscovery {
+    fn drop(&mut self) {
+        unsafe { nvme_ctrl_discover_fini(self.iter) }
+    }
+}
+
+impl ControllerDiscovery {
+    fn internal_step(
+        &self,
+    ) -> Result<Option<Box<dyn ControllerBackend>>, NvmeError> {
+        let mut nvme_ctr_disc: *const nvme_ctrl_disc_t = std::ptr::null_mut();
+        let state =
+            unsafe { nvme_ctrl_discover_step(self.iter, &mut nvme_ctr_disc) };
+        match state {
+            NVME_ITER_VALID => {
+                let di_node_t = unsafe { nvme_ctrl_disc_devi(nvme_ctr_disc) };
+                let mut nvme_ctrl: *mut nvme_ctrl_t = std::ptr::null_mut();
+                self.hdl
+                    .check_result(
+                        unsafe {
+                            nvme_ctrl_init(
+                                self.hdl.0,
+                                di_node_t,
+                                &mut nvme_ctrl,
+                            )
+                        },
+                        || "failed to init nvme controller",
+                    )
+                    .map(|_| {
+                        Some(Box::new(Controller {
+                            inner: nvme_ctrl,
+                            _hdl: Arc::clone(&self.hdl),
+                        })
+                            as Box<dyn ControllerBackend>)
+                    })
+            }
+            NVME_ITER_DONE => Ok(None),
+            NVME_ITER_ERROR => Err(self
+                .hdl
+                .fatal_context("failed to iterate nvme controllers")),
+            invalid => unreachable!(
+                "invalid nvme controller iteration state ({invalid})",
+            ),
+        }
+    }
+}
+
+impl Iterator for ControllerDiscovery {
+    type Item = Result<Box<dyn ControllerBackend>, NvmeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.internal_step().transpose()
+    }
+}
+
+struct Controller {
+    inner: *mut nvme_ctrl_t,
+    // Dropped after `inner` has been released.
+    _hdl: Arc<NvmeHandle>,
+}
+
+// SAFETY: libnvme(3LIB) is MT-Unsafe per handle: an `nvme_t` and every object
+// derived from it may be used from any thread, but by only one thread at a
+// time. That makes moving the whole handle family to another thread sound,
+// and moving one part of it unsound. This backend is only moved between
+// threads inside an `OwnedController`, which opens it from an `nvme_t` that
+// nothing else refers to once the controller is open, and which serializes
+// every call on it; the `Controller` and `Nvme` types that may share an
+// `nvme_t` are neither `Send` nor `Sync`. `Arc` makes sure that `nvme_fini`
+// runs once, after the controller has been released.
+unsafe impl Send for Controller {}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        unsafe { nvme_ctrl_fini(self.inner) }
+    }
+}
+
+impl LibraryError for Controller {
+    type Error = NvmeControllerError;
+
+    fn get_errmsg(&self) -> String {
+        let errmsg = unsafe { nvme_ctrl_errmsg(self.inner) };
+        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
+    }
+
+    fn get_syserr(&self) -> i32 {
+        unsafe { nvme_ctrl_syserr(self.inner) }
+    }
+
+    fn current_error(&self, internal: InternalError) -> Self::Error {
+        let mut device_status_code_type = 0;
+        let mut device_status_code = 0;
+
+        let raw = unsafe { nvme_ctrl_err(self.inner) };
+        unsafe {
+            nvme_ctrl_deverr(
+                self.inner,
+                &mut device_status_code_type,
+                &mut device_status_code,
+            )
+        };
+
+        NvmeControllerError::from_parts(
+            NvmeErrorCode::from_raw(raw),
+            device_status_code_type,
+            device_status_code,
+            internal,
+        )
+    }
+}
+
+/// Frees a libnvme request, iterator or snapshot when dropped.
+struct Owned<T> {
+    ptr: *mut T,
+    free: unsafe extern "C" fn(*mut T),
+}
+
+impl<T> Owned<T> {
+    fn new(ptr: *mut T, free: unsafe extern "C" fn(*mut T)) -> Self {
+        Self { ptr, free }
+    }
+}
+
+impl<T> Drop for Owned<T> {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.ptr) }
+    }
+}
+
+fn lba_format(lba: *const nvme_nvm_lba_fmt_t) -> LbaFormat {
+    unsafe {
+        LbaFormat::new(
+            nvme_nvm_lba_fmt_id(lba),
+            nvme_nvm_lba_fmt_data_size(lba),
+            nvme_nvm_lba_fmt_meta_size(lba),
+            nvme_nvm_lba_fmt_rel_perf(lba).into(),
+        )
+    }
+}
+
+struct CtrlInfo(Owned<nvme_ctrl_info_t>);
+
+impl LibraryError for CtrlInfo {
+    type Error = NvmeInfoError;
+
+    fn get_errmsg(&self) -> String {
+        let errmsg = unsafe { nvme_ctrl_info_errmsg(self.0.ptr) };
+        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
+    }
+
+    fn get_syserr(&self) -> i32 {
+        unsafe { nvme_ctrl_info_syserr(self.0.ptr) }
+    }
+
+    fn current_error(&self, internal: InternalError) -> Self::Error {
+        let raw = unsafe { nvme_ctrl_info_err(self.0.ptr) };
+        NvmeInfoError::from_code_and_error(
+            NvmeInfoErrorCode::from_raw(raw),
+            internal,
+        )
+    }
+}
+
+impl CtrlInfo {
+    fn snapshot(&self) -> ControllerInfo {
+        let ci = self.0.ptr;
+        let identify = unsafe { (*nvme_ctrl_info_identify(ci)).clone() };
+
+        let mut vid = 0;
+        let pci_vid = self
+            .check_result(
+                unsafe { nvme_ctrl_info_pci_vid(ci, &mut vid) },
+                || "failed to get pci vid",
+            )
+            .map(|_| vid);
+
+        let nformats = unsafe { nvme_ctrl_info_nformats(ci) };
+        let lba_formats = (0..nformats)
+            .map(|index| {
+                let mut lba: *const nvme_nvm_lba_fmt_t = std::ptr::null_mut();
+                self.check_result(
+                    unsafe { nvme_ctrl_info_format(ci, index, &mut lba) },
+                    || format!("failed to get lba fmt for index {index}"),
+                )
+                .map(|_| lba_format(lba))
+            })
+            .collect();
+
+        ControllerInfo::new(identify, pci_vid, lba_formats)
+    }
+}
+
+struct NsInfo(Owned<nvme_ns_info_t>);
+
+impl LibraryError for NsInfo {
+    type Error = NvmeInfoError;
+
+    fn get_errmsg(&self) -> String {
+        let errmsg = unsafe { nvme_ns_info_errmsg(self.0.ptr) };
+        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
+    }
+
+    fn get_syserr(&self) -> i32 {
+        unsafe { nvme_ns_info_syserr(self.0.ptr) }
+    }
+
+    fn current_error(&self, internal: InternalError) -> Self::Error {
+        let raw = unsafe { nvme_ns_info_err(self.0.ptr) };
+        NvmeInfoError::from_code_and_error(
+            NvmeInfoErrorCode::from_raw(raw),
+            internal,
+        )
+    }
+}
+
+fn ns_disc_level(level: NamespaceDiscoveryLevel) -> nvme_ns_disc_level_t {
+    match level {
+        NamespaceDiscoveryLevel::All => NVME_NS_DISC_F_ALL,
+        NamespaceDiscoveryLevel::Allocated => NVME_NS_DISC_F_ALLOCATED,
+        NamespaceDiscoveryLevel::Active => NVME_NS_DISC_F_ACTIVE,
+        NamespaceDiscoveryLevel::NotIgnored => NVME_NS_DISC_F_NOT_IGNORED,
+        NamespaceDiscoveryLevel::BlkDev => NVME_NS_DISC_F_BLKDEV,
+    }
+}
+
+fn vuc_command(disc: *const nvme_vuc_disc_t) -> VucCommand {
+    let name = unsafe { CStr::from_ptr(nvme_vuc_disc_name(disc)) };
+    let description = unsafe { CStr::from_ptr(nvme_vuc_disc_desc(disc)) };
+    VucCommand {
+        name: name.to_string_lossy().to_string(),
+        description: description.to_string_lossy().to_string(),
+        opcode: unsafe { nvme_vuc_disc_opcode(disc) },
+        data_transfer: unsafe { nvme_vuc_disc_dt(disc) }.into(),
+        impact: unsafe { nvme_vuc_disc_impact(disc) }.into(),
+        lock: unsafe { nvme_vuc_disc_lock(disc) }.into(),
+    }
+}
+
+impl Controller {
+    /// The device tree node of the controller, which lives as long as the
+    /// libnvme handle.
+    fn devi(&self) -> Result<*mut di_node, NvmeControllerError> {
+        let mut devi = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_ctrl_devi(self.inner, &mut devi) },
+            || "failed to get controller devinfo node",
+        )?;
+        Ok(devi)
+    }
+
+    fn namespace(
+        &self,
+        nsid: u32,
+    ) -> Result<Owned<nvme_ns_t>, NvmeControllerError> {
+        let mut ns: *mut nvme_ns_t = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_ns_init(self.inner, nsid, &mut ns) },
+            || "failed to init nvme namespace",
+        )
+        .map(|_| Owned::new(ns, nvme_ns_fini))
+    }
+
+    /// Determine the actual size of a log page discovered by name, which
+    /// for variable length log pages requires reading the start of it.
+    fn named_log_size(
+        &self,
+        name: LogPageName,
+        disc: &Owned<nvme_log_disc_t>,
+        req: &Owned<nvme_log_req_t>,
+    ) -> Result<usize, NvmeControllerError> {
+        let mut len = 0;
+        match unsafe { nvme_log_disc_size(disc.ptr, &mut len) } {
+            NVME_LOG_SIZE_K_VAR => {
+                // We have a log page with variable length. We need to
+                // determine the actual size.
+                let mut actual_size_needed = 0;
+                let len = len.try_into().expect("32-bit systems unsupported");
+                let mut buf = vec![0; len];
+
+                self.check_result(
+                    unsafe {
+                        nvme_log_req_set_output(
+                            req.ptr,
+                            buf.as_mut_ptr().cast(),
+                            len,
+                        )
+                    },
+                    || format!("failed to set output parameters to determine log length for {name:?}"),
+                )?;
+                self.check_result(
+                    unsafe { nvme_log_req_exec(req.ptr) },
+                    || format!("failed to execute log request to determine log length for {name:?}"),
+                )?;
+                self.check_result(
+                    unsafe {
+                        nvme_log_disc_calc_size(
+                            disc.ptr,
+                            &mut actual_size_needed,
+                            buf.as_mut_ptr().cast(),
+                            len,
+                        )
+                    },
+                    || {
+                        format!(
+                            "failed to determine full log page length for {name:?}"
+                        )
+                    },
+                )?;
+
+                // Clean up the temporary req output so that it's not left
+                // with a dangling pointer after this function returns.
+                self.check_result(
+                    unsafe { nvme_log_req_clear_output(req.ptr) },
+                    || format!("failed to clear req log output while determining the full log page length for {name:?}"),
+                )?;
+
+                Ok(actual_size_needed
+                    .try_into()
+                    .expect("32-bit systems unsupported"))
+            }
+            _ => Ok(len.try_into().expect("32-bit systems unsupported")),
+        }
+    }
+}
+
+impl ControllerBackend for Controller {
+    fn info(&self) -> Result<ControllerInfo, NvmeControllerError> {
+        let mut ctrl_info: *mut nvme_ctrl_info_t = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_ctrl_info_snap(self.inner, &mut ctrl_info) },
+            || "failed to get controller info snapshot",
+        )?;
+        Ok(CtrlInfo(Owned::new(ctrl_info, nvme_ctrl_info_free)).snapshot())
+    }
+
+    fn instance(&self) -> Result<i32, NvmeControllerError> {
+        let devi = self.devi()?;
+        Ok(unsafe { di_instance(devi) })
+    }
+
+    fn devinfo_path(&self) -> Result<String, NvmeControllerError> {
+        let devi = self.devi()?;
+        devfs_path(devi).ok_or_else(|| {
+            NvmeControllerError::new(
+                NvmeErrorCode::Libdevinfo,
+                "failed to get controller devinfo path",
+                "di_devfs_path failed",
+            )
+        })
+    }
+
+    fn lock(
+        &self,
+        level: LockLevel,
+        block: bool,
+    ) -> Result<(), NvmeControllerError> {
+        let level = match level {
+            LockLevel::Read => NVME_LOCK_L_READ,
+            LockLevel::Write => NVME_LOCK_L_WRITE,
+        };
+        let flags = if block { 0 } else { NVME_LOCK_F_DONT_BLOCK };
+        self.check_result(
+            unsafe { nvme_ctrl_lock(self.inner, level, flags) },
+            || "failed to grab nvme controller lock",
+        )
+    }
+
+    fn unlock(&self) {
+        unsafe { nvme_ctrl_unlock(self.inner) }
+    }
+
+    fn namespaces(
+        &self,
+        level: NamespaceDiscoveryLevel,
+    ) -> Result<Vec<u32>, NvmeControllerError> {
+        let mut iter = std::ptr::null_mut();
+        self.check_result(
+            unsafe {
+                nvme_ns_discover_init(
+                    self.inner,
+                    ns_disc_level(level),
+                    &mut iter,
+                )
+            },
+            || "failed to init nvme namespace discovery",
+        )?;
+        let iter = Owned::new(iter, nvme_ns_discover_fini);
+
+        let mut nsids = Vec::new();
+        loop {
+            let mut disc: *const nvme_ns_disc_t = std::ptr::null_mut();
+            match unsafe { nvme_ns_discover_step(iter.ptr, &mut disc) } {
+                NVME_ITER_VALID => {
+                    nsids.push(unsafe { nvme_ns_disc_nsid(disc) })
+                }
+                NVME_ITER_DONE => return Ok(nsids),
+                NVME_ITER_ERROR => {
+                    return Err(
+                        self.fatal_context("failed to iterate nvme namespaces")
+                    )
+                }
+                invalid => unreachable!(
+                    "invalid nvme namespace iteration state ({invalid})",
+                ),
+            }
+        }
+    }
+
+    fn namespace_info(
+        &self,
+        nsid: u32,
+    ) -> Result<NamespaceInfo, NvmeControllerError> {
+        let ns = self.namespace(nsid)?;
+        let mut nvme_ns_info: *mut nvme_ns_info_t = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_ns_info_snap(ns.ptr, &mut nvme_ns_info) },
+            || "failed to get ns info snapshot",
+        )?;
+        let info = NsInfo(Owned::new(nvme_ns_info, nvme_ns_info_free));
+
+        let mut lba: *const nvme_nvm_lba_fmt_t = std::ptr::null_mut();
+        let current_format = info
+            .check_result(
+                unsafe { nvme_ns_info_curformat(info.0.ptr, &mut lba) },
+                || "failed to get current format of NVMe namespace",
+            )
+            .map(|_| lba_format(lba));
+
+        let level = match unsafe { nvme_ns_info_level(info.0.ptr) } {
+            NVME_NS_DISC_F_ALL => NamespaceDiscoveryLevel::All,
+            NVME_NS_DISC_F_ALLOCATED => NamespaceDiscoveryLevel::Allocated,
+            NVME_NS_DISC_F_ACTIVE => NamespaceDiscoveryLevel::Active,
+            NVME_NS_DISC_F_NOT_IGNORED => NamespaceDiscoveryLevel::NotIgnored,
+            NVME_NS_DISC_F_BLKDEV => NamespaceDiscoveryLevel::BlkDev,
+            invalid => unreachable!("invalid nvme namespace level ({invalid})"),
+        };
+        let (mut nsze, mut capacity, mut used) = (0, 0, 0);
+        let size = info
+            .check_result(
+                unsafe { nvme_ns_info_size(info.0.ptr, &mut nsze) },
+                || "failed to get size of NVMe namespace",
+            )
+            .and_then(|_| {
+                info.check_result(
+                    unsafe { nvme_ns_info_cap(info.0.ptr, &mut capacity) },
+                    || "failed to get capacity of NVMe namespace",
+                )
+            })
+            .and_then(|_| {
+                info.check_result(
+                    unsafe { nvme_ns_info_use(info.0.ptr, &mut used) },
+                    || "failed to get utilization of NVMe namespace",
+                )
+            })
+            .map(|_| NamespaceSize { size: nsze, capacity, used });
+        Ok(NamespaceInfo::new(level, current_format, size))
+    }
+
+    fn blkdev_attach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        let ns = self.namespace(nsid)?;
+        self.check_result(unsafe { nvme_ns_bd_attach(ns.ptr) }, || {
+            "failed to attach blkdev to namespace"
+        })
+    }
+
+    fn blkdev_detach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        let ns = self.namespace(nsid)?;
+        self.check_result(unsafe { nvme_ns_bd_detach(ns.ptr) }, || {
+            "failed to detach blkdev to namespace"
+        })
+    }
+
+    fn named_log(
+        &self,
+        name: LogPageName,
+    ) -> Result<Vec<u8>, NvmeControllerError> {
+        let mut disc_ptr = std::ptr::null_mut();
+        let mut req_ptr = std::ptr::null_mut();
+
+        self.check_result(
+            unsafe {
+                nvme_log_req_init_by_name(
+                    self.inner,
+                    name.as_cstr().as_ptr(),
+                    0,
+                    &mut disc_ptr,
+                    &mut req_ptr,
+                )
+            },
+            || format!("failed to get logpage {:?}", name),
+        )?;
+        let disc = Owned::new(disc_ptr, nvme_log_disc_free);
+        let req = Owned::new(req_ptr, nvme_log_req_fini);
+
+        let size = self.named_log_size(name, &disc, &req)?;
+        let mut buf = vec![0u8; size];
+        self.check_result(
+            unsafe {
+                nvme_log_req_set_output(req.ptr, buf.as_mut_ptr().cast(), size)
+            },
+            || format!("failed to set logpage req size to {size}"),
+        )?;
+        self.check_result(unsafe { nvme_log_req_exec(req.ptr) }, || {
+            format!("failed to execute {name:?} log request")
+        })
+        .map(|_| buf)
+    }
+
+    fn get_log(
+        &self,
+        req: &LogRequest,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_log_req_init(self.inner, &mut ptr) },
+            || "failed to create log request",
+        )?;
+        let r = Owned::new(ptr, nvme_log_req_fini);
+
+        if let Some(lid) = req.lid {
+            self.check_result(
+                unsafe { nvme_log_req_set_lid(r.ptr, lid) },
+                || format!("failed to set lid {lid:#x} on log request"),
+            )?;
+        }
+        if let Some(lsp) = req.lsp {
+            self.check_result(
+                unsafe { nvme_log_req_set_lsp(r.ptr, lsp) },
+                || format!("failed to set lsp {lsp:#x} on log request"),
+            )?;
+        }
+        if let Some(nsid) = req.nsid {
+            self.check_result(
+                unsafe { nvme_log_req_set_nsid(r.ptr, nsid) },
+                || format!("failed to set nsid {nsid} on log request"),
+            )?;
+        }
+        if let Some(lsi) = req.lsi {
+            self.check_result(
+                unsafe { nvme_log_req_set_lsi(r.ptr, lsi) },
+                || format!("failed to set lsi {lsi:#x} on log request"),
+            )?;
+        }
+        if let Some(rae) = req.rae {
+            self.check_result(
+                unsafe { nvme_log_req_set_rae(r.ptr, rae) },
+                || format!("failed to set rae {rae} on log request"),
+            )?;
+        }
+        let offset = req.offset;
+        if offset != 0 {
+            self.check_result(
+                unsafe { nvme_log_req_set_offset(r.ptr, offset) },
+                || format!("failed to set offset {offset} on log request"),
+            )?;
+        }
+
+        let len = buf.len();
+        self.check_result(
+            unsafe {
+                nvme_log_req_set_output(r.ptr, buf.as_mut_ptr().cast(), len)
+            },
+            || format!("failed to set log request output to {len} bytes"),
+        )?;
+        self.check_result(unsafe { nvme_log_req_exec(r.ptr) }, || {
+            "failed to execute log request"
+        })
+    }
+
+    fn identify(
+        &self,
+        cns: u32,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe {
+                nvme_id_req_init_by_cns(self.inner, NVME_CSI_NVM, cns, &mut ptr)
+            },
+            || format!("failed to create identify request for cns {cns:#x}"),
+        )?;
+        let req = Owned::new(ptr, nvme_id_req_fini);
+
+        let len = buf.len();
+        self.check_result(
+            unsafe {
+                nvme_id_req_set_output(req.ptr, buf.as_mut_ptr().cast(), len)
+            },
+            || format!("failed to set identify output to {len} bytes"),
+        )?;
+        self.check_result(unsafe { nvme_id_req_exec(req.ptr) }, || {
+            "failed to execute identify request"
+        })
+    }
+
+    fn get_feature(
+        &self,
+        req: &GetFeatureRequest,
+        buf: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_get_feat_req_init(self.inner, &mut ptr) },
+            || "failed to create get feature request",
+        )?;
+        let r = Owned::new(ptr, nvme_get_feat_req_fini);
+
+        if let Some(fid) = req.fid {
+            self.check_result(
+                unsafe { nvme_get_feat_req_set_fid(r.ptr, fid) },
+                || format!("failed to set fid {fid:#x} on get feature request"),
+            )?;
+        }
+        if let Some(sel) = req.sel {
+            self.check_result(
+                unsafe { nvme_get_feat_req_set_sel(r.ptr, sel as u32) },
+                || {
+                    format!(
+                        "failed to set select {sel:?} on get feature request"
+                    )
+                },
+            )?;
+        }
+        if let Some(nsid) = req.nsid {
+            self.check_result(
+                unsafe { nvme_get_feat_req_set_nsid(r.ptr, nsid) },
+                || format!("failed to set nsid {nsid} on get feature request"),
+            )?;
+        }
+        if let Some(cdw11) = req.cdw11 {
+            self.check_result(
+                unsafe { nvme_get_feat_req_set_cdw11(r.ptr, cdw11) },
+                || {
+                    format!(
+                        "failed to set cdw11 {cdw11:#x} on get feature request"
+                    )
+                },
+            )?;
+        }
+
+        if let Some(buf) = buf {
+            let len = buf.len();
+            self.check_result(
+                unsafe {
+                    nvme_get_feat_req_set_output(
+                        r.ptr,
+                        buf.as_mut_ptr().cast(),
+                        len,
+                    )
+                },
+                || format!("failed to set get feature output to {len} bytes"),
+            )?;
+        }
+        self.check_result(unsafe { nvme_get_feat_req_exec(r.ptr) }, || {
+            "failed to execute get feature request"
+        })?;
+
+        let mut cdw0 = 0;
+        self.check_result(
+            unsafe { nvme_get_feat_req_get_cdw0(r.ptr, &mut cdw0) },
+            || "failed to get cdw0 from get feature request",
+        )
+        .map(|_| cdw0)
+    }
+
+    fn firmware_load(
+        &self,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<(), NvmeControllerError> {
+        self.check_result(
+            unsafe {
+                nvme_fw_load(
+                    self.inner,
+                    data.as_ptr().cast(),
+                    data.len(),
+                    offset,
+                )
+            },
+            || "failed to load firmware",
+        )
+    }
+
+    fn firmware_commit(
+        &self,
+        req: &FirmwareCommitRequest,
+    ) -> Result<(), NvmeControllerError> {
+        // libnvme has no way to set the Boot Partition ID, and rejects the
+        // boot partition commit actions that use it.
+        if let Some(bpid) = req.bpid {
+            return Err(NvmeControllerError::new(
+                NvmeErrorCode::FwCommitActionRange,
+                format!(
+                    "failed to set firmware commit request boot partition to \
+                    {bpid}"
+                ),
+                "libnvme does not support boot partition commit actions",
+            ));
+        }
+
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_fw_commit_req_init(self.inner, &mut ptr) },
+            || "failed to create firmware commit request",
+        )?;
+        let r = Owned::new(ptr, nvme_fw_commit_req_fini);
+
+        if let Some(slot) = req.slot {
+            self.check_result(
+                unsafe { nvme_fw_commit_req_set_slot(r.ptr, slot) },
+                || {
+                    format!(
+                        "failed to set firmware commit request slot to {slot}"
+                    )
+                },
+            )?;
+        }
+        if let Some(action) = req.action {
+            self.check_result(
+                unsafe { nvme_fw_commit_req_set_action(r.ptr, action as u32) },
+                || {
+                    format!(
+                    "failed to set firmware commit request action to {action:?}"
+                )
+                },
+            )?;
+        }
+
+        self.check_result(unsafe { nvme_fw_commit_req_exec(r.ptr) }, || {
+            "failed to execute firmware commit request"
+        })
+    }
+
+    fn format(&self, req: &FormatRequest) -> Result<(), NvmeControllerError> {
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_format_req_init(self.inner, &mut ptr) },
+            || "failed to create format request",
+        )?;
+        let r = Owned::new(ptr, nvme_format_req_fini);
+
+        if let Some(lbaf) = req.lbaf {
+            self.check_result(
+                unsafe { nvme_format_req_set_lbaf(r.ptr, lbaf) },
+                || format!("failed to set LBA format {lbaf} on format request"),
+            )?;
+        }
+        if let Some(nsid) = req.nsid {
+            self.check_result(
+                unsafe { nvme_format_req_set_nsid(r.ptr, nsid) },
+                || format!("failed to set nsid {nsid} on format request"),
+            )?;
+        }
+        if let Some(ses) = req.ses {
+            self.check_result(
+                unsafe { nvme_format_req_set_ses(r.ptr, ses) },
+                || format!("failed to set ses {ses} on format request"),
+            )?;
+        }
+
+        self.check_result(unsafe { nvme_format_req_exec(r.ptr) }, || {
+            "failed to execute format request"
+        })
+    }
+
+    fn vuc_commands(&self) -> Result<Vec<VucCommand>, NvmeControllerError> {
+        let mut iter = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_vuc_discover_init(self.inner, 0, &mut iter) },
+            || "failed to init vendor unique command discovery",
+        )?;
+        let iter = Owned::new(iter, nvme_vuc_discover_fini);
+
+        let mut commands = Vec::new();
+        loop {
+            let mut disc: *const nvme_vuc_disc_t = std::ptr::null_mut();
+            match unsafe { nvme_vuc_discover_step(iter.ptr, &mut disc) } {
+                // The discovery data is only valid until the next step so
+                // copy it out now.
+                NVME_ITER_VALID => commands.push(vuc_command(disc)),
+                NVME_ITER_DONE => return Ok(commands),
+                NVME_ITER_ERROR => {
+                    return Err(self.fatal_context(
+                        "failed to iterate vendor unique commands",
+                    ))
+                }
+                invalid => {
+                    unreachable!("invalid nvme vuc iteration state ({invalid})")
+                }
+            }
+        }
+    }
+
+    fn vuc(
+        &self,
+        req: &VucRequest,
+        input: Option<&[u8]>,
+        output: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_vuc_req_init(self.inner, &mut ptr) },
+            || "failed to create vendor unique command request",
+        )?;
+        let r = Owned::new(ptr, nvme_vuc_req_fini);
+
+        if let Some(opcode) = req.opcode {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_opcode(r.ptr, opcode) },
+                || format!("failed to set opcode {opcode:#x} on vuc request"),
+            )?;
+        }
+        if let Some(nsid) = req.nsid {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_nsid(r.ptr, nsid) },
+                || format!("failed to set nsid {nsid} on vuc request"),
+            )?;
+        }
+        if let Some(cdw) = req.cdw12 {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_cdw12(r.ptr, cdw) },
+                || format!("failed to set cdw12 {cdw:#x} on vuc request"),
+            )?;
+        }
+        if let Some(cdw) = req.cdw13 {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_cdw13(r.ptr, cdw) },
+                || format!("failed to set cdw13 {cdw:#x} on vuc request"),
+            )?;
+        }
+        if let Some(cdw) = req.cdw14 {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_cdw14(r.ptr, cdw) },
+                || format!("failed to set cdw14 {cdw:#x} on vuc request"),
+            )?;
+        }
+        if let Some(cdw) = req.cdw15 {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_cdw15(r.ptr, cdw) },
+                || format!("failed to set cdw15 {cdw:#x} on vuc request"),
+            )?;
+        }
+        if let Some(secs) = req.timeout {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_timeout(r.ptr, secs) },
+                || format!("failed to set timeout {secs}s on vuc request"),
+            )?;
+        }
+        if let Some(impact) = req.impact {
+            self.check_result(
+                unsafe { nvme_vuc_req_set_impact(r.ptr, impact.as_raw()) },
+                || format!("failed to set impact {impact:?} on vuc request"),
+            )?;
+        }
+        if let Some(data) = input {
+            let len = data.len();
+            self.check_result(
+                unsafe {
+                    nvme_vuc_req_set_input(r.ptr, data.as_ptr().cast(), len)
+                },
+                || format!("failed to set {len} byte input on vuc request"),
+            )?;
+        }
+        if let Some(data) = output {
+            let len = data.len();
+            self.check_result(
+                unsafe {
+                    nvme_vuc_req_set_output(
+                        r.ptr,
+                        data.as_mut_ptr().cast(),
+                        len,
+                    )
+                },
+                || format!("failed to set {len} byte output on vuc request"),
+            )?;
+        }
+
+        self.check_result(unsafe { nvme_vuc_req_exec(r.ptr) }, || {
+            "failed to execute vuc request"
+        })?;
+
+        let mut cdw0 = 0;
+        self.check_result(
+            unsafe { nvme_vuc_req_get_cdw0(r.ptr, &mut cdw0) },
+            || "failed to get cdw0 from vuc request",
+        )
+        .map(|_| cdw0)
+    }
+
+    fn wdc_resize_get(&self) -> Result<u32, NvmeControllerError> {
+        let mut size = 0;
+        self.check_result(
+            unsafe { nvme_wdc_resize_get(self.inner, &mut size) },
+            || "failed to get size of wdc device",
+        )
+        .map(|_| size)
+    }
+
+    fn wdc_resize_set(&self, gb: u32) -> Result<(), NvmeControllerError> {
+        self.check_result(
+            unsafe { nvme_wdc_resize_set(self.inner, gb) },
+            || "failed to resize wdc device",
+        )
+    }
+
+    fn wdc_e6_read(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let mut ptr = std::ptr::null_mut();
+        self.check_result(
+            unsafe { nvme_wdc_e6_req_init(self.inner, &mut ptr) },
+            || "failed to create e6 request",
+        )?;
+        let req = Owned::new(ptr, nvme_wdc_e6_req_fini);
+
+        let len = buf.len();
+        self.check_result(
+            unsafe { nvme_wdc_e6_req_set_offset(req.ptr, offset) },
+            || format!("failed to set offset {offset} on e6 request"),
+        )?;
+        self.check_result(
+            unsafe {
+                nvme_wdc_e6_req_set_output(
+                    req.ptr,
+                    buf.as_mut_ptr().cast(),
+                    len,
+                )
+            },
+            || format!("failed to set e6 request output to {len} bytes"),
+        )?;
+        self.check_result(unsafe { nvme_wdc_e6_req_exec(req.ptr) }, || {
+            format!("failed to execute e6 request at offset {offset}")
+        })
+    }
+
+    fn wdc_assert_clear(&self) -> Result<(), NvmeControllerError> {
+        self.check_result(unsafe { nvme_wdc_assert_clear(self.inner) }, || {
+            "failed to clear wdc assert"
+        })
+    }
+
+    fn wdc_assert_inject(&self) -> Result<(), NvmeControllerError> {
+        self.check_result(unsafe { nvme_wdc_assert_inject(self.inner) }, || {
+            "failed to inject wdc assert"
+        })
+    }
+}
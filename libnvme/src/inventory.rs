@@ -0,0 +1,335 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A snapshot of every controller on the system.
+//!
+//! [`Nvme::inventory`] reads everything commonly needed about each controller
+//! in one pass. A failure to read one part of one controller is recorded
+//! next to it rather than failing the scan, so that a single dead drive does
+//! not hide the rest of the system. Once a controller is found to be dead or
+//! gone, nothing further is read from it.
+
+use std::fmt::Display;
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    firmware::FirmwareLogPageError,
+    logpage::LogPageError,
+    monitor::HealthSnapshot,
+    namespace::NamespaceDiscoveryLevel,
+    nvmespec::ocp::OCP_SMART_LOG_LID,
+    snapshot::{
+        ControllerSnapshot, FirmwareSlotsSnapshot, HealthSummary,
+        NamespaceSnapshot, OcpSmartSummary,
+    },
+    Nvme, NvmeError, NvmeErrorCode,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Inventory {
+    pub controllers: Vec<ControllerInventory>,
+    /// Controllers that were discovered but could not be opened.
+    pub errors: Vec<InventoryError>,
+}
+
+/// What could be read of a single controller.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControllerInventory {
+    pub instance: Option<i32>,
+    /// The path of the controller in the device tree, without `/devices`.
+    pub devinfo_path: Option<String>,
+    pub controller: Option<ControllerSnapshot>,
+    /// Every namespace the controller has, whether or not it is active.
+    pub namespaces: Vec<NamespaceSnapshot>,
+    pub firmware: Option<FirmwareSlotsSnapshot>,
+    pub health: Option<HealthSummary>,
+    /// The OCP SMART / Health Information Extended log, if the controller
+    /// lists it among its supported log pages.
+    pub ocp_smart: Option<OcpSmartSummary>,
+    /// The parts of the controller that could not be read.
+    pub errors: Vec<InventoryError>,
+}
+
+/// A part of the inventory that could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InventoryError {
+    pub item: InventoryItem,
+    /// The libnvme error code, when the failure came from libnvme.
+    pub code: Option<NvmeErrorCode>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InventoryItem {
+    Controller,
+    Instance,
+    DevinfoPath,
+    Identify,
+    Namespaces,
+    Namespace { nsid: u32 },
+    Firmware,
+    Health,
+    OcpSmart,
+}
+
+/// An error from reading part of the inventory.
+trait Failure: Display {
+    fn code(&self) -> Option<NvmeErrorCode>;
+}
+
+impl Failure for NvmeError {
+    fn code(&self) -> Option<NvmeErrorCode> {
+        Some(self.code())
+    }
+}
+
+impl Failure for NvmeControllerError {
+    fn code(&self) -> Option<NvmeErrorCode> {
+        Some(self.code())
+    }
+}
+
+impl Failure for FirmwareLogPageError {
+    fn code(&self) -> Option<NvmeErrorCode> {
+        match self {
+            FirmwareLogPageError::ControllerError(e) => Some(e.code()),
+            FirmwareLogPageError::UnexpectedSize { .. } => None,
+        }
+    }
+}
+
+impl Failure for LogPageError {
+    fn code(&self) -> Option<NvmeErrorCode> {
+        match self {
+            LogPageError::ControllerError(e) => Some(e.code()),
+            LogPageError::Decode(_) | LogPageError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl InventoryError {
+    fn new<E: Failure>(item: InventoryItem, error: &E) -> Self {
+        Self { item, code: error.code(), message: error.to_string() }
+    }
+}
+
+impl Nvme {
+    /// Read the identity, namespaces, firmware slots and health of every
+    /// controller on the system, along with the OCP extended health of those
+    /// that support it.
+    ///
+    /// Only a failure to start controller discovery fails the whole call.
+    pub fn inventory(&self) -> Result<Inventory, NvmeError> {
+        let mut inventory = Inventory::default();
+        for controller in self.controller_discovery()? {
+            match controller {
+                Ok(controller) => inventory
+                    .controllers
+                    .push(ControllerInventory::collect(&controller)),
+                Err(e) => inventory
+                    .errors
+                    .push(InventoryError::new(InventoryItem::Controller, &e)),
+            }
+        }
+        Ok(inventory)
+    }
+}
+
+impl ControllerInventory {
+    /// Read what can be read of `controller`.
+    pub fn collect(controller: &Controller<'_>) -> Self {
+        let mut inventory = Self::default();
+        inventory.instance =
+            inventory.read(InventoryItem::Instance, || controller.instance());
+        inventory.devinfo_path = inventory
+            .read(InventoryItem::DevinfoPath, || controller.devinfo_path());
+
+        let info =
+            inventory.read(InventoryItem::Identify, || controller.get_info());
+        inventory.controller = info.as_ref().map(ControllerSnapshot::from);
+
+        let nsids = inventory.read(InventoryItem::Namespaces, || {
+            controller
+                .namespace_discovery(NamespaceDiscoveryLevel::All)?
+                .collect::<Result<Vec<_>, _>>()
+        });
+        for ns in nsids.unwrap_or_default() {
+            let item = InventoryItem::Namespace { nsid: ns.nsid() };
+            if let Some(snapshot) = inventory.read(item, || ns.snapshot()) {
+                inventory.namespaces.push(snapshot);
+            }
+        }
+
+        inventory.firmware = inventory
+            .read(InventoryItem::Firmware, || {
+                controller.get_firmware_log_page()
+            })
+            .map(|log| FirmwareSlotsSnapshot::from(&log));
+
+        if let Some(info) = &info {
+            inventory.health = inventory
+                .read(InventoryItem::Health, || {
+                    HealthSnapshot::read(controller, info)
+                })
+                .map(|health| HealthSummary::from(&health));
+        }
+
+        // The OCP log is optional, so a controller that does not list it, or
+        // whose supported log pages cannot be read, simply goes without.
+        let ocp = !inventory.is_dead()
+            && controller
+                .supported_log_pages()
+                .is_ok_and(|pages| pages.supports(OCP_SMART_LOG_LID as u8));
+        if ocp {
+            inventory.ocp_smart = inventory
+                .read(InventoryItem::OcpSmart, || {
+                    controller.get_ocp_smart_log()
+                })
+                .map(|log| OcpSmartSummary::from(&log));
+        }
+
+        inventory
+    }
+
+    /// Whether the controller was found to be dead or to have been removed.
+    pub fn is_dead(&self) -> bool {
+        self.errors.iter().any(|e| {
+            matches!(
+                e.code,
+                Some(NvmeErrorCode::CtrlDead | NvmeErrorCode::CtrlGone)
+            )
+        })
+    }
+
+    /// Run `f` unless the controller is already known to be dead, recording
+    /// its error if it fails.
+    fn read<T, E, F>(&mut self, item: InventoryItem, f: F) -> Option<T>
+    where
+        E: Failure,
+        F: FnOnce() -> Result<T, E>,
+    {
+        if self.is_dead() {
+            return None;
+        }
+        f().map_err(|e| self.errors.push(InventoryError::new(item, &e))).ok()
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::sim::{Sim, SimController, SimError, SimNamespace, SimOp};
+
+    #[test]
+    fn dead_controllers_do_not_fail_the_scan() {
+        let sim = Sim::new()
+            .with_controller(SimController::new(0).with_namespaces(vec![
+                SimNamespace::new(1),
+                SimNamespace { active: false, ..SimNamespace::new(2) },
+            ]))
+            .with_controller(SimController::new(1))
+            .with_controller(SimController::new(2).with_error(
+                SimOp::NamespaceInfo,
+                SimError::Library(NvmeErrorCode::NsRange),
+            ))
+            .with_controller(SimController::new(3).with_error(
+                SimOp::Open,
+                SimError::Library(NvmeErrorCode::CtrlGone),
+            ));
+        sim.controller(1, |c| c.set_dead(true));
+        let inventory = Nvme::with_backend(sim).inventory().unwrap();
+
+        assert_eq!(inventory.errors.len(), 1);
+        assert_eq!(inventory.errors[0].item, InventoryItem::Controller);
+        assert_eq!(inventory.errors[0].code, Some(NvmeErrorCode::CtrlGone));
+        assert_eq!(inventory.controllers.len(), 3);
+
+        let healthy = &inventory.controllers[0];
+        assert!(healthy.errors.is_empty());
+        assert_eq!(healthy.instance, Some(0));
+        assert_eq!(
+            healthy.devinfo_path.as_deref(),
+            Some("/pci@0,0/pci1b36,10@0")
+        );
+        assert_eq!(healthy.controller.as_ref().unwrap().serial, "SIM-0");
+        let levels = healthy
+            .namespaces
+            .iter()
+            .map(|ns| (ns.nsid, ns.level, ns.used.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            levels,
+            [
+                (1, NamespaceDiscoveryLevel::BlkDev, true),
+                (2, NamespaceDiscoveryLevel::Allocated, false)
+            ]
+        );
+        assert_eq!(healthy.firmware.as_ref().unwrap().active_slot, 1);
+        let health = healthy.health.unwrap();
+        assert_eq!(
+            (health.temperature, health.warning_temperature),
+            (300, 343)
+        );
+
+        // The dead controller is still listed, along with what is known of
+        // it without talking to the device.
+        let dead = &inventory.controllers[1];
+        assert!(dead.is_dead());
+        assert_eq!(dead.instance, Some(1));
+        assert!(dead.controller.is_none() && dead.health.is_none());
+        assert_eq!(dead.errors.len(), 1);
+        assert_eq!(dead.errors[0].item, InventoryItem::Identify);
+        assert_eq!(dead.errors[0].code, Some(NvmeErrorCode::CtrlDead));
+
+        let partial = &inventory.controllers[2];
+        assert!(!partial.is_dead());
+        assert!(partial.namespaces.is_empty());
+        assert_eq!(
+            partial.errors[0].item,
+            InventoryItem::Namespace { nsid: 1 }
+        );
+        assert!(partial.firmware.is_some() && partial.health.is_some());
+    }
+
+    #[test]
+    fn ocp_smart_is_read_when_supported() {
+        use crate::nvmespec::{
+            ocp::{OCP_SMART_LOG_GUID, OCP_SMART_LOG_LEN},
+            supported_log_pages::{
+                SUPPORTED_LOG_PAGES_LEN, SUPPORTED_LOG_PAGES_LID,
+            },
+        };
+
+        let mut supported = vec![0u8; SUPPORTED_LOG_PAGES_LEN];
+        for lid in [0x00, 0x01, 0x02, 0x03, 0xc0] {
+            supported[lid * 4] = 1;
+        }
+        let mut ocp = vec![0u8; OCP_SMART_LOG_LEN];
+        ocp[0..16].copy_from_slice(&4096u128.to_le_bytes());
+        ocp[494..496].copy_from_slice(&3u16.to_le_bytes());
+        ocp[496..512].copy_from_slice(&OCP_SMART_LOG_GUID);
+
+        let sim = Sim::new()
+            .with_controller(
+                SimController::new(0)
+                    .with_log_page(SUPPORTED_LOG_PAGES_LID, supported)
+                    .with_log_page(OCP_SMART_LOG_LID, ocp),
+            )
+            .with_controller(SimController::new(1));
+        let inventory = Nvme::with_backend(sim).inventory().unwrap();
+
+        let ocp = &inventory.controllers[0];
+        assert!(ocp.errors.is_empty());
+        assert_eq!(ocp.ocp_smart.unwrap().physical_media_units_written, 4096);
+        // Without the Supported Log Pages log, the supported log pages are
+        // inferred, which never includes vendor specific ones.
+        let plain = &inventory.controllers[1];
+        assert!(plain.errors.is_empty());
+        assert!(plain.ocp_smart.is_none());
+    }
+}
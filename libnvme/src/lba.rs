@@ -2,13 +2,8 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::marker::PhantomData;
-
-use crate::util::FfiPtr;
-
-use libnvme_sys::nvme::*;
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Performance {
     Best,
     Better,
@@ -29,34 +24,51 @@ impl From<u32> for Performance {
     }
 }
 
-pub struct LbaFormat<'info> {
-    lba: *const nvme_nvm_lba_fmt_t,
-    // This is tied to ControllerInfo or NamespaceInfo
-    _phantom: PhantomData<&'info ()>,
+impl From<Performance> for u32 {
+    fn from(value: Performance) -> Self {
+        match value {
+            Performance::Best => 0,
+            Performance::Better => 1,
+            Performance::Good => 2,
+            Performance::Degraded => 3,
+            Performance::Unknown(perf) => perf,
+        }
+    }
+}
+
+/// An LBA format supported by a controller or in use by a namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LbaFormat {
+    id: u32,
+    data_size: u64,
+    meta_size: u32,
+    rel_perf: Performance,
 }
 
-impl<'info> LbaFormat<'info> {
+impl LbaFormat {
+    pub fn new(
+        id: u32,
+        data_size: u64,
+        meta_size: u32,
+        rel_perf: Performance,
+    ) -> Self {
+        Self { id, data_size, meta_size, rel_perf }
+    }
+
     pub fn id(&self) -> u32 {
-        unsafe { nvme_nvm_lba_fmt_id(self.lba) }
+        self.id
     }
 
     pub fn meta_size(&self) -> u32 {
-        unsafe { nvme_nvm_lba_fmt_meta_size(self.lba) }
+        self.meta_size
     }
 
     pub fn data_size(&self) -> u64 {
-        unsafe { nvme_nvm_lba_fmt_data_size(self.lba) }
+        self.data_size
     }
 
     pub fn rel_perf(&self) -> Performance {
-        unsafe { nvme_nvm_lba_fmt_rel_perf(self.lba) }.into()
-    }
-}
-
-impl<'info> FfiPtr for LbaFormat<'info> {
-    type Ptr = *const nvme_nvm_lba_fmt_t;
-
-    unsafe fn from_raw(ptr: Self::Ptr) -> Self {
-        Self { lba: ptr, _phantom: PhantomData }
+        self.rel_perf
     }
 }
@@ -4,41 +4,82 @@
 
 #![deny(elided_lifetimes_in_paths)]
 
-use std::ffi::CStr;
+use std::rc::Rc;
 
-use error::{InternalError, LibraryError};
+use error::InternalError;
 use thiserror::Error;
 
+pub mod ana;
+#[cfg(feature = "async")]
+pub mod async_controller;
+pub mod async_event;
+pub mod backend;
+pub mod boot_partition;
+pub mod command_effects;
 pub mod controller;
 pub mod controller_info;
+pub mod endurance_group;
 mod error;
+pub mod feature;
 pub mod firmware;
-mod lba;
-mod logpage;
+mod identify;
+#[cfg(any(target_os = "illumos", not(feature = "sim")))]
+mod illumos;
+pub mod inventory;
+pub mod lba;
+pub mod logpage;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod monitor;
 pub mod namespace;
-mod util;
-mod wdc;
-
-use libnvme_sys::nvme::*;
+pub mod ocp;
+pub mod owned;
+pub mod persistent_event;
+pub mod pool;
+pub mod predictable_latency;
+pub mod selector;
+pub mod self_test;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod smart;
+pub mod snapshot;
+pub mod supported_log_pages;
+pub mod telemetry;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod vuc;
+pub mod wdc;
 
 pub use ::nvme as nvmespec;
 
-use crate::controller::ControllerDiscovery;
+use crate::{backend::Backend, controller::ControllerDiscovery};
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 #[error("{error}")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NvmeError {
     code: NvmeErrorCode,
     error: InternalError,
 }
 
 impl NvmeError {
+    /// Create an error for a backend other than libnvme, such as the
+    /// simulator.
+    pub fn new<C: Into<String>, M: Into<String>>(
+        code: NvmeErrorCode,
+        context: C,
+        errmsg: M,
+    ) -> Self {
+        Self { code, error: InternalError::new(context, errmsg) }
+    }
+
     pub fn code(&self) -> NvmeErrorCode {
         self.code
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NvmeErrorCode {
     Ok,
     Controller,
@@ -141,7 +182,10 @@ pub enum NvmeErrorCode {
 }
 
 impl NvmeErrorCode {
+    #[cfg(any(target_os = "illumos", not(feature = "sim")))]
     fn from_raw(raw: u32) -> Self {
+        use libnvme_sys::nvme::*;
+
         match raw {
             NVME_ERR_OK => NvmeErrorCode::Ok,
             NVME_ERR_CONTROLLER => NvmeErrorCode::Controller,
@@ -277,22 +321,47 @@ impl NvmeErrorCode {
 #[error("Failed to initialize nvme handle")]
 pub struct NvmeInitError;
 
-#[derive(Debug)]
-pub struct Nvme(*mut nvme_t);
+/// A handle to libnvme, or to another [`Backend`], through which controllers
+/// are found and opened.
+///
+/// An `Nvme`, and the controllers opened from it, must stay on the thread
+/// that opened it; see the [`pool`] module for using libnvme from several
+/// threads.
+pub struct Nvme {
+    backend: Rc<dyn Backend>,
+}
 
-impl Drop for Nvme {
-    fn drop(&mut self) {
-        unsafe { nvme_fini(self.0) }
+impl std::fmt::Debug for Nvme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Nvme").finish_non_exhaustive()
     }
 }
 
+/// The backend used by `Nvme::new`.
+#[cfg(any(target_os = "illumos", not(feature = "sim")))]
+pub(crate) fn system_backend() -> Result<Rc<dyn Backend>, NvmeInitError> {
+    Ok(Rc::new(illumos::Illumos::init()?))
+}
+
+/// Only the simulator is available when building for other platforms with
+/// the `sim` feature.
+#[cfg(not(any(target_os = "illumos", not(feature = "sim"))))]
+pub(crate) fn system_backend() -> Result<Rc<dyn Backend>, NvmeInitError> {
+    Err(NvmeInitError)
+}
+
 impl Nvme {
+    /// Open the illumos libnvme.
+    ///
+    /// This always fails on other platforms, where only the simulator is
+    /// built; use [`Nvme::with_backend`] there instead.
     pub fn new() -> Result<Self, NvmeInitError> {
-        let ptr = unsafe { nvme_init() };
-        if ptr.is_null() {
-            return Err(NvmeInitError);
-        }
-        Ok(Self(ptr))
+        Ok(Self { backend: system_backend()? })
+    }
+
+    /// Use `backend` in place of the illumos libnvme.
+    pub fn with_backend<B: Backend + 'static>(backend: B) -> Self {
+        Self { backend: Rc::new(backend) }
     }
 
     pub fn controller_discovery(
@@ -301,26 +370,3 @@ impl Nvme {
         ControllerDiscovery::new(self)
     }
 }
-
-impl LibraryError for Nvme {
-    type Error = NvmeError;
-
-    fn get_errmsg(&self) -> String {
-        let errmsg = unsafe { nvme_errmsg(self.0) };
-        unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().to_string()
-    }
-
-    fn get_syserr(&self) -> i32 {
-        unsafe { nvme_syserr(self.0) }
-    }
-
-    fn current_error(&self, internal: error::InternalError) -> Self::Error {
-        let code = NvmeErrorCode::from_raw(unsafe { nvme_err(self.0) });
-        assert_ne!(
-            code,
-            NvmeErrorCode::Ok,
-            "attempted to get current_error for a successful response"
-        );
-        NvmeError { code, error: internal }
-    }
-}
@@ -2,153 +2,217 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{ffi::CStr, marker::PhantomData};
-
-use libnvme_sys::nvme::*;
+use thiserror::Error;
 
 use crate::{
+    backend::{check_field, LogRequest},
     controller::{Controller, NvmeControllerError},
-    error::LibraryError,
+    nvmespec::{supported_log_pages::SupportedLogPages, DecodeError},
+    NvmeErrorCode,
 };
 
-pub(crate) struct NvmeLogReq<'a> {
-    pub(crate) inner: *mut nvme_log_req_t,
-    // The log page being requested
-    pub(crate) page_name: LogPageName,
-    // This is the `Controller` the log request was created from.
-    _phantom: PhantomData<&'a Controller<'a>>,
-}
-
-impl<'a> Drop for NvmeLogReq<'a> {
-    fn drop(&mut self) {
-        unsafe { nvme_log_req_fini(self.inner) }
-    }
-}
-
-pub(crate) struct NvmeLogDisc<'a> {
-    pub(crate) inner: *mut nvme_log_disc_t,
-    // This is the `Controller` the log disc was created from.
-    _phantom: PhantomData<&'a Controller<'a>>,
+/// An error reading a log page, or other data returned by the controller,
+/// and decoding it.
+#[derive(Debug, Error)]
+pub enum LogPageError {
+    #[error("libnvme error: {0}")]
+    ControllerError(#[from] NvmeControllerError),
+    #[error("failed to decode log page: {0}")]
+    Decode(#[from] DecodeError),
+    /// The controller does not report what was asked for. The message
+    /// names it.
+    #[error("NVMe device does not support {0}")]
+    Unsupported(&'static str),
 }
 
-impl<'a> Drop for NvmeLogDisc<'a> {
-    fn drop(&mut self) {
-        unsafe { nvme_log_disc_free(self.inner) }
-    }
-}
-
-pub(crate) struct LogPageInfo<'a> {
-    pub(crate) size: usize,
-    pub(crate) req: NvmeLogReq<'a>,
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LogPageName {
     Firmware,
 }
 
 impl LogPageName {
-    fn as_cstr(&self) -> &CStr {
+    #[cfg(any(target_os = "illumos", not(feature = "sim")))]
+    pub(crate) fn as_cstr(&self) -> &std::ffi::CStr {
         match self {
             LogPageName::Firmware => c"firmware",
         }
     }
 }
 
-/// Determine the actual size of an NVMe logpage, returning the original
-/// `NvmeLogReq` and the number of bytes needed to store the logpage data.
-fn get_logpage_size<'a>(
-    controller: &Controller<'_>,
-    disc: &NvmeLogDisc<'_>,
-    req: NvmeLogReq<'a>,
-) -> Result<(NvmeLogReq<'a>, usize), NvmeControllerError> {
-    let mut len = 0;
-    match unsafe { nvme_log_disc_size(disc.inner, &mut len) } {
-        NVME_LOG_SIZE_K_VAR => {
-            // We have a log page with variable length. We need to determine the
-            // actual size.
-            let mut actual_size_needed = 0;
-            let len = len.try_into().expect("32-bit systems unsupported");
-            let mut buf = vec![0; len];
-
-            controller.check_result(
-                unsafe {
-                    nvme_log_req_set_output(
-                        req.inner,
-                        buf.as_mut_ptr().cast(),
-                        len,
-                    )
-                },
-                || format!("failed to set output parameters to determine log length for {:?}", req.page_name),
-            )?;
-            controller.check_result(
-                unsafe { nvme_log_req_exec(req.inner) },
-                || format!("failed to execute log request to determine log length for {:?}", req.page_name),
-            )?;
-            controller.check_result(
-                unsafe {
-                    nvme_log_disc_calc_size(
-                        disc.inner,
-                        &mut actual_size_needed,
-                        buf.as_mut_ptr().cast(),
-                        len,
-                    )
-                },
-                || {
-                    format!(
-                        "failed to determine full log page length for {:?}",
-                        req.page_name
-                    )
-                },
-            )?;
-
-            // Clean up the temporary req output so that it's not left with a
-            // dangling pointer after this function returns.
-            controller.check_result(
-                unsafe { nvme_log_req_clear_output(req.inner) },
-                || format!("failed to clear req log output while determining the full log page length for {:?}", req.page_name),
-            )?;
-
-            Ok((
-                req,
-                actual_size_needed
-                    .try_into()
-                    .expect("32-bit systems unsupported"),
-            ))
+/// A request for an arbitrary log page identified by its Log Page Identifier
+/// (LID) rather than by a name libnvme knows about.
+///
+/// Each field is checked to be in range as it is set. Whether the controller
+/// supports the request is checked by the controller once it is executed, or
+/// before then against its supported log pages if they are given with
+/// [`LogRequestBuilder::check_supported`].
+pub struct LogRequestBuilder<'ctrl> {
+    req: LogRequest,
+    supported: Option<SupportedLogPages>,
+    controller: &'ctrl Controller<'ctrl>,
+}
+
+impl<'ctrl> LogRequestBuilder<'ctrl> {
+    /// Set the Log Page Identifier.
+    pub fn set_lid(mut self, lid: u32) -> Result<Self, NvmeControllerError> {
+        check_field(lid <= 0xff, NvmeErrorCode::LogLidRange, "LID", lid)?;
+        self.req.lid = Some(lid);
+        Ok(self)
+    }
+
+    /// Set the Log Specific Parameter.
+    pub fn set_lsp(mut self, lsp: u32) -> Result<Self, NvmeControllerError> {
+        check_field(lsp <= 0x7f, NvmeErrorCode::LogLspRange, "LSP", lsp)?;
+        self.req.lsp = Some(lsp);
+        Ok(self)
+    }
+
+    /// Set the namespace the log page is requested for.
+    pub fn set_nsid(mut self, nsid: u32) -> Result<Self, NvmeControllerError> {
+        self.req.nsid = Some(nsid);
+        Ok(self)
+    }
+
+    /// Set the Log Specific Identifier, such as the endurance group a log
+    /// page is requested for.
+    pub fn set_lsi(mut self, lsi: u32) -> Result<Self, NvmeControllerError> {
+        check_field(lsi <= 0xffff, NvmeErrorCode::LogLsiRange, "LSI", lsi)?;
+        self.req.lsi = Some(lsi);
+        Ok(self)
+    }
+
+    /// Set whether the controller should Retain Asynchronous Events that are
+    /// cleared by reading this log page.
+    pub fn set_rae(mut self, rae: bool) -> Result<Self, NvmeControllerError> {
+        self.req.rae = Some(rae);
+        Ok(self)
+    }
+
+    /// Check every read against `pages`, as returned by
+    /// [`Controller::supported_log_pages`], rather than leaving it to the
+    /// controller to reject log pages and offsets it does not support.
+    pub fn check_supported(
+        mut self,
+        pages: &SupportedLogPages,
+    ) -> Result<Self, NvmeControllerError> {
+        self.supported = Some(pages.clone());
+        Ok(self)
+    }
+
+    /// Fail with `LogUnsupByDev` or `LogOffsetUnsup` if the supported log
+    /// pages given to `check_supported` rule out reading at `offset`.
+    fn validate(&self, offset: u64) -> Result<(), NvmeControllerError> {
+        let (Some(pages), Some(lid)) = (&self.supported, self.req.lid) else {
+            return Ok(());
+        };
+        let lid = lid as u8;
+        if !pages.supports(lid) {
+            return Err(NvmeControllerError::new(
+                NvmeErrorCode::LogUnsupByDev,
+                format!("failed to read log page {lid:#x}"),
+                format!("log page {lid:#x} is not supported by the device"),
+            ));
         }
-        _ => Ok((req, len.try_into().expect("32-bit systems unsupported"))),
+        if !pages.supports_offset(lid, offset) {
+            return Err(NvmeControllerError::new(
+                NvmeErrorCode::LogOffsetUnsup,
+                format!("failed to read log page {lid:#x} at offset {offset}"),
+                format!("log page {lid:#x} does not support offsets"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read `buf.len()` bytes of the log page starting at byte `offset`.
+    ///
+    /// Note that non-zero offsets require the controller to support the
+    /// extended Get Log Page command.
+    pub fn read_at(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.validate(offset)?;
+        let req = LogRequest { offset, ..self.req };
+        self.controller.backend().get_log(&req, buf)
+    }
+
+    /// Read the first `buf.len()` bytes of the log page.
+    pub fn execute(&self, buf: &mut [u8]) -> Result<(), NvmeControllerError> {
+        self.read_at(0, buf)
     }
 }
 
 impl<'a> Controller<'a> {
-    pub(crate) fn get_logpage(
+    /// Returns a new `LogRequestBuilder` that can be used to read an arbitrary
+    /// log page from the controller.
+    pub fn log_request(
         &self,
-        name: LogPageName,
-    ) -> Result<LogPageInfo<'a>, NvmeControllerError> {
-        let mut disc_ptr = std::ptr::null_mut();
-        let mut req_ptr = std::ptr::null_mut();
-
-        self.check_result(
-            unsafe {
-                nvme_log_req_init_by_name(
-                    self.inner,
-                    name.as_cstr().as_ptr(),
-                    0,
-                    &mut disc_ptr,
-                    &mut req_ptr,
-                )
-            },
-            || format!("failed to get logpage {:?}", name),
-        )?;
-
-        let disc = NvmeLogDisc { inner: disc_ptr, _phantom: PhantomData };
-        let req = NvmeLogReq {
-            inner: req_ptr,
-            page_name: name,
-            _phantom: PhantomData,
-        };
-        let (req, size) = get_logpage_size(self, &disc, req)?;
+    ) -> Result<LogRequestBuilder<'_>, NvmeControllerError> {
+        Ok(LogRequestBuilder {
+            req: LogRequest::default(),
+            supported: None,
+            controller: self,
+        })
+    }
+}
 
-        Ok(LogPageInfo { size, req })
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        nvmespec::supported_log_pages::{
+            SUPPORTED_LOG_PAGES_LEN, SUPPORTED_LOG_PAGES_LID,
+        },
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    #[test]
+    fn checked_against_supported_log_pages() {
+        // SMART is supported with offsets, the Error Information log only
+        // without, and the vendor specific C0h log not at all.
+        let mut supported = vec![0u8; SUPPORTED_LOG_PAGES_LEN];
+        for (lid, support) in [(0x01, 0x1u32), (0x02, 0x3)] {
+            supported[lid * 4..][..4].copy_from_slice(&support.to_le_bytes());
+        }
+        let nvme = Nvme::with_backend(
+            Sim::new().with_controller(
+                SimController::new(0)
+                    .with_log_page(SUPPORTED_LOG_PAGES_LID, supported)
+                    .with_log_page(0x01, vec![0; 64])
+                    .with_log_page(0xc0, vec![0; 512]),
+            ),
+        );
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let pages = controller.supported_log_pages().unwrap();
+        let mut buf = [0u8; 64];
+
+        let req = |lid| {
+            controller
+                .log_request()
+                .unwrap()
+                .set_lid(lid)
+                .unwrap()
+                .check_supported(&pages)
+                .unwrap()
+        };
+        req(0x02).read_at(512, &mut buf).unwrap();
+        req(0x01).execute(&mut buf).unwrap();
+        let err = req(0x01).read_at(64, &mut buf).unwrap_err();
+        assert_eq!(err.code(), NvmeErrorCode::LogOffsetUnsup);
+        let err = req(0xc0).execute(&mut buf).unwrap_err();
+        assert_eq!(err.code(), NvmeErrorCode::LogUnsupByDev);
+
+        // Without the check the controller is left to decide, and the
+        // simulator serves what it has.
+        controller
+            .log_request()
+            .unwrap()
+            .set_lid(0xc0)
+            .unwrap()
+            .execute(&mut buf)
+            .unwrap();
     }
 }
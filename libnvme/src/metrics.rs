@@ -0,0 +1,636 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Controller health in the OpenMetrics text format.
+//!
+//! [`render`] turns an [`Inventory`] into text that Prometheus and compatible
+//! systems can scrape. It does not talk to any controller, so the output for
+//! a given inventory is always the same.
+//!
+//! Every sample of a controller is labelled with its `serial`, `model` and
+//! `fwrev`, and every sample of a namespace additionally with its `nsid`.
+//! Controllers whose identity could not be read have no samples of their
+//! own, but their errors are counted by `nvme_inventory_errors`. The names
+//! and labels of metrics are part of this crate's API, so are no more stable
+//! than the rest of it.
+
+use std::fmt::{self, Display};
+
+use crate::{
+    inventory::{ControllerInventory, Inventory},
+    snapshot::{ControllerSnapshot, FirmwareSlotSnapshot, NamespaceSnapshot},
+};
+
+/// The content type of the text [`render`] produces.
+pub const CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, Copy)]
+enum MetricType {
+    Gauge,
+    Counter,
+    Info,
+}
+
+impl MetricType {
+    fn name(self) -> &'static str {
+        match self {
+            MetricType::Gauge => "gauge",
+            MetricType::Counter => "counter",
+            MetricType::Info => "info",
+        }
+    }
+
+    /// The suffix of the names of samples of this type.
+    fn suffix(self) -> &'static str {
+        match self {
+            MetricType::Gauge => "",
+            MetricType::Counter => "_total",
+            MetricType::Info => "_info",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Unsigned(u128),
+    Signed(i64),
+    /// A percentage, shown as a ratio.
+    Percent(u16),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Unsigned(v) => write!(f, "{v}"),
+            Value::Signed(v) => write!(f, "{v}"),
+            Value::Percent(p) => write!(f, "{}.{:02}", p / 100, p % 100),
+        }
+    }
+}
+
+/// A metric family, and how to get the value of one of its samples from
+/// a `T`.
+struct Metric<T> {
+    name: &'static str,
+    metric_type: MetricType,
+    /// The unit, which `name` must end with.
+    unit: Option<&'static str>,
+    help: &'static str,
+    value: fn(&T) -> Option<Value>,
+}
+
+const INVENTORY_METRICS: &[Metric<Inventory>] = &[
+    Metric {
+        name: "nvme_controllers",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "Controllers that were discovered and opened.",
+        value: |i| Some(Value::Unsigned(i.controllers.len() as u128)),
+    },
+    Metric {
+        name: "nvme_inventory_errors",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "Controllers and parts of controllers that could not be read.",
+        value: |i| {
+            let errors = i.errors.len()
+                + i.controllers.iter().map(|c| c.errors.len()).sum::<usize>();
+            Some(Value::Unsigned(errors as u128))
+        },
+    },
+];
+
+/// The number of bytes in a SMART data unit.
+const DATA_UNIT: u128 = 512_000;
+
+/// The difference between degrees Kelvin and Celsius, which is all of it
+/// that controllers report.
+const KELVIN_OFFSET: i64 = 273;
+
+fn celsius(kelvin: u16) -> Value {
+    Value::Signed(i64::from(kelvin) - KELVIN_OFFSET)
+}
+
+const CONTROLLER_METRICS: &[Metric<ControllerInventory>] = &[
+    Metric {
+        name: "nvme_controller",
+        metric_type: MetricType::Info,
+        unit: None,
+        help: "Identity of the controller.",
+        value: |_| Some(Value::Unsigned(1)),
+    },
+    Metric {
+        name: "nvme_critical_warning",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "Critical Warning bits of the SMART / Health log.",
+        value: |c| Some(Value::Unsigned(c.health?.critical_warning.into())),
+    },
+    Metric {
+        name: "nvme_temperature_celsius",
+        metric_type: MetricType::Gauge,
+        unit: Some("celsius"),
+        help: "Composite temperature.",
+        value: |c| Some(celsius(c.health?.temperature)),
+    },
+    Metric {
+        name: "nvme_temperature_warning_celsius",
+        metric_type: MetricType::Gauge,
+        unit: Some("celsius"),
+        help: "Warning composite temperature threshold.",
+        value: |c| match c.health?.warning_temperature {
+            0 => None,
+            t => Some(celsius(t)),
+        },
+    },
+    Metric {
+        name: "nvme_temperature_critical_celsius",
+        metric_type: MetricType::Gauge,
+        unit: Some("celsius"),
+        help: "Critical composite temperature threshold.",
+        value: |c| match c.health?.critical_temperature {
+            0 => None,
+            t => Some(celsius(t)),
+        },
+    },
+    Metric {
+        name: "nvme_available_spare_ratio",
+        metric_type: MetricType::Gauge,
+        unit: Some("ratio"),
+        help: "Remaining spare capacity.",
+        value: |c| Some(Value::Percent(c.health?.available_spare.into())),
+    },
+    Metric {
+        name: "nvme_available_spare_threshold_ratio",
+        metric_type: MetricType::Gauge,
+        unit: Some("ratio"),
+        help: "Spare capacity below which the controller warns.",
+        value: |c| {
+            Some(Value::Percent(c.health?.available_spare_threshold.into()))
+        },
+    },
+    Metric {
+        name: "nvme_endurance_used_ratio",
+        metric_type: MetricType::Gauge,
+        unit: Some("ratio"),
+        help: "Estimate of the life used, which may exceed 1.",
+        value: |c| Some(Value::Percent(c.health?.percentage_used.into())),
+    },
+    Metric {
+        name: "nvme_data_read_bytes",
+        metric_type: MetricType::Counter,
+        unit: Some("bytes"),
+        help: "Data read by the host, in multiples of 512000 bytes.",
+        value: |c| {
+            let units = c.health?.data_units_read;
+            Some(Value::Unsigned(units.saturating_mul(DATA_UNIT)))
+        },
+    },
+    Metric {
+        name: "nvme_data_written_bytes",
+        metric_type: MetricType::Counter,
+        unit: Some("bytes"),
+        help: "Data written by the host, in multiples of 512000 bytes.",
+        value: |c| {
+            let units = c.health?.data_units_written;
+            Some(Value::Unsigned(units.saturating_mul(DATA_UNIT)))
+        },
+    },
+    Metric {
+        name: "nvme_host_read_commands",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Read commands completed.",
+        value: |c| Some(Value::Unsigned(c.health?.host_read_commands)),
+    },
+    Metric {
+        name: "nvme_host_write_commands",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Write commands completed.",
+        value: |c| Some(Value::Unsigned(c.health?.host_write_commands)),
+    },
+    Metric {
+        name: "nvme_power_cycles",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Power cycles.",
+        value: |c| Some(Value::Unsigned(c.health?.power_cycles)),
+    },
+    Metric {
+        name: "nvme_power_on_seconds",
+        metric_type: MetricType::Counter,
+        unit: Some("seconds"),
+        help: "Time powered on, in whole hours.",
+        value: |c| {
+            let hours = c.health?.power_on_hours;
+            Some(Value::Unsigned(hours.saturating_mul(3600)))
+        },
+    },
+    Metric {
+        name: "nvme_unsafe_shutdowns",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Shutdowns without notice from the host.",
+        value: |c| Some(Value::Unsigned(c.health?.unsafe_shutdowns)),
+    },
+    Metric {
+        name: "nvme_media_errors",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Unrecovered data integrity errors.",
+        value: |c| Some(Value::Unsigned(c.health?.media_errors)),
+    },
+    Metric {
+        name: "nvme_error_log_entries",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Error Information log entries created.",
+        value: |c| Some(Value::Unsigned(c.health?.error_log_entries)),
+    },
+    Metric {
+        name: "nvme_ocp_media_written_bytes",
+        metric_type: MetricType::Counter,
+        unit: Some("bytes"),
+        help: "Data written to the media, including internal writes.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.physical_media_units_written))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_media_read_bytes",
+        metric_type: MetricType::Counter,
+        unit: Some("bytes"),
+        help: "Data read from the media, including internal reads.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.physical_media_units_read))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_bad_user_nand_blocks",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "Retired user NAND blocks.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.bad_user_nand_blocks.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_bad_system_nand_blocks",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "Retired system NAND blocks.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.bad_system_nand_blocks.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_xor_recoveries",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Data recovered with XOR parity.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.xor_recovery_count.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_uncorrectable_read_errors",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Reads that could not be corrected.",
+        value: |c| {
+            let count = c.ocp_smart?.uncorrectable_read_error_count;
+            Some(Value::Unsigned(count.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_soft_ecc_errors",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Reads corrected by soft decoding.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.soft_ecc_error_count.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_e2e_detected_errors",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "End to end data protection errors detected.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.e2e_detected_errors.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_e2e_corrected_errors",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "End to end data protection errors corrected.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.e2e_corrected_errors.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_system_data_used_ratio",
+        metric_type: MetricType::Gauge,
+        unit: Some("ratio"),
+        help: "Rated endurance of the system area consumed.",
+        value: |c| {
+            Some(Value::Percent(c.ocp_smart?.system_data_percent_used.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_thermal_throttling_events",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Thermal throttling events, saturating at 255.",
+        value: |c| {
+            let events = c.ocp_smart?.thermal_throttling_events;
+            Some(Value::Unsigned(events.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_pcie_correctable_errors",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "PCIe correctable errors.",
+        value: |c| {
+            let count = c.ocp_smart?.pcie_correctable_error_count;
+            Some(Value::Unsigned(count.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_incomplete_shutdowns",
+        metric_type: MetricType::Counter,
+        unit: None,
+        help: "Shutdowns that did not complete.",
+        value: |c| {
+            Some(Value::Unsigned(c.ocp_smart?.incomplete_shutdowns.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_free_blocks_ratio",
+        metric_type: MetricType::Gauge,
+        unit: Some("ratio"),
+        help: "Free blocks remaining.",
+        value: |c| {
+            Some(Value::Percent(c.ocp_smart?.percent_free_blocks.into()))
+        },
+    },
+    Metric {
+        name: "nvme_ocp_capacitor_health_ratio",
+        metric_type: MetricType::Gauge,
+        unit: Some("ratio"),
+        help: "Charge the power loss protection capacitors can hold.",
+        value: |c| Some(Value::Percent(c.ocp_smart?.capacitor_health)),
+    },
+    Metric {
+        name: "nvme_firmware_active_slot",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "The firmware slot the controller is running from.",
+        value: |c| {
+            Some(Value::Unsigned(c.firmware.as_ref()?.active_slot.into()))
+        },
+    },
+    Metric {
+        name: "nvme_firmware_next_active_slot",
+        metric_type: MetricType::Gauge,
+        unit: None,
+        help: "The firmware slot that will be active after the next reset.",
+        value: |c| {
+            let slot = c.firmware.as_ref()?.next_active_slot?;
+            Some(Value::Unsigned(slot.into()))
+        },
+    },
+];
+
+const FIRMWARE_SLOT_METRIC: Metric<FirmwareSlotSnapshot> = Metric {
+    name: "nvme_firmware_slot",
+    metric_type: MetricType::Info,
+    unit: None,
+    help: "The firmware revision in each occupied slot.",
+    value: |s| s.version.as_ref().map(|_| Value::Unsigned(1)),
+};
+
+const NAMESPACE_METRICS: &[Metric<NamespaceSnapshot>] = &[
+    Metric {
+        name: "nvme_namespace_size_bytes",
+        metric_type: MetricType::Gauge,
+        unit: Some("bytes"),
+        help: "Size of the namespace.",
+        value: |ns| Some(Value::Unsigned(ns.size?.into())),
+    },
+    Metric {
+        name: "nvme_namespace_capacity_bytes",
+        metric_type: MetricType::Gauge,
+        unit: Some("bytes"),
+        help: "Space that may be allocated to the namespace.",
+        value: |ns| Some(Value::Unsigned(ns.capacity?.into())),
+    },
+    Metric {
+        name: "nvme_namespace_used_bytes",
+        metric_type: MetricType::Gauge,
+        unit: Some("bytes"),
+        help: "Space allocated to the namespace.",
+        value: |ns| Some(Value::Unsigned(ns.used?.into())),
+    },
+];
+
+/// The labels of a sample, in order.
+#[derive(Debug, Clone, Default)]
+struct Labels(Vec<(&'static str, String)>);
+
+impl Labels {
+    fn controller(snapshot: &ControllerSnapshot) -> Self {
+        Self(vec![
+            ("serial", snapshot.serial.clone()),
+            ("model", snapshot.model.clone()),
+            ("fwrev", snapshot.fwrev.clone()),
+        ])
+    }
+
+    fn with(&self, name: &'static str, value: impl Display) -> Self {
+        let mut labels = self.clone();
+        labels.0.push((name, value.to_string()));
+        labels
+    }
+}
+
+impl Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        f.write_str("{")?;
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{name}=\"")?;
+            for c in value.chars() {
+                match c {
+                    '\\' => f.write_str("\\\\")?,
+                    '"' => f.write_str("\\\"")?,
+                    '\n' => f.write_str("\\n")?,
+                    c => write!(f, "{c}")?,
+                }
+            }
+            f.write_str("\"")?;
+        }
+        f.write_str("}")
+    }
+}
+
+/// Write the metadata of `metric` followed by a sample for each item that
+/// has a value.
+fn family<T>(out: &mut String, metric: &Metric<T>, items: &[(Labels, &T)]) {
+    let Metric { name, metric_type, unit, help, value } = metric;
+    out.push_str(&format!("# TYPE {name} {}\n", metric_type.name()));
+    if let Some(unit) = unit {
+        out.push_str(&format!("# UNIT {name} {unit}\n"));
+    }
+    out.push_str(&format!("# HELP {name} {help}\n"));
+    let suffix = metric_type.suffix();
+    for (labels, item) in items {
+        if let Some(value) = value(item) {
+            out.push_str(&format!("{name}{suffix}{labels} {value}\n"));
+        }
+    }
+}
+
+/// Render the health, OCP extended health, firmware and namespace
+/// utilization of every controller in `inventory` as OpenMetrics text.
+pub fn render(inventory: &Inventory) -> String {
+    let controllers = inventory
+        .controllers
+        .iter()
+        .filter_map(|c| Some((Labels::controller(c.controller.as_ref()?), c)))
+        .collect::<Vec<_>>();
+    let slots = controllers
+        .iter()
+        .flat_map(|(labels, c)| {
+            c.firmware.iter().flat_map(|f| &f.slots).map(|slot| {
+                let version = slot.version.as_deref().unwrap_or_default();
+                let labels =
+                    labels.with("slot", slot.slot).with("version", version);
+                (labels, slot)
+            })
+        })
+        .collect::<Vec<_>>();
+    let namespaces = controllers
+        .iter()
+        .flat_map(|(labels, c)| {
+            c.namespaces.iter().map(|ns| (labels.with("nsid", ns.nsid), ns))
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    for metric in INVENTORY_METRICS {
+        family(&mut out, metric, &[(Labels::default(), inventory)]);
+    }
+    for metric in CONTROLLER_METRICS {
+        family(&mut out, metric, &controllers);
+    }
+    family(&mut out, &FIRMWARE_SLOT_METRIC, &slots);
+    for metric in NAMESPACE_METRICS {
+        family(&mut out, metric, &namespaces);
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        inventory::{InventoryError, InventoryItem},
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    fn inventory() -> Inventory {
+        let sim = Sim::new()
+            .with_controller(
+                SimController::new(0)
+                    .with_model("SIM \"Quoted\" NVMe")
+                    .with_firmware_slots(vec![Some("SIM1.0"), None], 1, false),
+            )
+            .with_controller(SimController::new(1));
+        sim.controller(1, |c| c.set_dead(true));
+        Nvme::with_backend(sim).inventory().unwrap()
+    }
+
+    #[test]
+    fn render_inventory() {
+        let text = render(&inventory());
+        let labels =
+            r#"serial="SIM-0",model="SIM \"Quoted\" NVMe",fwrev="SIM1.0""#;
+        for line in [
+            "nvme_controllers 2".to_string(),
+            "nvme_inventory_errors 1".to_string(),
+            format!("nvme_controller_info{{{labels}}} 1"),
+            format!("nvme_temperature_celsius{{{labels}}} 27"),
+            format!("nvme_temperature_warning_celsius{{{labels}}} 70"),
+            format!("nvme_available_spare_ratio{{{labels}}} 1.00"),
+            format!("nvme_available_spare_threshold_ratio{{{labels}}} 0.10"),
+            format!("nvme_power_on_seconds_total{{{labels}}} 0"),
+            format!("nvme_firmware_active_slot{{{labels}}} 1"),
+            format!(
+                "nvme_firmware_slot_info{{{labels},slot=\"1\",\
+                 version=\"SIM1.0\"}} 1"
+            ),
+            format!(
+                "nvme_namespace_size_bytes{{{labels},nsid=\"1\"}} 1073741824"
+            ),
+            format!(
+                "nvme_namespace_used_bytes{{{labels},nsid=\"1\"}} 268435456"
+            ),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line}");
+        }
+        assert!(text.contains("# TYPE nvme_data_read_bytes counter\n"));
+        assert!(text.contains("# UNIT nvme_data_read_bytes bytes\n"));
+        assert!(text.ends_with("\n# EOF\n"));
+
+        // Only the identified controller has samples, the empty slot has
+        // none and there is no OCP log.
+        assert!(!text.contains("SIM-1"));
+        assert!(!text.contains(r#"slot="2""#));
+        assert!(!text.contains("nvme_ocp_media_written_bytes_total{"));
+    }
+
+    #[test]
+    fn families_are_contiguous() {
+        let mut inventory = inventory();
+        inventory.controllers.push(inventory.controllers[0].clone());
+        inventory.controllers[2].controller.as_mut().unwrap().serial =
+            "SIM-2".to_string();
+        inventory.errors.push(InventoryError {
+            item: InventoryItem::Controller,
+            code: None,
+            message: "gone".to_string(),
+        });
+
+        let text = render(&inventory);
+        let mut families = Vec::new();
+        for line in text.lines() {
+            match line.strip_prefix("# TYPE ") {
+                Some(family) => {
+                    let name = family.split(' ').next().unwrap();
+                    assert!(!families.contains(&name), "{name} repeated");
+                    families.push(name);
+                }
+                None if line.starts_with('#') => {}
+                None => {
+                    let family = families.last().unwrap();
+                    assert!(line.starts_with(family), "{line} outside family");
+                }
+            }
+        }
+        assert!(text.lines().any(|l| l == "nvme_controllers 3"));
+        assert!(text.lines().any(|l| l == "nvme_inventory_errors 2"));
+    }
+}
@@ -0,0 +1,418 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Periodic health monitoring of NVMe controllers.
+//!
+//! A [`HealthMonitor`] reads the SMART / Health Information log of every
+//! discovered controller and compares it with the previous reading, emitting
+//! a [`HealthEvent`] for each change worth reporting. The comparison itself is
+//! performed by [`evaluate`], which has no dependency on a live device.
+
+use std::{
+    collections::HashMap,
+    sync::mpsc::Sender,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    controller_info::ControllerInfo,
+    logpage::LogPageError,
+    nvmespec::smart::SmartLog,
+    Nvme, NvmeError, NvmeInitError,
+};
+
+#[derive(Debug, Error)]
+pub enum MonitorError {
+    #[error(transparent)]
+    Init(#[from] NvmeInitError),
+    #[error(transparent)]
+    Nvme(#[from] NvmeError),
+    #[error("libnvme error: {0}")]
+    ControllerError(#[from] NvmeControllerError),
+    #[error("failed to read health of controller {serial}: {error}")]
+    Smart { serial: String, error: LogPageError },
+}
+
+/// A single reading of a controller's health.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthSnapshot {
+    pub smart: SmartLog,
+    /// Warning Composite Temperature Threshold (WCTEMP) in Kelvin, or zero
+    /// if the controller does not report one.
+    pub warning_temperature: u16,
+    /// Critical Composite Temperature Threshold (CCTEMP) in Kelvin, or zero
+    /// if the controller does not report one.
+    pub critical_temperature: u16,
+}
+
+impl HealthSnapshot {
+    /// Read the health of `controller`, whose information is `info`.
+    pub(crate) fn read(
+        controller: &Controller<'_>,
+        info: &ControllerInfo,
+    ) -> Result<Self, LogPageError> {
+        let id = info.get_controller_info_identify().data();
+        Ok(Self {
+            smart: controller.smart_log()?,
+            warning_temperature: id.ap_wctemp,
+            critical_temperature: id.ap_cctemp,
+        })
+    }
+
+    pub fn temperature_state(&self) -> TemperatureState {
+        let temp = self.smart.composite_temperature;
+        if self.critical_temperature != 0 && temp >= self.critical_temperature {
+            TemperatureState::Critical
+        } else if self.warning_temperature != 0
+            && temp >= self.warning_temperature
+        {
+            TemperatureState::Warning
+        } else {
+            TemperatureState::Normal
+        }
+    }
+}
+
+/// The composite temperature relative to the controller's thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TemperatureState {
+    Normal,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthEvent {
+    /// Critical warning bits that were not set in the previous reading.
+    CriticalWarning {
+        raised: u8,
+        current: u8,
+    },
+    MediaErrors {
+        previous: u128,
+        current: u128,
+    },
+    UnsafeShutdowns {
+        previous: u128,
+        current: u128,
+    },
+    PercentageUsed {
+        previous: u8,
+        current: u8,
+    },
+    /// The composite temperature crossed a threshold. `kelvin` is the
+    /// temperature of the current reading.
+    Temperature {
+        previous: TemperatureState,
+        current: TemperatureState,
+        kelvin: u16,
+    },
+}
+
+/// Compare two successive health readings of the same controller.
+///
+/// When there is no previous reading, only conditions that warrant attention
+/// on their own are reported: critical warnings and an elevated temperature.
+pub fn evaluate(
+    previous: Option<&HealthSnapshot>,
+    current: &HealthSnapshot,
+) -> Vec<HealthEvent> {
+    let mut events = Vec::new();
+    let cur = &current.smart;
+
+    let prev_warning = previous.map_or(0, |p| p.smart.critical_warning);
+    let raised = cur.critical_warning & !prev_warning;
+    if raised != 0 {
+        events.push(HealthEvent::CriticalWarning {
+            raised,
+            current: cur.critical_warning,
+        });
+    }
+
+    if let Some(prev) = previous.map(|p| &p.smart) {
+        if cur.media_errors > prev.media_errors {
+            events.push(HealthEvent::MediaErrors {
+                previous: prev.media_errors,
+                current: cur.media_errors,
+            });
+        }
+        if cur.unsafe_shutdowns > prev.unsafe_shutdowns {
+            events.push(HealthEvent::UnsafeShutdowns {
+                previous: prev.unsafe_shutdowns,
+                current: cur.unsafe_shutdowns,
+            });
+        }
+        if cur.percentage_used > prev.percentage_used {
+            events.push(HealthEvent::PercentageUsed {
+                previous: prev.percentage_used,
+                current: cur.percentage_used,
+            });
+        }
+    }
+
+    let prev_temp =
+        previous.map_or(TemperatureState::Normal, |p| p.temperature_state());
+    let cur_temp = current.temperature_state();
+    if cur_temp != prev_temp {
+        events.push(HealthEvent::Temperature {
+            previous: prev_temp,
+            current: cur_temp,
+            kelvin: cur.composite_temperature,
+        });
+    }
+
+    events
+}
+
+/// Tracks the health of every controller on the system across successive
+/// calls to [`HealthMonitor::tick`].
+///
+/// Controllers are identified by serial number, so a controller that
+/// disappears and comes back is compared against its last known reading.
+pub struct HealthMonitor<'a> {
+    nvme: &'a Nvme,
+    last: HashMap<String, HealthSnapshot>,
+}
+
+impl<'a> HealthMonitor<'a> {
+    pub fn new(nvme: &'a Nvme) -> Self {
+        Self { nvme, last: HashMap::new() }
+    }
+
+    /// The most recent reading of the controller with the given serial
+    /// number.
+    pub fn last_snapshot(&self, serial: &str) -> Option<&HealthSnapshot> {
+        self.last.get(serial)
+    }
+
+    /// Read the health of every controller and pass each resulting event to
+    /// `emit` along with the serial number of the controller it concerns.
+    ///
+    /// A controller that cannot be opened or read is passed to `emit` as an
+    /// error and the remaining controllers are still read. Only a failure to
+    /// start controller discovery fails the whole call.
+    pub fn tick<F>(&mut self, mut emit: F) -> Result<(), MonitorError>
+    where
+        F: FnMut(Result<(&str, HealthEvent), MonitorError>),
+    {
+        for controller in self.nvme.controller_discovery()? {
+            let reading =
+                controller.map_err(MonitorError::from).and_then(|c| {
+                    let info = c.get_info()?;
+                    let serial = info.serial().into_owned();
+                    match HealthSnapshot::read(&c, &info) {
+                        Ok(snapshot) => Ok((serial, snapshot)),
+                        Err(error) => {
+                            Err(MonitorError::Smart { serial, error })
+                        }
+                    }
+                });
+            let (serial, snapshot) = match reading {
+                Ok(reading) => reading,
+                Err(e) => {
+                    emit(Err(e));
+                    continue;
+                }
+            };
+            for event in evaluate(self.last.get(&serial), &snapshot) {
+                emit(Ok((&serial, event)));
+            }
+            self.last.insert(serial, snapshot);
+        }
+        Ok(())
+    }
+
+    /// Monitor all controllers from a background thread, reading them every
+    /// `interval` and sending events, and the errors of controllers that
+    /// could not be read, to `tx`.
+    ///
+    /// The thread opens its own libnvme handle, as an [`Nvme`] cannot be
+    /// shared between threads. It exits once the receiving side of `tx` has
+    /// been dropped, or if that handle cannot be opened.
+    pub fn spawn(
+        interval: Duration,
+        tx: Sender<Result<(String, HealthEvent), MonitorError>>,
+    ) -> JoinHandle<Result<(), MonitorError>> {
+        thread::spawn(move || {
+            let nvme = Nvme::new()?;
+            let mut monitor = HealthMonitor::new(&nvme);
+            loop {
+                let mut disconnected = false;
+                let mut send = |message| {
+                    disconnected |= tx.send(message).is_err();
+                };
+                let result = monitor.tick(|reading| {
+                    send(reading.map(|(serial, e)| (serial.to_string(), e)))
+                });
+                // Discovery may work again by the next interval.
+                if let Err(e) = result {
+                    send(Err(e));
+                }
+                if disconnected {
+                    return Ok(());
+                }
+                thread::sleep(interval);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(f: impl FnOnce(&mut SmartLog)) -> HealthSnapshot {
+        let mut smart = SmartLog {
+            composite_temperature: 310,
+            available_spare: 100,
+            available_spare_threshold: 10,
+            ..Default::default()
+        };
+        f(&mut smart);
+        HealthSnapshot {
+            smart,
+            warning_temperature: 343,
+            critical_temperature: 358,
+        }
+    }
+
+    #[test]
+    fn healthy_first_reading() {
+        assert!(evaluate(None, &snapshot(|_| ())).is_empty());
+    }
+
+    #[test]
+    fn unchanged_reading() {
+        let prev = snapshot(|s| s.media_errors = 4);
+        let cur = snapshot(|s| s.media_errors = 4);
+        assert!(evaluate(Some(&prev), &cur).is_empty());
+    }
+
+    #[test]
+    fn counter_deltas() {
+        let prev = snapshot(|s| {
+            s.media_errors = 1;
+            s.unsafe_shutdowns = 3;
+            s.percentage_used = 10;
+        });
+        let cur = snapshot(|s| {
+            s.media_errors = 5;
+            s.unsafe_shutdowns = 4;
+            s.percentage_used = 11;
+        });
+        assert_eq!(
+            evaluate(Some(&prev), &cur),
+            vec![
+                HealthEvent::MediaErrors { previous: 1, current: 5 },
+                HealthEvent::UnsafeShutdowns { previous: 3, current: 4 },
+                HealthEvent::PercentageUsed { previous: 10, current: 11 },
+            ]
+        );
+    }
+
+    #[test]
+    fn critical_warning_only_reports_new_bits() {
+        let prev = snapshot(|s| s.critical_warning = 0x1);
+        let cur = snapshot(|s| s.critical_warning = 0x5);
+        assert_eq!(
+            evaluate(Some(&prev), &cur),
+            vec![HealthEvent::CriticalWarning { raised: 0x4, current: 0x5 }]
+        );
+        assert!(evaluate(Some(&cur), &prev).is_empty());
+        assert_eq!(
+            evaluate(None, &prev),
+            vec![HealthEvent::CriticalWarning { raised: 0x1, current: 0x1 }]
+        );
+    }
+
+    #[test]
+    fn temperature_thresholds() {
+        let normal = snapshot(|_| ());
+        let warm = snapshot(|s| s.composite_temperature = 343);
+        let hot = snapshot(|s| s.composite_temperature = 360);
+
+        assert_eq!(
+            evaluate(Some(&normal), &warm),
+            vec![HealthEvent::Temperature {
+                previous: TemperatureState::Normal,
+                current: TemperatureState::Warning,
+                kelvin: 343,
+            }]
+        );
+        assert_eq!(
+            evaluate(Some(&warm), &hot),
+            vec![HealthEvent::Temperature {
+                previous: TemperatureState::Warning,
+                current: TemperatureState::Critical,
+                kelvin: 360,
+            }]
+        );
+        assert_eq!(
+            evaluate(Some(&hot), &normal),
+            vec![HealthEvent::Temperature {
+                previous: TemperatureState::Critical,
+                current: TemperatureState::Normal,
+                kelvin: 310,
+            }]
+        );
+        assert!(evaluate(Some(&hot), &hot).is_empty());
+    }
+
+    #[test]
+    fn unreported_thresholds_are_ignored() {
+        let mut hot = snapshot(|s| s.composite_temperature = 400);
+        hot.warning_temperature = 0;
+        hot.critical_temperature = 0;
+        assert_eq!(hot.temperature_state(), TemperatureState::Normal);
+        assert!(evaluate(None, &hot).is_empty());
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn tick_continues_past_failed_controllers() {
+        use crate::{
+            sim::{Sim, SimController, SimError, SimOp},
+            NvmeErrorCode,
+        };
+
+        let mut smart = vec![0u8; 512];
+        smart[0] = 0x1;
+        smart[1..3].copy_from_slice(&300u16.to_le_bytes());
+        let sim = Sim::new()
+            .with_controller(SimController::new(0).with_error(
+                SimOp::Open,
+                SimError::Library(NvmeErrorCode::CtrlGone),
+            ))
+            .with_controller(SimController::new(1))
+            .with_controller(SimController::new(2).with_log_page(0x2, smart));
+        sim.controller(1, |c| c.set_dead(true));
+        let nvme = Nvme::with_backend(sim);
+        let mut monitor = HealthMonitor::new(&nvme);
+
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        monitor
+            .tick(|reading| match reading {
+                Ok((serial, event)) => events.push((serial.to_string(), event)),
+                Err(e) => errors.push(e),
+            })
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![(
+                "SIM-2".to_string(),
+                HealthEvent::CriticalWarning { raised: 0x1, current: 0x1 }
+            )]
+        );
+        assert!(matches!(
+            errors[..],
+            [MonitorError::Nvme(_), MonitorError::ControllerError(_)]
+        ));
+        assert!(monitor.last_snapshot("SIM-2").is_some());
+    }
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    feature::FeatureSelect,
+    logpage::LogPageError,
+    nvmespec::ocp::{
+        LatencyMonitorConfig, OcpDeviceCapabilitiesLog, OcpErrorRecoveryLog,
+        OcpLatencyMonitorLog, OcpSmartLog, OcpUnsupportedRequirementsLog,
+        OCP_DEVICE_CAPABILITIES_LOG_LEN, OCP_DEVICE_CAPABILITIES_LOG_LID,
+        OCP_ERROR_RECOVERY_LOG_LEN, OCP_ERROR_RECOVERY_LOG_LID,
+        OCP_LATENCY_MONITOR_CONFIG_LEN, OCP_LATENCY_MONITOR_FID,
+        OCP_LATENCY_MONITOR_LOG_LEN, OCP_LATENCY_MONITOR_LOG_LID,
+        OCP_SMART_LOG_LEN, OCP_SMART_LOG_LID,
+        OCP_UNSUPPORTED_REQUIREMENTS_LOG_LEN,
+        OCP_UNSUPPORTED_REQUIREMENTS_LOG_LID,
+    },
+};
+
+impl<'a> Controller<'a> {
+    fn read_ocp_log(
+        &self,
+        lid: u32,
+        len: usize,
+    ) -> Result<Vec<u8>, NvmeControllerError> {
+        let mut buf = vec![0u8; len];
+        self.log_request()?.set_lid(lid)?.execute(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Get the OCP SMART / Health Information Extended log (C0h).
+    pub fn get_ocp_smart_log(&self) -> Result<OcpSmartLog, LogPageError> {
+        let buf = self.read_ocp_log(OCP_SMART_LOG_LID, OCP_SMART_LOG_LEN)?;
+        Ok(OcpSmartLog::decode(&buf)?)
+    }
+
+    /// Get the OCP Error Recovery log (C1h).
+    pub fn get_ocp_error_recovery_log(
+        &self,
+    ) -> Result<OcpErrorRecoveryLog, LogPageError> {
+        let buf = self.read_ocp_log(
+            OCP_ERROR_RECOVERY_LOG_LID,
+            OCP_ERROR_RECOVERY_LOG_LEN,
+        )?;
+        Ok(OcpErrorRecoveryLog::decode(&buf)?)
+    }
+
+    /// Get the OCP Latency Monitor log (C3h).
+    pub fn get_ocp_latency_monitor_log(
+        &self,
+    ) -> Result<OcpLatencyMonitorLog, LogPageError> {
+        let buf = self.read_ocp_log(
+            OCP_LATENCY_MONITOR_LOG_LID,
+            OCP_LATENCY_MONITOR_LOG_LEN,
+        )?;
+        Ok(OcpLatencyMonitorLog::decode(&buf)?)
+    }
+
+    /// Get the OCP Device Capabilities log (C4h).
+    pub fn get_ocp_device_capabilities_log(
+        &self,
+    ) -> Result<OcpDeviceCapabilitiesLog, LogPageError> {
+        let buf = self.read_ocp_log(
+            OCP_DEVICE_CAPABILITIES_LOG_LID,
+            OCP_DEVICE_CAPABILITIES_LOG_LEN,
+        )?;
+        Ok(OcpDeviceCapabilitiesLog::decode(&buf)?)
+    }
+
+    /// Get the OCP Unsupported Requirements log (C5h).
+    pub fn get_ocp_unsupported_requirements_log(
+        &self,
+    ) -> Result<OcpUnsupportedRequirementsLog, LogPageError> {
+        let buf = self.read_ocp_log(
+            OCP_UNSUPPORTED_REQUIREMENTS_LOG_LID,
+            OCP_UNSUPPORTED_REQUIREMENTS_LOG_LEN,
+        )?;
+        Ok(OcpUnsupportedRequirementsLog::decode(&buf)?)
+    }
+
+    /// Get the configuration of the OCP Latency Monitor feature (C5h).
+    ///
+    /// Note: libnvme does not support Set Features, so a new configuration
+    /// must be applied with `LatencyMonitorConfig::encode` and a tool that
+    /// can issue Set Features directly.
+    pub fn get_ocp_latency_monitor_config(
+        &self,
+        sel: FeatureSelect,
+    ) -> Result<LatencyMonitorConfig, LogPageError> {
+        let mut buf = [0u8; OCP_LATENCY_MONITOR_CONFIG_LEN];
+        self.get_feature_request()?
+            .set_fid(OCP_LATENCY_MONITOR_FID)?
+            .set_select(sel)?
+            .execute_with_output(&mut buf)?;
+        Ok(LatencyMonitorConfig::decode(&buf)?)
+    }
+}
@@ -0,0 +1,333 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Controller and namespace handles without lifetimes.
+//!
+//! An [`OwnedController`] keeps the underlying library handle alive by
+//! reference count rather than by borrowing an [`Nvme`], so it can be stored
+//! for as long as a long-running service needs it. It dereferences to a
+//! [`Controller`], and its lock guards dereference to the corresponding
+//! [`ReadLockedController`] and [`WriteLockedController`], so the methods
+//! available on each are the same as for the borrowed types.
+//!
+//! Clones of an `OwnedController` share a single controller handle, including
+//! any lock held on it: while one clone is write locked, operations made
+//! through the others, such as detaching blkdev from an [`OwnedNamespace`],
+//! are made under that lock.
+//!
+//! Unlike a [`Controller`], the owned handles are `Send`, though not `Sync`.
+//! An `OwnedController` is always opened through a library handle of its own,
+//! which nothing outside it and its clones can reach, so the library handle
+//! and the controller move between threads together. Calls on the controller
+//! are serialized, so clones on different threads never use it at once.
+
+use std::ops::Deref;
+
+use crate::{
+    controller::{
+        Controller, ControllerLocator, NvmeControllerError,
+        ReadLockedController, TryLockResult, WriteLockedController,
+    },
+    namespace::{Namespace, NamespaceDiscoveryLevel, NamespaceInfo},
+    pool::{NvmePool, PoolError},
+    Nvme, NvmeError,
+};
+
+pub struct OwnedController {
+    controller: Controller<'static>,
+}
+
+// SAFETY: libnvme lets a handle, with the objects derived from it, be used
+// from any thread so long as only one thread uses it at a time. Every
+// `OwnedController` is made by `open`, from an `Nvme` that is dropped once
+// the controller has been opened, so the library handle is only reachable
+// through this controller's backend and its clones (see `Controller::share`).
+// Each backend call takes the controller's mutex, and no library object
+// outlives a call, so the handle family is never used by two threads at once.
+unsafe impl Send for OwnedController {}
+
+impl Clone for OwnedController {
+    fn clone(&self) -> Self {
+        Self { controller: self.controller.share() }
+    }
+}
+
+impl Deref for OwnedController {
+    type Target = Controller<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.controller
+    }
+}
+
+impl OwnedController {
+    /// Open the controller identified by `locator` through `nvme`, which the
+    /// controller takes over as its own library handle.
+    pub fn open(
+        nvme: Nvme,
+        locator: &ControllerLocator,
+    ) -> Result<Self, NvmeError> {
+        // The backend keeps the library handle alive after `nvme` is dropped.
+        let controller = locator.open(&nvme)?.share();
+        Ok(Self { controller })
+    }
+
+    /// Initialize an `OwnedController` from an instance, as [`Self::open`].
+    pub fn init_by_instance(
+        nvme: Nvme,
+        instance: i32,
+    ) -> Result<Self, NvmeError> {
+        Self::open(nvme, &ControllerLocator::Instance(instance))
+    }
+
+    /// Every controller on the system, each opened with a library handle of
+    /// its own from `pool`.
+    pub fn discover(pool: &NvmePool) -> Result<Vec<Self>, PoolError> {
+        let locators = pool
+            .get()?
+            .controller_discovery()?
+            .map(|controller| Ok(controller?.locator()?))
+            .collect::<Result<Vec<_>, PoolError>>()?;
+        locators.iter().map(|locator| pool.controller(locator)).collect()
+    }
+
+    pub fn read_lock(
+        self,
+    ) -> Result<OwnedReadLockedController, (Self, NvmeControllerError)> {
+        self.controller
+            .read_lock()
+            .map(|guard| OwnedReadLockedController { guard })
+            .map_err(|(controller, e)| (Self { controller }, e))
+    }
+
+    pub fn write_lock(
+        self,
+    ) -> Result<OwnedWriteLockedController, (Self, NvmeControllerError)> {
+        self.controller
+            .write_lock()
+            .map(|guard| OwnedWriteLockedController { guard })
+            .map_err(|(controller, e)| (Self { controller }, e))
+    }
+
+    pub fn try_read_lock(
+        self,
+    ) -> TryLockResult<OwnedReadLockedController, Self, NvmeControllerError>
+    {
+        match self.controller.try_read_lock() {
+            TryLockResult::Ok(guard) => {
+                TryLockResult::Ok(OwnedReadLockedController { guard })
+            }
+            TryLockResult::Locked(controller) => {
+                TryLockResult::Locked(Self { controller })
+            }
+            TryLockResult::Err(e) => TryLockResult::Err(e),
+        }
+    }
+
+    pub fn try_write_lock(
+        self,
+    ) -> TryLockResult<OwnedWriteLockedController, Self, NvmeControllerError>
+    {
+        match self.controller.try_write_lock() {
+            TryLockResult::Ok(guard) => {
+                TryLockResult::Ok(OwnedWriteLockedController { guard })
+            }
+            TryLockResult::Locked(controller) => {
+                TryLockResult::Locked(Self { controller })
+            }
+            TryLockResult::Err(e) => TryLockResult::Err(e),
+        }
+    }
+
+    /// As [`Controller::namespace_discovery`], but yielding namespaces that
+    /// hold a reference to the controller rather than borrowing it.
+    pub fn namespace_discovery(
+        &self,
+        level: NamespaceDiscoveryLevel,
+    ) -> Result<OwnedNamespaceDiscovery, NvmeControllerError> {
+        let nsids = self
+            .controller
+            .namespace_discovery(level)?
+            .map(|ns| ns.map(|ns| ns.nsid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(OwnedNamespaceDiscovery {
+            controller: self.clone(),
+            nsids: nsids.into_iter(),
+        })
+    }
+
+    /// The namespace with the given NSID, which is not checked to exist.
+    pub fn namespace(&self, nsid: u32) -> OwnedNamespace {
+        OwnedNamespace { nsid, controller: self.clone() }
+    }
+}
+
+pub struct OwnedReadLockedController {
+    guard: ReadLockedController<'static>,
+}
+
+// SAFETY: as for `OwnedController`, which this holds the controller of.
+unsafe impl Send for OwnedReadLockedController {}
+
+impl OwnedReadLockedController {
+    pub fn unlock(self) -> OwnedController {
+        OwnedController { controller: self.guard.unlock() }
+    }
+}
+
+impl Deref for OwnedReadLockedController {
+    type Target = ReadLockedController<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+pub struct OwnedWriteLockedController {
+    guard: WriteLockedController<'static>,
+}
+
+// SAFETY: as for `OwnedController`, which this holds the controller of.
+unsafe impl Send for OwnedWriteLockedController {}
+
+impl OwnedWriteLockedController {
+    pub fn unlock(self) -> OwnedController {
+        OwnedController { controller: self.guard.unlock() }
+    }
+
+    /// Another handle onto the locked controller, for example to hand to an
+    /// [`OwnedNamespace`].
+    pub fn controller(&self) -> OwnedController {
+        OwnedController { controller: self.guard.share() }
+    }
+}
+
+impl Deref for OwnedWriteLockedController {
+    type Target = WriteLockedController<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+pub struct OwnedNamespaceDiscovery {
+    controller: OwnedController,
+    nsids: std::vec::IntoIter<u32>,
+}
+
+impl Iterator for OwnedNamespaceDiscovery {
+    type Item = Result<OwnedNamespace, NvmeControllerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nsids.next().map(|nsid| Ok(self.controller.namespace(nsid)))
+    }
+}
+
+#[derive(Clone)]
+pub struct OwnedNamespace {
+    nsid: u32,
+    controller: OwnedController,
+}
+
+impl OwnedNamespace {
+    /// The namespace identifier.
+    pub fn nsid(&self) -> u32 {
+        self.nsid
+    }
+
+    pub fn controller(&self) -> &OwnedController {
+        &self.controller
+    }
+
+    /// Borrow this as a [`Namespace`].
+    pub fn namespace(&self) -> Namespace<'_> {
+        Namespace::new(self.nsid, &self.controller)
+    }
+
+    pub fn get_info(&self) -> Result<NamespaceInfo, NvmeControllerError> {
+        self.namespace().get_info()
+    }
+
+    pub fn blkdev_attach(&self) -> Result<(), NvmeControllerError> {
+        self.namespace().blkdev_attach()
+    }
+
+    pub fn blkdev_detach(&self) -> Result<(), NvmeControllerError> {
+        self.namespace().blkdev_detach()
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        sim::{Sim, SimController},
+        NvmeErrorCode,
+    };
+
+    /// A service that holds on to its controllers for its whole lifetime.
+    struct Registry {
+        controllers: Vec<OwnedController>,
+    }
+
+    fn registry(sim: &Sim) -> Registry {
+        let sim = sim.clone();
+        let pool =
+            NvmePool::with_opener(move || Ok(Nvme::with_backend(sim.clone())));
+        Registry { controllers: OwnedController::discover(&pool).unwrap() }
+    }
+
+    #[test]
+    fn controllers_outlive_nvme() {
+        let sim = Sim::new()
+            .with_controller(SimController::new(0))
+            .with_controller(SimController::new(1));
+        let registry = registry(&sim);
+
+        let serials = registry
+            .controllers
+            .iter()
+            .map(|c| c.get_info().unwrap().serial().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(serials, ["SIM-0", "SIM-1"]);
+
+        let worker = registry.controllers[1].clone();
+        drop(registry);
+        assert_eq!(worker.get_info().unwrap().serial(), "SIM-1");
+    }
+
+    #[test]
+    fn format_under_owned_lock() {
+        let sim = Sim::new().with_controller(SimController::new(0));
+        let controller = registry(&sim).controllers.remove(0);
+        let namespaces = controller
+            .namespace_discovery(NamespaceDiscoveryLevel::Active)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // Without the lock the namespaces cannot be changed.
+        let err = namespaces[0].blkdev_detach().unwrap_err();
+        assert_eq!(err.code(), NvmeErrorCode::NeedCtrlWrlock);
+
+        let locked =
+            controller.clone().write_lock().map_err(|(_, e)| e).unwrap();
+        namespaces.iter().for_each(|ns| ns.blkdev_detach().unwrap());
+        locked
+            .format_request()
+            .unwrap()
+            .set_lbaf(1)
+            .unwrap()
+            .set_nsid(1)
+            .unwrap()
+            .execute()
+            .unwrap();
+        namespaces.iter().for_each(|ns| ns.blkdev_attach().unwrap());
+
+        let controller = locked.unlock();
+        let lbaf = namespaces[0].get_info().unwrap().current_format().unwrap();
+        assert_eq!(lbaf.data_size(), 4096);
+        assert!(matches!(controller.try_write_lock(), TryLockResult::Ok(_)));
+    }
+}
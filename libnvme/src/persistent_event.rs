@@ -0,0 +1,188 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use thiserror::Error;
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    nvmespec::{
+        persistent_event::{
+            PersistentEventHeader, PersistentEventLog,
+            PERSISTENT_EVENT_HDR_LEN, PERSISTENT_EVENT_LID,
+            PERSISTENT_EVENT_LSP_ESTABLISH, PERSISTENT_EVENT_LSP_READ,
+            PERSISTENT_EVENT_LSP_RELEASE, PERSISTENT_EVENT_SIZE_UNIT,
+        },
+        DecodeError,
+    },
+};
+
+/// The log is read in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum PersistentEventError {
+    #[error("libnvme error: {0}")]
+    ControllerError(#[from] NvmeControllerError),
+    #[error("failed to decode persistent event log: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("NVMe device does not support the persistent event log")]
+    Unsupported,
+    #[error(
+        "persistent event log reports {length} bytes but the device \
+        supports at most {max} bytes"
+    )]
+    InvalidLength { length: u64, max: u64 },
+    #[error(
+        "reading the {length} byte persistent event log needs a log page \
+         offset, but the controller does not support log page offsets"
+    )]
+    OffsetUnsupported { length: u64 },
+}
+
+impl<'a> Controller<'a> {
+    fn persistent_event_request(
+        &self,
+        lsp: u32,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.log_request()?
+            .set_lid(PERSISTENT_EVENT_LID)?
+            .set_lsp(lsp)?
+            .read_at(offset, buf)
+    }
+
+    /// Read the entire Persistent Event log.
+    ///
+    /// A new reporting context is established so that the log is a
+    /// consistent snapshot, and it is released once the log has been read.
+    pub fn persistent_event_log(
+        &self,
+    ) -> Result<PersistentEventLog, PersistentEventError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        let (lpa, pels) = (id.id_lpa, id.ap_pels);
+        if lpa.lp_persist() == 0 || pels == 0 {
+            return Err(PersistentEventError::Unsupported);
+        }
+        let max = u64::from(pels) * PERSISTENT_EVENT_SIZE_UNIT;
+        let extsup = lpa.lp_extsup() != 0;
+
+        let mut hdr = vec![0u8; PERSISTENT_EVENT_HDR_LEN];
+        self.persistent_event_request(
+            PERSISTENT_EVENT_LSP_ESTABLISH,
+            0,
+            &mut hdr,
+        )?;
+
+        let result = PersistentEventHeader::decode(&hdr)
+            .map_err(PersistentEventError::from)
+            .and_then(|header| {
+                self.read_persistent_events(header, hdr, max, extsup)
+            });
+
+        // Always release the reporting context, but report the original
+        // error in preference to one from the release.
+        let mut release = [0u8; PERSISTENT_EVENT_HDR_LEN];
+        let released = self.persistent_event_request(
+            PERSISTENT_EVENT_LSP_RELEASE,
+            0,
+            &mut release,
+        );
+        let log = result?;
+        released?;
+        Ok(log)
+    }
+
+    fn read_persistent_events(
+        &self,
+        header: PersistentEventHeader,
+        mut buf: Vec<u8>,
+        max: u64,
+        extsup: bool,
+    ) -> Result<PersistentEventLog, PersistentEventError> {
+        let length = header.total_length;
+        if length > max || length < PERSISTENT_EVENT_HDR_LEN as u64 {
+            return Err(PersistentEventError::InvalidLength { length, max });
+        }
+        // The events follow the header, so reading them needs an offset.
+        if length > PERSISTENT_EVENT_HDR_LEN as u64 && !extsup {
+            return Err(PersistentEventError::OffsetUnsupported { length });
+        }
+        let length =
+            usize::try_from(length).expect("32-bit systems unsupported");
+
+        buf.resize(length, 0);
+        let mut offset = PERSISTENT_EVENT_HDR_LEN;
+        for chunk in buf[PERSISTENT_EVENT_HDR_LEN..].chunks_mut(CHUNK_SIZE) {
+            self.persistent_event_request(
+                PERSISTENT_EVENT_LSP_READ,
+                offset as u64,
+                chunk,
+            )?;
+            offset += chunk.len();
+        }
+
+        Ok(PersistentEventLog::new(buf)?)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use libnvme_sys::identify::IdLpa;
+
+    use super::*;
+    use crate::{
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    const EXTENDED: u8 = 1 << 2;
+    const PERSISTENT_EVENT: u8 = 1 << 4;
+
+    /// A log with no events, padded to `length` bytes.
+    fn with_log(lpa: u8, length: usize) -> Nvme {
+        let mut log = vec![0u8; length];
+        log[8..16].copy_from_slice(&(length as u64).to_le_bytes());
+        log[PERSISTENT_EVENT_HDR_LEN..].fill(0xa5);
+        Nvme::with_backend(
+            Sim::new().with_controller(
+                SimController::new(0)
+                    .with_identify(|id| {
+                        id.id_lpa = IdLpa::from_bits(lpa);
+                        id.ap_pels = 1;
+                    })
+                    .with_log_page(PERSISTENT_EVENT_LID, log),
+            ),
+        )
+    }
+
+    #[test]
+    fn read_with_offsets() {
+        let nvme = with_log(PERSISTENT_EVENT | EXTENDED, 1024);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let log = controller.persistent_event_log().unwrap();
+        assert_eq!(log.header().total_length, 1024);
+        assert_eq!(log.as_bytes().len(), 1024);
+        assert!(log.as_bytes()[PERSISTENT_EVENT_HDR_LEN..]
+            .iter()
+            .all(|&b| b == 0xa5));
+    }
+
+    #[test]
+    fn offsets_need_extended_log_pages() {
+        // A log that is only a header needs no offset.
+        let nvme = with_log(PERSISTENT_EVENT, PERSISTENT_EVENT_HDR_LEN);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        controller.persistent_event_log().unwrap();
+
+        let nvme = with_log(PERSISTENT_EVENT, 1024);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let err = controller.persistent_event_log().unwrap_err();
+        assert!(matches!(
+            err,
+            PersistentEventError::OffsetUnsupported { length: 1024 }
+        ));
+    }
+}
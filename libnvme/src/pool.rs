@@ -0,0 +1,336 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Using libnvme from more than one thread.
+//!
+//! libnvme does not synchronize access to its handles: an `nvme_t`, and the
+//! controllers opened from it, may only be used by one thread at a time.
+//! [`Nvme`] shares its library handle with every controller opened through
+//! it, so neither it nor a [`Controller`](crate::controller::Controller) is
+//! `Send` or `Sync`.
+//!
+//! Threads that need to discover or open controllers should each open their
+//! own `Nvme`, which an [`NvmePool`] does on demand, and refer to controllers
+//! across threads with a [`ControllerLocator`]. A controller that has to move
+//! between threads is opened as an [`OwnedController`], which
+//! [`NvmePool::controller`] gives a library handle of its own so that the two
+//! move together; owned handles are `Send` but not `Sync`. Data that does not
+//! refer to a handle, such as `ControllerInfo`, `NamespaceInfo` and the errors
+//! of this crate, is both `Send` and `Sync`.
+//!
+//! Controller locks are held by the controller, not by the thread or handle,
+//! so a lock taken through one thread's handle excludes every other thread.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+};
+
+use thiserror::Error;
+
+use crate::{
+    controller::{ControllerLocator, NvmeControllerError},
+    owned::OwnedController,
+    Nvme, NvmeError, NvmeInitError,
+};
+
+#[derive(Debug, Error)]
+pub enum PoolError {
+    #[error(transparent)]
+    Init(#[from] NvmeInitError),
+    #[error(transparent)]
+    Nvme(#[from] NvmeError),
+    #[error(transparent)]
+    Controller(#[from] NvmeControllerError),
+}
+
+type Opener = dyn Fn() -> Result<Nvme, NvmeInitError> + Send + Sync;
+
+static NEXT_POOL_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The handle opened by each pool on this thread, keyed by pool ID. The
+    /// entries of pools that have since been dropped are removed by the next
+    /// call to `NvmePool::get` on the thread.
+    static HANDLES: RefCell<Handles> = RefCell::new(HashMap::new());
+}
+
+type Handles = HashMap<u64, (Weak<PoolInner>, Rc<Nvme>)>;
+
+struct PoolInner {
+    id: u64,
+    open: Box<Opener>,
+}
+
+/// Gives each thread that uses it a libnvme handle of its own.
+///
+/// A thread's handle is opened the first time it calls [`NvmePool::get`] and
+/// then reused until the thread exits or the pool, with every clone of it, is
+/// dropped. Clones of a pool share its handles.
+#[derive(Clone)]
+pub struct NvmePool {
+    inner: Arc<PoolInner>,
+}
+
+impl std::fmt::Debug for NvmePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NvmePool")
+            .field("id", &self.inner.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NvmePool {
+    /// A pool of handles to the illumos libnvme.
+    pub fn new() -> Self {
+        Self::with_opener(Nvme::new)
+    }
+
+    /// A pool that opens each thread's handle with `open`, for example to use
+    /// a backend other than libnvme.
+    pub fn with_opener<F>(open: F) -> Self
+    where
+        F: Fn() -> Result<Nvme, NvmeInitError> + Send + Sync + 'static,
+    {
+        let id = NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed);
+        Self { inner: Arc::new(PoolInner { id, open: Box::new(open) }) }
+    }
+
+    /// The calling thread's handle.
+    pub fn get(&self) -> Result<Rc<Nvme>, NvmeInitError> {
+        HANDLES.with(|handles| {
+            // Dropping a handle runs backend code, so is kept out of the
+            // borrow, as is opening one.
+            let (found, dead) = {
+                let mut handles = handles.borrow_mut();
+                let found =
+                    handles.get(&self.inner.id).map(|(_, n)| Rc::clone(n));
+                let (live, dead): (Handles, Handles) = mem::take(&mut *handles)
+                    .into_iter()
+                    .partition(|(_, (pool, _))| pool.strong_count() > 0);
+                *handles = live;
+                (found, dead)
+            };
+            drop(dead);
+            if let Some(nvme) = found {
+                return Ok(nvme);
+            }
+            let nvme = Rc::new((self.inner.open)()?);
+            handles.borrow_mut().insert(
+                self.inner.id,
+                (Arc::downgrade(&self.inner), Rc::clone(&nvme)),
+            );
+            Ok(nvme)
+        })
+    }
+
+    /// Open the controller identified by `locator` with a new handle of its
+    /// own, rather than the calling thread's, so that it can be moved to
+    /// another thread.
+    pub fn controller(
+        &self,
+        locator: &ControllerLocator,
+    ) -> Result<OwnedController, PoolError> {
+        let nvme = (self.inner.open)()?;
+        Ok(OwnedController::open(nvme, locator)?)
+    }
+
+    /// Run `f` with the calling thread's handle.
+    pub fn with<R, F>(&self, f: F) -> Result<R, NvmeInitError>
+    where
+        F: FnOnce(&Nvme) -> R,
+    {
+        let nvme = self.get()?;
+        Ok(f(&nvme))
+    }
+}
+
+impl Default for NvmePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::{sync::atomic::AtomicUsize, thread};
+
+    use static_assertions::{assert_impl_all, assert_not_impl_any};
+
+    use super::*;
+    use crate::{
+        controller::{
+            Controller, ReadLockedController, TryLockResult,
+            WriteLockedController,
+        },
+        controller_info::ControllerInfo,
+        namespace::{Namespace, NamespaceInfo},
+        owned::{
+            OwnedNamespace, OwnedReadLockedController,
+            OwnedWriteLockedController,
+        },
+        sim::{Sim, SimController},
+    };
+
+    assert_impl_all!(NvmePool: Send, Sync);
+    assert_impl_all!(ControllerLocator: Send, Sync);
+    assert_impl_all!(ControllerInfo: Send, Sync);
+    assert_impl_all!(NamespaceInfo: Send, Sync);
+    assert_impl_all!(NvmeError: Send, Sync);
+    assert_impl_all!(Sim: Send, Sync);
+
+    // Handles that may share a library handle stay on their thread.
+    assert_not_impl_any!(Nvme: Send, Sync);
+    assert_not_impl_any!(Controller<'static>: Send, Sync);
+    assert_not_impl_any!(ReadLockedController<'static>: Send, Sync);
+    assert_not_impl_any!(WriteLockedController<'static>: Send, Sync);
+    assert_not_impl_any!(Namespace<'static>: Send, Sync);
+
+    // Owned handles carry their library handle with them, one thread at a
+    // time.
+    assert_impl_all!(OwnedController: Send);
+    assert_impl_all!(OwnedReadLockedController: Send);
+    assert_impl_all!(OwnedWriteLockedController: Send);
+    assert_impl_all!(OwnedNamespace: Send);
+    assert_not_impl_any!(OwnedController: Sync);
+    assert_not_impl_any!(OwnedReadLockedController: Sync);
+    assert_not_impl_any!(OwnedWriteLockedController: Sync);
+    assert_not_impl_any!(OwnedNamespace: Sync);
+
+    fn pool() -> (NvmePool, Arc<AtomicUsize>) {
+        let sim =
+            Sim::new().with_controller(SimController::new(0)).with_controller(
+                SimController::new(4).with_devinfo_path("/pci@0,0/nvme@4"),
+            );
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&opened);
+        let pool = NvmePool::with_opener(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Nvme::with_backend(sim.clone()))
+        });
+        (pool, opened)
+    }
+
+    #[test]
+    fn controller_moves_between_threads() {
+        let (pool, opened) = pool();
+        pool.get().unwrap();
+        let controller =
+            pool.controller(&ControllerLocator::Instance(4)).unwrap();
+        // The controller has a handle of its own, not this thread's.
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+        let path = thread::spawn(move || controller.devinfo_path().unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(path, "/pci@0,0/nvme@4");
+    }
+
+    #[test]
+    fn one_handle_per_thread() {
+        let (pool, opened) = pool();
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+
+        let workers = (0..2)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    pool.with(|nvme| {
+                        nvme.controller_discovery().unwrap().count()
+                    })
+                    .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), 2);
+        }
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn dropped_pools_release_their_handles() {
+        let (first, _) = pool();
+        let handle = Rc::downgrade(&first.get().unwrap());
+        drop(first);
+        assert!(handle.upgrade().is_some());
+
+        let (other, _) = pool();
+        other.get().unwrap();
+        assert!(handle.upgrade().is_none());
+    }
+
+    #[test]
+    fn reopen_in_another_thread() {
+        let (pool, _) = pool();
+        let nvme = pool.get().unwrap();
+        let locators = nvme
+            .controller_discovery()
+            .unwrap()
+            .map(|c| c.unwrap().locator().unwrap())
+            .chain([ControllerLocator::DevinfoPath(
+                "/devices/pci@0,0/nvme@4".to_string(),
+            )])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locators[..2],
+            [ControllerLocator::Instance(0), ControllerLocator::Instance(4)]
+        );
+
+        let worker = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                locators
+                    .iter()
+                    .map(|locator| {
+                        let controller = pool.controller(locator).unwrap();
+                        controller.get_info().unwrap().serial().into_owned()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(worker.join().unwrap(), ["SIM-0", "SIM-4", "SIM-4"]);
+
+        let err = pool
+            .controller(&ControllerLocator::DevinfoPath("/pci@1,0".into()))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            PoolError::Nvme(e) if e.code() == crate::NvmeErrorCode::BadController
+        ));
+    }
+
+    #[test]
+    fn locks_exclude_other_threads() {
+        let (pool, _) = pool();
+        let locator = ControllerLocator::Instance(0);
+        let locked = pool
+            .controller(&locator)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        let contended = {
+            let pool = pool.clone();
+            let locator = locator.clone();
+            move || {
+                let controller = pool.controller(&locator).unwrap();
+                matches!(controller.try_read_lock(), TryLockResult::Locked(_))
+            }
+        };
+        assert!(thread::spawn(contended.clone()).join().unwrap());
+        drop(locked);
+        assert!(!thread::spawn(contended).join().unwrap());
+    }
+}
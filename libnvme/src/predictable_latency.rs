@@ -0,0 +1,92 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::Controller,
+    feature::FeatureSelect,
+    logpage::LogPageError,
+    nvmespec::predictable_latency::{
+        decode_pl_event_agg, pl_event_agg_len, plm_cdw11, PlWindow, PlmConfig,
+        PredictableLatencySetLog, PLM_CONFIG_FID, PLM_CONFIG_LEN,
+        PLM_WINDOW_FID, PL_EVENT_AGG_LID, PL_PER_SET_LEN, PL_PER_SET_LID,
+    },
+};
+
+// Note: libnvme does not support Set Features today, so Predictable Latency
+// Mode can only be inspected here.
+impl<'a> Controller<'a> {
+    fn check_plm_supported(&self) -> Result<u16, LogPageError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        if id.id_ctratt.ctrat_plm() == 0 {
+            return Err(LogPageError::Unsupported("predictable latency mode"));
+        }
+        Ok(id.ap_nsetidmax)
+    }
+
+    /// Get the Predictable Latency Per NVM Set log for `nvm_set`.
+    pub fn predictable_latency_log(
+        &self,
+        nvm_set: u16,
+    ) -> Result<PredictableLatencySetLog, LogPageError> {
+        self.check_plm_supported()?;
+        let mut buf = [0u8; PL_PER_SET_LEN];
+        self.log_request()?
+            .set_lid(PL_PER_SET_LID)?
+            .set_lsi(u32::from(nvm_set))?
+            .execute(&mut buf)?;
+        Ok(PredictableLatencySetLog::decode(&buf)?)
+    }
+
+    /// Get the NVM sets with predictable latency events outstanding from the
+    /// Predictable Latency Event Aggregate log.
+    ///
+    /// Reading the log clears the event unless `retain_event` is set.
+    pub fn predictable_latency_events(
+        &self,
+        retain_event: bool,
+    ) -> Result<Vec<u16>, LogPageError> {
+        let max_sets = self.check_plm_supported()?;
+        let mut buf = vec![0u8; pl_event_agg_len(max_sets)];
+        self.log_request()?
+            .set_lid(PL_EVENT_AGG_LID)?
+            .set_rae(retain_event)?
+            .execute(&mut buf)?;
+        Ok(decode_pl_event_agg(&buf)?)
+    }
+
+    /// Get the Predictable Latency Mode Config feature for `nvm_set`.
+    pub fn plm_config(
+        &self,
+        nvm_set: u16,
+        sel: FeatureSelect,
+    ) -> Result<PlmConfig, LogPageError> {
+        self.check_plm_supported()?;
+        let mut buf = [0u8; PLM_CONFIG_LEN];
+        let cdw0 = self
+            .get_feature_request()?
+            .set_fid(PLM_CONFIG_FID)?
+            .set_select(sel)?
+            .set_cdw11(plm_cdw11(nvm_set))?
+            .execute_with_output(&mut buf)?;
+        Ok(PlmConfig::decode(cdw0, &buf)?)
+    }
+
+    /// Get the window `nvm_set` is operating in from the Predictable Latency
+    /// Mode Window feature.
+    pub fn plm_window(
+        &self,
+        nvm_set: u16,
+        sel: FeatureSelect,
+    ) -> Result<PlWindow, LogPageError> {
+        self.check_plm_supported()?;
+        let cdw0 = self
+            .get_feature_request()?
+            .set_fid(PLM_WINDOW_FID)?
+            .set_select(sel)?
+            .set_cdw11(plm_cdw11(nvm_set))?
+            .execute()?;
+        Ok(PlWindow::from_cdw0(cdw0))
+    }
+}
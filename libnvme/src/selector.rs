@@ -0,0 +1,309 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Finding controllers by what is known about them.
+
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::{
+    controller::{Controller, ControllerLocator, NvmeControllerError},
+    snapshot::ControllerSnapshot,
+    Nvme, NvmeError, NvmeErrorCode,
+};
+
+/// Which controllers [`Nvme::find_controllers`] should return.
+///
+/// A selector can also be parsed from a string, as given on a command line:
+/// `nvme<N>` or `instance=<N>`, a device tree path starting with `/` or
+/// `path=<path>`, `serial=<serial>`, `model=<glob>`, `vid=<hex>` and
+/// `nqn=<subsystem nqn>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControllerSelector {
+    /// The controller with the given instance number.
+    Instance(i32),
+    /// The controller at the given path in the device tree, with or without
+    /// the leading `/devices`.
+    DevinfoPath(String),
+    Serial(String),
+    /// Controllers whose model matches a glob pattern, in which `*` matches
+    /// any run of characters and `?` matches any single character.
+    Model(String),
+    PciVid(u16),
+    /// Controllers in the NVM subsystem with the given NVMe Qualified Name.
+    SubsystemNqn(String),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid controller selector {0:?}")]
+pub struct SelectorParseError(String);
+
+/// A controller that [`Nvme::find_controllers`] could not compare against a
+/// selector, and so may or may not match it.
+#[derive(Debug, Error)]
+pub enum FindError {
+    #[error(transparent)]
+    Open(#[from] NvmeError),
+    #[error(transparent)]
+    Read(#[from] NvmeControllerError),
+}
+
+impl FromStr for ControllerSelector {
+    type Err = SelectorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SelectorParseError(s.to_string());
+        if let Some(instance) = s.strip_prefix("nvme") {
+            return instance.parse().map(Self::Instance).map_err(|_| invalid());
+        }
+        if s.starts_with('/') {
+            return Ok(Self::DevinfoPath(s.to_string()));
+        }
+
+        let (key, value) = s.split_once('=').ok_or_else(invalid)?;
+        if value.is_empty() {
+            return Err(invalid());
+        }
+        match key {
+            "instance" => {
+                value.parse().map(Self::Instance).map_err(|_| invalid())
+            }
+            "path" => Ok(Self::DevinfoPath(value.to_string())),
+            "serial" => Ok(Self::Serial(value.to_string())),
+            "model" => Ok(Self::Model(value.to_string())),
+            "vid" => {
+                let hex = value.strip_prefix("0x").unwrap_or(value);
+                u16::from_str_radix(hex, 16)
+                    .map(Self::PciVid)
+                    .map_err(|_| invalid())
+            }
+            "nqn" => Ok(Self::SubsystemNqn(value.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl ControllerSelector {
+    /// The locator of the one controller that can match, for selectors that
+    /// say where a controller is rather than what it is.
+    pub fn locator(&self) -> Option<ControllerLocator> {
+        match self {
+            Self::Instance(instance) => {
+                Some(ControllerLocator::Instance(*instance))
+            }
+            Self::DevinfoPath(path) => {
+                Some(ControllerLocator::DevinfoPath(path.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `controller` is selected.
+    pub fn matches(
+        &self,
+        controller: &Controller<'_>,
+    ) -> Result<bool, NvmeControllerError> {
+        let snapshot = || controller.snapshot();
+        Ok(match self {
+            Self::Instance(instance) => controller.instance()? == *instance,
+            Self::DevinfoPath(path) => {
+                let path = path.strip_prefix("/devices").unwrap_or(path);
+                controller.devinfo_path()? == path
+            }
+            Self::Serial(serial) => snapshot()?.serial == *serial,
+            Self::Model(pattern) => glob_match(pattern, &snapshot()?.model),
+            Self::PciVid(vid) => snapshot()?.pci_vid == Some(*vid),
+            Self::SubsystemNqn(nqn) => {
+                let ControllerSnapshot { identify, .. } = snapshot()?;
+                identify.subnqn == *nqn
+            }
+        })
+    }
+}
+
+/// Match `text` against a pattern of literal characters, `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume if the text so far turns out not to match: just after
+    // the last `*` seen, consuming one more character of the text with it.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    backtrack = Some((star, consumed + 1));
+                    p = star;
+                    t = consumed + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Nvme {
+    /// Every controller that `selector` matches, in discovery order, along
+    /// with an error for each controller that could not be opened or read to
+    /// compare against it.
+    ///
+    /// A selector with a [`ControllerSelector::locator`] opens that
+    /// controller directly rather than examining every controller, and
+    /// matches nothing if there is no controller there.
+    pub fn find_controllers(
+        &self,
+        selector: &ControllerSelector,
+    ) -> Result<Vec<Result<Controller<'_>, FindError>>, NvmeError> {
+        if let Some(locator) = selector.locator() {
+            return match locator.open(self) {
+                Ok(controller) => Ok(vec![Ok(controller)]),
+                Err(e) if e.code() == NvmeErrorCode::BadController => {
+                    Ok(Vec::new())
+                }
+                Err(e) => Err(e),
+            };
+        }
+
+        Ok(self
+            .controller_discovery()?
+            .filter_map(|controller| {
+                let controller = match controller {
+                    Ok(controller) => controller,
+                    Err(e) => return Some(Err(e.into())),
+                };
+                match selector.matches(&controller) {
+                    Ok(true) => Some(Ok(controller)),
+                    Ok(false) => None,
+                    Err(e) => Some(Err(e.into())),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("SAMSUNG MZ*", "SAMSUNG MZQL21T9HCJR"));
+        assert!(glob_match("*MZ?L*", "SAMSUNG MZQL21T9HCJR"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(!glob_match("MZ*", "SAMSUNG MZQL21T9HCJR"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn parse() {
+        let cases = [
+            ("nvme3", ControllerSelector::Instance(3)),
+            ("instance=12", ControllerSelector::Instance(12)),
+            (
+                "/devices/pci@0,0/nvme@4",
+                ControllerSelector::DevinfoPath(
+                    "/devices/pci@0,0/nvme@4".into(),
+                ),
+            ),
+            (
+                "path=/pci@0,0",
+                ControllerSelector::DevinfoPath("/pci@0,0".into()),
+            ),
+            ("serial=S1", ControllerSelector::Serial("S1".into())),
+            ("model=WUS4*", ControllerSelector::Model("WUS4*".into())),
+            ("vid=0x1b96", ControllerSelector::PciVid(0x1b96)),
+            ("vid=144d", ControllerSelector::PciVid(0x144d)),
+            ("nqn=nqn.a:b", ControllerSelector::SubsystemNqn("nqn.a:b".into())),
+        ];
+        for (s, selector) in cases {
+            assert_eq!(s.parse::<ControllerSelector>(), Ok(selector), "{s}");
+        }
+        for s in ["nvme", "nvmex", "serial=", "vid=xyz", "colour=red", "S1"] {
+            assert!(s.parse::<ControllerSelector>().is_err(), "{s}");
+        }
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn find() {
+        use crate::sim::{Sim, SimController};
+
+        let nqn = |nqn: &'static str| {
+            move |id: &mut libnvme_sys::nvme::nvme_identify_ctrl_t| {
+                id.id_subnqn[..nqn.len()].copy_from_slice(nqn.as_bytes())
+            }
+        };
+        let sim = Sim::new()
+            .with_controller(
+                SimController::new(0)
+                    .with_model("WUS4C6432DSP3X3")
+                    .with_pci_vid(Some(0x1b96))
+                    .with_identify(nqn("nqn.wdc:a")),
+            )
+            .with_controller(
+                SimController::new(1)
+                    .with_model("SAMSUNG MZQL21T9HCJR")
+                    .with_pci_vid(Some(0x144d))
+                    .with_identify(nqn("nqn.samsung:b")),
+            )
+            .with_controller(
+                SimController::new(2)
+                    .with_model("WUS5EA138ESP7E3")
+                    .with_pci_vid(Some(0x1b96))
+                    .with_devinfo_path("/pci@ab,0/nvme@2"),
+            )
+            .with_controller(SimController::new(3));
+        sim.controller(3, |c| c.set_dead(true));
+        let nvme = Nvme::with_backend(sim);
+        let find = |selector: &str| {
+            nvme.find_controllers(&selector.parse().unwrap()).unwrap()
+        };
+        let instances = |selector: &str| {
+            find(selector)
+                .into_iter()
+                .filter_map(Result::ok)
+                .map(|c| c.instance().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(instances("nvme1"), [1]);
+        // A dead controller can be found by where it is but not by what it
+        // is, and whether it matches is then unknown.
+        assert_eq!(instances("nvme3"), [3]);
+        let found = find("model=*");
+        assert_eq!(found.len(), 4);
+        assert!(matches!(
+            &found[3],
+            Err(FindError::Read(e)) if e.code() == NvmeErrorCode::CtrlDead
+        ));
+        assert_eq!(instances("model=*"), [0, 1, 2]);
+        assert_eq!(instances("nvme7"), [] as [i32; 0]);
+        assert_eq!(instances("/pci@ff,0"), [] as [i32; 0]);
+        assert_eq!(instances("/devices/pci@ab,0/nvme@2"), [2]);
+        assert_eq!(instances("path=/pci@ab,0/nvme@2"), [2]);
+        assert_eq!(instances("serial=SIM-2"), [2]);
+        assert_eq!(instances("model=WUS*"), [0, 2]);
+        assert_eq!(instances("model=*MZQL2?T9*"), [1]);
+        assert_eq!(instances("vid=1b96"), [0, 2]);
+        assert_eq!(instances("nqn=nqn.samsung:b"), [1]);
+
+        let controller = Controller::init_by_instance(&nvme, 2).unwrap();
+        assert_eq!(controller.instance().unwrap(), 2);
+        assert_eq!(controller.devinfo_path().unwrap(), "/pci@ab,0/nvme@2");
+    }
+}
@@ -0,0 +1,21 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::Controller,
+    logpage::LogPageError,
+    nvmespec::self_test::{SelfTestLog, SELF_TEST_LEN, SELF_TEST_LID},
+};
+
+impl<'a> Controller<'a> {
+    /// Get the Device Self-test log, which reports the progress of any test
+    /// in progress and the results of the last twenty.
+    ///
+    /// Note: libnvme does not support starting a self-test today.
+    pub fn self_test_log(&self) -> Result<SelfTestLog, LogPageError> {
+        let mut buf = vec![0u8; SELF_TEST_LEN];
+        self.log_request()?.set_lid(SELF_TEST_LID)?.execute(&mut buf)?;
+        Ok(SelfTestLog::decode(&buf)?)
+    }
+}
@@ -0,0 +1,1349 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An in-memory NVMe controller simulator.
+//!
+//! A [`Sim`] holds a set of [`SimController`]s and can be used in place of
+//! the illumos libnvme via `Nvme::with_backend`. It models enough of a
+//! controller to exercise the safe API off illumos: identify data,
+//! namespaces and their LBA formats, blkdev attachment, firmware slots and
+//! boot partitions, controller locks, and canned log pages and features. Errors, both from
+//! libnvme and from the device, can be injected for the next call of a given
+//! operation.
+//!
+//! `Sim` is a cheap handle onto shared state, so a clone kept by a test can be
+//! used to inspect the controllers after they have been operated on.
+
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
+    ffi::c_char,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use libnvme_sys::{identify::IdFrmw, nvme::*};
+
+use crate::{
+    backend::{
+        Backend, ControllerBackend, ControllerIter, FirmwareCommitRequest,
+        FormatRequest, GetFeatureRequest, LockLevel, LogRequest, VucRequest,
+    },
+    controller::NvmeControllerError,
+    controller_info::{ControllerInfo, NvmeInfoError, NvmeInfoErrorCode},
+    firmware::FirmwareCommitAction,
+    lba::{LbaFormat, Performance},
+    logpage::LogPageName,
+    namespace::{NamespaceDiscoveryLevel, NamespaceInfo, NamespaceSize},
+    nvmespec::{
+        smart::{SMART_LEN, SMART_LID},
+        NVME_CQE_SCT_GENERIC, NVME_CQE_SCT_SPECIFIC, NVME_CQE_SC_GEN_INV_FLD,
+        NVME_CQE_SC_GEN_INV_OPC, NVME_CQE_SC_SPC_INV_FW_IMG,
+        NVME_CQE_SC_SPC_INV_FW_SLOT, NVME_CQE_SC_SPC_INV_LOG_PAGE,
+    },
+    vuc::VucCommand,
+    NvmeError, NvmeErrorCode,
+};
+
+/// Log page identifier of the Firmware Slot Information log.
+const FIRMWARE_LID: u32 = 0x03;
+/// Size of the Firmware Slot Information log.
+const FIRMWARE_LOG_LEN: usize = 512;
+/// Log page identifier of the Boot Partition log.
+const BOOT_PARTITION_LID: u32 = 0x15;
+/// Size of the Boot Partition log header, which the selected partition
+/// follows.
+const BOOT_PARTITION_HDR_LEN: usize = 16;
+/// Boot partition sizes are reported in 128 KiB units.
+const BOOT_PARTITION_SIZE_UNIT: usize = 128 * 1024;
+/// Controller or Namespace Structure value of Identify Controller.
+const IDENTIFY_CTRL_CNS: u32 = 0x01;
+/// The broadcast NSID, which a format applies to every namespace.
+const NSID_ALL: u32 = u32::MAX;
+
+/// An operation on a simulated controller that an error can be injected
+/// into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimOp {
+    /// Opening the controller, either by instance or during discovery.
+    Open,
+    Info,
+    Lock,
+    Namespaces,
+    NamespaceInfo,
+    BlkdevAttach,
+    BlkdevDetach,
+    /// Reading any log page, including named log pages.
+    GetLog,
+    Identify,
+    GetFeature,
+    FirmwareLoad,
+    FirmwareCommit,
+    Format,
+    Vuc,
+}
+
+/// An injected failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimError {
+    /// libnvme itself fails with the given error.
+    Library(NvmeErrorCode),
+    /// The controller completes the command with the given Status Code Type
+    /// and Status Code.
+    Device { sct: u32, sc: u32 },
+}
+
+/// A simulated namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimNamespace {
+    pub nsid: u32,
+    /// Inactive namespaces are allocated but not attached to the controller.
+    pub active: bool,
+    /// Whether blkdev is attached to the namespace.
+    pub blkdev: bool,
+    /// The ID of the LBA format the namespace is formatted with.
+    pub lbaf: u32,
+    /// The size of the namespace in logical blocks, which is also its
+    /// capacity.
+    pub blocks: u64,
+    /// The number of logical blocks allocated.
+    pub used_blocks: u64,
+}
+
+impl SimNamespace {
+    /// An active namespace with blkdev attached, formatted with LBA format 0,
+    /// of 2097152 blocks of which a quarter are used.
+    pub fn new(nsid: u32) -> Self {
+        Self {
+            nsid,
+            active: true,
+            blkdev: true,
+            lbaf: 0,
+            blocks: 0x20_0000,
+            used_blocks: 0x8_0000,
+        }
+    }
+}
+
+/// A simulated controller.
+#[derive(Debug, Clone)]
+pub struct SimController {
+    instance: i32,
+    devinfo_path: String,
+    identify: nvme_identify_ctrl_t,
+    model: String,
+    serial: String,
+    pci_vid: Option<u16>,
+    lba_formats: Vec<LbaFormat>,
+    namespaces: BTreeMap<u32, SimNamespace>,
+    slots: Vec<Option<String>>,
+    slot1_read_only: bool,
+    active_slot: u8,
+    next_active_slot: Option<u8>,
+    staged_firmware: Vec<u8>,
+    boot_partitions: Vec<Vec<u8>>,
+    active_boot_partition: u8,
+    log_pages: BTreeMap<u32, Vec<u8>>,
+    identify_data: BTreeMap<u32, Vec<u8>>,
+    features: BTreeMap<u32, (u32, Vec<u8>)>,
+    vuc_commands: Vec<VucCommand>,
+    readers: u32,
+    writer: bool,
+    errors: VecDeque<(SimOp, SimError)>,
+    dead: bool,
+}
+
+fn fill_identify_string(field: &mut [c_char], value: &str) {
+    let bytes = value.as_bytes();
+    for (i, c) in field.iter_mut().enumerate() {
+        *c = bytes.get(i).copied().unwrap_or(b' ') as c_char;
+    }
+}
+
+impl SimController {
+    /// A controller with a single active namespace, two firmware slots with
+    /// firmware "SIM1.0" active in slot 1, 512 and 4096 byte LBA formats and
+    /// a SMART log reporting a healthy device.
+    pub fn new(instance: i32) -> Self {
+        let mut identify: nvme_identify_ctrl_t = unsafe { std::mem::zeroed() };
+        identify.ap_wctemp = 343;
+        identify.ap_cctemp = 353;
+
+        let mut smart = vec![0u8; SMART_LEN];
+        smart[1..3].copy_from_slice(&300u16.to_le_bytes());
+        smart[3] = 100;
+        smart[4] = 10;
+
+        Self {
+            instance,
+            devinfo_path: format!("/pci@0,0/pci1b36,10@{instance}"),
+            identify,
+            model: "SIM NVMe".to_string(),
+            serial: format!("SIM-{instance}"),
+            pci_vid: Some(0x1b36),
+            lba_formats: vec![
+                LbaFormat::new(0, 512, 0, Performance::Good),
+                LbaFormat::new(1, 4096, 0, Performance::Best),
+            ],
+            namespaces: BTreeMap::from([(1, SimNamespace::new(1))]),
+            slots: vec![Some("SIM1.0".to_string()), None],
+            slot1_read_only: false,
+            active_slot: 1,
+            next_active_slot: None,
+            staged_firmware: Vec::new(),
+            boot_partitions: Vec::new(),
+            active_boot_partition: 0,
+            log_pages: BTreeMap::from([(SMART_LID, smart)]),
+            identify_data: BTreeMap::new(),
+            features: BTreeMap::new(),
+            vuc_commands: Vec::new(),
+            readers: 0,
+            writer: false,
+            errors: VecDeque::new(),
+            dead: false,
+        }
+    }
+
+    /// Set the path of the controller in the device tree, which defaults to
+    /// one derived from its instance number.
+    pub fn with_devinfo_path(mut self, path: &str) -> Self {
+        self.devinfo_path = path.to_string();
+        self
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    pub fn with_serial(mut self, serial: &str) -> Self {
+        self.serial = serial.to_string();
+        self
+    }
+
+    /// Set the PCI vendor ID, or `None` for a controller that is not on PCI.
+    pub fn with_pci_vid(mut self, vid: Option<u16>) -> Self {
+        self.pci_vid = vid;
+        self
+    }
+
+    /// Modify the Identify Controller data structure.
+    ///
+    /// The model, serial number, firmware revision, number of namespaces and
+    /// firmware slot information are always overwritten from the rest of the
+    /// simulated state.
+    pub fn with_identify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut nvme_identify_ctrl_t),
+    {
+        f(&mut self.identify);
+        self
+    }
+
+    pub fn with_lba_formats(mut self, formats: Vec<LbaFormat>) -> Self {
+        self.lba_formats = formats;
+        self
+    }
+
+    /// Replace the namespaces of the controller.
+    pub fn with_namespaces(mut self, namespaces: Vec<SimNamespace>) -> Self {
+        self.namespaces =
+            namespaces.into_iter().map(|ns| (ns.nsid, ns)).collect();
+        self
+    }
+
+    /// Set the firmware in each slot, along with the active slot. The number
+    /// of slots is the length of `slots`.
+    pub fn with_firmware_slots(
+        mut self,
+        slots: Vec<Option<&str>>,
+        active_slot: u8,
+        slot1_read_only: bool,
+    ) -> Self {
+        self.slots =
+            slots.into_iter().map(|slot| slot.map(str::to_string)).collect();
+        self.active_slot = active_slot;
+        self.slot1_read_only = slot1_read_only;
+        self
+    }
+
+    /// Give the controller two zeroed boot partitions of `units` 128 KiB
+    /// units each, with `active` the one it boots from. Without boot
+    /// partitions the boot partition commit actions are rejected.
+    pub fn with_boot_partitions(mut self, units: u16, active: u8) -> Self {
+        let size = usize::from(units) * BOOT_PARTITION_SIZE_UNIT;
+        self.boot_partitions = vec![vec![0; size]; 2];
+        self.active_boot_partition = active;
+        self
+    }
+
+    /// Serve `data` for the log page `lid`. Reads past its end return
+    /// zeros.
+    pub fn with_log_page(mut self, lid: u32, data: Vec<u8>) -> Self {
+        self.log_pages.insert(lid, data);
+        self
+    }
+
+    /// Serve `data` for Identify commands with the given CNS value.
+    pub fn with_identify_data(mut self, cns: u32, data: Vec<u8>) -> Self {
+        self.identify_data.insert(cns, data);
+        self
+    }
+
+    /// Return `cdw0` and `data` for Get Features of `fid`, regardless of the
+    /// select value.
+    pub fn with_feature(mut self, fid: u32, cdw0: u32, data: Vec<u8>) -> Self {
+        self.features.insert(fid, (cdw0, data));
+        self
+    }
+
+    pub fn with_vuc_commands(mut self, commands: Vec<VucCommand>) -> Self {
+        self.vuc_commands = commands;
+        self
+    }
+
+    /// Fail the next call of `op` with `error`. Errors injected for the same
+    /// operation are returned in order.
+    pub fn with_error(mut self, op: SimOp, error: SimError) -> Self {
+        self.errors.push_back((op, error));
+        self
+    }
+
+    pub fn instance(&self) -> i32 {
+        self.instance
+    }
+
+    pub fn namespace(&self, nsid: u32) -> Option<&SimNamespace> {
+        self.namespaces.get(&nsid)
+    }
+
+    /// The firmware revision in `slot`, counting from 1.
+    pub fn firmware_slot(&self, slot: u8) -> Option<&str> {
+        let index = usize::from(slot).checked_sub(1)?;
+        self.slots.get(index)?.as_deref()
+    }
+
+    pub fn active_slot(&self) -> u8 {
+        self.active_slot
+    }
+
+    pub fn next_active_slot(&self) -> Option<u8> {
+        self.next_active_slot
+    }
+
+    /// The contents of boot partition `id`.
+    pub fn boot_partition(&self, id: u8) -> Option<&[u8]> {
+        self.boot_partitions.get(usize::from(id)).map(Vec::as_slice)
+    }
+
+    pub fn active_boot_partition(&self) -> u8 {
+        self.active_boot_partition
+    }
+
+    /// Reset the controller, activating the firmware selected by a previous
+    /// commit.
+    pub fn reset(&mut self) {
+        if let Some(slot) = self.next_active_slot.take() {
+            self.active_slot = slot;
+        }
+    }
+
+    /// Mark the controller as dead, failing every operation on it with
+    /// `CtrlDead` as libnvme does once the kernel has given up on a device.
+    pub fn set_dead(&mut self, dead: bool) {
+        self.dead = dead;
+    }
+
+    fn fwrev(&self) -> &str {
+        self.firmware_slot(self.active_slot).unwrap_or_default()
+    }
+
+    fn take_error(&mut self, op: SimOp) -> Option<SimError> {
+        let index = self.errors.iter().position(|(o, _)| *o == op)?;
+        self.errors.remove(index).map(|(_, error)| error)
+    }
+
+    fn check(&mut self, op: SimOp) -> Result<(), NvmeControllerError> {
+        if self.dead {
+            return Err(library_error(NvmeErrorCode::CtrlDead, op));
+        }
+        match self.take_error(op) {
+            None => Ok(()),
+            Some(SimError::Library(code)) => Err(library_error(code, op)),
+            Some(SimError::Device { sct, sc }) => {
+                Err(device_error(sct, sc, op))
+            }
+        }
+    }
+
+    fn identify(&self) -> nvme_identify_ctrl_t {
+        let mut id = self.identify.clone();
+        fill_identify_string(&mut id.id_model, &self.model);
+        fill_identify_string(&mut id.id_serial, &self.serial);
+        fill_identify_string(&mut id.id_fwrev, self.fwrev());
+        id.id_nn = self.namespaces.keys().max().copied().unwrap_or(0);
+        let nslot = (self.slots.len() as u8) & 0x7;
+        id.id_frmw =
+            IdFrmw::from_bits((nslot << 1) | u8::from(self.slot1_read_only));
+        id
+    }
+
+    fn firmware_log(&self) -> Vec<u8> {
+        let mut log = vec![0u8; FIRMWARE_LOG_LEN];
+        log[0] = self.active_slot | (self.next_active_slot.unwrap_or(0) << 4);
+        for (i, slot) in self.slots.iter().enumerate().take(7) {
+            if let Some(fw) = slot {
+                let off = 8 + i * 8;
+                let mut rev = [b' '; 8];
+                let len = fw.len().min(8);
+                rev[..len].copy_from_slice(&fw.as_bytes()[..len]);
+                log[off..off + 8].copy_from_slice(&rev);
+            }
+        }
+        log
+    }
+
+    /// The Boot Partition log for the partition selected by `lsp`, or `None`
+    /// if there is no such partition.
+    fn boot_partition_log(&self, lsp: Option<u32>) -> Option<Vec<u8>> {
+        let bp = self.boot_partitions.get(lsp.unwrap_or(0) as usize)?;
+        let units = (bp.len() / BOOT_PARTITION_SIZE_UNIT) as u32;
+        let bpinfo = units | u32::from(self.active_boot_partition) << 31;
+        let mut log = vec![0u8; BOOT_PARTITION_HDR_LEN];
+        log[0] = BOOT_PARTITION_LID as u8;
+        log[4..8].copy_from_slice(&bpinfo.to_le_bytes());
+        log.extend_from_slice(bp);
+        Some(log)
+    }
+
+    /// Commit the staged image to, or activate, the boot partition `bpid`.
+    fn commit_boot_partition(
+        &mut self,
+        action: FirmwareCommitAction,
+        bpid: Option<u32>,
+    ) -> Result<(), NvmeControllerError> {
+        let op = SimOp::FirmwareCommit;
+        let Some(bpid) = bpid else {
+            return Err(library_error(
+                NvmeErrorCode::FwCommitReqMissingFields,
+                op,
+            ));
+        };
+        let Some(bp) = self.boot_partitions.get_mut(bpid as usize) else {
+            return Err(device_error(
+                NVME_CQE_SCT_GENERIC,
+                NVME_CQE_SC_GEN_INV_FLD,
+                op,
+            ));
+        };
+
+        if action == FirmwareCommitAction::ActivateBootPartition {
+            self.active_boot_partition = bpid as u8;
+            return Ok(());
+        }
+        if self.staged_firmware.is_empty()
+            || self.staged_firmware.len() > bp.len()
+        {
+            return Err(device_error(
+                NVME_CQE_SCT_SPECIFIC,
+                NVME_CQE_SC_SPC_INV_FW_IMG,
+                op,
+            ));
+        }
+        let image = std::mem::take(&mut self.staged_firmware);
+        bp.fill(0);
+        bp[..image.len()].copy_from_slice(&image);
+        Ok(())
+    }
+
+    fn lba_format(&self, id: u32) -> Option<LbaFormat> {
+        self.lba_formats.iter().find(|f| f.id() == id).copied()
+    }
+}
+
+fn library_error(code: NvmeErrorCode, op: SimOp) -> NvmeControllerError {
+    NvmeControllerError::new(
+        code,
+        format!("simulated {op:?} failed"),
+        format!("{code:?}"),
+    )
+}
+
+fn device_error(sct: u32, sc: u32, op: SimOp) -> NvmeControllerError {
+    NvmeControllerError::device(
+        sct,
+        sc,
+        format!("simulated {op:?} failed"),
+        format!("controller returned status {sct:#x}/{sc:#x}"),
+    )
+}
+
+#[derive(Debug, Default)]
+struct SimState {
+    controllers: Vec<SimController>,
+}
+
+/// A simulated set of NVMe controllers.
+#[derive(Debug, Clone, Default)]
+pub struct Sim {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl Sim {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a controller to the simulation.
+    pub fn with_controller(self, controller: SimController) -> Self {
+        self.state().controllers.push(controller);
+        self
+    }
+
+    /// Inspect or modify the controller with the given instance number.
+    pub fn controller<R, F>(&self, instance: i32, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut SimController) -> R,
+    {
+        let mut state = self.state();
+        state.controllers.iter_mut().find(|c| c.instance == instance).map(f)
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().expect("sim state poisoned")
+    }
+
+    fn open(&self, instance: i32) -> Result<SimHandle, NvmeError> {
+        let error = self
+            .controller(instance, |c| c.take_error(SimOp::Open))
+            .ok_or_else(|| {
+                NvmeError::new(
+                    NvmeErrorCode::BadController,
+                    format!("failed to get controller for instance {instance}"),
+                    "no such simulated controller",
+                )
+            })?;
+        match error {
+            None => Ok(SimHandle {
+                sim: self.clone(),
+                instance,
+                held: Cell::new(None),
+            }),
+            Some(error) => {
+                let code = match error {
+                    SimError::Library(code) => code,
+                    SimError::Device { .. } => NvmeErrorCode::Controller,
+                };
+                Err(NvmeError::new(
+                    code,
+                    format!("failed to open simulated controller {instance}"),
+                    format!("{error:?}"),
+                ))
+            }
+        }
+    }
+}
+
+impl Backend for Sim {
+    fn discover(&self) -> Result<ControllerIter<'_>, NvmeError> {
+        let instances: Vec<i32> =
+            self.state().controllers.iter().map(|c| c.instance).collect();
+        Ok(Box::new(instances.into_iter().map(|instance| {
+            self.open(instance)
+                .map(|h| Box::new(h) as Box<dyn ControllerBackend>)
+        })))
+    }
+
+    fn controller_by_instance(
+        &self,
+        instance: i32,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        Ok(Box::new(self.open(instance)?))
+    }
+
+    fn controller_by_devinfo_path(
+        &self,
+        path: &str,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let instance = self
+            .state()
+            .controllers
+            .iter()
+            .find(|c| c.devinfo_path == path)
+            .map(|c| c.instance)
+            .ok_or_else(|| {
+                NvmeError::new(
+                    NvmeErrorCode::BadController,
+                    format!("failed to get controller at {path}"),
+                    "no such simulated controller",
+                )
+            })?;
+        Ok(Box::new(self.open(instance)?))
+    }
+}
+
+/// An open simulated controller, which tracks the lock it holds.
+struct SimHandle {
+    sim: Sim,
+    instance: i32,
+    held: Cell<Option<LockLevel>>,
+}
+
+impl SimHandle {
+    /// Run `f` against the controller after checking for an injected error
+    /// for `op`.
+    fn with<R, F>(&self, op: SimOp, f: F) -> Result<R, NvmeControllerError>
+    where
+        F: FnOnce(&mut SimController) -> Result<R, NvmeControllerError>,
+    {
+        self.sim
+            .controller(self.instance, |c| {
+                c.check(op)?;
+                f(c)
+            })
+            .unwrap_or_else(|| {
+                Err(NvmeControllerError::new(
+                    NvmeErrorCode::CtrlGone,
+                    format!("simulated {op:?} failed"),
+                    "controller has been removed",
+                ))
+            })
+    }
+
+    /// As `with`, for operations that require the controller write lock.
+    fn with_write<R, F>(
+        &self,
+        op: SimOp,
+        f: F,
+    ) -> Result<R, NvmeControllerError>
+    where
+        F: FnOnce(&mut SimController) -> Result<R, NvmeControllerError>,
+    {
+        if self.held.get() != Some(LockLevel::Write) {
+            return Err(library_error(NvmeErrorCode::NeedCtrlWrlock, op));
+        }
+        self.with(op, f)
+    }
+}
+
+impl Drop for SimHandle {
+    fn drop(&mut self) {
+        self.unlock();
+    }
+}
+
+impl ControllerBackend for SimHandle {
+    fn info(&self) -> Result<ControllerInfo, NvmeControllerError> {
+        self.with(SimOp::Info, |c| {
+            let pci_vid = c.pci_vid.ok_or_else(|| {
+                NvmeInfoError::new(
+                    NvmeInfoErrorCode::Transport,
+                    "failed to get pci vid",
+                    "controller is not a PCI device",
+                )
+            });
+            let formats = c.lba_formats.iter().copied().map(Ok).collect();
+            Ok(ControllerInfo::new(c.identify(), pci_vid, formats))
+        })
+    }
+
+    fn instance(&self) -> Result<i32, NvmeControllerError> {
+        Ok(self.instance)
+    }
+
+    fn devinfo_path(&self) -> Result<String, NvmeControllerError> {
+        self.sim
+            .controller(self.instance, |c| c.devinfo_path.clone())
+            .ok_or_else(|| {
+                NvmeControllerError::new(
+                    NvmeErrorCode::CtrlGone,
+                    "failed to get controller devinfo path",
+                    "controller has been removed",
+                )
+            })
+    }
+
+    /// The simulator never blocks: a lock that cannot be taken immediately
+    /// fails with `LockWouldBlock` even when blocking was requested.
+    fn lock(
+        &self,
+        level: LockLevel,
+        _block: bool,
+    ) -> Result<(), NvmeControllerError> {
+        if self.held.get().is_some() {
+            return Err(library_error(NvmeErrorCode::LockProg, SimOp::Lock));
+        }
+        self.with(SimOp::Lock, |c| {
+            let available = match level {
+                LockLevel::Read => !c.writer,
+                LockLevel::Write => !c.writer && c.readers == 0,
+            };
+            if !available {
+                return Err(library_error(
+                    NvmeErrorCode::LockWouldBlock,
+                    SimOp::Lock,
+                ));
+            }
+            match level {
+                LockLevel::Read => c.readers += 1,
+                LockLevel::Write => c.writer = true,
+            }
+            Ok(())
+        })?;
+        self.held.set(Some(level));
+        Ok(())
+    }
+
+    fn unlock(&self) {
+        let Some(level) = self.held.take() else {
+            return;
+        };
+        self.sim.controller(self.instance, |c| match level {
+            LockLevel::Read => c.readers -= 1,
+            LockLevel::Write => c.writer = false,
+        });
+    }
+
+    fn namespaces(
+        &self,
+        level: NamespaceDiscoveryLevel,
+    ) -> Result<Vec<u32>, NvmeControllerError> {
+        self.with(SimOp::Namespaces, |c| {
+            Ok(c.namespaces
+                .values()
+                .filter(|ns| match level {
+                    NamespaceDiscoveryLevel::All => true,
+                    NamespaceDiscoveryLevel::Allocated
+                    | NamespaceDiscoveryLevel::Active
+                    | NamespaceDiscoveryLevel::NotIgnored => ns.active,
+                    NamespaceDiscoveryLevel::BlkDev => ns.blkdev,
+                })
+                .map(|ns| ns.nsid)
+                .collect())
+        })
+    }
+
+    fn namespace_info(
+        &self,
+        nsid: u32,
+    ) -> Result<NamespaceInfo, NvmeControllerError> {
+        self.with(SimOp::NamespaceInfo, |c| {
+            let ns = *c.namespaces.get(&nsid).ok_or_else(|| {
+                library_error(NvmeErrorCode::NsRange, SimOp::NamespaceInfo)
+            })?;
+            let inactive = |what: &str| {
+                NvmeInfoError::new(
+                    NvmeInfoErrorCode::NsInactive,
+                    format!("failed to get {what} of NVMe namespace"),
+                    format!("namespace {nsid} is inactive"),
+                )
+            };
+            let level = match ns {
+                SimNamespace { active: false, .. } => {
+                    NamespaceDiscoveryLevel::Allocated
+                }
+                SimNamespace { blkdev: false, .. } => {
+                    NamespaceDiscoveryLevel::NotIgnored
+                }
+                _ => NamespaceDiscoveryLevel::BlkDev,
+            };
+            let current_format = if !ns.active {
+                Err(inactive("current format"))
+            } else {
+                c.lba_format(ns.lbaf).ok_or_else(|| {
+                    NvmeInfoError::new(
+                        NvmeInfoErrorCode::BadLbaFmt,
+                        "failed to get current format of NVMe namespace",
+                        format!("unknown LBA format {}", ns.lbaf),
+                    )
+                })
+            };
+            let size = if !ns.active {
+                Err(inactive("size"))
+            } else {
+                let block =
+                    current_format.as_ref().map_or(512, |f| f.data_size());
+                Ok(NamespaceSize {
+                    size: ns.blocks * block,
+                    capacity: ns.blocks * block,
+                    used: ns.used_blocks * block,
+                })
+            };
+            Ok(NamespaceInfo::new(level, current_format, size))
+        })
+    }
+
+    fn blkdev_attach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        self.with_write(SimOp::BlkdevAttach, |c| {
+            let ns = c.namespaces.get_mut(&nsid).ok_or_else(|| {
+                library_error(NvmeErrorCode::NsRange, SimOp::BlkdevAttach)
+            })?;
+            if !ns.active {
+                return Err(library_error(
+                    NvmeErrorCode::AttachKern,
+                    SimOp::BlkdevAttach,
+                ));
+            }
+            ns.blkdev = true;
+            Ok(())
+        })
+    }
+
+    fn blkdev_detach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        self.with_write(SimOp::BlkdevDetach, |c| {
+            let ns = c.namespaces.get_mut(&nsid).ok_or_else(|| {
+                library_error(NvmeErrorCode::NsRange, SimOp::BlkdevDetach)
+            })?;
+            ns.blkdev = false;
+            Ok(())
+        })
+    }
+
+    fn named_log(
+        &self,
+        name: LogPageName,
+    ) -> Result<Vec<u8>, NvmeControllerError> {
+        self.with(SimOp::GetLog, |c| match name {
+            LogPageName::Firmware => Ok(c.firmware_log()),
+        })
+    }
+
+    fn get_log(
+        &self,
+        req: &LogRequest,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.with(SimOp::GetLog, |c| {
+            let lid = req.lid.ok_or_else(|| {
+                library_error(NvmeErrorCode::LogReqMissingFields, SimOp::GetLog)
+            })?;
+            let data = match lid {
+                FIRMWARE_LID => Some(c.firmware_log()),
+                BOOT_PARTITION_LID if !c.boot_partitions.is_empty() => {
+                    c.boot_partition_log(req.lsp)
+                }
+                lid => c.log_pages.get(&lid).cloned(),
+            }
+            .ok_or_else(|| {
+                device_error(
+                    NVME_CQE_SCT_SPECIFIC,
+                    NVME_CQE_SC_SPC_INV_LOG_PAGE,
+                    SimOp::GetLog,
+                )
+            })?;
+            copy_at(&data, req.offset, buf, SimOp::GetLog)
+        })
+    }
+
+    fn identify(
+        &self,
+        cns: u32,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.with(SimOp::Identify, |c| {
+            let data = match cns {
+                IDENTIFY_CTRL_CNS => {
+                    let id = c.identify();
+                    let ptr = (&id as *const nvme_identify_ctrl_t).cast::<u8>();
+                    let len = std::mem::size_of::<nvme_identify_ctrl_t>();
+                    unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec()
+                }
+                cns => c.identify_data.get(&cns).cloned().ok_or_else(|| {
+                    device_error(
+                        NVME_CQE_SCT_GENERIC,
+                        NVME_CQE_SC_GEN_INV_FLD,
+                        SimOp::Identify,
+                    )
+                })?,
+            };
+            copy_at(&data, 0, buf, SimOp::Identify)
+        })
+    }
+
+    fn get_feature(
+        &self,
+        req: &GetFeatureRequest,
+        buf: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        self.with(SimOp::GetFeature, |c| {
+            let fid = req.fid.ok_or_else(|| {
+                library_error(
+                    NvmeErrorCode::GetFeatReqMissingFields,
+                    SimOp::GetFeature,
+                )
+            })?;
+            let (cdw0, data) = c.features.get(&fid).ok_or_else(|| {
+                device_error(
+                    NVME_CQE_SCT_GENERIC,
+                    NVME_CQE_SC_GEN_INV_FLD,
+                    SimOp::GetFeature,
+                )
+            })?;
+            if let Some(buf) = buf {
+                copy_at(data, 0, buf, SimOp::GetFeature)?;
+            }
+            Ok(*cdw0)
+        })
+    }
+
+    fn firmware_load(
+        &self,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<(), NvmeControllerError> {
+        self.with_write(SimOp::FirmwareLoad, |c| {
+            if data.is_empty() || !data.len().is_multiple_of(4) {
+                return Err(library_error(
+                    NvmeErrorCode::FwLoadLenRange,
+                    SimOp::FirmwareLoad,
+                ));
+            }
+            if !offset.is_multiple_of(4) {
+                return Err(library_error(
+                    NvmeErrorCode::FwLoadOffsetRange,
+                    SimOp::FirmwareLoad,
+                ));
+            }
+            let start = offset as usize;
+            let end = start + data.len();
+            if c.staged_firmware.len() < end {
+                c.staged_firmware.resize(end, 0);
+            }
+            c.staged_firmware[start..end].copy_from_slice(data);
+            Ok(())
+        })
+    }
+
+    /// The firmware revision of a committed image is taken from its first
+    /// eight bytes.
+    fn firmware_commit(
+        &self,
+        req: &FirmwareCommitRequest,
+    ) -> Result<(), NvmeControllerError> {
+        self.with_write(SimOp::FirmwareCommit, |c| {
+            let op = SimOp::FirmwareCommit;
+            if let Some(
+                action @ (FirmwareCommitAction::ReplaceBootPartition
+                | FirmwareCommitAction::ActivateBootPartition),
+            ) = req.action
+            {
+                return c.commit_boot_partition(action, req.bpid);
+            }
+            let (Some(slot), Some(action)) = (req.slot, req.action) else {
+                return Err(library_error(
+                    NvmeErrorCode::FwCommitReqMissingFields,
+                    op,
+                ));
+            };
+            let index = slot as usize;
+            if index == 0 || index > c.slots.len() {
+                return Err(device_error(
+                    NVME_CQE_SCT_SPECIFIC,
+                    NVME_CQE_SC_SPC_INV_FW_SLOT,
+                    op,
+                ));
+            }
+
+            let save = match action {
+                FirmwareCommitAction::Save
+                | FirmwareCommitAction::SaveActivate => true,
+                FirmwareCommitAction::Activate => false,
+                FirmwareCommitAction::ActivateImmediately => {
+                    return Err(library_error(
+                        NvmeErrorCode::FwCommitActionRange,
+                        op,
+                    ));
+                }
+                FirmwareCommitAction::ReplaceBootPartition
+                | FirmwareCommitAction::ActivateBootPartition => {
+                    unreachable!("boot partition actions are handled above")
+                }
+            };
+
+            if save {
+                if slot == 1 && c.slot1_read_only {
+                    return Err(library_error(NvmeErrorCode::FwSlotRo, op));
+                }
+                if c.staged_firmware.is_empty() {
+                    return Err(device_error(
+                        NVME_CQE_SCT_SPECIFIC,
+                        NVME_CQE_SC_SPC_INV_FW_IMG,
+                        op,
+                    ));
+                }
+                let image = std::mem::take(&mut c.staged_firmware);
+                let len = image.len().min(8);
+                let rev = String::from_utf8_lossy(&image[..len])
+                    .trim_end_matches(['\0', ' '])
+                    .to_string();
+                c.slots[index - 1] = Some(rev);
+            } else if c.slots[index - 1].is_none() {
+                return Err(device_error(
+                    NVME_CQE_SCT_SPECIFIC,
+                    NVME_CQE_SC_SPC_INV_FW_SLOT,
+                    op,
+                ));
+            }
+
+            if action != FirmwareCommitAction::Save {
+                c.next_active_slot = Some(slot as u8);
+            }
+            Ok(())
+        })
+    }
+
+    fn format(&self, req: &FormatRequest) -> Result<(), NvmeControllerError> {
+        self.with_write(SimOp::Format, |c| {
+            let op = SimOp::Format;
+            let (Some(lbaf), Some(nsid)) = (req.lbaf, req.nsid) else {
+                return Err(library_error(
+                    NvmeErrorCode::FormatReqMissingFields,
+                    op,
+                ));
+            };
+            if c.lba_format(lbaf).is_none() {
+                return Err(library_error(NvmeErrorCode::FormatLbafRange, op));
+            }
+            if req.ses.unwrap_or(0) > 2 {
+                return Err(library_error(NvmeErrorCode::FormatSesRange, op));
+            }
+            if nsid != NSID_ALL && !c.namespaces.contains_key(&nsid) {
+                return Err(library_error(NvmeErrorCode::NsRange, op));
+            }
+
+            let affected =
+                |ns: &SimNamespace| nsid == NSID_ALL || ns.nsid == nsid;
+            if c.namespaces.values().any(|ns| affected(ns) && ns.blkdev) {
+                return Err(library_error(NvmeErrorCode::NsBlkdevAttach, op));
+            }
+            for ns in c.namespaces.values_mut().filter(|ns| affected(ns)) {
+                ns.lbaf = lbaf;
+            }
+            Ok(())
+        })
+    }
+
+    fn vuc_commands(&self) -> Result<Vec<VucCommand>, NvmeControllerError> {
+        self.with(SimOp::Vuc, |c| Ok(c.vuc_commands.clone()))
+    }
+
+    /// The simulator does not implement any vendor unique commands.
+    fn vuc(
+        &self,
+        _req: &VucRequest,
+        _input: Option<&[u8]>,
+        _output: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        self.with_write(SimOp::Vuc, |_| {
+            Err(device_error(
+                NVME_CQE_SCT_GENERIC,
+                NVME_CQE_SC_GEN_INV_OPC,
+                SimOp::Vuc,
+            ))
+        })
+    }
+
+    fn wdc_resize_get(&self) -> Result<u32, NvmeControllerError> {
+        Err(library_error(NvmeErrorCode::VuFuncUnsupByDev, SimOp::Vuc))
+    }
+
+    fn wdc_resize_set(&self, _gb: u32) -> Result<(), NvmeControllerError> {
+        Err(library_error(NvmeErrorCode::VuFuncUnsupByDev, SimOp::Vuc))
+    }
+
+    fn wdc_e6_read(
+        &self,
+        _offset: u64,
+        _buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        Err(library_error(NvmeErrorCode::VuFuncUnsupByDev, SimOp::Vuc))
+    }
+
+    fn wdc_assert_clear(&self) -> Result<(), NvmeControllerError> {
+        Err(library_error(NvmeErrorCode::VuFuncUnsupByDev, SimOp::Vuc))
+    }
+
+    fn wdc_assert_inject(&self) -> Result<(), NvmeControllerError> {
+        Err(library_error(NvmeErrorCode::VuFuncUnsupByDev, SimOp::Vuc))
+    }
+}
+
+/// Copy `data` starting at `offset` into `buf`, zero filling anything past
+/// the end of `data`.
+fn copy_at(
+    data: &[u8],
+    offset: u64,
+    buf: &mut [u8],
+    op: SimOp,
+) -> Result<(), NvmeControllerError> {
+    let start = usize::try_from(offset)
+        .ok()
+        .filter(|&start| start <= data.len())
+        .ok_or_else(|| {
+            device_error(NVME_CQE_SCT_GENERIC, NVME_CQE_SC_GEN_INV_FLD, op)
+        })?;
+    let src = &data[start..];
+    let len = src.len().min(buf.len());
+    buf[..len].copy_from_slice(&src[..len]);
+    buf[len..].fill(0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        controller::{Controller, TryLockResult},
+        firmware::NvmeSlot,
+        namespace::NamespaceDiscoveryLevel,
+        nvmespec::smart::SmartLog,
+        Nvme,
+    };
+
+    fn sim() -> Sim {
+        Sim::new()
+            .with_controller(SimController::new(0))
+            .with_controller(SimController::new(1).with_serial("SECOND"))
+    }
+
+    #[test]
+    fn discovery_and_info() {
+        let nvme = Nvme::with_backend(sim());
+        let serials = nvme
+            .controller_discovery()
+            .unwrap()
+            .map(|c| c.unwrap().get_info().unwrap().serial().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(serials, ["SIM-0", "SECOND"]);
+
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let info = controller.get_info().unwrap();
+        assert_eq!(info.model(), "SIM NVMe");
+        assert_eq!(info.fwrev(), "SIM1.0");
+        assert_eq!(info.num_namespaces(), 1);
+        assert_eq!(info.pci_vid().unwrap(), 0x1b36);
+        assert_eq!(info.lba_formats().count(), 2);
+
+        let err = Controller::init_by_instance(&nvme, 7).err().unwrap();
+        assert_eq!(err.code(), NvmeErrorCode::BadController);
+    }
+
+    #[test]
+    fn format_requires_blkdev_detach() {
+        let sim = sim();
+        let nvme = Nvme::with_backend(sim.clone());
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+        let format =
+            |controller: &crate::controller::WriteLockedController<'_>| {
+                controller
+                    .format_request()?
+                    .set_lbaf(1)?
+                    .set_nsid(u32::MAX)?
+                    .set_ses(0)?
+                    .execute()
+            };
+
+        let err = format(&controller).unwrap_err();
+        assert_eq!(err.code(), NvmeErrorCode::NsBlkdevAttach);
+
+        let namespaces = controller
+            .namespace_discovery(NamespaceDiscoveryLevel::Active)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        namespaces.iter().for_each(|ns| ns.blkdev_detach().unwrap());
+        format(&controller).unwrap();
+        namespaces.iter().for_each(|ns| ns.blkdev_attach().unwrap());
+
+        let lbaf = namespaces[0].get_info().unwrap().current_format().unwrap();
+        assert_eq!(lbaf.data_size(), 4096);
+        assert_eq!(
+            sim.controller(0, |c| *c.namespace(1).unwrap()),
+            Some(SimNamespace { lbaf: 1, ..SimNamespace::new(1) })
+        );
+    }
+
+    #[test]
+    fn firmware_update() {
+        let sim = sim();
+        let nvme = Nvme::with_backend(sim.clone());
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        let mut image = b"SIM2.0  ".to_vec();
+        image.resize(128 * 1024, 0xff);
+        controller.firmware_load(&image).unwrap();
+        controller
+            .firmware_commit_request()
+            .unwrap()
+            .set_slot(NvmeSlot::try_from(2).unwrap())
+            .unwrap()
+            .set_action(FirmwareCommitAction::SaveActivate)
+            .unwrap()
+            .execute()
+            .unwrap();
+
+        let log = controller.get_firmware_log_page().unwrap();
+        assert_eq!(log.active_slot, 1);
+        assert_eq!(log.next_active_slot, Some(2));
+        assert_eq!(log.number_of_slots, 2);
+        assert_eq!(
+            log.slot_iter().collect::<Vec<_>>(),
+            [Some("SIM1.0"), Some("SIM2.0")]
+        );
+
+        sim.controller(0, SimController::reset);
+        assert_eq!(controller.get_info().unwrap().fwrev(), "SIM2.0");
+    }
+
+    #[test]
+    fn write_operations_need_write_lock() {
+        let nvme = Nvme::with_backend(sim());
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .read_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+        let ns = controller
+            .namespace_discovery(NamespaceDiscoveryLevel::All)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let err = ns.blkdev_detach().unwrap_err();
+        assert_eq!(err.code(), NvmeErrorCode::NeedCtrlWrlock);
+    }
+
+    #[test]
+    fn setters_check_ranges() {
+        let nvme = Nvme::with_backend(sim());
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        fn code<T>(r: Result<T, NvmeControllerError>) -> Option<NvmeErrorCode> {
+            r.err().map(|e| e.code())
+        }
+        let log = || controller.log_request().unwrap();
+        assert_eq!(
+            code(log().set_lid(0x100)),
+            Some(NvmeErrorCode::LogLidRange)
+        );
+        assert_eq!(code(log().set_lsp(0x80)), Some(NvmeErrorCode::LogLspRange));
+        assert_eq!(
+            code(log().set_lsi(0x1_0000)),
+            Some(NvmeErrorCode::LogLsiRange)
+        );
+        assert!(log().set_lid(0xff).and_then(|r| r.set_lsi(0xffff)).is_ok());
+
+        let feature = || controller.get_feature_request().unwrap();
+        assert_eq!(
+            code(feature().set_fid(0x100)),
+            Some(NvmeErrorCode::FeatFidRange)
+        );
+
+        let format = || controller.format_request().unwrap();
+        assert_eq!(
+            code(format().set_lbaf(64)),
+            Some(NvmeErrorCode::FormatLbafRange)
+        );
+        assert_eq!(
+            code(format().set_ses(3)),
+            Some(NvmeErrorCode::FormatSesRange)
+        );
+        assert_eq!(code(format().set_nsid(0)), Some(NvmeErrorCode::NsRange));
+        assert!(format().set_nsid(u32::MAX).is_ok());
+
+        let vuc = || controller.vuc_request().unwrap();
+        assert_eq!(
+            code(vuc().set_opcode(0xbf)),
+            Some(NvmeErrorCode::VucOpcodeRange)
+        );
+        assert_eq!(
+            code(vuc().set_timeout(0)),
+            Some(NvmeErrorCode::VucTimeoutRange)
+        );
+        assert!(vuc().set_opcode(0xc0).is_ok());
+    }
+
+    #[test]
+    fn lock_contention() {
+        let nvme = Nvme::with_backend(sim());
+        let first = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .read_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        let second = Controller::init_by_instance(&nvme, 0).unwrap();
+        let second = match second.try_write_lock() {
+            TryLockResult::Locked(c) => c,
+            _ => panic!("write lock should be contended"),
+        };
+        let second = match second.try_read_lock() {
+            TryLockResult::Ok(c) => c.unlock(),
+            _ => panic!("read locks should be shared"),
+        };
+
+        drop(first);
+        assert!(matches!(second.try_write_lock(), TryLockResult::Ok(_)));
+    }
+
+    #[test]
+    fn injected_errors() {
+        let sim = Sim::new().with_controller(
+            SimController::new(0)
+                .with_error(
+                    SimOp::GetLog,
+                    SimError::Library(NvmeErrorCode::LogUnsupByDev),
+                )
+                .with_error(
+                    SimOp::GetLog,
+                    SimError::Device {
+                        sct: NVME_CQE_SCT_GENERIC,
+                        sc: NVME_CQE_SC_GEN_INV_FLD,
+                    },
+                ),
+        );
+        let nvme = Nvme::with_backend(sim);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+
+        let err = controller.smart_log().unwrap_err();
+        assert!(matches!(
+            err,
+            crate::logpage::LogPageError::ControllerError(e)
+                if e.code() == NvmeErrorCode::LogUnsupByDev
+        ));
+
+        let mut buf = [0u8; SMART_LEN];
+        let req = controller.log_request().unwrap().set_lid(SMART_LID).unwrap();
+        let err = req.execute(&mut buf).unwrap_err();
+        assert_eq!(err.code(), NvmeErrorCode::Controller);
+        assert_eq!(err.device_status_code_type(), NVME_CQE_SCT_GENERIC);
+        assert_eq!(err.device_status_code(), NVME_CQE_SC_GEN_INV_FLD);
+
+        // Injected errors are only returned once.
+        let smart = controller.smart_log().unwrap();
+        assert_eq!(smart, SmartLog::decode(&sim_smart_log()).unwrap(),);
+
+        let err = controller.log_request().unwrap().execute(&mut buf);
+        assert_eq!(err.unwrap_err().code(), NvmeErrorCode::LogReqMissingFields);
+    }
+
+    fn sim_smart_log() -> Vec<u8> {
+        let mut smart = vec![0u8; SMART_LEN];
+        smart[1..3].copy_from_slice(&300u16.to_le_bytes());
+        smart[3] = 100;
+        smart[4] = 10;
+        smart
+    }
+}
@@ -0,0 +1,18 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::Controller,
+    logpage::LogPageError,
+    nvmespec::smart::{SmartLog, SMART_LEN, SMART_LID},
+};
+
+impl<'a> Controller<'a> {
+    /// Get the controller wide SMART / Health Information log.
+    pub fn smart_log(&self) -> Result<SmartLog, LogPageError> {
+        let mut buf = vec![0u8; SMART_LEN];
+        self.log_request()?.set_lid(SMART_LID)?.execute(&mut buf)?;
+        Ok(SmartLog::decode(&buf)?)
+    }
+}
@@ -0,0 +1,431 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Owned copies of controller, namespace and firmware slot information.
+//!
+//! Unlike [`ControllerInfo`] and the other types they are taken from, these
+//! hold only plain data, so they can be kept, compared and sent anywhere.
+//! With the `serde` feature they can also be serialized; the names of their
+//! fields are part of this crate's API and are not changed between releases.
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    controller_info::ControllerInfo,
+    firmware::FirmwareLogPage,
+    lba::LbaFormat,
+    monitor::HealthSnapshot,
+    namespace::{Namespace, NamespaceDiscoveryLevel, NamespaceInfo},
+    nvmespec::ocp::OcpSmartLog,
+};
+
+/// Information about a controller, mostly from its Identify Controller data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControllerSnapshot {
+    pub model: String,
+    pub serial: String,
+    pub fwrev: String,
+    /// The number of namespaces the controller supports.
+    pub nns: u32,
+    /// The PCI vendor ID, if the controller is attached over PCI.
+    pub pci_vid: Option<u16>,
+    pub identify: IdentifySnapshot,
+    /// The LBA formats that libnvme could make sense of.
+    pub lba_formats: Vec<LbaFormat>,
+}
+
+/// Selected fields of the Identify Controller data structure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IdentifySnapshot {
+    /// The PCI vendor ID reported by the controller itself.
+    pub vid: u16,
+    /// PCI Subsystem Vendor ID.
+    pub ssvid: u16,
+    /// IEEE OUI of the vendor, most significant byte first.
+    pub oui: [u8; 3],
+    /// Controller ID.
+    pub cntlid: u16,
+    /// The NVMe version the controller implements, such as "1.4.0", or
+    /// `None` for controllers from before the field existed.
+    pub version: Option<String>,
+    /// Maximum Data Transfer Size, as a power of two of the minimum memory
+    /// page size; 0 if there is no limit.
+    pub mdts: u8,
+    /// The number of firmware slots.
+    pub firmware_slots: u8,
+    pub firmware_slot1_read_only: bool,
+    /// Warning and critical composite temperature thresholds in Kelvin, 0 if
+    /// not reported.
+    pub wctemp: u16,
+    pub cctemp: u16,
+    /// Total and unallocated NVM capacity in bytes.
+    pub tnvmcap: u128,
+    pub unvmcap: u128,
+    /// NVM Subsystem NVMe Qualified Name, empty if not reported.
+    pub subnqn: String,
+}
+
+/// Information about a namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NamespaceSnapshot {
+    pub nsid: u32,
+    /// The most specific discovery level the namespace was found at.
+    pub level: NamespaceDiscoveryLevel,
+    /// Whether blkdev is attached to the namespace.
+    pub blkdev: bool,
+    /// The LBA format the namespace is formatted with, if it is active and
+    /// the format is one libnvme understands.
+    pub format: Option<LbaFormat>,
+    /// The size, capacity and allocated space of the namespace in bytes, if
+    /// it is active.
+    pub size: Option<u64>,
+    pub capacity: Option<u64>,
+    pub used: Option<u64>,
+}
+
+/// The most commonly watched parts of a controller's SMART / Health
+/// Information log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthSummary {
+    pub critical_warning: u8,
+    /// Composite temperature in Kelvin.
+    pub temperature: u16,
+    /// Warning and critical composite temperature thresholds in Kelvin, 0 if
+    /// not reported.
+    pub warning_temperature: u16,
+    pub critical_temperature: u16,
+    pub available_spare: u8,
+    pub available_spare_threshold: u8,
+    pub percentage_used: u8,
+    /// Data read and written in thousands of 512 byte units.
+    pub data_units_read: u128,
+    pub data_units_written: u128,
+    pub host_read_commands: u128,
+    pub host_write_commands: u128,
+    pub power_cycles: u128,
+    pub power_on_hours: u128,
+    pub unsafe_shutdowns: u128,
+    pub media_errors: u128,
+    pub error_log_entries: u128,
+}
+
+/// The most commonly watched parts of the OCP SMART / Health Information
+/// Extended log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OcpSmartSummary {
+    /// Bytes written to and read from the media, including internal
+    /// transfers.
+    pub physical_media_units_written: u128,
+    pub physical_media_units_read: u128,
+    pub bad_user_nand_blocks: u64,
+    pub bad_system_nand_blocks: u64,
+    pub xor_recovery_count: u64,
+    pub uncorrectable_read_error_count: u64,
+    pub soft_ecc_error_count: u64,
+    pub e2e_detected_errors: u32,
+    pub e2e_corrected_errors: u32,
+    pub system_data_percent_used: u8,
+    pub thermal_throttling_events: u8,
+    pub pcie_correctable_error_count: u64,
+    pub incomplete_shutdowns: u32,
+    pub percent_free_blocks: u8,
+    /// Percentage of the original charge the power loss protection
+    /// capacitors can hold.
+    pub capacitor_health: u16,
+}
+
+/// The firmware slots of a controller and which of them is active.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirmwareSlotsSnapshot {
+    pub active_slot: u8,
+    /// The slot that will be active after the next reset, if the controller
+    /// says.
+    pub next_active_slot: Option<u8>,
+    pub slot1_read_only: bool,
+    pub slots: Vec<FirmwareSlotSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirmwareSlotSnapshot {
+    /// The slot number, starting from 1.
+    pub slot: u8,
+    /// The firmware revision in the slot, if any.
+    pub version: Option<String>,
+}
+
+/// A string field that is nul terminated if it is shorter than the field.
+fn nul_terminated(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).trim_end().to_string()
+}
+
+impl From<&ControllerInfo> for ControllerSnapshot {
+    fn from(info: &ControllerInfo) -> Self {
+        let id = info.get_controller_info_identify().data();
+
+        let version = match id.id_ver {
+            0 => None,
+            ver => Some(format!(
+                "{}.{}.{}",
+                ver >> 16,
+                (ver >> 8) & 0xff,
+                ver & 0xff
+            )),
+        };
+        let u128 = |v: libnvme_sys::identify::nvme_uint128_t| {
+            (u128::from(v.hi) << 64) | u128::from(v.lo)
+        };
+
+        Self {
+            model: info.model().into_owned(),
+            serial: info.serial().into_owned(),
+            fwrev: info.fwrev().into_owned(),
+            nns: info.num_namespaces(),
+            pci_vid: info.pci_vid().ok(),
+            identify: IdentifySnapshot {
+                vid: id.id_vid,
+                ssvid: id.id_ssvid,
+                // The OUI is stored least significant byte first.
+                oui: [id.id_oui[2], id.id_oui[1], id.id_oui[0]],
+                cntlid: id.id_cntlid,
+                version,
+                mdts: id.id_mdts,
+                firmware_slots: id.id_frmw.fw_nslot(),
+                firmware_slot1_read_only: id.id_frmw.fw_readonly(),
+                wctemp: id.ap_wctemp,
+                cctemp: id.ap_cctemp,
+                tnvmcap: u128(id.ap_tnvmcap),
+                unvmcap: u128(id.ap_unvmcap),
+                subnqn: nul_terminated(&id.id_subnqn),
+            },
+            lba_formats: info.lba_formats().filter_map(Result::ok).collect(),
+        }
+    }
+}
+
+impl NamespaceSnapshot {
+    pub fn new(nsid: u32, info: &NamespaceInfo) -> Self {
+        let size = info.size().ok();
+        Self {
+            nsid,
+            level: info.level(),
+            blkdev: info.level() == NamespaceDiscoveryLevel::BlkDev,
+            format: info.current_format().ok(),
+            size: size.map(|s| s.size),
+            capacity: size.map(|s| s.capacity),
+            used: size.map(|s| s.used),
+        }
+    }
+}
+
+impl From<&HealthSnapshot> for HealthSummary {
+    fn from(health: &HealthSnapshot) -> Self {
+        let smart = &health.smart;
+        Self {
+            critical_warning: smart.critical_warning,
+            temperature: smart.composite_temperature,
+            warning_temperature: health.warning_temperature,
+            critical_temperature: health.critical_temperature,
+            available_spare: smart.available_spare,
+            available_spare_threshold: smart.available_spare_threshold,
+            percentage_used: smart.percentage_used,
+            data_units_read: smart.data_units_read,
+            data_units_written: smart.data_units_written,
+            host_read_commands: smart.host_read_commands,
+            host_write_commands: smart.host_write_commands,
+            power_cycles: smart.power_cycles,
+            power_on_hours: smart.power_on_hours,
+            unsafe_shutdowns: smart.unsafe_shutdowns,
+            media_errors: smart.media_errors,
+            error_log_entries: smart.error_log_entries,
+        }
+    }
+}
+
+impl From<&OcpSmartLog> for OcpSmartSummary {
+    fn from(log: &OcpSmartLog) -> Self {
+        Self {
+            physical_media_units_written: log.physical_media_units_written,
+            physical_media_units_read: log.physical_media_units_read,
+            bad_user_nand_blocks: log.bad_user_nand_blocks.raw,
+            bad_system_nand_blocks: log.bad_system_nand_blocks.raw,
+            xor_recovery_count: log.xor_recovery_count,
+            uncorrectable_read_error_count: log.uncorrectable_read_error_count,
+            soft_ecc_error_count: log.soft_ecc_error_count,
+            e2e_detected_errors: log.e2e_detected_errors,
+            e2e_corrected_errors: log.e2e_corrected_errors,
+            system_data_percent_used: log.system_data_percent_used,
+            thermal_throttling_events: log.thermal_throttling_events,
+            pcie_correctable_error_count: log.pcie_correctable_error_count,
+            incomplete_shutdowns: log.incomplete_shutdowns,
+            percent_free_blocks: log.percent_free_blocks,
+            capacitor_health: log.capacitor_health,
+        }
+    }
+}
+
+impl From<&FirmwareLogPage> for FirmwareSlotsSnapshot {
+    fn from(log: &FirmwareLogPage) -> Self {
+        Self {
+            active_slot: log.active_slot,
+            next_active_slot: log.next_active_slot,
+            slot1_read_only: log.slot1_is_read_only,
+            slots: (1..)
+                .zip(log.slot_iter())
+                .map(|(slot, version)| FirmwareSlotSnapshot {
+                    slot,
+                    version: version.map(str::to_string),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a> Controller<'a> {
+    pub fn snapshot(&self) -> Result<ControllerSnapshot, NvmeControllerError> {
+        Ok(ControllerSnapshot::from(&self.get_info()?))
+    }
+}
+
+impl<'a> Namespace<'a> {
+    pub fn snapshot(&self) -> Result<NamespaceSnapshot, NvmeControllerError> {
+        Ok(NamespaceSnapshot::new(self.nsid(), &self.get_info()?))
+    }
+}
+
+#[cfg(all(test, feature = "serde", feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    fn round_trip<T>(value: &T) -> serde_json::Value
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq,
+        T: std::fmt::Debug,
+    {
+        let json = serde_json::to_value(value).unwrap();
+        let back: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(&back, value);
+        json
+    }
+
+    fn keys(value: &serde_json::Value) -> Vec<&str> {
+        let mut keys = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let sim = Sim::new().with_controller(
+            SimController::new(0)
+                .with_model("Snapshot NVMe")
+                .with_firmware_slots(
+                    vec![Some("A1"), None, Some("B2")],
+                    3,
+                    true,
+                )
+                .with_identify(|id| {
+                    id.id_ver = 0x0001_0400;
+                    id.id_oui = [0x56, 0x34, 0x12];
+                    id.ap_tnvmcap.lo = 1 << 40;
+                    id.id_subnqn[..9].copy_from_slice(b"nqn.2014.");
+                }),
+        );
+        let nvme = Nvme::with_backend(sim);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+
+        let ctrl = controller.snapshot().unwrap();
+        assert_eq!(ctrl.model, "Snapshot NVMe");
+        assert_eq!(ctrl.pci_vid, Some(0x1b36));
+        assert_eq!(ctrl.identify.version.as_deref(), Some("1.4.0"));
+        assert_eq!(ctrl.identify.oui, [0x12, 0x34, 0x56]);
+        assert_eq!(ctrl.identify.tnvmcap, 1 << 40);
+        assert_eq!(ctrl.identify.subnqn, "nqn.2014.");
+        assert_eq!(ctrl.lba_formats.len(), 2);
+        let json = round_trip(&ctrl);
+        assert_eq!(
+            keys(&json),
+            [
+                "fwrev",
+                "identify",
+                "lba_formats",
+                "model",
+                "nns",
+                "pci_vid",
+                "serial"
+            ]
+        );
+        assert_eq!(
+            keys(&json["identify"]),
+            [
+                "cctemp",
+                "cntlid",
+                "firmware_slot1_read_only",
+                "firmware_slots",
+                "mdts",
+                "oui",
+                "ssvid",
+                "subnqn",
+                "tnvmcap",
+                "unvmcap",
+                "version",
+                "vid",
+                "wctemp"
+            ]
+        );
+        assert_eq!(
+            keys(&json["lba_formats"][1]),
+            ["data_size", "id", "meta_size", "rel_perf"]
+        );
+
+        let namespaces = controller
+            .namespace_discovery(NamespaceDiscoveryLevel::Active)
+            .unwrap()
+            .map(|ns| ns.unwrap().snapshot().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(namespaces.len(), 1);
+        assert_eq!(namespaces[0].format.unwrap().data_size(), 512);
+        assert_eq!(namespaces[0].level, NamespaceDiscoveryLevel::BlkDev);
+        assert_eq!(namespaces[0].used, Some(0x8_0000 * 512));
+        assert_eq!(
+            keys(&round_trip(&namespaces[0])),
+            ["blkdev", "capacity", "format", "level", "nsid", "size", "used"]
+        );
+
+        let slots = FirmwareSlotsSnapshot::from(
+            &controller.get_firmware_log_page().unwrap(),
+        );
+        assert_eq!(slots.active_slot, 3);
+        assert!(slots.slot1_read_only);
+        assert_eq!(
+            slots.slots,
+            [
+                FirmwareSlotSnapshot { slot: 1, version: Some("A1".into()) },
+                FirmwareSlotSnapshot { slot: 2, version: None },
+                FirmwareSlotSnapshot { slot: 3, version: Some("B2".into()) },
+            ]
+        );
+        let json = round_trip(&slots);
+        assert_eq!(
+            keys(&json),
+            ["active_slot", "next_active_slot", "slot1_read_only", "slots"]
+        );
+        assert_eq!(keys(&json["slots"][0]), ["slot", "version"]);
+    }
+}
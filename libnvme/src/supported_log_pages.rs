@@ -0,0 +1,78 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    logpage::LogPageError,
+    nvmespec::{
+        supported_log_pages::{
+            SupportedLogPages, SUPPORTED_LOG_PAGES_LEN, SUPPORTED_LOG_PAGES_LID,
+        },
+        NVME_CQE_SCT_GENERIC, NVME_CQE_SCT_SPECIFIC, NVME_CQE_SC_GEN_INV_FLD,
+        NVME_CQE_SC_SPC_INV_LOG_PAGE,
+    },
+    NvmeErrorCode,
+};
+
+/// Whether the controller rejected a log page request because it does not
+/// know the log page.
+fn is_unsupported_log_page(e: &NvmeControllerError) -> bool {
+    e.code() == NvmeErrorCode::Controller
+        && matches!(
+            (e.device_status_code_type(), e.device_status_code()),
+            (NVME_CQE_SCT_GENERIC, NVME_CQE_SC_GEN_INV_FLD)
+                | (NVME_CQE_SCT_SPECIFIC, NVME_CQE_SC_SPC_INV_LOG_PAGE)
+        )
+}
+
+impl<'a> Controller<'a> {
+    /// Get the log pages the controller supports.
+    ///
+    /// Controllers that predate the Supported Log Pages log have their
+    /// supported log pages inferred from Identify Controller instead, which
+    /// `SupportedLogPages::is_inferred` reports.
+    pub fn supported_log_pages(
+        &self,
+    ) -> Result<SupportedLogPages, LogPageError> {
+        let mut buf = vec![0u8; SUPPORTED_LOG_PAGES_LEN];
+        match self
+            .log_request()?
+            .set_lid(SUPPORTED_LOG_PAGES_LID)?
+            .execute(&mut buf)
+        {
+            Ok(()) => Ok(SupportedLogPages::decode(&buf)?),
+            Err(e) if is_unsupported_log_page(&e) => {
+                Ok(self.infer_supported_log_pages()?)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn infer_supported_log_pages(
+        &self,
+    ) -> Result<SupportedLogPages, NvmeControllerError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+
+        // The Error Information, SMART / Health Information and Firmware
+        // Slot Information log pages are mandatory.
+        let mut lids = vec![0x01, 0x02, 0x03];
+        for (supported, pages) in [
+            (id.id_oaes.oaes_nsan(), &[0x04][..]),
+            (id.id_lpa.lp_cmdeff(), &[0x05]),
+            (id.id_oacs.oa_selftest(), &[0x06]),
+            (id.id_lpa.lp_telemetry(), &[0x07, 0x08]),
+            (id.id_ctratt.ctrat_engrp(), &[0x09]),
+            (id.id_ctratt.ctrat_plm(), &[0x0a, 0x0b]),
+            (id.id_mic.m_anar_sup(), &[0x0c]),
+            (id.id_lpa.lp_persist(), &[0x0d]),
+        ] {
+            if supported != 0 {
+                lids.extend_from_slice(pages);
+            }
+        }
+
+        Ok(SupportedLogPages::infer(lids, id.id_lpa.lp_extsup() != 0))
+    }
+}
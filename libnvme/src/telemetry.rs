@@ -0,0 +1,255 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::Write;
+
+use thiserror::Error;
+
+use crate::{
+    controller::{Controller, NvmeControllerError},
+    logpage::LogRequestBuilder,
+    nvmespec::{
+        telemetry::{
+            DataArea, TelemetryHeader, TELEMETRY_CONTROLLER_LID,
+            TELEMETRY_HDR_LEN, TELEMETRY_HOST_LID, TELEMETRY_LSP_CREATE,
+        },
+        DecodeError,
+    },
+};
+
+/// The minimum memory page size, which MDTS is reported in units of. libnvme
+/// does not expose CAP.MPSMIN so assume the 4 KiB used by every device we
+/// have seen.
+const MIN_PAGE_SIZE: usize = 4096;
+/// Upper bound on the size of each read, used when the controller does not
+/// limit the transfer size.
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum TelemetryError {
+    #[error("libnvme error: {0}")]
+    ControllerError(#[from] NvmeControllerError),
+    #[error("failed to decode telemetry header: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("failed to write telemetry data: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("NVMe device does not support telemetry log pages")]
+    Unsupported,
+    #[error("controller has no controller-initiated telemetry data available")]
+    NoControllerData,
+    #[error("telemetry data area {0:?} is not available")]
+    AreaUnavailable(DataArea),
+    #[error(
+        "capturing {len} bytes of telemetry needs more than one read, but \
+         the controller does not support log page offsets"
+    )]
+    OffsetUnsupported { len: u64 },
+    #[error(
+        "telemetry generation changed from {before} to {after} during capture"
+    )]
+    GenerationChanged { before: u8, after: u8 },
+}
+
+/// Which telemetry log page to capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryKind {
+    /// Telemetry Host-Initiated data. When `create` is set the controller
+    /// captures new data rather than returning what it captured last.
+    HostInitiated { create: bool },
+    /// Telemetry Controller-Initiated data, which the controller captures on
+    /// its own, typically in response to an internal error.
+    ControllerInitiated,
+}
+
+impl TelemetryKind {
+    fn lid(&self) -> u32 {
+        match self {
+            TelemetryKind::HostInitiated { .. } => TELEMETRY_HOST_LID,
+            TelemetryKind::ControllerInitiated => TELEMETRY_CONTROLLER_LID,
+        }
+    }
+
+    fn generation(&self, hdr: &TelemetryHeader) -> u8 {
+        match self {
+            TelemetryKind::HostInitiated { .. } => hdr.host_generation,
+            TelemetryKind::ControllerInitiated => hdr.controller_generation,
+        }
+    }
+}
+
+impl<'a> Controller<'a> {
+    fn read_telemetry_header(
+        &self,
+        req: &LogRequestBuilder<'_>,
+    ) -> Result<TelemetryHeader, TelemetryError> {
+        let mut hdr = [0u8; TELEMETRY_HDR_LEN];
+        req.execute(&mut hdr)?;
+        Ok(TelemetryHeader::decode(&hdr)?)
+    }
+
+    /// Capture telemetry data up to and including `area` into `writer`,
+    /// returning the number of bytes written.
+    ///
+    /// If the controller reports no data up to `area`, as it does for Data
+    /// Area 4 unless the host has enabled it, the capture fails with
+    /// `TelemetryError::AreaUnavailable` before anything is written.
+    ///
+    /// The output is the log page exactly as returned by the controller,
+    /// starting with the header, which is the format vendors expect. If the
+    /// controller captures new data while the log page is being read the
+    /// capture fails with `TelemetryError::GenerationChanged` and the
+    /// output should be discarded.
+    pub fn capture_telemetry<W: Write>(
+        &self,
+        kind: TelemetryKind,
+        area: DataArea,
+        mut writer: W,
+    ) -> Result<u64, TelemetryError> {
+        let info = self.get_info()?;
+        let id = info.get_controller_info_identify().data();
+        let (lpa, mdts) = (id.id_lpa, id.id_mdts);
+        if lpa.lp_telemetry() == 0 {
+            return Err(TelemetryError::Unsupported);
+        }
+        let chunk_size = match mdts {
+            0 => MAX_CHUNK_SIZE,
+            mdts => MIN_PAGE_SIZE
+                .checked_shl(u32::from(mdts))
+                .map_or(MAX_CHUNK_SIZE, |size| size.min(MAX_CHUNK_SIZE)),
+        };
+
+        // Creating new host-initiated data is only requested when reading
+        // the header, otherwise every chunk would capture new data.
+        let hdr = if let TelemetryKind::HostInitiated { create: true } = kind {
+            let req = self
+                .log_request()?
+                .set_lid(kind.lid())?
+                .set_lsp(TELEMETRY_LSP_CREATE)?;
+            self.read_telemetry_header(&req)?
+        } else {
+            let req = self.log_request()?.set_lid(kind.lid())?;
+            self.read_telemetry_header(&req)?
+        };
+        if kind == TelemetryKind::ControllerInitiated
+            && !hdr.controller_data_available
+        {
+            return Err(TelemetryError::NoControllerData);
+        }
+
+        let len = hdr
+            .capture_len(area)
+            .ok_or(TelemetryError::AreaUnavailable(area))?;
+        // Reading past the first chunk needs a log page offset.
+        if len > chunk_size as u64 && lpa.lp_extsup() == 0 {
+            return Err(TelemetryError::OffsetUnsupported { len });
+        }
+
+        let req = self.log_request()?.set_lid(kind.lid())?;
+        let mut buf = vec![0u8; chunk_size];
+        let mut offset = 0;
+        while offset < len {
+            let size = (len - offset).min(chunk_size as u64) as usize;
+            let chunk = &mut buf[..size];
+            req.read_at(offset, chunk)?;
+            writer.write_all(chunk)?;
+            offset += size as u64;
+        }
+        writer.flush()?;
+
+        // Use a new request to re-read the header as `req` is left at the
+        // offset of the last chunk.
+        let req = self.log_request()?.set_lid(kind.lid())?;
+        let before = kind.generation(&hdr);
+        let after = kind.generation(&self.read_telemetry_header(&req)?);
+        if before != after {
+            return Err(TelemetryError::GenerationChanged { before, after });
+        }
+
+        Ok(offset)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use libnvme_sys::identify::IdLpa;
+
+    use super::*;
+    use crate::{
+        nvmespec::telemetry::TELEMETRY_BLOCK_SIZE,
+        sim::{Sim, SimController},
+        Nvme,
+    };
+
+    /// A host-initiated telemetry log with data areas 1 to 3 ending at the
+    /// given blocks and no Data Area 4.
+    fn telemetry_log(last_blocks: [u16; 3]) -> Vec<u8> {
+        let len = (u64::from(last_blocks[2]) + 1) * TELEMETRY_BLOCK_SIZE;
+        let mut log = vec![0xa5u8; len as usize];
+        log[..TELEMETRY_HDR_LEN].fill(0);
+        log[0] = TELEMETRY_HOST_LID as u8;
+        for (i, last) in last_blocks.iter().enumerate() {
+            log[8 + 2 * i..10 + 2 * i].copy_from_slice(&last.to_le_bytes());
+        }
+        log
+    }
+
+    fn controller(lpa: u8, mdts: u8, log: Vec<u8>) -> Nvme {
+        Nvme::with_backend(
+            Sim::new().with_controller(
+                SimController::new(0)
+                    .with_identify(|id| {
+                        id.id_lpa = IdLpa::from_bits(lpa);
+                        id.id_mdts = mdts;
+                    })
+                    .with_log_page(TELEMETRY_HOST_LID, log),
+            ),
+        )
+    }
+
+    const TELEMETRY: u8 = 1 << 3;
+    const EXTENDED: u8 = 1 << 2;
+
+    #[test]
+    fn capture_areas() {
+        let log = telemetry_log([3, 3, 20]);
+        let nvme = controller(TELEMETRY | EXTENDED, 1, log.clone());
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let kind = TelemetryKind::HostInitiated { create: false };
+
+        let mut out = Vec::new();
+        let len = controller
+            .capture_telemetry(kind, DataArea::Three, &mut out)
+            .unwrap();
+        assert_eq!(len, 21 * TELEMETRY_BLOCK_SIZE);
+        assert_eq!(out, log);
+
+        let mut out = Vec::new();
+        let err = controller
+            .capture_telemetry(kind, DataArea::Four, &mut out)
+            .unwrap_err();
+        assert!(matches!(err, TelemetryError::AreaUnavailable(DataArea::Four)));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn offsets_need_extended_log_pages() {
+        let log = telemetry_log([3, 3, 20]);
+        let nvme = controller(TELEMETRY, 1, log);
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        let kind = TelemetryKind::HostInitiated { create: false };
+
+        // Areas 1 and 2 fit in a single 8 KiB read, area 3 does not.
+        let mut out = Vec::new();
+        controller.capture_telemetry(kind, DataArea::Two, &mut out).unwrap();
+        assert_eq!(out.len(), 4 * TELEMETRY_BLOCK_SIZE as usize);
+
+        let err = controller
+            .capture_telemetry(kind, DataArea::Three, Vec::new())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TelemetryError::OffsetUnsupported { len } if len == 21 * 512
+        ));
+    }
+}
@@ -0,0 +1,1055 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recording and replaying the calls made to a backend.
+//!
+//! A [`Recorder`] wraps another backend, normally the illumos libnvme, and
+//! notes every call made through it along with what it returned: identify
+//! data, log page contents, lock results, libnvme error codes and device
+//! status. The resulting [`Trace`] is serialized as versioned JSON so that
+//! captures from real drives can be checked in.
+//!
+//! A [`Replay`] serves a trace back. Each call made against a replayed
+//! controller is answered by the first call recorded against that
+//! controller with identical arguments that has not yet been replayed, so
+//! calls may be replayed in a different order to the one they were recorded
+//! in but each recorded result is only returned once. A call that cannot be
+//! answered from the trace fails with `NvmeErrorCode::Internal`.
+//!
+//! Data written to a controller is recorded by length only; firmware images
+//! in particular are not included in a trace.
+
+use std::{
+    fmt,
+    mem::size_of,
+    rc::Rc,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use libnvme_sys::nvme::nvme_identify_ctrl_t;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::{
+    backend::{
+        Backend, ControllerBackend, ControllerIter, FirmwareCommitRequest,
+        FormatRequest, GetFeatureRequest, LockLevel, LogRequest, VucRequest,
+    },
+    controller::{ControllerLocator, NvmeControllerError},
+    controller_info::{ControllerInfo, NvmeInfoError},
+    lba::LbaFormat,
+    logpage::LogPageName,
+    namespace::{NamespaceDiscoveryLevel, NamespaceInfo},
+    vuc::VucCommand,
+    NvmeError, NvmeErrorCode, NvmeInitError,
+};
+
+/// The version of the trace format written by this crate. Traces with any
+/// other version are rejected.
+///
+/// This must be bumped by any change to the serialized format once a release
+/// has shipped with the current one; `testdata/sim-trace.json` catches changes
+/// made by accident.
+pub const TRACE_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("failed to parse trace: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported trace version {found} (expected {TRACE_VERSION})")]
+    Version { found: u32 },
+}
+
+/// Bytes of data, serialized as a hex string.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bytes({})", self.0.len())
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let hex: String = self.0.iter().map(|b| format!("{b:02x}")).collect();
+        s.serialize_str(&hex)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(d)?;
+        if !hex.len().is_multiple_of(2) {
+            return Err(serde::de::Error::custom("odd length hex string"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map(Bytes)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A recorded `ControllerInfo`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceInfo {
+    /// The Identify Controller data structure.
+    pub identify: Bytes,
+    pub pci_vid: Result<u16, NvmeInfoError>,
+    pub lba_formats: Vec<Result<LbaFormat, NvmeInfoError>>,
+}
+
+impl From<&ControllerInfo> for TraceInfo {
+    fn from(info: &ControllerInfo) -> Self {
+        let identify = info.get_controller_info_identify();
+        Self {
+            identify: Bytes(identify.bytes().to_vec()),
+            pci_vid: info.pci_vid(),
+            lba_formats: info.lba_formats().collect(),
+        }
+    }
+}
+
+impl TraceInfo {
+    fn to_info(&self) -> Option<ControllerInfo> {
+        if self.identify.0.len() != size_of::<nvme_identify_ctrl_t>() {
+            return None;
+        }
+        let identify = unsafe {
+            std::ptr::read_unaligned(
+                self.identify.0.as_ptr().cast::<nvme_identify_ctrl_t>(),
+            )
+        };
+        Some(ControllerInfo::new(
+            identify,
+            self.pci_vid.clone(),
+            self.lba_formats.clone(),
+        ))
+    }
+}
+
+/// A call made against an open controller. Output buffers are recorded by
+/// their length.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Call {
+    Info,
+    Instance,
+    DevinfoPath,
+    Lock { level: LockLevel, block: bool },
+    Namespaces { level: NamespaceDiscoveryLevel },
+    NamespaceInfo { nsid: u32 },
+    BlkdevAttach { nsid: u32 },
+    BlkdevDetach { nsid: u32 },
+    NamedLog { name: LogPageName },
+    GetLog { req: LogRequest, len: usize },
+    Identify { cns: u32, len: usize },
+    GetFeature { req: GetFeatureRequest, len: Option<usize> },
+    FirmwareLoad { len: usize, offset: u64 },
+    FirmwareCommit { req: FirmwareCommitRequest },
+    Format { req: FormatRequest },
+    VucCommands,
+    Vuc { req: VucRequest, input: Option<Bytes>, output_len: Option<usize> },
+    WdcResizeGet,
+    WdcResizeSet { gb: u32 },
+    WdcE6Read { offset: u64, len: usize },
+    WdcAssertClear,
+    WdcAssertInject,
+}
+
+/// What a successful call returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Reply {
+    Unit,
+    Info(Box<TraceInfo>),
+    Instance(i32),
+    DevinfoPath(String),
+    Namespaces(Vec<u32>),
+    NamespaceInfo(NamespaceInfo),
+    /// Data read from the controller.
+    Data(Bytes),
+    /// Dword 0 of the completion queue entry, along with any data read.
+    Dword {
+        cdw0: u32,
+        data: Option<Bytes>,
+    },
+    VucCommands(Vec<VucCommand>),
+}
+
+/// A single recorded event. Controllers are identified by a handle number
+/// assigned as they are opened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    Discover { result: Result<Vec<Result<u32, NvmeError>>, NvmeError> },
+    Open { locator: ControllerLocator, result: Result<u32, NvmeError> },
+    Call { handle: u32, call: Call, result: Result<Reply, NvmeControllerError> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    pub version: u32,
+    pub events: Vec<Event>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self { version: TRACE_VERSION, events: Vec::new() }
+    }
+}
+
+impl Trace {
+    pub fn from_json(json: &str) -> Result<Self, TraceError> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        // Check the version before anything else so that a trace in a newer
+        // format is reported as such rather than as malformed.
+        let Version { version } = serde_json::from_str(json)?;
+        if version != TRACE_VERSION {
+            return Err(TraceError::Version { found: version });
+        }
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("trace is serializable")
+    }
+}
+
+#[derive(Debug, Default)]
+struct Recording {
+    trace: Trace,
+    next_handle: u32,
+}
+
+impl Recording {
+    fn handle(&mut self) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+}
+
+/// A backend that records every call made to another backend.
+///
+/// Clones share the same recording, so one can be kept to retrieve the trace
+/// after another has been handed to `Nvme::with_backend`.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Rc<dyn Backend>,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Record calls made to `backend`.
+    pub fn new<B: Backend + 'static>(backend: B) -> Self {
+        Self {
+            inner: Rc::new(backend),
+            recording: Arc::new(Mutex::new(Recording::default())),
+        }
+    }
+
+    /// Record calls made to the illumos libnvme.
+    pub fn system() -> Result<Self, NvmeInitError> {
+        Ok(Self {
+            inner: crate::system_backend()?,
+            recording: Arc::new(Mutex::new(Recording::default())),
+        })
+    }
+
+    /// The calls recorded so far.
+    pub fn trace(&self) -> Trace {
+        self.recording().trace.clone()
+    }
+
+    fn recording(&self) -> MutexGuard<'_, Recording> {
+        self.recording.lock().expect("recording poisoned")
+    }
+
+    fn wrap(
+        &self,
+        backend: Box<dyn ControllerBackend>,
+        recording: &mut Recording,
+    ) -> (u32, Box<dyn ControllerBackend>) {
+        let handle = recording.handle();
+        let recorded = RecordedController {
+            inner: backend,
+            handle,
+            recording: Arc::clone(&self.recording),
+        };
+        (handle, Box::new(recorded))
+    }
+}
+
+impl Backend for Recorder {
+    /// Discovery is recorded in full as soon as it starts, so every
+    /// controller is opened up front.
+    fn discover(&self) -> Result<ControllerIter<'_>, NvmeError> {
+        let discovered = self.inner.discover().map(Iterator::collect::<Vec<_>>);
+        let mut recording = self.recording();
+        let (result, controllers) = match discovered {
+            Ok(discovered) => {
+                let mut handles = Vec::new();
+                let mut controllers = Vec::new();
+                for item in discovered {
+                    match item {
+                        Ok(backend) => {
+                            let (h, c) = self.wrap(backend, &mut recording);
+                            handles.push(Ok(h));
+                            controllers.push(Ok(c));
+                        }
+                        Err(e) => {
+                            handles.push(Err(e.clone()));
+                            controllers.push(Err(e));
+                        }
+                    }
+                }
+                (Ok(handles), Ok(controllers))
+            }
+            Err(e) => (Err(e.clone()), Err(e)),
+        };
+        recording.trace.events.push(Event::Discover { result });
+        Ok(Box::new(controllers?.into_iter()))
+    }
+
+    fn controller_by_instance(
+        &self,
+        instance: i32,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let opened = self.inner.controller_by_instance(instance);
+        self.record_open(ControllerLocator::Instance(instance), opened)
+    }
+
+    fn controller_by_devinfo_path(
+        &self,
+        path: &str,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let opened = self.inner.controller_by_devinfo_path(path);
+        let locator = ControllerLocator::DevinfoPath(path.to_string());
+        self.record_open(locator, opened)
+    }
+}
+
+impl Recorder {
+    fn record_open(
+        &self,
+        locator: ControllerLocator,
+        opened: Result<Box<dyn ControllerBackend>, NvmeError>,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let mut recording = self.recording();
+        let (result, controller) = match opened {
+            Ok(backend) => {
+                let (handle, c) = self.wrap(backend, &mut recording);
+                (Ok(handle), Ok(c))
+            }
+            Err(e) => (Err(e.clone()), Err(e)),
+        };
+        recording.trace.events.push(Event::Open { locator, result });
+        controller
+    }
+}
+
+struct RecordedController {
+    inner: Box<dyn ControllerBackend>,
+    handle: u32,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl RecordedController {
+    /// Record `call` along with its result, as converted by `reply`.
+    fn record<T, F>(
+        &self,
+        call: Call,
+        result: Result<T, NvmeControllerError>,
+        reply: F,
+    ) -> Result<T, NvmeControllerError>
+    where
+        F: FnOnce(&T) -> Reply,
+    {
+        let recorded = result.as_ref().map(reply).map_err(Clone::clone);
+        self.recording
+            .lock()
+            .expect("recording poisoned")
+            .trace
+            .events
+            .push(Event::Call { handle: self.handle, call, result: recorded });
+        result
+    }
+}
+
+impl ControllerBackend for RecordedController {
+    fn info(&self) -> Result<ControllerInfo, NvmeControllerError> {
+        self.record(Call::Info, self.inner.info(), |info| {
+            Reply::Info(Box::new(TraceInfo::from(info)))
+        })
+    }
+
+    fn instance(&self) -> Result<i32, NvmeControllerError> {
+        self.record(Call::Instance, self.inner.instance(), |&instance| {
+            Reply::Instance(instance)
+        })
+    }
+
+    fn devinfo_path(&self) -> Result<String, NvmeControllerError> {
+        self.record(Call::DevinfoPath, self.inner.devinfo_path(), |path| {
+            Reply::DevinfoPath(path.clone())
+        })
+    }
+
+    fn lock(
+        &self,
+        level: LockLevel,
+        block: bool,
+    ) -> Result<(), NvmeControllerError> {
+        let result = self.inner.lock(level, block);
+        self.record(Call::Lock { level, block }, result, |_| Reply::Unit)
+    }
+
+    fn unlock(&self) {
+        self.inner.unlock()
+    }
+
+    fn namespaces(
+        &self,
+        level: NamespaceDiscoveryLevel,
+    ) -> Result<Vec<u32>, NvmeControllerError> {
+        let result = self.inner.namespaces(level);
+        self.record(Call::Namespaces { level }, result, |nsids| {
+            Reply::Namespaces(nsids.clone())
+        })
+    }
+
+    fn namespace_info(
+        &self,
+        nsid: u32,
+    ) -> Result<NamespaceInfo, NvmeControllerError> {
+        let result = self.inner.namespace_info(nsid);
+        self.record(Call::NamespaceInfo { nsid }, result, |info| {
+            Reply::NamespaceInfo(info.clone())
+        })
+    }
+
+    fn blkdev_attach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        let result = self.inner.blkdev_attach(nsid);
+        self.record(Call::BlkdevAttach { nsid }, result, |_| Reply::Unit)
+    }
+
+    fn blkdev_detach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        let result = self.inner.blkdev_detach(nsid);
+        self.record(Call::BlkdevDetach { nsid }, result, |_| Reply::Unit)
+    }
+
+    fn named_log(
+        &self,
+        name: LogPageName,
+    ) -> Result<Vec<u8>, NvmeControllerError> {
+        let result = self.inner.named_log(name);
+        self.record(Call::NamedLog { name }, result, |data| {
+            Reply::Data(Bytes(data.clone()))
+        })
+    }
+
+    fn get_log(
+        &self,
+        req: &LogRequest,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let result = self.inner.get_log(req, buf);
+        let call = Call::GetLog { req: *req, len: buf.len() };
+        self.record(call, result, |_| Reply::Data(Bytes(buf.to_vec())))
+    }
+
+    fn identify(
+        &self,
+        cns: u32,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let result = self.inner.identify(cns, buf);
+        let call = Call::Identify { cns, len: buf.len() };
+        self.record(call, result, |_| Reply::Data(Bytes(buf.to_vec())))
+    }
+
+    fn get_feature(
+        &self,
+        req: &GetFeatureRequest,
+        mut buf: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let result = self.inner.get_feature(req, buf.as_deref_mut());
+        let call =
+            Call::GetFeature { req: *req, len: buf.as_ref().map(|b| b.len()) };
+        self.record(call, result, |&cdw0| Reply::Dword {
+            cdw0,
+            data: buf.map(|b| Bytes(b.to_vec())),
+        })
+    }
+
+    fn firmware_load(
+        &self,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<(), NvmeControllerError> {
+        let result = self.inner.firmware_load(data, offset);
+        let call = Call::FirmwareLoad { len: data.len(), offset };
+        self.record(call, result, |_| Reply::Unit)
+    }
+
+    fn firmware_commit(
+        &self,
+        req: &FirmwareCommitRequest,
+    ) -> Result<(), NvmeControllerError> {
+        let result = self.inner.firmware_commit(req);
+        self.record(Call::FirmwareCommit { req: *req }, result, |_| Reply::Unit)
+    }
+
+    fn format(&self, req: &FormatRequest) -> Result<(), NvmeControllerError> {
+        let result = self.inner.format(req);
+        self.record(Call::Format { req: *req }, result, |_| Reply::Unit)
+    }
+
+    fn vuc_commands(&self) -> Result<Vec<VucCommand>, NvmeControllerError> {
+        let result = self.inner.vuc_commands();
+        self.record(Call::VucCommands, result, |commands| {
+            Reply::VucCommands(commands.clone())
+        })
+    }
+
+    fn vuc(
+        &self,
+        req: &VucRequest,
+        input: Option<&[u8]>,
+        mut output: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let result = self.inner.vuc(req, input, output.as_deref_mut());
+        let call = Call::Vuc {
+            req: *req,
+            input: input.map(|i| Bytes(i.to_vec())),
+            output_len: output.as_ref().map(|o| o.len()),
+        };
+        self.record(call, result, |&cdw0| Reply::Dword {
+            cdw0,
+            data: output.map(|o| Bytes(o.to_vec())),
+        })
+    }
+
+    fn wdc_resize_get(&self) -> Result<u32, NvmeControllerError> {
+        let result = self.inner.wdc_resize_get();
+        self.record(Call::WdcResizeGet, result, |&cdw0| Reply::Dword {
+            cdw0,
+            data: None,
+        })
+    }
+
+    fn wdc_resize_set(&self, gb: u32) -> Result<(), NvmeControllerError> {
+        let result = self.inner.wdc_resize_set(gb);
+        self.record(Call::WdcResizeSet { gb }, result, |_| Reply::Unit)
+    }
+
+    fn wdc_e6_read(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let result = self.inner.wdc_e6_read(offset, buf);
+        let call = Call::WdcE6Read { offset, len: buf.len() };
+        self.record(call, result, |_| Reply::Data(Bytes(buf.to_vec())))
+    }
+
+    fn wdc_assert_clear(&self) -> Result<(), NvmeControllerError> {
+        let result = self.inner.wdc_assert_clear();
+        self.record(Call::WdcAssertClear, result, |_| Reply::Unit)
+    }
+
+    fn wdc_assert_inject(&self) -> Result<(), NvmeControllerError> {
+        let result = self.inner.wdc_assert_inject();
+        self.record(Call::WdcAssertInject, result, |_| Reply::Unit)
+    }
+}
+
+type CallResult = Result<Reply, NvmeControllerError>;
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    discoveries: Vec<Result<Vec<Result<u32, NvmeError>>, NvmeError>>,
+    opens: Vec<(ControllerLocator, Result<u32, NvmeError>)>,
+    calls: Vec<(u32, Call, CallResult)>,
+}
+
+/// A backend that answers calls from a recorded [`Trace`].
+#[derive(Debug, Clone)]
+pub struct Replay {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+fn not_recorded(what: String) -> NvmeError {
+    NvmeError::new(NvmeErrorCode::Internal, "failed to replay trace", what)
+}
+
+impl Replay {
+    pub fn new(trace: Trace) -> Result<Self, TraceError> {
+        if trace.version != TRACE_VERSION {
+            return Err(TraceError::Version { found: trace.version });
+        }
+        let mut state = ReplayState::default();
+        for event in trace.events {
+            match event {
+                Event::Discover { result } => state.discoveries.push(result),
+                Event::Open { locator, result } => {
+                    state.opens.push((locator, result))
+                }
+                Event::Call { handle, call, result } => {
+                    state.calls.push((handle, call, result))
+                }
+            }
+        }
+        Ok(Self { state: Arc::new(Mutex::new(state)) })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, TraceError> {
+        Self::new(Trace::from_json(json)?)
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReplayState> {
+        self.state.lock().expect("replay state poisoned")
+    }
+
+    fn controller(&self, handle: u32) -> Box<dyn ControllerBackend> {
+        Box::new(ReplayedController { replay: self.clone(), handle })
+    }
+}
+
+impl Backend for Replay {
+    fn discover(&self) -> Result<ControllerIter<'_>, NvmeError> {
+        let mut state = self.state();
+        if state.discoveries.is_empty() {
+            return Err(not_recorded(
+                "no further controller discovery in trace".to_string(),
+            ));
+        }
+        let handles = state.discoveries.remove(0)?;
+        Ok(Box::new(handles.into_iter().map(|h| h.map(|h| self.controller(h)))))
+    }
+
+    fn controller_by_instance(
+        &self,
+        instance: i32,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        self.open(ControllerLocator::Instance(instance))
+    }
+
+    fn controller_by_devinfo_path(
+        &self,
+        path: &str,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        self.open(ControllerLocator::DevinfoPath(path.to_string()))
+    }
+}
+
+impl Replay {
+    fn open(
+        &self,
+        locator: ControllerLocator,
+    ) -> Result<Box<dyn ControllerBackend>, NvmeError> {
+        let mut state = self.state();
+        let index =
+            state.opens.iter().position(|(l, _)| *l == locator).ok_or_else(
+                || {
+                    not_recorded(format!(
+                        "no further opens of {locator:?} in trace"
+                    ))
+                },
+            )?;
+        let handle = state.opens.remove(index).1?;
+        Ok(self.controller(handle))
+    }
+}
+
+struct ReplayedController {
+    replay: Replay,
+    handle: u32,
+}
+
+impl ReplayedController {
+    /// Find the next recorded result of `call` and convert it with `reply`,
+    /// which returns `None` if the recorded reply is not of the expected
+    /// kind.
+    fn replay<T, F>(
+        &self,
+        call: Call,
+        reply: F,
+    ) -> Result<T, NvmeControllerError>
+    where
+        F: FnOnce(Reply) -> Option<T>,
+    {
+        let recorded = {
+            let mut state = self.replay.state();
+            state
+                .calls
+                .iter()
+                .position(|(h, c, _)| *h == self.handle && *c == call)
+                .map(|index| state.calls.remove(index).2)
+        };
+        let mismatch = |what: &str| {
+            NvmeControllerError::new(
+                NvmeErrorCode::Internal,
+                "failed to replay trace",
+                format!("{what} for {call:?} on handle {}", self.handle),
+            )
+        };
+        match recorded {
+            None => Err(mismatch("no further calls in trace")),
+            Some(Err(e)) => Err(e),
+            Some(Ok(r)) => reply(r).ok_or_else(|| mismatch("unexpected reply")),
+        }
+    }
+
+    fn replay_unit(&self, call: Call) -> Result<(), NvmeControllerError> {
+        self.replay(call, |r| matches!(r, Reply::Unit).then_some(()))
+    }
+
+    fn replay_data(
+        &self,
+        call: Call,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        let data = self.replay(call, |r| match r {
+            Reply::Data(Bytes(data)) if data.len() == buf.len() => Some(data),
+            _ => None,
+        })?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn replay_dword(
+        &self,
+        call: Call,
+        buf: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let len = buf.as_ref().map(|b| b.len());
+        let (cdw0, data) = self.replay(call, |r| match r {
+            Reply::Dword { cdw0, data }
+                if data.as_ref().map(|d| d.0.len()) == len =>
+            {
+                Some((cdw0, data))
+            }
+            _ => None,
+        })?;
+        if let (Some(buf), Some(Bytes(data))) = (buf, data) {
+            buf.copy_from_slice(&data);
+        }
+        Ok(cdw0)
+    }
+}
+
+impl ControllerBackend for ReplayedController {
+    fn info(&self) -> Result<ControllerInfo, NvmeControllerError> {
+        self.replay(Call::Info, |r| match r {
+            Reply::Info(info) => info.to_info(),
+            _ => None,
+        })
+    }
+
+    fn instance(&self) -> Result<i32, NvmeControllerError> {
+        self.replay(Call::Instance, |r| match r {
+            Reply::Instance(instance) => Some(instance),
+            _ => None,
+        })
+    }
+
+    fn devinfo_path(&self) -> Result<String, NvmeControllerError> {
+        self.replay(Call::DevinfoPath, |r| match r {
+            Reply::DevinfoPath(path) => Some(path),
+            _ => None,
+        })
+    }
+
+    fn lock(
+        &self,
+        level: LockLevel,
+        block: bool,
+    ) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::Lock { level, block })
+    }
+
+    /// Unlocking is not recorded, as it cannot fail.
+    fn unlock(&self) {}
+
+    fn namespaces(
+        &self,
+        level: NamespaceDiscoveryLevel,
+    ) -> Result<Vec<u32>, NvmeControllerError> {
+        self.replay(Call::Namespaces { level }, |r| match r {
+            Reply::Namespaces(nsids) => Some(nsids),
+            _ => None,
+        })
+    }
+
+    fn namespace_info(
+        &self,
+        nsid: u32,
+    ) -> Result<NamespaceInfo, NvmeControllerError> {
+        self.replay(Call::NamespaceInfo { nsid }, |r| match r {
+            Reply::NamespaceInfo(info) => Some(info.clone()),
+            _ => None,
+        })
+    }
+
+    fn blkdev_attach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::BlkdevAttach { nsid })
+    }
+
+    fn blkdev_detach(&self, nsid: u32) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::BlkdevDetach { nsid })
+    }
+
+    fn named_log(
+        &self,
+        name: LogPageName,
+    ) -> Result<Vec<u8>, NvmeControllerError> {
+        self.replay(Call::NamedLog { name }, |r| match r {
+            Reply::Data(Bytes(data)) => Some(data),
+            _ => None,
+        })
+    }
+
+    fn get_log(
+        &self,
+        req: &LogRequest,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.replay_data(Call::GetLog { req: *req, len: buf.len() }, buf)
+    }
+
+    fn identify(
+        &self,
+        cns: u32,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.replay_data(Call::Identify { cns, len: buf.len() }, buf)
+    }
+
+    fn get_feature(
+        &self,
+        req: &GetFeatureRequest,
+        buf: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let call =
+            Call::GetFeature { req: *req, len: buf.as_ref().map(|b| b.len()) };
+        self.replay_dword(call, buf)
+    }
+
+    fn firmware_load(
+        &self,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::FirmwareLoad { len: data.len(), offset })
+    }
+
+    fn firmware_commit(
+        &self,
+        req: &FirmwareCommitRequest,
+    ) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::FirmwareCommit { req: *req })
+    }
+
+    fn format(&self, req: &FormatRequest) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::Format { req: *req })
+    }
+
+    fn vuc_commands(&self) -> Result<Vec<VucCommand>, NvmeControllerError> {
+        self.replay(Call::VucCommands, |r| match r {
+            Reply::VucCommands(commands) => Some(commands),
+            _ => None,
+        })
+    }
+
+    fn vuc(
+        &self,
+        req: &VucRequest,
+        input: Option<&[u8]>,
+        output: Option<&mut [u8]>,
+    ) -> Result<u32, NvmeControllerError> {
+        let call = Call::Vuc {
+            req: *req,
+            input: input.map(|i| Bytes(i.to_vec())),
+            output_len: output.as_ref().map(|o| o.len()),
+        };
+        self.replay_dword(call, output)
+    }
+
+    fn wdc_resize_get(&self) -> Result<u32, NvmeControllerError> {
+        self.replay_dword(Call::WdcResizeGet, None)
+    }
+
+    fn wdc_resize_set(&self, gb: u32) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::WdcResizeSet { gb })
+    }
+
+    fn wdc_e6_read(
+        &self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), NvmeControllerError> {
+        self.replay_data(Call::WdcE6Read { offset, len: buf.len() }, buf)
+    }
+
+    fn wdc_assert_clear(&self) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::WdcAssertClear)
+    }
+
+    fn wdc_assert_inject(&self) -> Result<(), NvmeControllerError> {
+        self.replay_unit(Call::WdcAssertInject)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::{
+        controller::{Controller, TryLockResult},
+        nvmespec::smart::SMART_LID,
+        sim::{Sim, SimController, SimError, SimOp},
+        Nvme,
+    };
+
+    /// Exercise a controller, returning a summary of what was seen.
+    fn exercise(nvme: &Nvme) -> Vec<String> {
+        let mut seen = Vec::new();
+        for controller in nvme.controller_discovery().unwrap() {
+            let controller = controller.unwrap();
+            let info = controller.get_info().unwrap();
+            seen.push(format!(
+                "{} {} {}",
+                info.model(),
+                info.serial(),
+                info.fwrev()
+            ));
+
+            match controller.get_firmware_log_page() {
+                Ok(fw) => seen
+                    .push(format!("{:?}", fw.slot_iter().collect::<Vec<_>>())),
+                Err(e) => seen.push(e.to_string()),
+            }
+
+            match controller.smart_log() {
+                Ok(smart) => seen.push(format!("{smart:?}")),
+                Err(e) => seen.push(e.to_string()),
+            }
+        }
+
+        let controller = Controller::init_by_instance(nvme, 1).unwrap();
+        let locked = match controller.try_write_lock() {
+            TryLockResult::Ok(locked) => locked,
+            _ => panic!("controller should not be locked"),
+        };
+        let mut buf = [0u8; 64];
+        let err = locked
+            .log_request()
+            .unwrap()
+            .set_lid(SMART_LID)
+            .unwrap()
+            .execute(&mut buf)
+            .unwrap_err();
+        seen.push(format!(
+            "{:?} {:#x}/{:#x}",
+            err.code(),
+            err.device_status_code_type(),
+            err.device_status_code()
+        ));
+        seen
+    }
+
+    /// A trace of `exercise` run against `sim()`, written by this crate when
+    /// the trace format was last changed.
+    const FIXTURE: &str = include_str!("../testdata/sim-trace.json");
+
+    fn sim() -> Sim {
+        Sim::new()
+            .with_controller(SimController::new(0).with_firmware_slots(
+                vec![Some("ABCDEFGH"), Some("1.0")],
+                1,
+                true,
+            ))
+            .with_controller(
+                SimController::new(1)
+                    .with_error(
+                        SimOp::GetLog,
+                        SimError::Library(NvmeErrorCode::LogUnsupByDev),
+                    )
+                    .with_error(
+                        SimOp::GetLog,
+                        SimError::Device { sct: 0, sc: 0x2 },
+                    )
+                    .with_error(
+                        SimOp::GetLog,
+                        SimError::Device { sct: 1, sc: 0x9 },
+                    ),
+            )
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let recorder = Recorder::new(sim());
+        let recorded = exercise(&Nvme::with_backend(recorder.clone()));
+        assert_eq!(recorded[1], r#"[Some("ABCDEFGH"), Some("1.0")]"#);
+        assert_eq!(recorded.last().unwrap(), "Controller 0x1/0x9");
+
+        let json = recorder.trace().to_json();
+        let replay = Replay::from_json(&json).unwrap();
+        let replayed = exercise(&Nvme::with_backend(replay));
+        assert_eq!(recorded, replayed);
+    }
+
+    #[test]
+    fn checked_in_trace() {
+        let expected = exercise(&Nvme::with_backend(sim()));
+        let replayed =
+            exercise(&Nvme::with_backend(Replay::from_json(FIXTURE).unwrap()));
+        assert_eq!(expected, replayed);
+
+        // A format change that still parses old traces is caught here.
+        let recorder = Recorder::new(sim());
+        exercise(&Nvme::with_backend(recorder.clone()));
+        assert_eq!(
+            recorder.trace().to_json().trim_end(),
+            FIXTURE.trim_end(),
+            "the trace format changed; bump TRACE_VERSION if needed and \
+             regenerate testdata/sim-trace.json"
+        );
+    }
+
+    #[test]
+    fn unrecorded_calls_fail() {
+        let recorder =
+            Recorder::new(Sim::new().with_controller(SimController::new(0)));
+        let nvme = Nvme::with_backend(recorder.clone());
+        Controller::init_by_instance(&nvme, 0).unwrap().get_info().unwrap();
+
+        let nvme = Nvme::with_backend(Replay::new(recorder.trace()).unwrap());
+        let controller = Controller::init_by_instance(&nvme, 0).unwrap();
+        controller.get_info().unwrap();
+        let err = controller.get_info().err().unwrap();
+        assert_eq!(err.code(), NvmeErrorCode::Internal);
+        let err = Controller::init_by_instance(&nvme, 0).err().unwrap();
+        assert_eq!(err.code(), NvmeErrorCode::Internal);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let json = r#"{"version": 999, "events": "something new"}"#;
+        assert!(matches!(
+            Trace::from_json(json),
+            Err(TraceError::Version { found: 999 })
+        ));
+    }
+}
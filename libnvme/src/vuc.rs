@@ -0,0 +1,255 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::marker::PhantomData;
+
+use libnvme_sys::nvme::*;
+
+use crate::{
+    backend::{check_field, VucRequest},
+    controller::{Controller, NvmeControllerError, WriteLockedController},
+    nvmespec::command_effects::CommandEffects,
+    NvmeErrorCode,
+};
+
+/// The direction of data transfer for a vendor unique command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VucDataTransfer {
+    None,
+    /// Data is sent from the host to the controller.
+    Input,
+    /// Data is sent from the controller to the host.
+    Output,
+    Unknown(u32),
+}
+
+impl From<nvme_vuc_disc_io_t> for VucDataTransfer {
+    fn from(value: nvme_vuc_disc_io_t) -> Self {
+        match value {
+            NVME_VUC_DISC_IO_NONE => Self::None,
+            NVME_VUC_DISC_IO_INPUT => Self::Input,
+            NVME_VUC_DISC_IO_OUTPUT => Self::Output,
+            dt => Self::Unknown(dt),
+        }
+    }
+}
+
+/// The lock that must be held to execute a vendor unique command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VucLock {
+    None,
+    Read,
+    Write,
+    Unknown(u32),
+}
+
+impl From<nvme_vuc_disc_lock_t> for VucLock {
+    fn from(value: nvme_vuc_disc_lock_t) -> Self {
+        match value {
+            NVME_VUC_DISC_LOCK_NONE => Self::None,
+            NVME_VUC_DISC_LOCK_READ => Self::Read,
+            NVME_VUC_DISC_LOCK_WRITE => Self::Write,
+            lock => Self::Unknown(lock),
+        }
+    }
+}
+
+/// What a vendor unique command may change on the device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VucImpact {
+    /// The command may change the data stored in namespaces.
+    pub data: bool,
+    /// The command may change the namespaces of the controller, such as their
+    /// number, size, or format.
+    pub namespace: bool,
+}
+
+impl VucImpact {
+    #[cfg(any(target_os = "illumos", not(feature = "sim")))]
+    pub(crate) fn as_raw(&self) -> nvme_vuc_disc_impact_t {
+        let mut impact = NVME_VUC_DISC_IMPACT_NONE;
+        if self.data {
+            impact |= NVME_VUC_DISC_IMPACT_DATA;
+        }
+        if self.namespace {
+            impact |= NVME_VUC_DISC_IMPACT_NS;
+        }
+        impact
+    }
+}
+
+impl From<nvme_vuc_disc_impact_t> for VucImpact {
+    fn from(value: nvme_vuc_disc_impact_t) -> Self {
+        Self {
+            data: value & NVME_VUC_DISC_IMPACT_DATA != 0,
+            namespace: value & NVME_VUC_DISC_IMPACT_NS != 0,
+        }
+    }
+}
+
+impl From<CommandEffects> for VucImpact {
+    /// Derive the impact of a command from its entry in the Commands
+    /// Supported and Effects log.
+    fn from(effects: CommandEffects) -> Self {
+        Self {
+            data: effects.lba_content_change(),
+            namespace: effects.namespace_capability_change()
+                || effects.namespace_inventory_change(),
+        }
+    }
+}
+
+/// A vendor unique command that libnvme knows about for a controller.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VucCommand {
+    pub name: String,
+    pub description: String,
+    pub opcode: u32,
+    pub data_transfer: VucDataTransfer,
+    pub impact: VucImpact,
+    pub lock: VucLock,
+}
+
+/// The vendor unique commands of a controller. They are all read up front by
+/// `Controller::vuc_discovery`, so iterating cannot fail.
+pub struct VucDiscovery<'a> {
+    commands: std::vec::IntoIter<VucCommand>,
+    _controller: PhantomData<&'a Controller<'a>>,
+}
+
+impl<'a> Iterator for VucDiscovery<'a> {
+    type Item = VucCommand;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.commands.next()
+    }
+}
+
+impl<'a> Controller<'a> {
+    /// Returns an iterator over the named vendor unique commands that libnvme
+    /// knows about for this controller.
+    pub fn vuc_discovery(
+        &self,
+    ) -> Result<VucDiscovery<'_>, NvmeControllerError> {
+        let commands = self.backend().vuc_commands()?;
+        Ok(VucDiscovery {
+            commands: commands.into_iter(),
+            _controller: PhantomData,
+        })
+    }
+}
+
+impl<'ctrl> WriteLockedController<'ctrl> {
+    /// Returns a new `VucRequestBuilder` that can be used to issue a vendor
+    /// unique admin command to the controller.
+    pub fn vuc_request<'buf>(
+        &self,
+    ) -> Result<VucRequestBuilder<'_, 'buf>, NvmeControllerError> {
+        Ok(VucRequestBuilder {
+            req: VucRequest::default(),
+            input: None,
+            output: None,
+            controller: self,
+        })
+    }
+}
+
+/// A vendor unique command request.
+///
+/// Each field is checked to be in range as it is set. Whether the controller
+/// supports the request is only checked once it is executed.
+pub struct VucRequestBuilder<'ctrl, 'buf> {
+    req: VucRequest,
+    input: Option<&'buf [u8]>,
+    output: Option<&'buf mut [u8]>,
+    controller: &'ctrl WriteLockedController<'ctrl>,
+}
+
+impl<'ctrl, 'buf> VucRequestBuilder<'ctrl, 'buf> {
+    /// Set the vendor unique opcode.
+    pub fn set_opcode(
+        mut self,
+        opcode: u32,
+    ) -> Result<Self, NvmeControllerError> {
+        // The admin opcodes set aside for vendor specific commands.
+        let valid = (0xc0..=0xff).contains(&opcode);
+        check_field(valid, NvmeErrorCode::VucOpcodeRange, "opcode", opcode)?;
+        self.req.opcode = Some(opcode);
+        Ok(self)
+    }
+
+    pub fn set_nsid(mut self, nsid: u32) -> Result<Self, NvmeControllerError> {
+        self.req.nsid = Some(nsid);
+        Ok(self)
+    }
+
+    pub fn set_cdw12(mut self, cdw: u32) -> Result<Self, NvmeControllerError> {
+        self.req.cdw12 = Some(cdw);
+        Ok(self)
+    }
+
+    pub fn set_cdw13(mut self, cdw: u32) -> Result<Self, NvmeControllerError> {
+        self.req.cdw13 = Some(cdw);
+        Ok(self)
+    }
+
+    pub fn set_cdw14(mut self, cdw: u32) -> Result<Self, NvmeControllerError> {
+        self.req.cdw14 = Some(cdw);
+        Ok(self)
+    }
+
+    pub fn set_cdw15(mut self, cdw: u32) -> Result<Self, NvmeControllerError> {
+        self.req.cdw15 = Some(cdw);
+        Ok(self)
+    }
+
+    /// Set the command timeout in seconds.
+    pub fn set_timeout(
+        mut self,
+        secs: u32,
+    ) -> Result<Self, NvmeControllerError> {
+        check_field(secs > 0, NvmeErrorCode::VucTimeoutRange, "timeout", secs)?;
+        self.req.timeout = Some(secs);
+        Ok(self)
+    }
+
+    /// Declare what the command may change on the device. libnvme uses this
+    /// to decide which locks must be held and whether the kernel must detach
+    /// blkdev from namespaces.
+    pub fn set_impact(
+        mut self,
+        impact: VucImpact,
+    ) -> Result<Self, NvmeControllerError> {
+        self.req.impact = Some(impact);
+        Ok(self)
+    }
+
+    /// Set the data that will be sent to the controller.
+    pub fn set_input(
+        mut self,
+        data: &'buf [u8],
+    ) -> Result<Self, NvmeControllerError> {
+        self.input = Some(data);
+        Ok(self)
+    }
+
+    /// Set the buffer that data from the controller will be written into.
+    pub fn set_output(
+        mut self,
+        data: &'buf mut [u8],
+    ) -> Result<Self, NvmeControllerError> {
+        self.output = Some(data);
+        Ok(self)
+    }
+
+    /// Execute the vendor unique command, returning Dword 0 of the completion
+    /// queue entry, which is the only completion data libnvme reports.
+    pub fn execute(self) -> Result<u32, NvmeControllerError> {
+        self.controller.backend().vuc(&self.req, self.input, self.output)
+    }
+}
@@ -2,32 +2,244 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::ops::Deref;
+use std::{io::Write, ops::Deref};
+
+use thiserror::Error;
 
 use crate::{
     controller::{NvmeControllerError, WriteLockedController},
-    error::LibraryError,
+    controller_info::NvmeInfoError,
+    namespace::NamespaceDiscoveryLevel,
+    nvmespec::wdc::{e6_dump_len, WDC_E6_HDR_LEN},
 };
 
-use libnvme_sys::nvme::{nvme_wdc_resize_get, nvme_wdc_resize_set};
+/// The E6 dump is read in chunks of this size.
+const E6_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum WdcError {
+    #[error("libnvme error: {0}")]
+    ControllerError(#[from] NvmeControllerError),
+    #[error("failed to write E6 dump: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("E6 dump header reports an invalid length of {0} bytes")]
+    InvalidE6Length(u32),
+    #[error("{0}")]
+    InfoError(#[from] NvmeInfoError),
+    #[error("device {model} (vid {vid:#x}) does not support resizing")]
+    ResizeUnsupported { vid: u16, model: String },
+    #[error(
+        "requested capacity {requested} is larger than the {max_bytes} byte \
+        capacity of the device"
+    )]
+    CapacityTooLarge { requested: Capacity, max_bytes: u128 },
+    #[error("namespace {0} has blkdev attached")]
+    BlkdevAttached(u32),
+    #[error("device reports {current} after being resized to {requested}")]
+    ResizeNotApplied { requested: Capacity, current: Capacity },
+}
+
+/// The PCI vendor ID used by Western Digital NVMe devices.
+const WDC_PCI_VID: u16 = 0x1b96;
+
+/// Model number prefixes of the families that support the resize commands:
+/// the SN840 (WUS4BA and WUS4CB) and the SN650 and SN655 (WUS5EA), as listed
+/// in the ordering information of the Ultrastar DC data sheets for those
+/// drives. Support is keyed on the model rather than the PCI device ID since
+/// `ControllerInfo` only carries the vendor ID.
+const WDC_RESIZE_MODELS: &[&str] = &["WUS4BA", "WUS4CB", "WUS5EA"];
+
+fn resize_supported(vid: u16, model: &str) -> bool {
+    vid == WDC_PCI_VID
+        && WDC_RESIZE_MODELS.iter().any(|prefix| model.starts_with(prefix))
+}
+
+/// The capacity of a WDC device as understood by the resize commands, which
+/// operate in decimal gigabytes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub struct Capacity {
+    gb: u32,
+}
+
+impl Capacity {
+    pub fn from_gigabytes(gb: u32) -> Self {
+        Self { gb }
+    }
+
+    pub fn gigabytes(&self) -> u32 {
+        self.gb
+    }
+
+    pub fn bytes(&self) -> u64 {
+        u64::from(self.gb) * 1_000_000_000
+    }
+}
+
+impl std::fmt::Display for Capacity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} GB", self.gb)
+    }
+}
+
+/// The outcome of a successful `wdc_resize_set`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ResizeReport {
+    /// The capacity of the device before it was resized.
+    pub previous: Capacity,
+    /// The capacity reported by the device after it was resized.
+    pub current: Capacity,
+}
 
 impl<'a> WriteLockedController<'a> {
-    pub fn wdc_resize_set(&self, size: u32) -> Result<(), NvmeControllerError> {
+    /// Resize a WDC device to `capacity`.
+    ///
+    /// This verifies up front that the device is a model known to support
+    /// resizing, that `capacity` is no larger than the Total NVM Capacity
+    /// (TNVMCAP) of the device, and that no namespace has blkdev attached.
+    /// The new size is read back from the device to confirm it was applied.
+    pub fn wdc_resize_set(
+        &self,
+        capacity: Capacity,
+    ) -> Result<ResizeReport, WdcError> {
         let controller = self.deref();
-        controller.check_result(
-            unsafe { nvme_wdc_resize_set(controller.inner, size) },
-            || "failed to resize wdc device",
-        )
+        let info = controller.get_info()?;
+        let vid = info.pci_vid()?;
+        let model = info.model();
+        if !resize_supported(vid, &model) {
+            return Err(WdcError::ResizeUnsupported {
+                vid,
+                model: model.into_owned(),
+            });
+        }
+
+        let tnvmcap = info.get_controller_info_identify().data().ap_tnvmcap;
+        let max = (u128::from(tnvmcap.hi) << 64) | u128::from(tnvmcap.lo);
+        if u128::from(capacity.bytes()) > max {
+            return Err(WdcError::CapacityTooLarge {
+                requested: capacity,
+                max_bytes: max,
+            });
+        }
+
+        let nsdisc =
+            controller.namespace_discovery(NamespaceDiscoveryLevel::BlkDev)?;
+        if let Some(ns) = nsdisc.into_iter().next() {
+            return Err(WdcError::BlkdevAttached(ns?.nsid()));
+        }
+
+        let previous = self.wdc_resize_get()?;
+        controller.backend().wdc_resize_set(capacity.gb)?;
+
+        let current = self.wdc_resize_get()?;
+        if current != capacity {
+            return Err(WdcError::ResizeNotApplied {
+                requested: capacity,
+                current,
+            });
+        }
+
+        Ok(ResizeReport { previous, current })
     }
 
-    pub fn wdc_resize_get(&self) -> Result<u32, NvmeControllerError> {
-        let mut size = 0;
-        let controller = self.deref();
-        controller
-            .check_result(
-                unsafe { nvme_wdc_resize_get(controller.inner, &mut size) },
-                || "failed to get size of wdc device",
-            )
-            .map(|_| size)
+    pub fn wdc_resize_get(&self) -> Result<Capacity, NvmeControllerError> {
+        self.backend().wdc_resize_get().map(Capacity::from_gigabytes)
+    }
+
+    /// Stream the full E6 diagnostic dump of the device into `writer`,
+    /// returning the number of bytes written.
+    pub fn wdc_e6_dump<W: Write>(
+        &self,
+        mut writer: W,
+    ) -> Result<u64, WdcError> {
+        // The header tells us how large the whole dump is.
+        let mut hdr = [0u8; WDC_E6_HDR_LEN];
+        self.backend().wdc_e6_read(0, &mut hdr)?;
+        let len = e6_dump_len(&hdr);
+        if (len as usize) < WDC_E6_HDR_LEN {
+            return Err(WdcError::InvalidE6Length(len));
+        }
+        writer.write_all(&hdr)?;
+
+        let mut buf = vec![0u8; E6_CHUNK_SIZE];
+        let mut offset = WDC_E6_HDR_LEN as u64;
+        while offset < u64::from(len) {
+            let remaining = u64::from(len) - offset;
+            let size = remaining.min(E6_CHUNK_SIZE as u64) as usize;
+            let chunk = &mut buf[..size];
+            self.backend().wdc_e6_read(offset, chunk)?;
+            writer.write_all(chunk)?;
+            offset += size as u64;
+        }
+
+        writer.flush()?;
+        Ok(offset)
+    }
+
+    /// Clear an outstanding firmware assert on the device.
+    pub fn wdc_assert_clear(&self) -> Result<(), NvmeControllerError> {
+        self.backend().wdc_assert_clear()
+    }
+
+    /// Inject a firmware assert into the device. This is intended for testing
+    /// diagnostic collection.
+    pub fn wdc_assert_inject(&self) -> Result<(), NvmeControllerError> {
+        self.backend().wdc_assert_inject()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_models() {
+        for model in ["WUS4BA119DSP3X3", "WUS4CB016D7P3E3", "WUS5EA138ESP7E3"] {
+            assert!(resize_supported(WDC_PCI_VID, model), "{model}");
+        }
+        // A supported model number from another vendor's ID.
+        assert!(!resize_supported(0x1b36, "WUS4BA119DSP3X3"));
+        // Other WDC models, including ones only differing in case.
+        for model in ["WUS3BA138C7P3E3", "SDAPNUW-512G", "wus4ba119dsp3x3", ""]
+        {
+            assert!(!resize_supported(WDC_PCI_VID, model), "{model}");
+        }
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn resize_larger_than_device() {
+        use crate::{
+            controller::Controller,
+            sim::{Sim, SimController},
+            Nvme,
+        };
+
+        let sim = Sim::new().with_controller(
+            SimController::new(0)
+                .with_pci_vid(Some(WDC_PCI_VID))
+                .with_model("WUS4BA119DSP3X3")
+                .with_identify(|id| id.ap_tnvmcap.lo = 1_920_383_410_176),
+        );
+        let nvme = Nvme::with_backend(sim);
+        let controller = Controller::init_by_instance(&nvme, 0)
+            .unwrap()
+            .write_lock()
+            .map_err(|(_, e)| e)
+            .unwrap();
+
+        // Refused before the namespace, which has blkdev attached, is looked
+        // at or anything is sent to the device.
+        let requested = Capacity::from_gigabytes(1921);
+        let err = controller.wdc_resize_set(requested).unwrap_err();
+        assert!(matches!(
+            err,
+            WdcError::CapacityTooLarge { requested: r, max_bytes }
+                if r == requested && max_bytes == 1_920_383_410_176
+        ));
+
+        let err = controller
+            .wdc_resize_set(Capacity::from_gigabytes(1920))
+            .unwrap_err();
+        assert!(matches!(err, WdcError::BlkdevAttached(1)));
     }
 }
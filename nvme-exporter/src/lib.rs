@@ -0,0 +1,249 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A minimal HTTP server for the metrics of `libnvme::metrics`.
+//!
+//! Each scrape of `/metrics` takes a fresh inventory of every controller.
+//! Connections are served one at a time, which is plenty for a scraper or
+//! two and keeps the controllers from being read concurrently. Each client
+//! gets a fixed time and number of bytes to send its request in, so that
+//! none can hold up the others for long.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use libnvme::{metrics, Nvme};
+
+/// The path metrics are served on.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// How long a client has to send its request, and to read each part of the
+/// response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most bytes of a request read, request line and headers included. The
+/// rest of a longer request is ignored.
+const MAX_REQUEST_LEN: u64 = 8 * 1024;
+
+/// Serve connections from `listener` until accepting one fails.
+pub fn serve(nvme: &Nvme, listener: &TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        // A client that goes away mid-request is no reason to stop.
+        if let Err(e) = handle(nvme, stream?) {
+            eprintln!("nvme-exporter: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Read one request from `stream` and respond to it.
+pub fn handle(nvme: &Nvme, stream: TcpStream) -> io::Result<()> {
+    handle_within(nvme, stream, CLIENT_TIMEOUT)
+}
+
+fn handle_within(
+    nvme: &Nvme,
+    stream: TcpStream,
+    timeout: Duration,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(timeout))?;
+    let deadline = Deadline { stream: &stream, at: Instant::now() + timeout };
+    let mut reader = BufReader::new(deadline).take(MAX_REQUEST_LEN);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are of no interest, but are read so that the client is
+    // not reset for closing the connection with data unread.
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    let path = target.map(|t| t.split('?').next().unwrap_or(t));
+    let response = match (method, path) {
+        (Some("GET" | "HEAD"), Some(METRICS_PATH)) => match nvme.inventory() {
+            Ok(inventory) => Response::ok(metrics::render(&inventory)),
+            Err(e) => Response::text(
+                "500 Internal Server Error",
+                format!("failed to discover controllers: {e}\n"),
+            ),
+        },
+        (Some("GET" | "HEAD"), Some(_)) => Response::text(
+            "404 Not Found",
+            format!("metrics are served on {METRICS_PATH}\n"),
+        ),
+        _ => Response::text("405 Method Not Allowed", String::new()),
+    };
+    response.write(&stream, method == Some("HEAD"))
+}
+
+/// Reads from a stream until a fixed time, however slowly the data arrives.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    at: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "client did not send its request in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Self {
+        Self { status: "200 OK", content_type: metrics::CONTENT_TYPE, body }
+    }
+
+    fn text(status: &'static str, body: String) -> Self {
+        Self { status, content_type: "text/plain; charset=utf-8", body }
+    }
+
+    fn write(&self, mut stream: &TcpStream, head_only: bool) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        if !head_only {
+            stream.write_all(self.body.as_bytes())?;
+        }
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::SocketAddr, thread};
+
+    use libnvme::sim::{Sim, SimController};
+
+    use super::*;
+
+    /// Send `request` to a server for a simulated controller and return the
+    /// response.
+    fn request(request: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let nvme = Nvme::with_backend(
+            Sim::new().with_controller(SimController::new(0)),
+        );
+        let (stream, _) = listener.accept().unwrap();
+        handle(&nvme, stream).unwrap();
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let response =
+            request("GET /metrics?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(
+            head.contains(&format!("Content-Type: {}", metrics::CONTENT_TYPE))
+        );
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(body.contains("nvme_controller_info{serial=\"SIM-0\""));
+        assert!(body.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn long_requests_are_cut_off() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let headers = "X-Padding: 0123456789abcdef\r\n".repeat(1000);
+            // The headers never end, and the server stops reading part way
+            // through them, so resets the connection once it has responded.
+            let _ = write!(stream, "GET /metrics HTTP/1.1\r\n{headers}");
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+
+        let nvme = Nvme::with_backend(
+            Sim::new().with_controller(SimController::new(0)),
+        );
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        handle(&nvme, stream).unwrap();
+        assert!(start.elapsed() < CLIENT_TIMEOUT / 2);
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn slow_clients_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            // Send a byte at a time, each well within the timeout, until the
+            // server gives up.
+            let mut request = b"GET /metrics HTTP/1.1\r\n".iter().cycle();
+            for byte in request.by_ref().take(1000) {
+                if stream.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let nvme = Nvme::with_backend(
+            Sim::new().with_controller(SimController::new(0)),
+        );
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let err = handle_within(&nvme, stream, Duration::from_millis(200))
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        ));
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_other_requests() {
+        let response = request("GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request("POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        let response = request("HEAD /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+}
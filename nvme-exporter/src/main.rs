@@ -0,0 +1,47 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{net::TcpListener, process::ExitCode};
+
+use clap::Parser;
+use libnvme::Nvme;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "nvme-exporter",
+    about = "Serve the health of NVMe controllers as OpenMetrics"
+)]
+struct Args {
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:9998")]
+    listen: String,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let nvme = match Nvme::new() {
+        Ok(nvme) => nvme,
+        Err(e) => {
+            eprintln!("nvme-exporter: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let listener = match TcpListener::bind(&args.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!(
+                "nvme-exporter: failed to listen on {}: {e}",
+                args.listen
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    match nvme_exporter::serve(&nvme, &listener) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nvme-exporter: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
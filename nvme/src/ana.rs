@@ -0,0 +1,204 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Asymmetric Namespace Access log page (1.4).
+
+use std::collections::BTreeMap;
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{le_u16, le_u32, le_u64},
+};
+
+/// Log page identifier of the ANA log.
+pub const ANA_LID: u32 = 0x0c;
+/// Log Specific Parameter that omits the namespace lists from the group
+/// descriptors (Return Groups Only).
+pub const ANA_LSP_GROUPS_ONLY: u32 = 1;
+/// Size of the ANA log header.
+const ANA_HDR_LEN: usize = 16;
+/// Size of each group descriptor, excluding its namespace list.
+const ANA_DESC_LEN: usize = 32;
+
+/// The largest the ANA log can be for a controller with `groups` ANA groups
+/// and `namespaces` namespaces.
+pub fn ana_log_len(groups: u32, namespaces: u32) -> usize {
+    let groups = usize::try_from(groups).expect("32-bit systems unsupported");
+    let nsids =
+        usize::try_from(namespaces).expect("32-bit systems unsupported");
+    ANA_HDR_LEN + groups * ANA_DESC_LEN + nsids * 4
+}
+
+/// The ANA state of a group, which applies to every namespace in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnaState {
+    Optimized,
+    NonOptimized,
+    Inaccessible,
+    PersistentLoss,
+    /// The group is transitioning between states.
+    Change,
+    Unknown(u8),
+}
+
+impl From<u8> for AnaState {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => Self::Optimized,
+            0x02 => Self::NonOptimized,
+            0x03 => Self::Inaccessible,
+            0x04 => Self::PersistentLoss,
+            0x0f => Self::Change,
+            state => Self::Unknown(state),
+        }
+    }
+}
+
+/// An ANA group descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnaGroup {
+    pub id: u32,
+    pub change_count: u64,
+    pub state: AnaState,
+    /// The namespaces in the group. This is empty if the log was read with
+    /// `ANA_LSP_GROUPS_ONLY`.
+    pub nsids: Vec<u32>,
+}
+
+/// The Asymmetric Namespace Access log (0Ch).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnaLog {
+    pub change_count: u64,
+    pub groups: Vec<AnaGroup>,
+}
+
+impl AnaLog {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, ANA_HDR_LEN)?;
+        let ngroups = le_u16(buf, 8);
+
+        let mut groups = Vec::with_capacity(usize::from(ngroups));
+        let mut off = ANA_HDR_LEN;
+        for _ in 0..ngroups {
+            check_len(buf, off + ANA_DESC_LEN)?;
+            let desc = &buf[off..];
+            let nnsids = usize::try_from(le_u32(desc, 4))
+                .expect("32-bit systems unsupported");
+            let len = ANA_DESC_LEN + nnsids * 4;
+            check_len(desc, len)?;
+
+            groups.push(AnaGroup {
+                id: le_u32(desc, 0),
+                change_count: le_u64(desc, 8),
+                state: (desc[16] & 0xf).into(),
+                nsids: desc[ANA_DESC_LEN..len]
+                    .chunks_exact(4)
+                    .map(|nsid| le_u32(nsid, 0))
+                    .collect(),
+            });
+            off += len;
+        }
+
+        Ok(Self { change_count: le_u64(buf, 0), groups })
+    }
+
+    /// How long the log is, as far as can be told from `buf`, the start of
+    /// it. This assumes that groups not yet seen have no namespaces, so may
+    /// be short until `buf` holds every group descriptor, and is exact once
+    /// `buf` holds the whole log.
+    pub fn required_len(buf: &[u8]) -> usize {
+        if buf.len() < ANA_HDR_LEN {
+            return ANA_HDR_LEN;
+        }
+        let mut groups = usize::from(le_u16(buf, 8));
+        let mut off = ANA_HDR_LEN;
+        while groups > 0 && buf.len() >= off + ANA_DESC_LEN {
+            let nnsids = usize::try_from(le_u32(buf, off + 4))
+                .expect("32-bit systems unsupported");
+            off += ANA_DESC_LEN + nnsids * 4;
+            groups -= 1;
+        }
+        off + groups * ANA_DESC_LEN
+    }
+
+    /// The ANA group and state of each namespace in the log.
+    pub fn namespace_states(&self) -> BTreeMap<u32, (u32, AnaState)> {
+        self.groups
+            .iter()
+            .flat_map(|group| {
+                group.nsids.iter().map(|&nsid| (nsid, (group.id, group.state)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(id: u32, state: u8, nsids: &[u32]) -> Vec<u8> {
+        let mut buf = vec![0u8; ANA_DESC_LEN];
+        buf[0..4].copy_from_slice(&id.to_le_bytes());
+        buf[4..8].copy_from_slice(&(nsids.len() as u32).to_le_bytes());
+        buf[8..16].copy_from_slice(&(u64::from(id) * 10).to_le_bytes());
+        buf[16] = state;
+        for nsid in nsids {
+            buf.extend_from_slice(&nsid.to_le_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn decode_ana_log() {
+        let mut buf = vec![0u8; ANA_HDR_LEN];
+        buf[0..8].copy_from_slice(&42u64.to_le_bytes());
+        buf[8..10].copy_from_slice(&3u16.to_le_bytes());
+        buf.extend(group(1, 0x01, &[1, 3]));
+        buf.extend(group(2, 0x03, &[2]));
+        buf.extend(group(3, 0x0f, &[]));
+        // Trailing space left over from reading more than the log holds.
+        buf.extend([0u8; 64]);
+
+        let log = AnaLog::decode(&buf).unwrap();
+        assert_eq!(log.change_count, 42);
+        assert_eq!(log.groups.len(), 3);
+        assert_eq!(log.groups[0].nsids, vec![1, 3]);
+        assert_eq!(log.groups[1].change_count, 20);
+        assert_eq!(log.groups[2].state, AnaState::Change);
+
+        let states = log.namespace_states();
+        assert_eq!(states[&1], (1, AnaState::Optimized));
+        assert_eq!(states[&2], (2, AnaState::Inaccessible));
+        assert_eq!(states[&3], (1, AnaState::Optimized));
+        assert_eq!(states.len(), 3);
+    }
+
+    #[test]
+    fn ana_log_required_len() {
+        let mut buf = vec![0u8; ANA_HDR_LEN];
+        buf[8..10].copy_from_slice(&3u16.to_le_bytes());
+        buf.extend(group(1, 0x01, &[1, 3]));
+        buf.extend(group(2, 0x03, &[2]));
+        buf.extend(group(3, 0x0f, &[]));
+
+        assert_eq!(AnaLog::required_len(&buf[..8]), ANA_HDR_LEN);
+        assert_eq!(AnaLog::required_len(&buf[..ANA_HDR_LEN]), 16 + 3 * 32);
+        assert_eq!(AnaLog::required_len(&buf[..48]), 16 + 40 + 2 * 32);
+        assert_eq!(AnaLog::required_len(&buf[..120]), 16 + 40 + 36 + 32);
+        assert_eq!(AnaLog::required_len(&buf), buf.len());
+        assert_eq!(AnaLog::required_len(&[0u8; 64]), ANA_HDR_LEN);
+    }
+
+    #[test]
+    fn decode_truncated_ana_log() {
+        let mut buf = vec![0u8; ANA_HDR_LEN];
+        buf[8..10].copy_from_slice(&1u16.to_le_bytes());
+        buf.extend(group(1, 0x02, &[1, 2, 3]));
+        buf.truncate(buf.len() - 4);
+        assert_eq!(
+            AnaLog::decode(&buf),
+            Err(DecodeError::TooShort { expected: 44, actual: 40 })
+        );
+    }
+}
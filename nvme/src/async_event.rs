@@ -0,0 +1,135 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Changed Namespace List log page and the Asynchronous Event
+//! Configuration feature.
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::le_u32,
+};
+
+/// Log page identifier of the Changed Namespace List log (1.2).
+pub const CHANGED_NS_LID: u32 = 0x04;
+/// Size of the Changed Namespace List log.
+pub const CHANGED_NS_LEN: usize = 4096;
+/// When more namespaces have changed than fit in the log, the first entry is
+/// set to this value and the rest are zero.
+const CHANGED_NS_OVERFLOW: u32 = 0xffff_ffff;
+
+/// The Changed Namespace List log (04h).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangedNamespaces {
+    /// The namespaces that have changed since the log was last read.
+    List(Vec<u32>),
+    /// More than 1024 namespaces have changed, so every namespace should be
+    /// rediscovered.
+    Overflow,
+}
+
+impl ChangedNamespaces {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, CHANGED_NS_LEN)?;
+        if le_u32(buf, 0) == CHANGED_NS_OVERFLOW {
+            return Ok(Self::Overflow);
+        }
+
+        // The list is terminated by the first zero entry.
+        let nsids = buf[..CHANGED_NS_LEN]
+            .chunks_exact(4)
+            .map(|nsid| le_u32(nsid, 0))
+            .take_while(|&nsid| nsid != 0)
+            .collect();
+        Ok(Self::List(nsids))
+    }
+}
+
+/// Feature identifier of the Asynchronous Event Configuration feature.
+pub const ASYNC_EVENT_CONFIG_FID: u32 = 0x0b;
+
+/// The Asynchronous Event Configuration feature (0Bh), which selects the
+/// events that are reported to the host. The same layout is used to describe
+/// which events a controller supports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AsyncEventConfig(pub u32);
+
+impl AsyncEventConfig {
+    /// The SMART / Health critical warnings that generate an event.
+    pub fn smart_critical_warnings(&self) -> u8 {
+        (self.0 & 0xff) as u8
+    }
+
+    /// Namespace Attribute Notices, which report changes through the Changed
+    /// Namespace List log.
+    pub fn namespace_attribute(&self) -> bool {
+        self.0 & (1 << 8) != 0
+    }
+
+    pub fn firmware_activation(&self) -> bool {
+        self.0 & (1 << 9) != 0
+    }
+
+    pub fn telemetry_log(&self) -> bool {
+        self.0 & (1 << 10) != 0
+    }
+
+    pub fn ana_change(&self) -> bool {
+        self.0 & (1 << 11) != 0
+    }
+
+    pub fn predictable_latency(&self) -> bool {
+        self.0 & (1 << 12) != 0
+    }
+
+    pub fn lba_status(&self) -> bool {
+        self.0 & (1 << 13) != 0
+    }
+
+    pub fn endurance_group(&self) -> bool {
+        self.0 & (1 << 14) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_changed_namespaces() {
+        let mut buf = vec![0u8; CHANGED_NS_LEN];
+        buf[0..4].copy_from_slice(&3u32.to_le_bytes());
+        buf[4..8].copy_from_slice(&17u32.to_le_bytes());
+        // Anything after the terminating zero is ignored.
+        buf[12..16].copy_from_slice(&9u32.to_le_bytes());
+        assert_eq!(
+            ChangedNamespaces::decode(&buf),
+            Ok(ChangedNamespaces::List(vec![3, 17]))
+        );
+
+        buf[0..4].copy_from_slice(&CHANGED_NS_OVERFLOW.to_le_bytes());
+        assert_eq!(
+            ChangedNamespaces::decode(&buf),
+            Ok(ChangedNamespaces::Overflow)
+        );
+
+        let full: Vec<u8> = (1..=1024u32).flat_map(u32::to_le_bytes).collect();
+        let ChangedNamespaces::List(nsids) =
+            ChangedNamespaces::decode(&full).unwrap()
+        else {
+            panic!("expected a list of namespaces");
+        };
+        assert_eq!(nsids.len(), 1024);
+    }
+
+    #[test]
+    fn decode_async_event_config() {
+        let config = AsyncEventConfig(0x0000_0a1f);
+        assert_eq!(config.smart_critical_warnings(), 0x1f);
+        assert!(!config.namespace_attribute());
+        assert!(config.firmware_activation());
+        assert!(config.ana_change());
+        assert!(!config.telemetry_log());
+        assert!(!config.endurance_group());
+    }
+}
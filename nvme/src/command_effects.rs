@@ -0,0 +1,196 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Commands Supported and Effects log page (1.2).
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::le_u32,
+};
+
+/// Log page identifier of the Commands Supported and Effects log.
+pub const COMMAND_EFFECTS_LID: u32 = 0x05;
+/// Size of the Commands Supported and Effects log.
+pub const COMMAND_EFFECTS_LEN: usize = 4096;
+/// Number of opcodes in each of the admin and I/O tables.
+const NUM_OPCODES: usize = 256;
+/// Offset of the I/O command table.
+const IO_TABLE_OFF: usize = NUM_OPCODES * 4;
+
+/// Restrictions on submitting a command while other commands are
+/// outstanding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandSubmission {
+    /// No restriction.
+    Unrestricted,
+    /// The command may only be submitted when no other command is
+    /// outstanding to the same namespace.
+    SingleNamespace,
+    /// The command may only be submitted when no other command is
+    /// outstanding to any namespace.
+    AllNamespaces,
+    Unknown(u8),
+}
+
+impl From<u8> for CommandSubmission {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Unrestricted,
+            1 => Self::SingleNamespace,
+            2 => Self::AllNamespaces,
+            cse => Self::Unknown(cse),
+        }
+    }
+}
+
+/// The entry for a single opcode in the Commands Supported and Effects log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandEffects(pub u32);
+
+impl CommandEffects {
+    /// Command Supported (CSUPP).
+    pub fn supported(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+
+    /// Logical Block Content Change (LBCC): the command may change the data
+    /// stored in namespaces.
+    pub fn lba_content_change(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    /// Namespace Capability Change (NCC): the command may change the
+    /// capabilities of namespaces, such as their size or format.
+    pub fn namespace_capability_change(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    /// Namespace Inventory Change (NIC): the command may create or delete
+    /// namespaces.
+    pub fn namespace_inventory_change(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+
+    /// Controller Capability Change (CCC): the command may change the
+    /// capabilities of the controller.
+    pub fn controller_capability_change(&self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+
+    /// Command Submission and Execution (CSE).
+    pub fn submission(&self) -> CommandSubmission {
+        (((self.0 >> 16) & 0x7) as u8).into()
+    }
+
+    /// UUID Selection Supported (1.4).
+    pub fn uuid_selection(&self) -> bool {
+        self.0 & (1 << 19) != 0
+    }
+
+    /// Whether the command may change anything beyond returning data, and so
+    /// should only be issued while holding a write lock.
+    pub fn modifies_device(&self) -> bool {
+        self.lba_content_change()
+            || self.namespace_capability_change()
+            || self.namespace_inventory_change()
+            || self.controller_capability_change()
+    }
+}
+
+/// The Commands Supported and Effects log (05h).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandEffectsLog {
+    admin: Vec<CommandEffects>,
+    io: Vec<CommandEffects>,
+}
+
+impl CommandEffectsLog {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, COMMAND_EFFECTS_LEN)?;
+        let table = |off: usize| {
+            (0..NUM_OPCODES)
+                .map(|op| CommandEffects(le_u32(buf, off + op * 4)))
+                .collect()
+        };
+        Ok(Self { admin: table(0), io: table(IO_TABLE_OFF) })
+    }
+
+    /// The effects of the admin command `opcode`.
+    pub fn admin(&self, opcode: u8) -> CommandEffects {
+        self.admin[usize::from(opcode)]
+    }
+
+    /// The effects of the I/O command `opcode`.
+    pub fn io(&self, opcode: u8) -> CommandEffects {
+        self.io[usize::from(opcode)]
+    }
+
+    /// The supported admin commands and their effects.
+    pub fn supported_admin(
+        &self,
+    ) -> impl Iterator<Item = (u8, CommandEffects)> + '_ {
+        supported(&self.admin)
+    }
+
+    /// The supported I/O commands and their effects.
+    pub fn supported_io(
+        &self,
+    ) -> impl Iterator<Item = (u8, CommandEffects)> + '_ {
+        supported(&self.io)
+    }
+}
+
+fn supported(
+    table: &[CommandEffects],
+) -> impl Iterator<Item = (u8, CommandEffects)> + '_ {
+    (0..=u8::MAX)
+        .zip(table.iter().copied())
+        .filter(|(_, effects)| effects.supported())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_command_effects() {
+        let mut buf = vec![0u8; COMMAND_EFFECTS_LEN];
+        // Admin Get Log Page: supported, no effects.
+        buf[0x02 * 4..0x02 * 4 + 4].copy_from_slice(&0x1u32.to_le_bytes());
+        // Admin Format NVM: LBCC, NCC, all namespaces.
+        buf[0x80 * 4..0x80 * 4 + 4]
+            .copy_from_slice(&0x0002_0007u32.to_le_bytes());
+        // Admin vendor unique C1h: LBCC, NIC, CCC, UUID selection.
+        buf[0xc1 * 4..0xc1 * 4 + 4]
+            .copy_from_slice(&0x0008_001bu32.to_le_bytes());
+        // I/O Write: LBCC.
+        let io = IO_TABLE_OFF + 4;
+        buf[io..io + 4].copy_from_slice(&0x3u32.to_le_bytes());
+
+        let log = CommandEffectsLog::decode(&buf).unwrap();
+        let getlog = log.admin(0x02);
+        assert!(getlog.supported());
+        assert!(!getlog.modifies_device());
+        assert_eq!(getlog.submission(), CommandSubmission::Unrestricted);
+
+        let format = log.admin(0x80);
+        assert!(format.lba_content_change());
+        assert!(format.namespace_capability_change());
+        assert!(!format.namespace_inventory_change());
+        assert_eq!(format.submission(), CommandSubmission::AllNamespaces);
+
+        let vuc = log.admin(0xc1);
+        assert!(vuc.namespace_inventory_change());
+        assert!(vuc.controller_capability_change());
+        assert!(vuc.uuid_selection());
+
+        assert!(!log.admin(0x06).supported());
+        assert!(log.io(0x01).lba_content_change());
+        assert!(!log.io(0x02).supported());
+
+        let admin: Vec<_> = log.supported_admin().map(|(op, _)| op).collect();
+        assert_eq!(admin, vec![0x02, 0x80, 0xc1]);
+        assert_eq!(log.supported_io().count(), 1);
+    }
+}
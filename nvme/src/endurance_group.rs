@@ -0,0 +1,219 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Endurance Group Information log page, the Identify NVM Set List and
+//! the Identify Endurance Group List (1.4).
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{le_u128, le_u16, le_u32},
+};
+
+/// Log page identifier of the Endurance Group Information log.
+pub const ENDURANCE_GROUP_LID: u32 = 0x09;
+/// Size of the Endurance Group Information log.
+pub const ENDURANCE_GROUP_LEN: usize = 512;
+
+/// The Endurance Group Information log (09h) for a single endurance group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnduranceGroupLog {
+    pub critical_warning: u8,
+    /// Percentage of the spare capacity remaining.
+    pub available_spare: u8,
+    pub available_spare_threshold: u8,
+    /// Estimate of the percentage of the rated endurance consumed. This may
+    /// exceed 100.
+    pub percentage_used: u8,
+    /// Estimate of the total data that can be written over the life of the
+    /// endurance group, in billions of bytes.
+    pub endurance_estimate: u128,
+    /// Data read by the host, in billions of bytes.
+    pub data_units_read: u128,
+    /// Data written by the host, in billions of bytes.
+    pub data_units_written: u128,
+    /// Data written to the media, including internal writes, in billions of
+    /// bytes.
+    pub media_units_written: u128,
+    pub host_read_commands: u128,
+    pub host_write_commands: u128,
+    pub media_integrity_errors: u128,
+    pub error_log_entries: u128,
+}
+
+impl EnduranceGroupLog {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, ENDURANCE_GROUP_LEN)?;
+        Ok(Self {
+            critical_warning: buf[0],
+            available_spare: buf[3],
+            available_spare_threshold: buf[4],
+            percentage_used: buf[5],
+            endurance_estimate: le_u128(buf, 32),
+            data_units_read: le_u128(buf, 48),
+            data_units_written: le_u128(buf, 64),
+            media_units_written: le_u128(buf, 80),
+            host_read_commands: le_u128(buf, 96),
+            host_write_commands: le_u128(buf, 112),
+            media_integrity_errors: le_u128(buf, 128),
+            error_log_entries: le_u128(buf, 144),
+        })
+    }
+
+    /// The ratio of data written to the media to data written by the host,
+    /// or `None` if the host has not written anything.
+    pub fn write_amplification(&self) -> Option<f64> {
+        if self.data_units_written == 0 {
+            return None;
+        }
+        Some(self.media_units_written as f64 / self.data_units_written as f64)
+    }
+}
+
+/// Controller or Namespace Structure value of the Identify NVM Set List.
+pub const NVM_SET_LIST_CNS: u32 = 0x04;
+/// Size of the Identify NVM Set List.
+pub const NVM_SET_LIST_LEN: usize = 4096;
+/// The set entries start after a 128 byte header.
+const NVM_SET_HDR_LEN: usize = 128;
+/// Size of each NVM Set Attributes entry.
+const NVM_SET_ENTRY_LEN: usize = 128;
+/// The maximum number of entries in the NVM Set List.
+const MAX_NVM_SETS: usize = 31;
+
+/// An NVM Set Attributes entry from the Identify NVM Set List.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvmSet {
+    pub id: u16,
+    pub endurance_group: u16,
+    /// Typical time to complete a 4 KiB random read, in 100 nanosecond
+    /// units.
+    pub random_4k_read_typical: u32,
+    /// Optimal write size in bytes.
+    pub optimal_write_size: u32,
+    /// Total capacity of the set in bytes.
+    pub total_capacity: u128,
+    /// Capacity of the set not allocated to namespaces, in bytes.
+    pub unallocated_capacity: u128,
+}
+
+impl NvmSet {
+    fn decode(buf: &[u8]) -> Self {
+        Self {
+            id: le_u16(buf, 0),
+            endurance_group: le_u16(buf, 2),
+            random_4k_read_typical: le_u32(buf, 8),
+            optimal_write_size: le_u32(buf, 12),
+            total_capacity: le_u128(buf, 16),
+            unallocated_capacity: le_u128(buf, 32),
+        }
+    }
+
+    /// Decode the Identify NVM Set List.
+    pub fn decode_list(buf: &[u8]) -> Result<Vec<Self>, DecodeError> {
+        check_len(buf, NVM_SET_LIST_LEN)?;
+        let count = usize::from(buf[0]);
+        if count > MAX_NVM_SETS {
+            return Err(DecodeError::InvalidCount { count, max: MAX_NVM_SETS });
+        }
+        Ok(buf[NVM_SET_HDR_LEN..NVM_SET_LIST_LEN]
+            .chunks_exact(NVM_SET_ENTRY_LEN)
+            .take(count)
+            .map(Self::decode)
+            .collect())
+    }
+}
+
+/// Controller or Namespace Structure value of the Identify Endurance Group
+/// List.
+pub const ENDURANCE_GROUP_LIST_CNS: u32 = 0x19;
+/// Size of the Identify Endurance Group List.
+pub const ENDURANCE_GROUP_LIST_LEN: usize = 4096;
+/// The maximum number of identifiers in the Endurance Group List.
+const MAX_ENDURANCE_GROUP_IDS: usize = 2047;
+
+/// Decode the Identify Endurance Group List into the endurance group
+/// identifiers it holds, in increasing order.
+pub fn decode_endurance_group_list(
+    buf: &[u8],
+) -> Result<Vec<u16>, DecodeError> {
+    check_len(buf, ENDURANCE_GROUP_LIST_LEN)?;
+    let count = usize::from(le_u16(buf, 0));
+    if count > MAX_ENDURANCE_GROUP_IDS {
+        return Err(DecodeError::InvalidCount {
+            count,
+            max: MAX_ENDURANCE_GROUP_IDS,
+        });
+    }
+    Ok((1..=count).map(|i| le_u16(buf, i * 2)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_endurance_group_log() {
+        let mut buf = [0u8; ENDURANCE_GROUP_LEN];
+        buf[0] = 0x1;
+        buf[3] = 95;
+        buf[4] = 10;
+        buf[5] = 3;
+        buf[32..48].copy_from_slice(&7000u128.to_le_bytes());
+        buf[64..80].copy_from_slice(&1000u128.to_le_bytes());
+        buf[80..96].copy_from_slice(&2500u128.to_le_bytes());
+        buf[112..128].copy_from_slice(&42u128.to_le_bytes());
+
+        let log = EnduranceGroupLog::decode(&buf).unwrap();
+        assert_eq!(log.critical_warning, 1);
+        assert_eq!(log.available_spare, 95);
+        assert_eq!(log.percentage_used, 3);
+        assert_eq!(log.endurance_estimate, 7000);
+        assert_eq!(log.host_write_commands, 42);
+        assert_eq!(log.write_amplification(), Some(2.5));
+
+        let idle = EnduranceGroupLog::decode(&[0u8; 512]).unwrap();
+        assert_eq!(idle.write_amplification(), None);
+    }
+
+    #[test]
+    fn decode_nvm_set_list() {
+        let mut buf = vec![0u8; NVM_SET_LIST_LEN];
+        buf[0] = 2;
+        for (i, (id, engid)) in [(1u16, 1u16), (2, 1)].into_iter().enumerate() {
+            let off = NVM_SET_HDR_LEN + i * NVM_SET_ENTRY_LEN;
+            buf[off..off + 2].copy_from_slice(&id.to_le_bytes());
+            buf[off + 2..off + 4].copy_from_slice(&engid.to_le_bytes());
+            buf[off + 16..off + 32]
+                .copy_from_slice(&(u128::from(id) << 40).to_le_bytes());
+        }
+
+        let sets = NvmSet::decode_list(&buf).unwrap();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[1].id, 2);
+        assert_eq!(sets[1].endurance_group, 1);
+        assert_eq!(sets[1].total_capacity, 2 << 40);
+
+        buf[0] = 32;
+        assert_eq!(
+            NvmSet::decode_list(&buf),
+            Err(DecodeError::InvalidCount { count: 32, max: 31 })
+        );
+    }
+
+    #[test]
+    fn decode_endurance_group_ids() {
+        let mut buf = vec![0u8; ENDURANCE_GROUP_LIST_LEN];
+        buf[0..2].copy_from_slice(&3u16.to_le_bytes());
+        for (i, id) in [1u16, 4, 0x100].into_iter().enumerate() {
+            buf[2 + i * 2..4 + i * 2].copy_from_slice(&id.to_le_bytes());
+        }
+        assert_eq!(decode_endurance_group_list(&buf), Ok(vec![1, 4, 0x100]));
+
+        buf[0..2].copy_from_slice(&2048u16.to_le_bytes());
+        assert_eq!(
+            decode_endurance_group_list(&buf),
+            Err(DecodeError::InvalidCount { count: 2048, max: 2047 })
+        );
+    }
+}
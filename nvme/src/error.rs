@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+
+/// An error encountered while decoding a log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer is smaller than the log page requires.
+    TooShort { expected: usize, actual: usize },
+    /// The GUID identifying the log page does not match.
+    BadGuid,
+    /// The log page version is not one that can be decoded.
+    UnsupportedVersion(u16),
+    /// A count in the log page exceeds the number of entries it can hold.
+    InvalidCount { count: usize, max: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooShort { expected, actual } => write!(
+                f,
+                "log page is {actual} bytes but at least {expected} bytes \
+                are required"
+            ),
+            DecodeError::BadGuid => write!(f, "log page GUID does not match"),
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported log page version {v}")
+            }
+            DecodeError::InvalidCount { count, max } => write!(
+                f,
+                "log page reports {count} entries but holds at most {max}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Ensure `buf` holds at least `expected` bytes.
+pub(crate) fn check_len(
+    buf: &[u8],
+    expected: usize,
+) -> Result<(), DecodeError> {
+    if buf.len() < expected {
+        return Err(DecodeError::TooShort { expected, actual: buf.len() });
+    }
+    Ok(())
+}
@@ -2,6 +2,23 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+pub mod ana;
+pub mod async_event;
+pub mod command_effects;
+pub mod endurance_group;
+mod error;
+pub mod ocp;
+pub mod persistent_event;
+pub mod predictable_latency;
+pub mod self_test;
+pub mod smart;
+pub mod supported_log_pages;
+pub mod telemetry;
+mod util;
+pub mod wdc;
+
+pub use error::DecodeError;
+
 // NVMe completion status code type
 
 /// Generic Command Status
@@ -15,6 +32,35 @@ pub const NVME_CQE_SCT_PATH: u32 = 3;
 /// Vendor Specific
 pub const NVME_CQE_SCT_VENDOR: u32 = 7;
 
+// NVMe completion status code (generic)
+
+/// Successful Completion
+pub const NVME_CQE_SC_GEN_SUCCESS: u32 = 0x0;
+/// Invalid Command Opcode
+pub const NVME_CQE_SC_GEN_INV_OPC: u32 = 0x1;
+/// Invalid Field in Command
+pub const NVME_CQE_SC_GEN_INV_FLD: u32 = 0x2;
+/// Command ID Conflict
+pub const NVME_CQE_SC_GEN_ID_CNFL: u32 = 0x3;
+/// Data Transfer Error
+pub const NVME_CQE_SC_GEN_DATA_XFR_ERR: u32 = 0x4;
+/// Aborted due to Power Loss
+pub const NVME_CQE_SC_GEN_ABORT_PWRLOSS: u32 = 0x5;
+/// Internal Error
+pub const NVME_CQE_SC_GEN_INTERNAL_ERR: u32 = 0x6;
+/// Command Abort Requested
+pub const NVME_CQE_SC_GEN_ABORT_REQUEST: u32 = 0x7;
+/// Command Aborted due to SQ Deletion
+pub const NVME_CQE_SC_GEN_ABORT_SQ_DEL: u32 = 0x8;
+/// Command Aborted due to Failed Fused Command
+pub const NVME_CQE_SC_GEN_ABORT_FUSE_FAIL: u32 = 0x9;
+/// Command Aborted due to Missing Fused Command
+pub const NVME_CQE_SC_GEN_ABORT_FUSE_MISS: u32 = 0xa;
+/// Invalid Namespace or Format
+pub const NVME_CQE_SC_GEN_INV_NS: u32 = 0xb;
+/// Command Sequence Error
+pub const NVME_CQE_SC_GEN_CMD_SEQ_ERR: u32 = 0xc;
+
 // NVMe completion status code (command specific)
 
 /// Completion Queue Invalid
@@ -0,0 +1,857 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Log pages defined by the OCP Datacenter NVMe SSD Specification.
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{ascii_field, le_u128, le_u16, le_u32, le_u64},
+};
+use std::time::Duration;
+
+/// Length of the GUID that ends each OCP log page.
+pub const OCP_GUID_LEN: usize = 16;
+
+/// Offset of the GUID in the 512 byte OCP log pages.
+const GUID_OFF_512: usize = 0x1f0;
+/// Offset of the GUID in the 4096 byte OCP log pages.
+const GUID_OFF_4096: usize = 0xff0;
+
+/// Verify the GUID found at `off` in an OCP log page and return the log page
+/// version that precedes it.
+fn check_guid_and_version(
+    buf: &[u8],
+    off: usize,
+    guid: &[u8; OCP_GUID_LEN],
+) -> Result<u16, DecodeError> {
+    if &buf[off..off + OCP_GUID_LEN] != guid {
+        return Err(DecodeError::BadGuid);
+    }
+    match le_u16(buf, off - 2) {
+        0 => Err(DecodeError::UnsupportedVersion(0)),
+        version => Ok(version),
+    }
+}
+
+/// Log page identifier of the SMART / Health Information Extended log.
+pub const OCP_SMART_LOG_LID: u32 = 0xc0;
+/// Size of the SMART / Health Information Extended log.
+pub const OCP_SMART_LOG_LEN: usize = 512;
+/// GUID of the SMART / Health Information Extended log
+/// (AFD514C97C6F4F9CA4F2BFEA2810AFC5h) as it appears in the log page.
+pub const OCP_SMART_LOG_GUID: [u8; OCP_GUID_LEN] = [
+    0xc5, 0xaf, 0x10, 0x28, 0xea, 0xbf, 0xf2, 0xa4, 0x9c, 0x4f, 0x6f, 0x7c,
+    0xc9, 0x14, 0xd5, 0xaf,
+];
+/// The first log page version that includes the fields added in version 2.0
+/// of the specification.
+const OCP_SMART_LOG_V2: u16 = 3;
+
+/// A NAND block count made up of a raw count and a normalized value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NandBlockCount {
+    pub raw: u64,
+    /// Percentage of the spare blocks remaining.
+    pub normalized: u16,
+}
+
+impl NandBlockCount {
+    fn decode(buf: &[u8], off: usize) -> Self {
+        // A 6 byte raw count followed by a 2 byte normalized value.
+        Self {
+            raw: le_u64(buf, off) & 0xffff_ffff_ffff,
+            normalized: le_u16(buf, off + 6),
+        }
+    }
+}
+
+/// The current thermal throttling state of the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleStatus {
+    Unthrottled,
+    FirstLevel,
+    SecondLevel,
+    ThirdLevel,
+    Unknown(u8),
+}
+
+impl From<u8> for ThrottleStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Unthrottled,
+            1 => Self::FirstLevel,
+            2 => Self::SecondLevel,
+            3 => Self::ThirdLevel,
+            status => Self::Unknown(status),
+        }
+    }
+}
+
+/// The version of the OCP specification a device implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcpSpecVersion {
+    pub major: u8,
+    pub minor: u16,
+    pub point: u16,
+    pub errata: u8,
+}
+
+/// Fields of the SMART / Health Information Extended log that were added in
+/// version 2.0 of the specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcpSmartLogV2 {
+    /// Number of write commands that were not aligned to the indirection
+    /// unit.
+    pub unaligned_io: u64,
+    pub security_version: u64,
+    /// Namespace utilization, in logical blocks, across all namespaces.
+    pub total_nuse: u64,
+    /// Number of times the power loss protection circuitry has been used.
+    pub plp_start_count: u128,
+    /// Estimated total data, in bytes, that can be written to the device
+    /// over its lifetime.
+    pub endurance_estimate: u128,
+    pub pcie_link_retraining_count: u64,
+    pub power_state_change_count: u64,
+}
+
+/// The OCP SMART / Health Information Extended log (C0h).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcpSmartLog {
+    /// Bytes written to the media, including internal writes.
+    pub physical_media_units_written: u128,
+    /// Bytes read from the media, including internal reads.
+    pub physical_media_units_read: u128,
+    pub bad_user_nand_blocks: NandBlockCount,
+    pub bad_system_nand_blocks: NandBlockCount,
+    /// Number of times data was recovered with XOR parity.
+    pub xor_recovery_count: u64,
+    pub uncorrectable_read_error_count: u64,
+    pub soft_ecc_error_count: u64,
+    pub e2e_detected_errors: u32,
+    pub e2e_corrected_errors: u32,
+    /// Percentage of the rated endurance of the system area consumed.
+    pub system_data_percent_used: u8,
+    pub refresh_count: u64,
+    pub max_user_data_erase_count: u32,
+    pub min_user_data_erase_count: u32,
+    pub thermal_throttling_events: u8,
+    pub thermal_throttling_status: ThrottleStatus,
+    pub spec_version: OcpSpecVersion,
+    pub pcie_correctable_error_count: u64,
+    pub incomplete_shutdowns: u32,
+    pub percent_free_blocks: u8,
+    /// Percentage of the original charge the power loss protection
+    /// capacitors can hold.
+    pub capacitor_health: u16,
+    /// Present when the log page version includes the OCP 2.0 fields.
+    pub v2: Option<OcpSmartLogV2>,
+    pub log_page_version: u16,
+}
+
+impl OcpSmartLog {
+    /// Decode the log page from the raw bytes returned by the controller,
+    /// verifying the log page GUID and version.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, OCP_SMART_LOG_LEN)?;
+        let log_page_version =
+            check_guid_and_version(buf, GUID_OFF_512, &OCP_SMART_LOG_GUID)?;
+
+        let v2 =
+            (log_page_version >= OCP_SMART_LOG_V2).then(|| OcpSmartLogV2 {
+                unaligned_io: le_u64(buf, 136),
+                security_version: le_u64(buf, 144),
+                total_nuse: le_u64(buf, 152),
+                plp_start_count: le_u128(buf, 160),
+                endurance_estimate: le_u128(buf, 176),
+                pcie_link_retraining_count: le_u64(buf, 192),
+                power_state_change_count: le_u64(buf, 200),
+            });
+
+        Ok(Self {
+            physical_media_units_written: le_u128(buf, 0),
+            physical_media_units_read: le_u128(buf, 16),
+            bad_user_nand_blocks: NandBlockCount::decode(buf, 32),
+            bad_system_nand_blocks: NandBlockCount::decode(buf, 40),
+            xor_recovery_count: le_u64(buf, 48),
+            uncorrectable_read_error_count: le_u64(buf, 56),
+            soft_ecc_error_count: le_u64(buf, 64),
+            e2e_detected_errors: le_u32(buf, 72),
+            e2e_corrected_errors: le_u32(buf, 76),
+            system_data_percent_used: buf[80],
+            // A 7 byte count.
+            refresh_count: le_u64(buf, 80) >> 8,
+            max_user_data_erase_count: le_u32(buf, 88),
+            min_user_data_erase_count: le_u32(buf, 92),
+            thermal_throttling_events: buf[96],
+            thermal_throttling_status: buf[97].into(),
+            spec_version: OcpSpecVersion {
+                errata: buf[98],
+                point: le_u16(buf, 99),
+                minor: le_u16(buf, 101),
+                major: buf[103],
+            },
+            pcie_correctable_error_count: le_u64(buf, 104),
+            incomplete_shutdowns: le_u32(buf, 112),
+            percent_free_blocks: buf[120],
+            capacitor_health: le_u16(buf, 128),
+            v2,
+            log_page_version,
+        })
+    }
+}
+
+/// Log page identifier of the Error Recovery log.
+pub const OCP_ERROR_RECOVERY_LOG_LID: u32 = 0xc1;
+/// Size of the Error Recovery log.
+pub const OCP_ERROR_RECOVERY_LOG_LEN: usize = 512;
+/// GUID of the Error Recovery log (5A1983BA3DFD4DABAE3430FE2131D944h) as it
+/// appears in the log page.
+pub const OCP_ERROR_RECOVERY_LOG_GUID: [u8; OCP_GUID_LEN] = [
+    0x44, 0xd9, 0x31, 0x21, 0xfe, 0x30, 0x34, 0xae, 0xab, 0x4d, 0xfd, 0x3d,
+    0xba, 0x83, 0x19, 0x5a,
+];
+
+/// The action the host should take to recover a device after a panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceRecoveryAction {
+    NoAction,
+    FormatNvm,
+    VendorSpecificCommand,
+    VendorAnalysis,
+    DeviceReplacement,
+    Sanitize,
+    Unknown(u8),
+}
+
+impl From<u8> for DeviceRecoveryAction {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::NoAction,
+            1 => Self::FormatNvm,
+            2 => Self::VendorSpecificCommand,
+            3 => Self::VendorAnalysis,
+            4 => Self::DeviceReplacement,
+            5 => Self::Sanitize,
+            action => Self::Unknown(action),
+        }
+    }
+}
+
+/// The OCP Error Recovery log (C1h).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcpErrorRecoveryLog {
+    /// Time in milliseconds the host should wait after a panic before
+    /// resetting the device.
+    pub panic_reset_wait_time: u16,
+    /// Bitmask of the resets the host may use to recover from a panic.
+    pub panic_reset_action: u8,
+    pub device_recovery_action_1: DeviceRecoveryAction,
+    /// Identifier of the most recent panic, or zero if none has occurred.
+    pub panic_id: u64,
+    /// Bitmask of the recovery mechanisms the device supports.
+    pub device_capabilities: u32,
+    /// The vendor unique opcode to issue when recovery requires a vendor
+    /// specific command.
+    pub vendor_specific_recovery_opcode: u8,
+    pub vendor_specific_cdw12: u32,
+    pub vendor_specific_cdw13: u32,
+    /// Timeout of the vendor specific command in seconds.
+    pub vendor_specific_command_timeout: u8,
+    pub device_recovery_action_2: DeviceRecoveryAction,
+    /// Timeout of the second recovery action in seconds.
+    pub device_recovery_action_2_timeout: u8,
+    pub log_page_version: u16,
+}
+
+impl OcpErrorRecoveryLog {
+    /// Decode the log page from the raw bytes returned by the controller,
+    /// verifying the log page GUID and version.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, OCP_ERROR_RECOVERY_LOG_LEN)?;
+        let log_page_version = check_guid_and_version(
+            buf,
+            GUID_OFF_512,
+            &OCP_ERROR_RECOVERY_LOG_GUID,
+        )?;
+
+        Ok(Self {
+            panic_reset_wait_time: le_u16(buf, 0x00),
+            panic_reset_action: buf[0x02],
+            device_recovery_action_1: buf[0x03].into(),
+            panic_id: le_u64(buf, 0x04),
+            device_capabilities: le_u32(buf, 0x0c),
+            vendor_specific_recovery_opcode: buf[0x10],
+            vendor_specific_cdw12: le_u32(buf, 0x14),
+            vendor_specific_cdw13: le_u32(buf, 0x18),
+            vendor_specific_command_timeout: buf[0x1c],
+            device_recovery_action_2: buf[0x1d].into(),
+            device_recovery_action_2_timeout: buf[0x1e],
+            log_page_version,
+        })
+    }
+}
+
+/// Log page identifier of the Latency Monitor log.
+pub const OCP_LATENCY_MONITOR_LOG_LID: u32 = 0xc3;
+/// Size of the Latency Monitor log.
+pub const OCP_LATENCY_MONITOR_LOG_LEN: usize = 512;
+/// GUID of the Latency Monitor log (85D45E58D4E643709C6C84D08CC07A92h) as it
+/// appears in the log page.
+pub const OCP_LATENCY_MONITOR_LOG_GUID: [u8; OCP_GUID_LEN] = [
+    0x92, 0x7a, 0xc0, 0x8c, 0xd0, 0x84, 0x6c, 0x9c, 0x70, 0x43, 0xe6, 0xd4,
+    0x58, 0x5e, 0xd4, 0x85,
+];
+/// Number of latency buckets tracked by the latency monitor.
+pub const LATENCY_BUCKETS: usize = 4;
+/// Latency thresholds are reported in 5 millisecond increments.
+const LATENCY_THRESHOLD_UNIT_MS: u32 = 5;
+
+/// Convert a latency threshold as stored by the device, which is in 5 ms
+/// increments offset by one, into a `Duration`.
+pub fn latency_threshold(raw: u8) -> Duration {
+    Duration::from_millis(u64::from(
+        (u32::from(raw) + 1) * LATENCY_THRESHOLD_UNIT_MS,
+    ))
+}
+
+/// Convert a latency threshold in milliseconds into the value stored by the
+/// device. Thresholds must be a non-zero multiple of 5 ms no larger than
+/// 1280 ms.
+pub fn latency_threshold_from_millis(ms: u32) -> Option<u8> {
+    if ms == 0 || !ms.is_multiple_of(LATENCY_THRESHOLD_UNIT_MS) {
+        return None;
+    }
+    u8::try_from(ms / LATENCY_THRESHOLD_UNIT_MS - 1).ok()
+}
+
+/// Counts of commands, by type, whose latency fell in a bucket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BucketCounters {
+    pub read: u32,
+    pub write: u32,
+    pub deallocate: u32,
+}
+
+/// The latency range covered by a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyBucket {
+    /// Commands with a latency at least this long are counted in the bucket.
+    pub lower: Duration,
+    /// Commands with a latency this long or longer are counted in the next
+    /// bucket. The last bucket is unbounded.
+    pub upper: Option<Duration>,
+}
+
+/// The bucket counters and the most recent latency events recorded for each
+/// bucket, in either the active or the static measurement window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub counters: [BucketCounters; LATENCY_BUCKETS],
+    /// Timestamp of the most recent read, write and deallocate command that
+    /// fell in each bucket.
+    pub timestamps: [[u64; 3]; LATENCY_BUCKETS],
+    /// Latency in milliseconds of the most recent read, write and deallocate
+    /// command that fell in each bucket.
+    pub measured_latency: [[u16; 3]; LATENCY_BUCKETS],
+    /// Bitmask of the timestamps that are in units of the host-provided
+    /// timestamp rather than time since power on.
+    pub latency_stamp_units: u16,
+}
+
+impl LatencyStats {
+    fn decode(buf: &[u8], off: usize) -> Self {
+        let mut stats = Self {
+            counters: [BucketCounters::default(); LATENCY_BUCKETS],
+            timestamps: [[0; 3]; LATENCY_BUCKETS],
+            measured_latency: [[0; 3]; LATENCY_BUCKETS],
+            latency_stamp_units: le_u16(buf, off + 0xb8),
+        };
+        for bucket in 0..LATENCY_BUCKETS {
+            // Each bucket holds a read, write, deallocate and reserved
+            // counter.
+            let counters = off + bucket * 16;
+            stats.counters[bucket] = BucketCounters {
+                read: le_u32(buf, counters),
+                write: le_u32(buf, counters + 4),
+                deallocate: le_u32(buf, counters + 8),
+            };
+            for io in 0..3 {
+                let idx = bucket * 3 + io;
+                stats.timestamps[bucket][io] =
+                    le_u64(buf, off + 0x40 + idx * 8);
+                stats.measured_latency[bucket][io] =
+                    le_u16(buf, off + 0xa0 + idx * 2);
+            }
+        }
+        stats
+    }
+}
+
+/// The OCP Latency Monitor log (C3h).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcpLatencyMonitorLog {
+    pub feature_status: u8,
+    /// Time remaining in the active measurement window in 5 minute
+    /// increments.
+    pub active_bucket_timer: u16,
+    /// Length of the active measurement window in 5 minute increments.
+    pub active_bucket_timer_threshold: u16,
+    /// Raw latency thresholds A through D. See `buckets`.
+    pub active_thresholds: [u8; LATENCY_BUCKETS],
+    pub active_latency_config: u16,
+    /// Minimum time in 100 ms increments between recorded latency events.
+    pub active_latency_min_window: u8,
+    /// Statistics for the current measurement window.
+    pub active: LatencyStats,
+    /// Statistics for the last completed measurement window.
+    pub static_: LatencyStats,
+    pub debug_log_trigger_enable: u16,
+    pub debug_log_measured_latency: u16,
+    pub debug_log_latency_stamp: u64,
+    pub debug_log_ptr: u16,
+    pub debug_counter_trigger_source: u16,
+    pub debug_log_stamp_units: u8,
+    pub log_page_version: u16,
+}
+
+impl OcpLatencyMonitorLog {
+    /// Decode the log page from the raw bytes returned by the controller,
+    /// verifying the log page GUID and version.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, OCP_LATENCY_MONITOR_LOG_LEN)?;
+        let log_page_version = check_guid_and_version(
+            buf,
+            GUID_OFF_512,
+            &OCP_LATENCY_MONITOR_LOG_GUID,
+        )?;
+
+        Ok(Self {
+            feature_status: buf[0x00],
+            active_bucket_timer: le_u16(buf, 0x02),
+            active_bucket_timer_threshold: le_u16(buf, 0x04),
+            active_thresholds: [buf[0x06], buf[0x07], buf[0x08], buf[0x09]],
+            active_latency_config: le_u16(buf, 0x0a),
+            active_latency_min_window: buf[0x0c],
+            active: LatencyStats::decode(buf, 0x20),
+            static_: LatencyStats::decode(buf, 0xf0),
+            debug_log_trigger_enable: le_u16(buf, 0x1c0),
+            debug_log_measured_latency: le_u16(buf, 0x1c2),
+            debug_log_latency_stamp: le_u64(buf, 0x1c4),
+            debug_log_ptr: le_u16(buf, 0x1cc),
+            debug_counter_trigger_source: le_u16(buf, 0x1ce),
+            debug_log_stamp_units: buf[0x1d0],
+            log_page_version,
+        })
+    }
+
+    /// The latency range counted by each bucket, derived from the active
+    /// thresholds.
+    pub fn buckets(&self) -> [LatencyBucket; LATENCY_BUCKETS] {
+        let thresholds = self.active_thresholds.map(latency_threshold);
+        std::array::from_fn(|i| LatencyBucket {
+            lower: thresholds[i],
+            upper: thresholds.get(i + 1).copied(),
+        })
+    }
+}
+
+/// Feature identifier of the OCP Latency Monitor feature.
+pub const OCP_LATENCY_MONITOR_FID: u32 = 0xc5;
+/// Size of the data structure used to get and set the Latency Monitor
+/// feature.
+pub const OCP_LATENCY_MONITOR_CONFIG_LEN: usize = 512;
+
+/// The configuration of the OCP Latency Monitor feature (C5h).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyMonitorConfig {
+    /// Length of the active measurement window in 5 minute increments.
+    pub active_bucket_timer_threshold: u16,
+    /// Raw latency thresholds A through D. See
+    /// `latency_threshold_from_millis`.
+    pub active_thresholds: [u8; LATENCY_BUCKETS],
+    pub active_latency_config: u16,
+    /// Minimum time in 100 ms increments between recorded latency events.
+    pub active_latency_min_window: u8,
+    pub debug_log_trigger_enable: u16,
+    pub discard_debug_log: bool,
+    pub enable: bool,
+}
+
+impl LatencyMonitorConfig {
+    /// Set the latency thresholds from values in milliseconds, returning
+    /// `None` if any of them cannot be represented.
+    pub fn with_thresholds_millis(
+        mut self,
+        thresholds: [u32; LATENCY_BUCKETS],
+    ) -> Option<Self> {
+        for (raw, ms) in self.active_thresholds.iter_mut().zip(thresholds) {
+            *raw = latency_threshold_from_millis(ms)?;
+        }
+        Some(self)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, 0x0d)?;
+        Ok(Self {
+            active_bucket_timer_threshold: le_u16(buf, 0x00),
+            active_thresholds: [buf[0x02], buf[0x03], buf[0x04], buf[0x05]],
+            active_latency_config: le_u16(buf, 0x06),
+            active_latency_min_window: buf[0x08],
+            debug_log_trigger_enable: le_u16(buf, 0x09),
+            discard_debug_log: buf[0x0b] != 0,
+            enable: buf[0x0c] != 0,
+        })
+    }
+
+    /// Encode the configuration into the data structure sent with Set
+    /// Features.
+    pub fn encode(&self) -> [u8; OCP_LATENCY_MONITOR_CONFIG_LEN] {
+        let mut buf = [0u8; OCP_LATENCY_MONITOR_CONFIG_LEN];
+        buf[0x00..0x02]
+            .copy_from_slice(&self.active_bucket_timer_threshold.to_le_bytes());
+        buf[0x02..0x06].copy_from_slice(&self.active_thresholds);
+        buf[0x06..0x08]
+            .copy_from_slice(&self.active_latency_config.to_le_bytes());
+        buf[0x08] = self.active_latency_min_window;
+        buf[0x09..0x0b]
+            .copy_from_slice(&self.debug_log_trigger_enable.to_le_bytes());
+        buf[0x0b] = u8::from(self.discard_debug_log);
+        buf[0x0c] = u8::from(self.enable);
+        buf
+    }
+}
+
+/// Log page identifier of the Device Capabilities log.
+pub const OCP_DEVICE_CAPABILITIES_LOG_LID: u32 = 0xc4;
+/// Size of the Device Capabilities log.
+pub const OCP_DEVICE_CAPABILITIES_LOG_LEN: usize = 4096;
+/// GUID of the Device Capabilities log (B7053C914B58495D98C9E1D10D054297h) as
+/// it appears in the log page.
+pub const OCP_DEVICE_CAPABILITIES_LOG_GUID: [u8; OCP_GUID_LEN] = [
+    0x97, 0x42, 0x05, 0x0d, 0xd1, 0xe1, 0xc9, 0x98, 0x5d, 0x49, 0x58, 0x4b,
+    0x91, 0x3c, 0x05, 0xb7,
+];
+/// Number of DSSD power state descriptors in the Device Capabilities log.
+pub const DSSD_POWER_STATE_DESCRIPTORS: usize = 127;
+
+/// The OCP Device Capabilities log (C4h).
+///
+/// The command support fields are bitmasks defined by the specification
+/// where bit 0 indicates the command is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OcpDeviceCapabilitiesLog {
+    pub pcie_ports: u16,
+    pub oob_management_support: u16,
+    pub write_zeroes_support: u16,
+    pub sanitize_support: u16,
+    pub dataset_management_support: u16,
+    pub write_uncorrectable_support: u16,
+    pub fused_operation_support: u16,
+    /// The lowest DSSD power state the device supports.
+    pub min_valid_dssd_power_state: u16,
+    /// Each descriptor maps a DSSD power state to an NVMe power state.
+    pub dssd_power_state_descriptors: [u8; DSSD_POWER_STATE_DESCRIPTORS],
+    pub log_page_version: u16,
+}
+
+impl OcpDeviceCapabilitiesLog {
+    /// Decode the log page from the raw bytes returned by the controller,
+    /// verifying the log page GUID and version.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, OCP_DEVICE_CAPABILITIES_LOG_LEN)?;
+        let log_page_version = check_guid_and_version(
+            buf,
+            GUID_OFF_4096,
+            &OCP_DEVICE_CAPABILITIES_LOG_GUID,
+        )?;
+
+        let mut dssd_power_state_descriptors =
+            [0u8; DSSD_POWER_STATE_DESCRIPTORS];
+        dssd_power_state_descriptors
+            .copy_from_slice(&buf[0x11..0x11 + DSSD_POWER_STATE_DESCRIPTORS]);
+
+        Ok(Self {
+            pcie_ports: le_u16(buf, 0x00),
+            oob_management_support: le_u16(buf, 0x02),
+            write_zeroes_support: le_u16(buf, 0x04),
+            sanitize_support: le_u16(buf, 0x06),
+            dataset_management_support: le_u16(buf, 0x08),
+            write_uncorrectable_support: le_u16(buf, 0x0a),
+            fused_operation_support: le_u16(buf, 0x0c),
+            min_valid_dssd_power_state: le_u16(buf, 0x0e),
+            dssd_power_state_descriptors,
+            log_page_version,
+        })
+    }
+}
+
+/// Log page identifier of the Unsupported Requirements log.
+pub const OCP_UNSUPPORTED_REQUIREMENTS_LOG_LID: u32 = 0xc5;
+/// Size of the Unsupported Requirements log.
+pub const OCP_UNSUPPORTED_REQUIREMENTS_LOG_LEN: usize = 4096;
+/// GUID of the Unsupported Requirements log
+/// (C7BB98B7D0324863BB2C23990E9C722Fh) as it appears in the log page.
+pub const OCP_UNSUPPORTED_REQUIREMENTS_LOG_GUID: [u8; OCP_GUID_LEN] = [
+    0x2f, 0x72, 0x9c, 0x0e, 0x99, 0x23, 0x2c, 0xbb, 0x63, 0x48, 0x32, 0xd0,
+    0xb7, 0x98, 0xbb, 0xc7,
+];
+/// Each requirement identifier is a 16 byte ASCII string.
+const REQUIREMENT_ID_LEN: usize = 16;
+/// The maximum number of requirement identifiers the log page can hold.
+pub const MAX_UNSUPPORTED_REQUIREMENTS: usize = 253;
+
+/// The OCP Unsupported Requirements log (C5h).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcpUnsupportedRequirementsLog {
+    /// Identifiers of the OCP requirements the device does not meet, such as
+    /// "GETF-1".
+    pub requirements: Vec<String>,
+    pub log_page_version: u16,
+}
+
+impl OcpUnsupportedRequirementsLog {
+    /// Decode the log page from the raw bytes returned by the controller,
+    /// verifying the log page GUID and version.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, OCP_UNSUPPORTED_REQUIREMENTS_LOG_LEN)?;
+        let log_page_version = check_guid_and_version(
+            buf,
+            GUID_OFF_4096,
+            &OCP_UNSUPPORTED_REQUIREMENTS_LOG_GUID,
+        )?;
+
+        let count = usize::from(le_u16(buf, 0x00));
+        if count > MAX_UNSUPPORTED_REQUIREMENTS {
+            return Err(DecodeError::InvalidCount {
+                count,
+                max: MAX_UNSUPPORTED_REQUIREMENTS,
+            });
+        }
+
+        let requirements = buf[0x10..]
+            .chunks_exact(REQUIREMENT_ID_LEN)
+            .take(count)
+            .map(ascii_field)
+            .collect();
+
+        Ok(Self { requirements, log_page_version })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smart_fixture(version: u16) -> [u8; OCP_SMART_LOG_LEN] {
+        let mut buf = [0u8; OCP_SMART_LOG_LEN];
+        buf[0..16].copy_from_slice(&0x1234_5678_9abc_u128.to_le_bytes());
+        buf[16..32].copy_from_slice(&0x42_u128.to_le_bytes());
+        // Bad user NAND blocks: raw 7, normalized 100.
+        buf[32..38].copy_from_slice(&[7, 0, 0, 0, 0, 0]);
+        buf[38..40].copy_from_slice(&100u16.to_le_bytes());
+        buf[48..56].copy_from_slice(&3u64.to_le_bytes());
+        buf[76..80].copy_from_slice(&9u32.to_le_bytes());
+        buf[80] = 5;
+        buf[81..88].copy_from_slice(&[0x01, 0x02, 0, 0, 0, 0, 0]);
+        buf[96] = 4;
+        buf[97] = 2;
+        buf[98] = 1;
+        buf[101..103].copy_from_slice(&5u16.to_le_bytes());
+        buf[103] = 2;
+        buf[128..130].copy_from_slice(&98u16.to_le_bytes());
+        buf[176..192].copy_from_slice(&(1u128 << 100).to_le_bytes());
+        buf[494..496].copy_from_slice(&version.to_le_bytes());
+        buf[496..512].copy_from_slice(&OCP_SMART_LOG_GUID);
+        buf
+    }
+
+    #[test]
+    fn decode_smart_log() {
+        let log = OcpSmartLog::decode(&smart_fixture(4)).unwrap();
+        assert_eq!(log.physical_media_units_written, 0x1234_5678_9abc);
+        assert_eq!(log.physical_media_units_read, 0x42);
+        assert_eq!(
+            log.bad_user_nand_blocks,
+            NandBlockCount { raw: 7, normalized: 100 }
+        );
+        assert_eq!(log.xor_recovery_count, 3);
+        assert_eq!(log.e2e_corrected_errors, 9);
+        assert_eq!(log.system_data_percent_used, 5);
+        assert_eq!(log.refresh_count, 0x0201);
+        assert_eq!(log.thermal_throttling_events, 4);
+        assert_eq!(log.thermal_throttling_status, ThrottleStatus::SecondLevel);
+        assert_eq!(
+            log.spec_version,
+            OcpSpecVersion { major: 2, minor: 5, point: 0, errata: 1 }
+        );
+        assert_eq!(log.capacitor_health, 98);
+        assert_eq!(log.v2.unwrap().endurance_estimate, 1 << 100);
+    }
+
+    #[test]
+    fn decode_smart_log_v1() {
+        let log = OcpSmartLog::decode(&smart_fixture(2)).unwrap();
+        assert_eq!(log.log_page_version, 2);
+        assert!(log.v2.is_none());
+    }
+
+    #[test]
+    fn decode_smart_log_bad_guid() {
+        let mut buf = smart_fixture(3);
+        buf[511] = 0;
+        assert_eq!(OcpSmartLog::decode(&buf), Err(DecodeError::BadGuid));
+    }
+
+    #[test]
+    fn decode_smart_log_bad_version() {
+        assert_eq!(
+            OcpSmartLog::decode(&smart_fixture(0)),
+            Err(DecodeError::UnsupportedVersion(0))
+        );
+    }
+
+    fn with_guid(
+        mut buf: Vec<u8>,
+        guid: &[u8; OCP_GUID_LEN],
+        version: u16,
+    ) -> Vec<u8> {
+        let off = buf.len() - OCP_GUID_LEN;
+        buf[off - 2..off].copy_from_slice(&version.to_le_bytes());
+        buf[off..].copy_from_slice(guid);
+        buf
+    }
+
+    #[test]
+    fn decode_error_recovery_log() {
+        let mut buf = vec![0u8; OCP_ERROR_RECOVERY_LOG_LEN];
+        buf[0x00..0x02].copy_from_slice(&250u16.to_le_bytes());
+        buf[0x02] = 0x3;
+        buf[0x03] = 2;
+        buf[0x04..0x0c].copy_from_slice(&0xdead_beef_u64.to_le_bytes());
+        buf[0x10] = 0xd0;
+        buf[0x14..0x18].copy_from_slice(&0x1234u32.to_le_bytes());
+        buf[0x1d] = 9;
+        let buf = with_guid(buf, &OCP_ERROR_RECOVERY_LOG_GUID, 2);
+
+        let log = OcpErrorRecoveryLog::decode(&buf).unwrap();
+        assert_eq!(log.panic_reset_wait_time, 250);
+        assert_eq!(log.panic_reset_action, 0x3);
+        assert_eq!(
+            log.device_recovery_action_1,
+            DeviceRecoveryAction::VendorSpecificCommand
+        );
+        assert_eq!(log.panic_id, 0xdead_beef);
+        assert_eq!(log.vendor_specific_recovery_opcode, 0xd0);
+        assert_eq!(log.vendor_specific_cdw12, 0x1234);
+        assert_eq!(
+            log.device_recovery_action_2,
+            DeviceRecoveryAction::Unknown(9)
+        );
+
+        // The GUID of another OCP log page must be rejected.
+        let buf = with_guid(buf, &OCP_SMART_LOG_GUID, 2);
+        assert_eq!(
+            OcpErrorRecoveryLog::decode(&buf),
+            Err(DecodeError::BadGuid)
+        );
+    }
+
+    #[test]
+    fn decode_latency_monitor_log() {
+        let mut buf = vec![0u8; OCP_LATENCY_MONITOR_LOG_LEN];
+        buf[0x00] = 1;
+        buf[0x06..0x0a].copy_from_slice(&[0, 1, 3, 19]);
+        // Active bucket 1 write counter.
+        buf[0x34..0x38].copy_from_slice(&17u32.to_le_bytes());
+        // Active bucket 3 deallocate timestamp and latency.
+        buf[0xb8..0xc0].copy_from_slice(&99u64.to_le_bytes());
+        buf[0xd6..0xd8].copy_from_slice(&120u16.to_le_bytes());
+        // Static bucket 0 read counter.
+        buf[0xf0..0xf4].copy_from_slice(&5u32.to_le_bytes());
+        buf[0x1c4..0x1cc].copy_from_slice(&7u64.to_le_bytes());
+        let buf = with_guid(buf, &OCP_LATENCY_MONITOR_LOG_GUID, 1);
+
+        let log = OcpLatencyMonitorLog::decode(&buf).unwrap();
+        assert_eq!(log.feature_status, 1);
+        assert_eq!(log.active.counters[1].write, 17);
+        assert_eq!(log.active.timestamps[3][2], 99);
+        assert_eq!(log.active.measured_latency[3][2], 120);
+        assert_eq!(log.static_.counters[0].read, 5);
+        assert_eq!(log.debug_log_latency_stamp, 7);
+
+        let buckets = log.buckets();
+        assert_eq!(buckets[0].lower, Duration::from_millis(5));
+        assert_eq!(buckets[0].upper, Some(Duration::from_millis(10)));
+        assert_eq!(buckets[2].lower, Duration::from_millis(20));
+        assert_eq!(buckets[3].lower, Duration::from_millis(100));
+        assert_eq!(buckets[3].upper, None);
+    }
+
+    #[test]
+    fn latency_monitor_config_round_trip() {
+        let config = LatencyMonitorConfig {
+            active_bucket_timer_threshold: 2016,
+            active_latency_min_window: 10,
+            enable: true,
+            ..Default::default()
+        }
+        .with_thresholds_millis([5, 10, 20, 1280])
+        .unwrap();
+        assert_eq!(config.active_thresholds, [0, 1, 3, 255]);
+
+        let buf = config.encode();
+        assert_eq!(&buf[0x00..0x02], &2016u16.to_le_bytes());
+        assert_eq!(buf[0x0c], 1);
+        assert_eq!(LatencyMonitorConfig::decode(&buf), Ok(config));
+
+        assert_eq!(latency_threshold_from_millis(0), None);
+        assert_eq!(latency_threshold_from_millis(7), None);
+        assert_eq!(latency_threshold_from_millis(1285), None);
+    }
+
+    #[test]
+    fn decode_device_capabilities_log() {
+        let mut buf = vec![0u8; OCP_DEVICE_CAPABILITIES_LOG_LEN];
+        buf[0x00..0x02].copy_from_slice(&2u16.to_le_bytes());
+        buf[0x06..0x08].copy_from_slice(&0x3u16.to_le_bytes());
+        buf[0x0e..0x10].copy_from_slice(&4u16.to_le_bytes());
+        buf[0x11] = 0x80;
+        buf[0x11 + 126] = 0x84;
+        let buf = with_guid(buf, &OCP_DEVICE_CAPABILITIES_LOG_GUID, 1);
+
+        let log = OcpDeviceCapabilitiesLog::decode(&buf).unwrap();
+        assert_eq!(log.pcie_ports, 2);
+        assert_eq!(log.sanitize_support, 0x3);
+        assert_eq!(log.min_valid_dssd_power_state, 4);
+        assert_eq!(log.dssd_power_state_descriptors[0], 0x80);
+        assert_eq!(log.dssd_power_state_descriptors[126], 0x84);
+        assert_eq!(log.log_page_version, 1);
+    }
+
+    #[test]
+    fn decode_unsupported_requirements_log() {
+        let mut buf = vec![0u8; OCP_UNSUPPORTED_REQUIREMENTS_LOG_LEN];
+        buf[0x00..0x02].copy_from_slice(&2u16.to_le_bytes());
+        buf[0x10..0x16].copy_from_slice(b"GETF-1");
+        buf[0x20..0x29].copy_from_slice(b"SEC-10   ");
+        // Entries past the count are ignored.
+        buf[0x30..0x33].copy_from_slice(b"X-1");
+        let good = with_guid(buf, &OCP_UNSUPPORTED_REQUIREMENTS_LOG_GUID, 1);
+
+        let log = OcpUnsupportedRequirementsLog::decode(&good).unwrap();
+        assert_eq!(log.requirements, vec!["GETF-1", "SEC-10"]);
+
+        let mut bad = good.clone();
+        bad[0x00..0x02].copy_from_slice(&254u16.to_le_bytes());
+        assert_eq!(
+            OcpUnsupportedRequirementsLog::decode(&bad),
+            Err(DecodeError::InvalidCount { count: 254, max: 253 })
+        );
+        assert!(matches!(
+            OcpUnsupportedRequirementsLog::decode(&good[..512]),
+            Err(DecodeError::TooShort { .. })
+        ));
+    }
+}
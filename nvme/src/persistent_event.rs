@@ -0,0 +1,525 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Persistent Event log page (1.4).
+
+use crate::{
+    error::{check_len, DecodeError},
+    smart::SmartLog,
+    util::{ascii_field, le_u128, le_u16, le_u32, le_u64},
+};
+
+/// Log page identifier of the Persistent Event log.
+pub const PERSISTENT_EVENT_LID: u32 = 0x0d;
+/// Size of the Persistent Event log header.
+pub const PERSISTENT_EVENT_HDR_LEN: usize = 512;
+/// The Persistent Event Log Size (PELS) reported by Identify Controller is in
+/// 64 KiB units.
+pub const PERSISTENT_EVENT_SIZE_UNIT: u64 = 64 * 1024;
+/// Log Specific Parameter that reads the log without changing the reporting
+/// context.
+pub const PERSISTENT_EVENT_LSP_READ: u32 = 0;
+/// Log Specific Parameter that establishes a new reporting context and reads
+/// the log.
+pub const PERSISTENT_EVENT_LSP_ESTABLISH: u32 = 1;
+/// Log Specific Parameter that releases the reporting context.
+pub const PERSISTENT_EVENT_LSP_RELEASE: u32 = 2;
+
+/// Every event starts with a fixed 24 byte event header.
+const EVENT_HDR_LEN: usize = 24;
+/// Size of each descriptor in a Power-on or Reset event.
+const RESET_INFO_LEN: usize = 36;
+
+/// The header of the Persistent Event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistentEventHeader {
+    /// Number of events in the log.
+    pub total_events: u32,
+    /// Length of the log in bytes, including this header.
+    pub total_length: u64,
+    pub revision: u8,
+    pub header_length: u16,
+    /// The controller timestamp when the reporting context was established.
+    pub timestamp: u64,
+    pub power_on_hours: u128,
+    pub power_cycle_count: u64,
+    pub pci_vid: u16,
+    pub pci_ssvid: u16,
+    pub serial: String,
+    pub model: String,
+    pub subnqn: String,
+    /// Incremented each time the log wraps and the oldest events are lost.
+    pub generation: u16,
+    pub reporting_context: u32,
+    /// Bitmap of the event types the controller supports, indexed by event
+    /// type.
+    pub supported_events: [u8; 32],
+}
+
+impl PersistentEventHeader {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, PERSISTENT_EVENT_HDR_LEN)?;
+        let mut supported_events = [0u8; 32];
+        supported_events.copy_from_slice(&buf[480..512]);
+        Ok(Self {
+            total_events: le_u32(buf, 4),
+            total_length: le_u64(buf, 8),
+            revision: buf[16],
+            header_length: le_u16(buf, 18),
+            timestamp: le_u64(buf, 20),
+            power_on_hours: le_u128(buf, 28),
+            power_cycle_count: le_u64(buf, 44),
+            pci_vid: le_u16(buf, 52),
+            pci_ssvid: le_u16(buf, 54),
+            serial: ascii_field(&buf[56..76]),
+            model: ascii_field(&buf[76..116]),
+            subnqn: ascii_field(&buf[116..372]),
+            generation: le_u16(buf, 372),
+            reporting_context: le_u32(buf, 374),
+            supported_events,
+        })
+    }
+
+    /// Whether the controller reports events of type `event_type`.
+    pub fn supports_event(&self, event_type: u8) -> bool {
+        let byte = self.supported_events[usize::from(event_type / 8)];
+        byte & (1 << (event_type % 8)) != 0
+    }
+}
+
+/// A controller that was powered on or reset, from a Power-on or Reset
+/// event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetInfo {
+    pub controller_id: u16,
+    /// Whether firmware was activated as part of the reset.
+    pub firmware_activation: bool,
+    /// Whether an operation, such as a format or sanitize, was in progress
+    /// when the reset occurred.
+    pub operation_in_progress: bool,
+    pub controller_power_cycle: u32,
+    /// Time in milliseconds the controller had been powered on.
+    pub power_on_ms: u64,
+    pub controller_timestamp: u64,
+}
+
+impl ResetInfo {
+    fn decode(buf: &[u8]) -> Self {
+        Self {
+            controller_id: le_u16(buf, 0),
+            firmware_activation: buf[2] != 0,
+            operation_in_progress: buf[3] != 0,
+            controller_power_cycle: le_u32(buf, 16),
+            power_on_ms: le_u64(buf, 20),
+            controller_timestamp: le_u64(buf, 28),
+        }
+    }
+}
+
+/// The event specific data of a persistent event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistentEventData {
+    SmartSnapshot(Box<SmartLog>),
+    FirmwareCommit {
+        old_firmware: String,
+        new_firmware: String,
+        action: u8,
+        slot: u8,
+        status_code_type: u8,
+        status_code: u8,
+        vendor_result: u16,
+    },
+    TimestampChange {
+        previous_timestamp: u64,
+        ms_since_reset: u64,
+    },
+    PowerOnReset {
+        firmware: String,
+        controllers: Vec<ResetInfo>,
+    },
+    HardwareError {
+        code: u16,
+        additional_info: Vec<u8>,
+    },
+    ChangeNamespace {
+        cdw10: u32,
+        size: u64,
+        capacity: u64,
+        flbas: u8,
+        dps: u8,
+        nmic: u8,
+        ana_group: u32,
+        nvm_set: u16,
+        nsid: u32,
+    },
+    FormatStart {
+        nsid: u32,
+        fna: u8,
+        cdw10: u32,
+    },
+    FormatCompletion {
+        nsid: u32,
+        smallest_fpi: u8,
+        status: u8,
+        completion_info: u16,
+        status_field: u32,
+    },
+    SanitizeStart {
+        sanicap: u32,
+        cdw10: u32,
+        cdw11: u32,
+    },
+    SanitizeCompletion {
+        progress: u16,
+        status: u16,
+        completion_info: u16,
+    },
+    ThermalExcursion {
+        /// Degrees Celsius over the threshold.
+        over_temperature: u8,
+        threshold: u8,
+    },
+    /// An event type without a decoder, including vendor specific events.
+    Other(Vec<u8>),
+}
+
+impl PersistentEventData {
+    fn decode(event_type: u8, data: &[u8]) -> Result<Self, DecodeError> {
+        let event = match event_type {
+            0x01 => Self::SmartSnapshot(Box::new(SmartLog::decode(data)?)),
+            0x02 => {
+                check_len(data, 22)?;
+                Self::FirmwareCommit {
+                    old_firmware: ascii_field(&data[0..8]),
+                    new_firmware: ascii_field(&data[8..16]),
+                    action: data[16],
+                    slot: data[17],
+                    status_code_type: data[18],
+                    status_code: data[19],
+                    vendor_result: le_u16(data, 20),
+                }
+            }
+            0x03 => {
+                check_len(data, 16)?;
+                Self::TimestampChange {
+                    previous_timestamp: le_u64(data, 0),
+                    ms_since_reset: le_u64(data, 8),
+                }
+            }
+            0x04 => {
+                check_len(data, 8)?;
+                Self::PowerOnReset {
+                    firmware: ascii_field(&data[0..8]),
+                    controllers: data[8..]
+                        .chunks_exact(RESET_INFO_LEN)
+                        .map(ResetInfo::decode)
+                        .collect(),
+                }
+            }
+            0x05 => {
+                check_len(data, 4)?;
+                Self::HardwareError {
+                    code: le_u16(data, 0),
+                    additional_info: data[4..].to_vec(),
+                }
+            }
+            0x06 => {
+                check_len(data, 48)?;
+                Self::ChangeNamespace {
+                    cdw10: le_u32(data, 0),
+                    size: le_u64(data, 8),
+                    capacity: le_u64(data, 24),
+                    flbas: data[32],
+                    dps: data[33],
+                    nmic: data[34],
+                    ana_group: le_u32(data, 36),
+                    nvm_set: le_u16(data, 40),
+                    nsid: le_u32(data, 44),
+                }
+            }
+            0x07 => {
+                check_len(data, 12)?;
+                Self::FormatStart {
+                    nsid: le_u32(data, 0),
+                    fna: data[4],
+                    cdw10: le_u32(data, 8),
+                }
+            }
+            0x08 => {
+                check_len(data, 12)?;
+                Self::FormatCompletion {
+                    nsid: le_u32(data, 0),
+                    smallest_fpi: data[4],
+                    status: data[5],
+                    completion_info: le_u16(data, 6),
+                    status_field: le_u32(data, 8),
+                }
+            }
+            0x09 => {
+                check_len(data, 12)?;
+                Self::SanitizeStart {
+                    sanicap: le_u32(data, 0),
+                    cdw10: le_u32(data, 4),
+                    cdw11: le_u32(data, 8),
+                }
+            }
+            0x0a => {
+                check_len(data, 6)?;
+                Self::SanitizeCompletion {
+                    progress: le_u16(data, 0),
+                    status: le_u16(data, 2),
+                    completion_info: le_u16(data, 4),
+                }
+            }
+            0x0d => {
+                check_len(data, 2)?;
+                Self::ThermalExcursion {
+                    over_temperature: data[0],
+                    threshold: data[1],
+                }
+            }
+            _ => Self::Other(data.to_vec()),
+        };
+        Ok(event)
+    }
+}
+
+/// A single event from the Persistent Event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistentEvent {
+    pub event_type: u8,
+    pub revision: u8,
+    pub controller_id: u16,
+    /// The raw event timestamp. See `timestamp_ms`.
+    pub timestamp: u64,
+    pub port_id: u16,
+    pub vendor_specific: Vec<u8>,
+    pub data: PersistentEventData,
+}
+
+impl PersistentEvent {
+    /// The event timestamp in milliseconds, without the attribute bits
+    /// describing how the timestamp was set.
+    pub fn timestamp_ms(&self) -> u64 {
+        self.timestamp & 0xffff_ffff_ffff
+    }
+}
+
+/// A complete Persistent Event log as read from the controller.
+#[derive(Debug, Clone)]
+pub struct PersistentEventLog {
+    header: PersistentEventHeader,
+    buf: Vec<u8>,
+}
+
+impl PersistentEventLog {
+    /// Wrap the raw log page, which must start with the log header.
+    pub fn new(buf: Vec<u8>) -> Result<Self, DecodeError> {
+        let header = PersistentEventHeader::decode(&buf)?;
+        Ok(Self { header, buf })
+    }
+
+    pub fn header(&self) -> &PersistentEventHeader {
+        &self.header
+    }
+
+    /// The raw log page.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Iterate over the events in the log, oldest first.
+    pub fn events(&self) -> PersistentEvents<'_> {
+        let len = usize::try_from(self.header.total_length)
+            .map_or(self.buf.len(), |len| len.min(self.buf.len()));
+        PersistentEvents {
+            buf: &self.buf[..len],
+            offset: PERSISTENT_EVENT_HDR_LEN,
+            remaining: self.header.total_events,
+        }
+    }
+}
+
+/// An iterator over the events in a `PersistentEventLog`. Iteration stops at
+/// the first event that cannot be decoded.
+pub struct PersistentEvents<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    remaining: u32,
+}
+
+impl<'a> PersistentEvents<'a> {
+    fn decode_next(&mut self) -> Result<PersistentEvent, DecodeError> {
+        let buf = &self.buf[self.offset.min(self.buf.len())..];
+        check_len(buf, EVENT_HDR_LEN)?;
+
+        // The Event Header Length excludes the first three bytes of the
+        // header, and the Event Length covers the vendor specific
+        // information followed by the event data.
+        let hdr_len = usize::from(buf[2]) + 3;
+        let vsi_len = usize::from(le_u16(buf, 20));
+        let event_len = usize::from(le_u16(buf, 22));
+        check_len(buf, hdr_len + event_len)?;
+        if vsi_len > event_len {
+            return Err(DecodeError::InvalidCount {
+                count: vsi_len,
+                max: event_len,
+            });
+        }
+
+        let body = &buf[hdr_len..hdr_len + event_len];
+        let (vendor_specific, data) = body.split_at(vsi_len);
+        let event = PersistentEvent {
+            event_type: buf[0],
+            revision: buf[1],
+            controller_id: le_u16(buf, 4),
+            timestamp: le_u64(buf, 6),
+            port_id: le_u16(buf, 14),
+            vendor_specific: vendor_specific.to_vec(),
+            data: PersistentEventData::decode(buf[0], data)?,
+        };
+        self.offset += hdr_len + event_len;
+        Ok(event)
+    }
+}
+
+impl<'a> Iterator for PersistentEvents<'a> {
+    type Item = Result<PersistentEvent, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let event = self.decode_next();
+        self.remaining = if event.is_ok() { self.remaining - 1 } else { 0 };
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: u8, vsi: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; EVENT_HDR_LEN];
+        buf[0] = event_type;
+        buf[2] = (EVENT_HDR_LEN - 3) as u8;
+        buf[4..6].copy_from_slice(&1u16.to_le_bytes());
+        buf[6..14].copy_from_slice(&(0x2_0000_0000_1000u64).to_le_bytes());
+        buf[20..22].copy_from_slice(&(vsi.len() as u16).to_le_bytes());
+        buf[22..24]
+            .copy_from_slice(&((vsi.len() + data.len()) as u16).to_le_bytes());
+        buf.extend_from_slice(vsi);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    fn log(events: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![0u8; PERSISTENT_EVENT_HDR_LEN];
+        for e in events {
+            buf.extend_from_slice(e);
+        }
+        let len = buf.len() as u64;
+        buf[0] = PERSISTENT_EVENT_LID as u8;
+        buf[4..8].copy_from_slice(&(events.len() as u32).to_le_bytes());
+        buf[8..16].copy_from_slice(&len.to_le_bytes());
+        buf[52..54].copy_from_slice(&0x1b96u16.to_le_bytes());
+        buf[56..62].copy_from_slice(b"SN1234");
+        buf[372..374].copy_from_slice(&3u16.to_le_bytes());
+        // Supports events 02h and 0Dh.
+        buf[480] = 0x04;
+        buf[481] = 0x20;
+        buf
+    }
+
+    #[test]
+    fn decode_header() {
+        let pel = PersistentEventLog::new(log(&[])).unwrap();
+        let hdr = pel.header();
+        assert_eq!(hdr.total_events, 0);
+        assert_eq!(hdr.total_length, 512);
+        assert_eq!(hdr.pci_vid, 0x1b96);
+        assert_eq!(hdr.serial, "SN1234");
+        assert_eq!(hdr.generation, 3);
+        assert!(hdr.supports_event(0x02));
+        assert!(hdr.supports_event(0x0d));
+        assert!(!hdr.supports_event(0x01));
+        assert_eq!(pel.events().count(), 0);
+    }
+
+    #[test]
+    fn decode_events() {
+        let mut fw = [0u8; 24];
+        fw[0..4].copy_from_slice(b"1.0 ");
+        fw[8..11].copy_from_slice(b"1.1");
+        fw[16] = 3;
+        fw[17] = 2;
+
+        let mut reset = vec![0u8; 8 + 2 * RESET_INFO_LEN];
+        reset[0..3].copy_from_slice(b"1.1");
+        reset[8 + RESET_INFO_LEN..8 + RESET_INFO_LEN + 2]
+            .copy_from_slice(&7u16.to_le_bytes());
+        reset[8 + RESET_INFO_LEN + 2] = 1;
+
+        let buf = log(&[
+            event(0x02, &[0xaa, 0xbb], &fw),
+            event(0x04, &[], &reset),
+            event(0x0d, &[], &[4, 70]),
+            event(0xde, &[], &[1, 2, 3]),
+        ]);
+        let pel = PersistentEventLog::new(buf).unwrap();
+        let events = pel.events().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(events.len(), 4);
+
+        assert_eq!(events[0].controller_id, 1);
+        assert_eq!(events[0].timestamp_ms(), 0x1000);
+        assert_eq!(events[0].vendor_specific, vec![0xaa, 0xbb]);
+        assert_eq!(
+            events[0].data,
+            PersistentEventData::FirmwareCommit {
+                old_firmware: "1.0".to_string(),
+                new_firmware: "1.1".to_string(),
+                action: 3,
+                slot: 2,
+                status_code_type: 0,
+                status_code: 0,
+                vendor_result: 0,
+            }
+        );
+
+        let PersistentEventData::PowerOnReset { firmware, controllers } =
+            &events[1].data
+        else {
+            panic!("unexpected event {:?}", events[1].data);
+        };
+        assert_eq!(firmware, "1.1");
+        assert_eq!(controllers.len(), 2);
+        assert_eq!(controllers[1].controller_id, 7);
+        assert!(controllers[1].firmware_activation);
+
+        assert_eq!(
+            events[2].data,
+            PersistentEventData::ThermalExcursion {
+                over_temperature: 4,
+                threshold: 70
+            }
+        );
+        assert_eq!(events[3].data, PersistentEventData::Other(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn truncated_event_stops_iteration() {
+        let mut buf = log(&[event(0x03, &[], &[0u8; 16])]);
+        // Claim a second event that isn't there.
+        buf[4..8].copy_from_slice(&2u32.to_le_bytes());
+        let pel = PersistentEventLog::new(buf).unwrap();
+        let mut events = pel.events();
+        assert!(events.next().unwrap().is_ok());
+        assert!(matches!(
+            events.next(),
+            Some(Err(DecodeError::TooShort { .. }))
+        ));
+        assert!(events.next().is_none());
+    }
+}
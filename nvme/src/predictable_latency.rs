@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Predictable Latency Mode log pages and features (1.4).
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{le_u16, le_u64},
+};
+
+/// Log page identifier of the Predictable Latency Per NVM Set log.
+pub const PL_PER_SET_LID: u32 = 0x0a;
+/// Size of the Predictable Latency Per NVM Set log.
+pub const PL_PER_SET_LEN: usize = 512;
+/// Log page identifier of the Predictable Latency Event Aggregate log.
+pub const PL_EVENT_AGG_LID: u32 = 0x0b;
+/// Feature identifier of the Predictable Latency Mode Config feature.
+pub const PLM_CONFIG_FID: u32 = 0x13;
+/// Size of the data structure used by the Predictable Latency Mode Config
+/// feature.
+pub const PLM_CONFIG_LEN: usize = 512;
+/// Feature identifier of the Predictable Latency Mode Window feature.
+pub const PLM_WINDOW_FID: u32 = 0x14;
+
+/// The window an NVM set is operating in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlWindow {
+    /// Predictable Latency Mode is not enabled.
+    NotEnabled,
+    /// The Deterministic Window (DTWIN), during which the NVM set provides
+    /// predictable latency.
+    Deterministic,
+    /// The Non-Deterministic Window (NDWIN), during which the NVM set may
+    /// perform background operations.
+    NonDeterministic,
+    Unknown(u8),
+}
+
+impl From<u8> for PlWindow {
+    fn from(value: u8) -> Self {
+        match value & 0x7 {
+            0 => Self::NotEnabled,
+            1 => Self::Deterministic,
+            2 => Self::NonDeterministic,
+            window => Self::Unknown(window),
+        }
+    }
+}
+
+impl PlWindow {
+    /// Decode Dword 0 of a Get Features completion for the Predictable
+    /// Latency Mode Window feature.
+    pub fn from_cdw0(cdw0: u32) -> Self {
+        ((cdw0 & 0x7) as u8).into()
+    }
+}
+
+/// The Command Dword 11 of the Predictable Latency Mode features, which
+/// selects the NVM set.
+pub fn plm_cdw11(nvm_set: u16) -> u32 {
+    u32::from(nvm_set)
+}
+
+/// Predictable latency events, used both to report the events that have
+/// occurred and to select the events that are reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlEvents(pub u16);
+
+impl PlEvents {
+    /// The DTWIN reads threshold has been exceeded.
+    pub fn dtwin_reads_warning(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+
+    /// The DTWIN writes threshold has been exceeded.
+    pub fn dtwin_writes_warning(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    /// The DTWIN time threshold has been exceeded.
+    pub fn dtwin_time_warning(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    /// The NVM set autonomously transitioned from the deterministic to the
+    /// non-deterministic window.
+    pub fn autonomous_ndwin_transition(&self) -> bool {
+        self.0 & (1 << 14) != 0
+    }
+}
+
+/// The Predictable Latency Per NVM Set log (0Ah).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PredictableLatencySetLog {
+    pub window: PlWindow,
+    pub events: PlEvents,
+    /// Typical number of 4 KiB random reads that may be performed in the
+    /// deterministic window.
+    pub dtwin_reads_typical: u64,
+    /// Typical number of writes, in optimal write size units, that may be
+    /// performed in the deterministic window.
+    pub dtwin_writes_typical: u64,
+    /// Maximum time in milliseconds the set may stay in the deterministic
+    /// window.
+    pub dtwin_time_maximum: u64,
+    /// Minimum time in milliseconds the set must stay in the
+    /// non-deterministic window after high utilization.
+    pub ndwin_time_minimum_high: u64,
+    /// Minimum time in milliseconds the set must stay in the
+    /// non-deterministic window after low utilization.
+    pub ndwin_time_minimum_low: u64,
+    /// Estimated reads remaining in the current deterministic window.
+    pub dtwin_reads_estimate: u64,
+    /// Estimated writes remaining in the current deterministic window.
+    pub dtwin_writes_estimate: u64,
+    /// Estimated time in milliseconds remaining in the current deterministic
+    /// window.
+    pub dtwin_time_estimate: u64,
+}
+
+impl PredictableLatencySetLog {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, PL_PER_SET_LEN)?;
+        Ok(Self {
+            window: buf[0].into(),
+            events: PlEvents(le_u16(buf, 2)),
+            dtwin_reads_typical: le_u64(buf, 32),
+            dtwin_writes_typical: le_u64(buf, 40),
+            dtwin_time_maximum: le_u64(buf, 48),
+            ndwin_time_minimum_high: le_u64(buf, 56),
+            ndwin_time_minimum_low: le_u64(buf, 64),
+            dtwin_reads_estimate: le_u64(buf, 128),
+            dtwin_writes_estimate: le_u64(buf, 136),
+            dtwin_time_estimate: le_u64(buf, 144),
+        })
+    }
+}
+
+/// The size of the Predictable Latency Event Aggregate log needed to report
+/// up to `max_sets` NVM sets.
+pub fn pl_event_agg_len(max_sets: u16) -> usize {
+    8 + usize::from(max_sets) * 2
+}
+
+/// Decode the Predictable Latency Event Aggregate log (0Bh), returning the
+/// NVM sets that have predictable latency events outstanding.
+pub fn decode_pl_event_agg(buf: &[u8]) -> Result<Vec<u16>, DecodeError> {
+    check_len(buf, 8)?;
+    let max = (buf.len() - 8) / 2;
+    let count = usize::try_from(le_u64(buf, 0)).unwrap_or(usize::MAX);
+    if count > max {
+        return Err(DecodeError::InvalidCount { count, max });
+    }
+    Ok(buf[8..8 + count * 2].chunks_exact(2).map(|id| le_u16(id, 0)).collect())
+}
+
+/// The Predictable Latency Mode Config feature (13h) for an NVM set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlmConfig {
+    /// Whether Predictable Latency Mode is enabled.
+    pub enable: bool,
+    /// The events that generate a predictable latency event.
+    pub enable_events: PlEvents,
+    /// Number of 4 KiB random reads in the deterministic window after which
+    /// a reads warning is reported. Zero disables the warning.
+    pub dtwin_reads_threshold: u64,
+    /// Number of writes, in optimal write size units, in the deterministic
+    /// window after which a writes warning is reported.
+    pub dtwin_writes_threshold: u64,
+    /// Time in milliseconds in the deterministic window after which a time
+    /// warning is reported.
+    pub dtwin_time_threshold: u64,
+}
+
+impl PlmConfig {
+    /// Decode the feature from Dword 0 of the Get Features completion and
+    /// the returned data structure.
+    pub fn decode(cdw0: u32, buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, 56)?;
+        Ok(Self {
+            enable: cdw0 & 0x1 != 0,
+            enable_events: PlEvents(le_u16(buf, 0)),
+            dtwin_reads_threshold: le_u64(buf, 32),
+            dtwin_writes_threshold: le_u64(buf, 40),
+            dtwin_time_threshold: le_u64(buf, 48),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_per_set_log() {
+        let mut buf = [0u8; PL_PER_SET_LEN];
+        buf[0] = 2;
+        buf[2..4].copy_from_slice(&0x4005u16.to_le_bytes());
+        buf[32..40].copy_from_slice(&1000u64.to_le_bytes());
+        buf[48..56].copy_from_slice(&5000u64.to_le_bytes());
+        buf[64..72].copy_from_slice(&300u64.to_le_bytes());
+        buf[144..152].copy_from_slice(&20u64.to_le_bytes());
+
+        let log = PredictableLatencySetLog::decode(&buf).unwrap();
+        assert_eq!(log.window, PlWindow::NonDeterministic);
+        assert!(log.events.dtwin_reads_warning());
+        assert!(!log.events.dtwin_writes_warning());
+        assert!(log.events.dtwin_time_warning());
+        assert!(log.events.autonomous_ndwin_transition());
+        assert_eq!(log.dtwin_reads_typical, 1000);
+        assert_eq!(log.dtwin_time_maximum, 5000);
+        assert_eq!(log.ndwin_time_minimum_low, 300);
+        assert_eq!(log.dtwin_time_estimate, 20);
+    }
+
+    #[test]
+    fn decode_event_aggregate() {
+        let mut buf = vec![0u8; pl_event_agg_len(4)];
+        buf[0..8].copy_from_slice(&2u64.to_le_bytes());
+        buf[8..10].copy_from_slice(&3u16.to_le_bytes());
+        buf[10..12].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(decode_pl_event_agg(&buf), Ok(vec![3, 1]));
+
+        buf[0..8].copy_from_slice(&5u64.to_le_bytes());
+        assert_eq!(
+            decode_pl_event_agg(&buf),
+            Err(DecodeError::InvalidCount { count: 5, max: 4 })
+        );
+    }
+
+    #[test]
+    fn decode_plm_config() {
+        let mut buf = [0u8; PLM_CONFIG_LEN];
+        buf[0..2].copy_from_slice(&0x7u16.to_le_bytes());
+        buf[32..40].copy_from_slice(&(1u64 << 20).to_le_bytes());
+        buf[48..56].copy_from_slice(&2000u64.to_le_bytes());
+        assert_eq!(
+            PlmConfig::decode(1, &buf),
+            Ok(PlmConfig {
+                enable: true,
+                enable_events: PlEvents(0x7),
+                dtwin_reads_threshold: 1 << 20,
+                dtwin_writes_threshold: 0,
+                dtwin_time_threshold: 2000,
+            })
+        );
+        assert_eq!(
+            PlmConfig::decode(1, &buf[..55]),
+            Err(DecodeError::TooShort { expected: 56, actual: 55 })
+        );
+    }
+
+    #[test]
+    fn plm_window() {
+        assert_eq!(PlWindow::from_cdw0(0x1), PlWindow::Deterministic);
+        assert_eq!(PlWindow::from_cdw0(0xfff2), PlWindow::NonDeterministic);
+        assert_eq!(plm_cdw11(3), 3);
+    }
+}
@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Device Self-test log page.
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{le_u32, le_u64},
+};
+
+/// Log page identifier of the Device Self-test log.
+pub const SELF_TEST_LID: u32 = 0x06;
+/// Size of the Device Self-test log.
+pub const SELF_TEST_LEN: usize = 564;
+/// The results start after a 4 byte header.
+const SELF_TEST_HDR_LEN: usize = 4;
+/// Size of each Self-test Result Data Structure.
+const SELF_TEST_RESULT_LEN: usize = 28;
+/// A result whose status is this value is not in use.
+const SELF_TEST_RESULT_UNUSED: u8 = 0xf;
+
+/// The kind of a device self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestCode {
+    Short,
+    Extended,
+    VendorSpecific,
+    Unknown(u8),
+}
+
+impl From<u8> for SelfTestCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x1 => Self::Short,
+            0x2 => Self::Extended,
+            0xe => Self::VendorSpecific,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// How a device self-test ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestResult {
+    Passed,
+    /// Aborted by a Device Self-test command.
+    Aborted,
+    AbortedByReset,
+    AbortedByNamespaceRemoval,
+    AbortedByFormat,
+    /// A fatal error or unknown test error occurred during the test.
+    FatalError,
+    /// A segment failed, but which one is not known.
+    FailedUnknownSegment,
+    /// The segment given in the result failed.
+    FailedSegment,
+    AbortedUnknown,
+    /// Aborted by a sanitize operation (1.4).
+    AbortedBySanitize,
+    Unknown(u8),
+}
+
+impl From<u8> for SelfTestResult {
+    fn from(value: u8) -> Self {
+        match value {
+            0x0 => Self::Passed,
+            0x1 => Self::Aborted,
+            0x2 => Self::AbortedByReset,
+            0x3 => Self::AbortedByNamespaceRemoval,
+            0x4 => Self::AbortedByFormat,
+            0x5 => Self::FatalError,
+            0x6 => Self::FailedUnknownSegment,
+            0x7 => Self::FailedSegment,
+            0x8 => Self::AbortedUnknown,
+            0x9 => Self::AbortedBySanitize,
+            result => Self::Unknown(result),
+        }
+    }
+}
+
+/// A Self-test Result Data Structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestEntry {
+    pub code: SelfTestCode,
+    pub result: SelfTestResult,
+    /// The first segment that failed, or 0 if none did.
+    pub segment: u8,
+    /// The power on hours of the controller when the test completed.
+    pub power_on_hours: u64,
+    /// The namespace, failing LBA and command status of a failure, when the
+    /// controller reports them.
+    pub nsid: Option<u32>,
+    pub failing_lba: Option<u64>,
+    pub status_code_type: Option<u8>,
+    pub status_code: Option<u8>,
+}
+
+impl SelfTestEntry {
+    /// Decode a single result, or `None` if the entry is unused.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let result = buf[0] & 0xf;
+        if result == SELF_TEST_RESULT_UNUSED {
+            return None;
+        }
+        let valid = buf[2];
+        let has = |bit: u8| valid & bit != 0;
+        Some(Self {
+            code: SelfTestCode::from(buf[0] >> 4),
+            result: SelfTestResult::from(result),
+            segment: buf[1],
+            power_on_hours: le_u64(buf, 4),
+            nsid: has(0x1).then(|| le_u32(buf, 12)),
+            failing_lba: has(0x2).then(|| le_u64(buf, 16)),
+            status_code_type: has(0x4).then_some(buf[24] & 0x7),
+            status_code: has(0x8).then_some(buf[25]),
+        })
+    }
+}
+
+/// The Device Self-test log (06h).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestLog {
+    /// The test in progress, if any.
+    pub current: Option<SelfTestCode>,
+    /// How far through the test in progress the controller is, in percent.
+    pub current_completion: u8,
+    /// The results of the most recent tests, newest first.
+    pub results: Vec<SelfTestEntry>,
+}
+
+impl SelfTestLog {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, SELF_TEST_LEN)?;
+        let current = match buf[0] & 0xf {
+            0 => None,
+            code => Some(SelfTestCode::from(code)),
+        };
+        Ok(Self {
+            current,
+            current_completion: buf[1] & 0x7f,
+            results: buf[SELF_TEST_HDR_LEN..SELF_TEST_LEN]
+                .chunks_exact(SELF_TEST_RESULT_LEN)
+                .filter_map(SelfTestEntry::decode)
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_self_test_log() {
+        let mut buf = [0u8; SELF_TEST_LEN];
+        buf[0] = 0x2;
+        buf[1] = 40;
+        for entry in buf[SELF_TEST_HDR_LEN..].chunks_exact_mut(28) {
+            entry[0] = 0xf;
+        }
+        let first = &mut buf[SELF_TEST_HDR_LEN..SELF_TEST_HDR_LEN + 28];
+        first[0] = 0x17;
+        first[1] = 3;
+        first[2] = 0x3;
+        first[4..12].copy_from_slice(&1200u64.to_le_bytes());
+        first[12..16].copy_from_slice(&1u32.to_le_bytes());
+        first[16..24].copy_from_slice(&0x1000u64.to_le_bytes());
+        first[24] = 0x2;
+        let second = &mut buf[SELF_TEST_HDR_LEN + 28..SELF_TEST_HDR_LEN + 56];
+        second[0] = 0x20;
+        second[4..12].copy_from_slice(&1100u64.to_le_bytes());
+
+        let log = SelfTestLog::decode(&buf).unwrap();
+        assert_eq!(log.current, Some(SelfTestCode::Extended));
+        assert_eq!(log.current_completion, 40);
+        assert_eq!(
+            log.results,
+            [
+                SelfTestEntry {
+                    code: SelfTestCode::Short,
+                    result: SelfTestResult::FailedSegment,
+                    segment: 3,
+                    power_on_hours: 1200,
+                    nsid: Some(1),
+                    failing_lba: Some(0x1000),
+                    status_code_type: None,
+                    status_code: None,
+                },
+                SelfTestEntry {
+                    code: SelfTestCode::Extended,
+                    result: SelfTestResult::Passed,
+                    segment: 0,
+                    power_on_hours: 1100,
+                    nsid: None,
+                    failing_lba: None,
+                    status_code_type: None,
+                    status_code: None,
+                },
+            ]
+        );
+
+        assert_eq!(
+            SelfTestLog::decode(&buf[..512]),
+            Err(DecodeError::TooShort { expected: 564, actual: 512 })
+        );
+    }
+}
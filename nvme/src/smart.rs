@@ -0,0 +1,119 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The SMART / Health Information log page.
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{le_u128, le_u16, le_u32},
+};
+
+/// Log page identifier of the SMART / Health Information log.
+pub const SMART_LID: u32 = 0x02;
+/// Size of the SMART / Health Information log.
+pub const SMART_LEN: usize = 512;
+/// Number of temperature sensors reported in the log.
+pub const SMART_TEMP_SENSORS: usize = 8;
+
+/// The SMART / Health Information log (02h).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SmartLog {
+    /// Bitmask of critical warnings, such as the spare falling below its
+    /// threshold or the media becoming read-only.
+    pub critical_warning: u8,
+    /// Composite temperature in Kelvin.
+    pub composite_temperature: u16,
+    /// Percentage of the spare capacity remaining.
+    pub available_spare: u8,
+    pub available_spare_threshold: u8,
+    /// Estimate of the percentage of the rated endurance consumed. This may
+    /// exceed 100.
+    pub percentage_used: u8,
+    /// Bitmask of the critical warnings reported by any endurance group
+    /// (1.4).
+    pub endurance_group_critical_warning: u8,
+    /// Data read in thousands of 512 byte units.
+    pub data_units_read: u128,
+    /// Data written in thousands of 512 byte units.
+    pub data_units_written: u128,
+    pub host_read_commands: u128,
+    pub host_write_commands: u128,
+    /// Time in minutes the controller has been busy with I/O commands.
+    pub controller_busy_time: u128,
+    pub power_cycles: u128,
+    pub power_on_hours: u128,
+    pub unsafe_shutdowns: u128,
+    pub media_errors: u128,
+    pub error_log_entries: u128,
+    /// Time in minutes spent at or above the warning composite temperature
+    /// threshold.
+    pub warning_temperature_time: u32,
+    /// Time in minutes spent at or above the critical composite temperature
+    /// threshold.
+    pub critical_temperature_time: u32,
+    /// Temperature sensors in Kelvin, where zero means the sensor is not
+    /// implemented.
+    pub temperature_sensors: [u16; SMART_TEMP_SENSORS],
+}
+
+impl SmartLog {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, SMART_LEN)?;
+        Ok(Self {
+            critical_warning: buf[0],
+            composite_temperature: le_u16(buf, 1),
+            available_spare: buf[3],
+            available_spare_threshold: buf[4],
+            percentage_used: buf[5],
+            endurance_group_critical_warning: buf[6],
+            data_units_read: le_u128(buf, 32),
+            data_units_written: le_u128(buf, 48),
+            host_read_commands: le_u128(buf, 64),
+            host_write_commands: le_u128(buf, 80),
+            controller_busy_time: le_u128(buf, 96),
+            power_cycles: le_u128(buf, 112),
+            power_on_hours: le_u128(buf, 128),
+            unsafe_shutdowns: le_u128(buf, 144),
+            media_errors: le_u128(buf, 160),
+            error_log_entries: le_u128(buf, 176),
+            warning_temperature_time: le_u32(buf, 192),
+            critical_temperature_time: le_u32(buf, 196),
+            temperature_sensors: std::array::from_fn(|i| {
+                le_u16(buf, 200 + i * 2)
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_smart_log() {
+        let mut buf = [0u8; SMART_LEN];
+        buf[0] = 0x4;
+        buf[1..3].copy_from_slice(&310u16.to_le_bytes());
+        buf[3] = 90;
+        buf[4] = 10;
+        buf[5] = 7;
+        buf[48..64].copy_from_slice(&1234u128.to_le_bytes());
+        buf[144..160].copy_from_slice(&12u128.to_le_bytes());
+        buf[160..176].copy_from_slice(&2u128.to_le_bytes());
+        buf[196..200].copy_from_slice(&30u32.to_le_bytes());
+        buf[202..204].copy_from_slice(&305u16.to_le_bytes());
+
+        let log = SmartLog::decode(&buf).unwrap();
+        assert_eq!(log.critical_warning, 0x4);
+        assert_eq!(log.composite_temperature, 310);
+        assert_eq!(log.available_spare, 90);
+        assert_eq!(log.available_spare_threshold, 10);
+        assert_eq!(log.percentage_used, 7);
+        assert_eq!(log.data_units_written, 1234);
+        assert_eq!(log.unsafe_shutdowns, 12);
+        assert_eq!(log.media_errors, 2);
+        assert_eq!(log.critical_temperature_time, 30);
+        assert_eq!(log.temperature_sensors, [0, 305, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The Supported Log Pages log page (2.0).
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::le_u32,
+};
+
+/// Log page identifier of the Supported Log Pages log.
+pub const SUPPORTED_LOG_PAGES_LID: u32 = 0x00;
+/// Size of the Supported Log Pages log.
+pub const SUPPORTED_LOG_PAGES_LEN: usize = 1024;
+/// Number of log page identifiers.
+const NUM_LIDS: usize = 256;
+
+/// The LID Supported and Effects entry for a single log page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogPageSupport(pub u32);
+
+impl LogPageSupport {
+    const SUPPORTED: u32 = 1 << 0;
+    const INDEX_OFFSET: u32 = 1 << 1;
+
+    /// LID Supported (LSUPP).
+    pub fn supported(&self) -> bool {
+        self.0 & Self::SUPPORTED != 0
+    }
+
+    /// Index Offset Supported (IOS): the log page may be read from a non-zero
+    /// offset.
+    pub fn index_offset(&self) -> bool {
+        self.0 & Self::INDEX_OFFSET != 0
+    }
+
+    /// The LID Specific Parameter field, such as the Log Specific Parameters
+    /// a log page supports.
+    pub fn lid_specific(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+}
+
+/// The log pages a controller supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SupportedLogPages {
+    entries: Vec<LogPageSupport>,
+    inferred: bool,
+}
+
+impl SupportedLogPages {
+    /// Decode the Supported Log Pages log (00h).
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, SUPPORTED_LOG_PAGES_LEN)?;
+        let entries = (0..NUM_LIDS)
+            .map(|lid| LogPageSupport(le_u32(buf, lid * 4)))
+            .collect();
+        Ok(Self { entries, inferred: false })
+    }
+
+    /// Build the set of supported log pages from some other source of
+    /// information, for controllers that predate the Supported Log Pages
+    /// log. `index_offset` applies to every log page.
+    pub fn infer(
+        lids: impl IntoIterator<Item = u8>,
+        index_offset: bool,
+    ) -> Self {
+        let mut entries = vec![LogPageSupport::default(); NUM_LIDS];
+        let mut support = LogPageSupport::SUPPORTED;
+        if index_offset {
+            support |= LogPageSupport::INDEX_OFFSET;
+        }
+        for lid in lids {
+            entries[usize::from(lid)] = LogPageSupport(support);
+        }
+        Self { entries, inferred: true }
+    }
+
+    /// Whether this was inferred rather than reported by the controller.
+    /// Vendor specific log pages are never included when inferred.
+    pub fn is_inferred(&self) -> bool {
+        self.inferred
+    }
+
+    pub fn get(&self, lid: u8) -> LogPageSupport {
+        self.entries[usize::from(lid)]
+    }
+
+    pub fn supports(&self, lid: u8) -> bool {
+        self.get(lid).supported()
+    }
+
+    /// Whether log page `lid` is supported and may be read starting at
+    /// `offset`.
+    pub fn supports_offset(&self, lid: u8, offset: u64) -> bool {
+        let support = self.get(lid);
+        support.supported() && (offset == 0 || support.index_offset())
+    }
+
+    /// The supported log pages and their support entries.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, LogPageSupport)> + '_ {
+        (0..=u8::MAX)
+            .zip(self.entries.iter().copied())
+            .filter(|(_, support)| support.supported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_supported_log_pages() {
+        let mut buf = vec![0u8; SUPPORTED_LOG_PAGES_LEN];
+        for (lid, entry) in [(0x00, 0x1), (0x02, 0x1), (0x0d, 0x0003_0003)] {
+            buf[lid * 4..lid * 4 + 4]
+                .copy_from_slice(&(entry as u32).to_le_bytes());
+        }
+
+        let pages = SupportedLogPages::decode(&buf).unwrap();
+        assert!(!pages.is_inferred());
+        assert!(pages.supports(0x02));
+        assert!(!pages.supports(0x03));
+        assert!(pages.supports_offset(0x0d, 512));
+        assert!(!pages.supports_offset(0x02, 512));
+        assert!(pages.supports_offset(0x02, 0));
+        assert_eq!(pages.get(0x0d).lid_specific(), 0x3);
+        let lids: Vec<_> = pages.iter().map(|(lid, _)| lid).collect();
+        assert_eq!(lids, vec![0x00, 0x02, 0x0d]);
+    }
+
+    #[test]
+    fn infer_supported_log_pages() {
+        let pages = SupportedLogPages::infer([0x01, 0x02, 0x03], false);
+        assert!(pages.is_inferred());
+        assert!(pages.supports(0x03));
+        assert!(!pages.supports(0x05));
+        assert!(!pages.supports_offset(0x02, 4096));
+
+        let pages = SupportedLogPages::infer([0x02], true);
+        assert!(pages.supports_offset(0x02, 4096));
+    }
+}
@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Telemetry Host-Initiated and Telemetry Controller-Initiated log pages.
+
+use crate::{
+    error::{check_len, DecodeError},
+    util::{le_u16, le_u32},
+};
+
+/// Log page identifier of the Telemetry Host-Initiated log (1.3).
+pub const TELEMETRY_HOST_LID: u32 = 0x07;
+/// Log page identifier of the Telemetry Controller-Initiated log (1.3).
+pub const TELEMETRY_CONTROLLER_LID: u32 = 0x08;
+/// Telemetry data is organized in 512 byte blocks, the first of which is the
+/// header.
+pub const TELEMETRY_BLOCK_SIZE: u64 = 512;
+/// Size of the telemetry header.
+pub const TELEMETRY_HDR_LEN: usize = 512;
+/// Log Specific Parameter that asks the controller to capture new Telemetry
+/// Host-Initiated data.
+pub const TELEMETRY_LSP_CREATE: u32 = 1;
+/// Length of the Reason Identifier in the telemetry header.
+pub const TELEMETRY_REASON_ID_LEN: usize = 128;
+
+/// One of the telemetry data areas. Each data area includes all of the
+/// smaller numbered ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DataArea {
+    One,
+    Two,
+    Three,
+    /// Data Area 4 (2.0) is only reported if the host has enabled it with
+    /// the Host Behavior Support feature.
+    Four,
+}
+
+impl TryFrom<u8> for DataArea {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::One),
+            2 => Ok(Self::Two),
+            3 => Ok(Self::Three),
+            4 => Ok(Self::Four),
+            invalid => Err(invalid),
+        }
+    }
+}
+
+/// The header shared by both telemetry log pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryHeader {
+    pub log_id: u8,
+    pub ieee_oui: [u8; 3],
+    /// The last block of each data area, indexed by data area - 1.
+    pub data_area_last_block: [u32; 4],
+    /// Incremented each time new Telemetry Host-Initiated data is captured.
+    pub host_generation: u8,
+    /// Whether the controller has Telemetry Controller-Initiated data
+    /// available.
+    pub controller_data_available: bool,
+    /// Incremented each time new Telemetry Controller-Initiated data is
+    /// captured.
+    pub controller_generation: u8,
+    /// Vendor specific identifier of the reason the data was captured.
+    pub reason_identifier: [u8; TELEMETRY_REASON_ID_LEN],
+}
+
+impl TelemetryHeader {
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        check_len(buf, TELEMETRY_HDR_LEN)?;
+        let mut reason_identifier = [0u8; TELEMETRY_REASON_ID_LEN];
+        reason_identifier.copy_from_slice(&buf[384..512]);
+        Ok(Self {
+            log_id: buf[0],
+            ieee_oui: [buf[5], buf[6], buf[7]],
+            data_area_last_block: [
+                u32::from(le_u16(buf, 8)),
+                u32::from(le_u16(buf, 10)),
+                u32::from(le_u16(buf, 12)),
+                le_u32(buf, 16),
+            ],
+            host_generation: buf[381],
+            controller_data_available: buf[382] != 0,
+            controller_generation: buf[383],
+            reason_identifier,
+        })
+    }
+
+    /// The number of bytes that must be read, including the header, to
+    /// capture everything up to and including `area`.
+    ///
+    /// Returns `None` if the controller reports no data up to `area`, which
+    /// is also how Data Area 4 is reported when it is unsupported or not
+    /// enabled.
+    pub fn capture_len(&self, area: DataArea) -> Option<u64> {
+        match self.data_area_last_block[area as usize] {
+            0 => None,
+            last_block => {
+                Some((u64::from(last_block) + 1) * TELEMETRY_BLOCK_SIZE)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_telemetry_header() {
+        let mut buf = [0u8; TELEMETRY_HDR_LEN];
+        buf[0] = TELEMETRY_HOST_LID as u8;
+        buf[5..8].copy_from_slice(&[0x00, 0x1b, 0x96]);
+        buf[8..10].copy_from_slice(&3u16.to_le_bytes());
+        buf[10..12].copy_from_slice(&20u16.to_le_bytes());
+        buf[12..14].copy_from_slice(&100u16.to_le_bytes());
+        buf[16..20].copy_from_slice(&70_000u32.to_le_bytes());
+        buf[381] = 7;
+        buf[382] = 1;
+        buf[383] = 2;
+        buf[384..388].copy_from_slice(b"ecc!");
+
+        let hdr = TelemetryHeader::decode(&buf).unwrap();
+        assert_eq!(hdr.log_id, 0x07);
+        assert_eq!(hdr.ieee_oui, [0x00, 0x1b, 0x96]);
+        assert_eq!(hdr.host_generation, 7);
+        assert!(hdr.controller_data_available);
+        assert_eq!(hdr.controller_generation, 2);
+        assert_eq!(&hdr.reason_identifier[..4], b"ecc!");
+
+        assert_eq!(hdr.capture_len(DataArea::One), Some(4 * 512));
+        assert_eq!(hdr.capture_len(DataArea::Three), Some(101 * 512));
+        assert_eq!(hdr.capture_len(DataArea::Four), Some(70_001 * 512));
+
+        buf[16..20].fill(0);
+        let hdr = TelemetryHeader::decode(&buf).unwrap();
+        assert_eq!(hdr.capture_len(DataArea::Four), None);
+        assert_eq!(DataArea::try_from(2), Ok(DataArea::Two));
+        assert_eq!(DataArea::try_from(5), Err(5));
+    }
+}
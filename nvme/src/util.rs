@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Helpers for pulling little-endian fields out of log page buffers. Callers
+// are expected to have checked the buffer length up front.
+
+pub(crate) fn le_u16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(buf[off..off + 2].try_into().unwrap())
+}
+
+pub(crate) fn le_u32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+pub(crate) fn le_u64(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+pub(crate) fn le_u128(buf: &[u8], off: usize) -> u128 {
+    u128::from_le_bytes(buf[off..off + 16].try_into().unwrap())
+}
+
+/// Decode a fixed length ASCII field, dropping the trailing space or NUL
+/// padding.
+pub(crate) fn ascii_field(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf).trim_end_matches(['\0', ' ']).to_string()
+}
@@ -0,0 +1,29 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Western Digital vendor unique diagnostic data.
+//!
+//! The extended SMART data of the SN840 and SN650 families is reported in the
+//! OCP SMART / Health Information Extended log (C0h); see [`crate::ocp`].
+
+/// Length of the header at the start of an E6 diagnostic dump.
+pub const WDC_E6_HDR_LEN: usize = 8;
+
+/// Get the total length in bytes of an E6 diagnostic dump, including the
+/// header, from its header.
+pub fn e6_dump_len(hdr: &[u8; WDC_E6_HDR_LEN]) -> u32 {
+    // The length is stored big-endian after a four byte signature.
+    u32::from_be_bytes([hdr[4], hdr[5], hdr[6], hdr[7]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn e6_header_len() {
+        let hdr = [b'E', b'6', 0, 0, 0x00, 0x01, 0x00, 0x08];
+        assert_eq!(e6_dump_len(&hdr), 0x10008);
+    }
+}
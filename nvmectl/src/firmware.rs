@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Showing firmware slots and loading and activating new firmware.
+
+use std::path::PathBuf;
+
+use clap::{Subcommand, ValueEnum};
+use libnvme::{
+    controller::Controller,
+    firmware::{FirmwareCommitAction, NvmeSlot},
+    snapshot::FirmwareSlotsSnapshot,
+};
+
+use crate::{
+    instance,
+    output::{or_missing, Action, Labelled, Output, Table},
+    Changing, Context, Error, Target, Targets,
+};
+
+#[derive(Debug, Subcommand)]
+pub enum FirmwareCommand {
+    /// Show the firmware in each slot.
+    Show(Targets),
+    /// Download a firmware image to the controller without committing it.
+    Load {
+        #[command(flatten)]
+        target: Target,
+        image: PathBuf,
+    },
+    /// Commit a downloaded image to a slot, or activate a slot.
+    Commit {
+        #[command(flatten)]
+        target: Target,
+        #[arg(long)]
+        slot: u8,
+        #[arg(long, value_enum, default_value_t = CommitAction::SaveActivate)]
+        action: CommitAction,
+    },
+    /// Download an image, save it to a slot and activate it at the next
+    /// reset.
+    Update {
+        #[command(flatten)]
+        target: Target,
+        image: PathBuf,
+        #[arg(long)]
+        slot: u8,
+    },
+}
+
+/// The commit actions illumos supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CommitAction {
+    /// Save the image to the slot.
+    Save,
+    /// Save the image to the slot and activate it at the next reset.
+    SaveActivate,
+    /// Activate the slot at the next reset.
+    Activate,
+}
+
+impl From<CommitAction> for FirmwareCommitAction {
+    fn from(action: CommitAction) -> Self {
+        match action {
+            CommitAction::Save => FirmwareCommitAction::Save,
+            CommitAction::SaveActivate => FirmwareCommitAction::SaveActivate,
+            CommitAction::Activate => FirmwareCommitAction::Activate,
+        }
+    }
+}
+
+pub(crate) fn firmware(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    cmd: &FirmwareCommand,
+) -> Result<(), Error> {
+    let actions = match cmd {
+        FirmwareCommand::Show(targets) => return show(ctx, out, targets),
+        FirmwareCommand::Load { target, image } => {
+            let image = read_image(image)?;
+            let controller = ctx.changing(target)?;
+            vec![load(&controller, &image)?]
+        }
+        FirmwareCommand::Commit { target, slot, action } => {
+            let controller = ctx.changing(target)?;
+            vec![commit(&controller, *slot, *action)?]
+        }
+        FirmwareCommand::Update { target, image, slot } => {
+            let image = read_image(image)?;
+            let controller = ctx.changing(target)?;
+            // Check the slot before downloading anything.
+            check_slot(&controller, *slot, CommitAction::SaveActivate)?;
+            vec![
+                load(&controller, &image)?,
+                commit(&controller, *slot, CommitAction::SaveActivate)?,
+            ]
+        }
+    };
+    out.actions(&actions)
+}
+
+fn show(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    targets: &Targets,
+) -> Result<(), Error> {
+    let mut controllers = Vec::new();
+    for controller in ctx.controllers(targets)? {
+        let instance = instance(&controller)?;
+        let log =
+            controller.get_firmware_log_page().map_err(Error::on(instance))?;
+        let item = FirmwareSlotsSnapshot::from(&log);
+        controllers.push(Labelled { instance, item });
+    }
+
+    let mut table = Table::new(&["INSTANCE", "SLOT", "VERSION", "STATUS"]);
+    for Labelled { instance, item } in &controllers {
+        for slot in &item.slots {
+            let mut status = Vec::new();
+            if slot.slot == item.active_slot {
+                status.push("active");
+            }
+            if Some(slot.slot) == item.next_active_slot {
+                status.push("next");
+            }
+            if slot.slot == 1 && item.slot1_read_only {
+                status.push("read-only");
+            }
+            table.row(vec![
+                format!("nvme{instance}"),
+                slot.slot.to_string(),
+                or_missing(slot.version.as_ref()),
+                status.join(","),
+            ]);
+        }
+    }
+    out.emit(&table, &controllers)
+}
+
+fn read_image(path: &PathBuf) -> Result<Vec<u8>, Error> {
+    std::fs::read(path)
+        .map_err(|error| Error::Read { path: path.clone(), error })
+}
+
+fn load(controller: &Changing<'_>, image: &[u8]) -> Result<Action, Error> {
+    let instance = instance(controller)?;
+    if let Some(locked) = controller.locked() {
+        locked.firmware_load(image).map_err(Error::on(instance))?;
+    }
+    Ok(Action {
+        instance,
+        action: format!("load {} byte firmware image", image.len()),
+        dry_run: controller.is_dry_run(),
+    })
+}
+
+/// Check that `slot` exists and, if the image is to be saved to it, that it
+/// is writable.
+fn check_slot(
+    controller: &Controller<'_>,
+    slot: u8,
+    action: CommitAction,
+) -> Result<NvmeSlot, Error> {
+    let instance = instance(controller)?;
+    let nvme_slot = NvmeSlot::try_from(slot).map_err(Error::on(instance))?;
+    let log =
+        controller.get_firmware_log_page().map_err(Error::on(instance))?;
+    if slot > log.number_of_slots {
+        return Err(Error::Invalid(format!(
+            "nvme{instance} has {} firmware slots",
+            log.number_of_slots
+        )));
+    }
+    if slot == 1 && log.slot1_is_read_only && action != CommitAction::Activate {
+        return Err(Error::Invalid(format!(
+            "firmware slot 1 of nvme{instance} is read-only"
+        )));
+    }
+    Ok(nvme_slot)
+}
+
+fn commit(
+    controller: &Changing<'_>,
+    slot: u8,
+    action: CommitAction,
+) -> Result<Action, Error> {
+    let instance = instance(controller)?;
+    let nvme_slot = check_slot(controller, slot, action)?;
+    if let Some(locked) = controller.locked() {
+        locked
+            .firmware_commit_request()?
+            .set_slot(nvme_slot)?
+            .set_action(action.into())?
+            .execute()
+            .map_err(Error::on(instance))?;
+    }
+    let action = match action {
+        CommitAction::Save => format!("save firmware to slot {slot}"),
+        CommitAction::SaveActivate => {
+            format!("save firmware to slot {slot} and activate it on reset")
+        }
+        CommitAction::Activate => format!("activate slot {slot} on reset"),
+    };
+    Ok(Action { instance, action, dry_run: controller.is_dry_run() })
+}
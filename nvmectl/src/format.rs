@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Formatting namespaces and attaching and detaching blkdev from them.
+
+use clap::{Args, Subcommand};
+use libnvme::namespace::{Namespace, NamespaceDiscoveryLevel};
+
+use crate::{
+    instance,
+    output::{Action, Output},
+    parse_number, Changing, Context, Error, Target,
+};
+
+/// The broadcast NSID, which a format applies to every namespace.
+const NSID_ALL: u32 = u32::MAX;
+
+#[derive(Debug, Args)]
+pub struct FormatArgs {
+    #[command(flatten)]
+    target: Target,
+    /// The ID of the LBA format to use, as shown by `identify`.
+    #[arg(long, value_parser = parse_number::<u32>)]
+    lbaf: u32,
+    /// The namespace to format; every namespace if not given.
+    #[arg(long, value_parser = parse_number::<u32>)]
+    nsid: Option<u32>,
+    /// The Secure Erase Setting: 0 for none, 1 to erase user data and 2 for
+    /// a cryptographic erase.
+    #[arg(long, default_value_t = 0)]
+    ses: u32,
+}
+
+/// Format the namespaces, detaching blkdev from any that have it attached
+/// first and attaching it again afterwards.
+pub(crate) fn format(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    args: &FormatArgs,
+) -> Result<(), Error> {
+    let controller = ctx.changing(&args.target)?;
+    let instance = instance(&controller)?;
+    let dry_run = controller.is_dry_run();
+
+    let info = controller.get_info().map_err(Error::on(instance))?;
+    let lbaf = info
+        .lba_formats()
+        .filter_map(Result::ok)
+        .find(|f| f.id() == args.lbaf)
+        .ok_or_else(|| {
+            Error::Invalid(format!(
+                "nvme{instance} has no LBA format {}",
+                args.lbaf
+            ))
+        })?;
+
+    let nsid = args.nsid.unwrap_or(NSID_ALL);
+    let attached = controller
+        .namespace_discovery(NamespaceDiscoveryLevel::BlkDev)
+        .map_err(Error::on(instance))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::on(instance))?
+        .into_iter()
+        .filter(|ns| nsid == NSID_ALL || ns.nsid() == nsid)
+        .collect::<Vec<_>>();
+
+    let namespaces = match args.nsid {
+        Some(nsid) => format!("namespace {nsid}"),
+        None => "all namespaces".to_string(),
+    };
+    let format = Action {
+        instance,
+        action: format!(
+            "format {namespaces} with LBA format {} ({}+{} bytes)",
+            lbaf.id(),
+            lbaf.data_size(),
+            lbaf.meta_size()
+        ),
+        dry_run,
+    };
+
+    let mut actions = Vec::new();
+    let mut detached = Vec::new();
+    let mut detach_and_format = || -> Result<(), Error> {
+        for ns in &attached {
+            actions.push(blkdev(&controller, ns, false)?);
+            detached.push(ns);
+        }
+        if let Some(locked) = controller.locked() {
+            locked
+                .format_request()?
+                .set_lbaf(lbaf.id())?
+                .set_nsid(nsid)?
+                .set_ses(args.ses)?
+                .execute()
+                .map_err(Error::on(instance))?;
+        }
+        Ok(())
+    };
+    let result = detach_and_format();
+    // blkdev is attached again to whatever it was detached from, whether or
+    // not the format went ahead.
+    let reattached = detached
+        .iter()
+        .map(|ns| blkdev(&controller, ns, true))
+        .collect::<Vec<_>>();
+    result?;
+    actions.push(format);
+    for action in reattached {
+        actions.push(action?);
+    }
+    out.actions(&actions)
+}
+
+/// Attach or detach blkdev.
+fn blkdev(
+    controller: &Changing<'_>,
+    ns: &Namespace<'_>,
+    attach: bool,
+) -> Result<Action, Error> {
+    let instance = instance(controller)?;
+    if controller.locked().is_some() {
+        let result =
+            if attach { ns.blkdev_attach() } else { ns.blkdev_detach() };
+        result.map_err(Error::on(instance))?;
+    }
+    let action = if attach {
+        format!("attach blkdev to namespace {}", ns.nsid())
+    } else {
+        format!("detach blkdev from namespace {}", ns.nsid())
+    };
+    Ok(Action { instance, action, dry_run: controller.is_dry_run() })
+}
+
+/// Namespace management.
+///
+/// `attach` and `detach` bind and unbind the illumos blkdev driver, which is
+/// what makes a namespace available as a disk. They do not issue the NVMe
+/// Namespace Attachment command, and the namespace stays attached to the
+/// controller throughout.
+#[derive(Debug, Subcommand)]
+pub enum NamespaceCommand {
+    /// Attach blkdev to a namespace, making it available as a disk.
+    Attach {
+        #[command(flatten)]
+        target: Target,
+        #[arg(value_parser = parse_number::<u32>)]
+        nsid: u32,
+    },
+    /// Detach blkdev from a namespace.
+    Detach {
+        #[command(flatten)]
+        target: Target,
+        #[arg(value_parser = parse_number::<u32>)]
+        nsid: u32,
+    },
+    /// Create a namespace. Not supported by libnvme today.
+    Create {
+        #[command(flatten)]
+        target: Target,
+        /// The size of the namespace in logical blocks.
+        #[arg(long, value_parser = parse_number::<u64>)]
+        blocks: u64,
+        #[arg(long, value_parser = parse_number::<u32>)]
+        lbaf: u32,
+    },
+    /// Delete a namespace. Not supported by libnvme today.
+    Delete {
+        #[command(flatten)]
+        target: Target,
+        #[arg(value_parser = parse_number::<u32>)]
+        nsid: u32,
+    },
+}
+
+pub(crate) fn namespace(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    cmd: &NamespaceCommand,
+) -> Result<(), Error> {
+    let (target, nsid, attach) = match cmd {
+        NamespaceCommand::Attach { target, nsid } => (target, *nsid, true),
+        NamespaceCommand::Detach { target, nsid } => (target, *nsid, false),
+        NamespaceCommand::Create { .. } => {
+            return Err(Error::Unsupported("namespace creation"));
+        }
+        NamespaceCommand::Delete { .. } => {
+            return Err(Error::Unsupported("namespace deletion"));
+        }
+    };
+
+    let controller = ctx.changing(target)?;
+    let instance = instance(&controller)?;
+    let ns = controller
+        .namespace_discovery(NamespaceDiscoveryLevel::All)
+        .map_err(Error::on(instance))?
+        .filter_map(Result::ok)
+        .find(|ns| ns.nsid() == nsid)
+        .ok_or_else(|| {
+            Error::Invalid(format!("nvme{instance} has no namespace {nsid}"))
+        })?;
+    let action = blkdev(&controller, &ns, attach)?;
+    out.actions(&[action])
+}
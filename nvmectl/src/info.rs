@@ -0,0 +1,211 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Commands that only read what a controller is and how it is doing.
+
+use libnvme::{
+    inventory::ControllerInventory,
+    namespace::NamespaceDiscoveryLevel,
+    nvmespec::self_test::{SelfTestCode, SelfTestResult},
+    snapshot::{ControllerSnapshot, NamespaceSnapshot},
+};
+use serde::Serialize;
+
+use crate::{
+    instance,
+    output::{or_missing, Labelled, Output, Table},
+    Context, Error, Targets,
+};
+
+pub(crate) fn list(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    targets: &Targets,
+) -> Result<(), Error> {
+    let inventory = ctx
+        .controllers(targets)?
+        .iter()
+        .map(ControllerInventory::collect)
+        .collect::<Vec<_>>();
+
+    let mut table =
+        Table::new(&["INSTANCE", "MODEL", "SERIAL", "FWREV", "NS", "PATH"]);
+    for ctrl in &inventory {
+        let snapshot = ctrl.controller.as_ref();
+        table.row(vec![
+            or_missing(ctrl.instance.map(|i| format!("nvme{i}"))),
+            or_missing(snapshot.map(|c| &c.model)),
+            or_missing(snapshot.map(|c| &c.serial)),
+            or_missing(snapshot.map(|c| &c.fwrev)),
+            ctrl.namespaces.len().to_string(),
+            or_missing(ctrl.devinfo_path.as_ref()),
+        ]);
+    }
+    out.emit(&table, &inventory)
+}
+
+pub(crate) fn identify(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    targets: &Targets,
+) -> Result<(), Error> {
+    let mut controllers = Vec::new();
+    for controller in ctx.controllers(targets)? {
+        let instance = instance(&controller)?;
+        let item = controller.snapshot().map_err(Error::on(instance))?;
+        controllers.push(Labelled { instance, item });
+    }
+
+    let mut table = Table::new(&[
+        "INSTANCE", "MODEL", "SERIAL", "FWREV", "VERSION", "VID", "NNS",
+        "CAPACITY", "SUBNQN",
+    ]);
+    for Labelled { instance, item } in &controllers {
+        let ControllerSnapshot { identify, .. } = item;
+        table.row(vec![
+            format!("nvme{instance}"),
+            item.model.clone(),
+            item.serial.clone(),
+            item.fwrev.clone(),
+            or_missing(identify.version.as_ref()),
+            format!("{:#06x}", identify.vid),
+            item.nns.to_string(),
+            identify.tnvmcap.to_string(),
+            identify.subnqn.clone(),
+        ]);
+    }
+    out.emit(&table, &controllers)
+}
+
+pub(crate) fn identify_namespaces(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    targets: &Targets,
+) -> Result<(), Error> {
+    let mut namespaces = Vec::new();
+    for controller in ctx.controllers(targets)? {
+        let instance = instance(&controller)?;
+        for ns in controller
+            .namespace_discovery(NamespaceDiscoveryLevel::All)
+            .map_err(Error::on(instance))?
+        {
+            let item =
+                ns.and_then(|ns| ns.snapshot()).map_err(Error::on(instance))?;
+            namespaces.push(Labelled { instance, item });
+        }
+    }
+
+    let mut table = Table::new(&[
+        "INSTANCE", "NSID", "LEVEL", "FORMAT", "SIZE", "CAPACITY", "USED",
+    ]);
+    for Labelled { instance, item } in &namespaces {
+        let NamespaceSnapshot { format, size, capacity, used, .. } = item;
+        table.row(vec![
+            format!("nvme{instance}"),
+            item.nsid.to_string(),
+            format!("{:?}", item.level),
+            or_missing(format.map(|f| {
+                format!("{}: {}+{}", f.id(), f.data_size(), f.meta_size())
+            })),
+            or_missing(*size),
+            or_missing(*capacity),
+            or_missing(*used),
+        ]);
+    }
+    out.emit(&table, &namespaces)
+}
+
+/// A self-test that is in progress or has finished.
+#[derive(Debug, Serialize)]
+struct SelfTest {
+    test: String,
+    /// The result, or `in-progress` along with `completion`.
+    result: String,
+    completion: Option<u8>,
+    segment: Option<u8>,
+    power_on_hours: Option<u64>,
+    nsid: Option<u32>,
+    failing_lba: Option<u64>,
+}
+
+fn test_name(code: SelfTestCode) -> String {
+    match code {
+        SelfTestCode::Short => "short".to_string(),
+        SelfTestCode::Extended => "extended".to_string(),
+        SelfTestCode::VendorSpecific => "vendor".to_string(),
+        SelfTestCode::Unknown(code) => format!("{code:#x}"),
+    }
+}
+
+fn result_name(result: SelfTestResult) -> String {
+    match result {
+        SelfTestResult::Passed => "passed".to_string(),
+        SelfTestResult::Aborted => "aborted".to_string(),
+        SelfTestResult::AbortedByReset => "aborted-reset".to_string(),
+        SelfTestResult::AbortedByNamespaceRemoval => {
+            "aborted-ns-removed".to_string()
+        }
+        SelfTestResult::AbortedByFormat => "aborted-format".to_string(),
+        SelfTestResult::FatalError => "fatal-error".to_string(),
+        SelfTestResult::FailedUnknownSegment => "failed".to_string(),
+        SelfTestResult::FailedSegment => "failed-segment".to_string(),
+        SelfTestResult::AbortedUnknown => "aborted-unknown".to_string(),
+        SelfTestResult::AbortedBySanitize => "aborted-sanitize".to_string(),
+        SelfTestResult::Unknown(result) => format!("{result:#x}"),
+    }
+}
+
+pub(crate) fn selftest(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    targets: &Targets,
+) -> Result<(), Error> {
+    let mut tests = Vec::new();
+    for controller in ctx.controllers(targets)? {
+        let instance = instance(&controller)?;
+        let log = controller.self_test_log().map_err(Error::on(instance))?;
+        if let Some(code) = log.current {
+            let item = SelfTest {
+                test: test_name(code),
+                result: "in-progress".to_string(),
+                completion: Some(log.current_completion),
+                segment: None,
+                power_on_hours: None,
+                nsid: None,
+                failing_lba: None,
+            };
+            tests.push(Labelled { instance, item });
+        }
+        for entry in log.results {
+            let item = SelfTest {
+                test: test_name(entry.code),
+                result: result_name(entry.result),
+                completion: None,
+                segment: Some(entry.segment).filter(|&s| s != 0),
+                power_on_hours: Some(entry.power_on_hours),
+                nsid: entry.nsid,
+                failing_lba: entry.failing_lba,
+            };
+            tests.push(Labelled { instance, item });
+        }
+    }
+
+    let mut table =
+        Table::new(&["INSTANCE", "TEST", "RESULT", "POH", "SEGMENT", "LBA"]);
+    for Labelled { instance, item } in &tests {
+        let result = match item.completion {
+            Some(percent) => format!("{} {percent}%", item.result),
+            None => item.result.clone(),
+        };
+        table.row(vec![
+            format!("nvme{instance}"),
+            item.test.clone(),
+            result,
+            or_missing(item.power_on_hours),
+            or_missing(item.segment),
+            or_missing(item.failing_lba),
+        ]);
+    }
+    out.emit(&table, &tests)
+}
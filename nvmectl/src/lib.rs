@@ -0,0 +1,495 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The `nvmectl` command.
+//!
+//! Everything but opening libnvme lives here, so that [`run`] can be driven
+//! against any [`Nvme`], including one backed by the simulator.
+
+use std::{io::Write, ops::Deref, path::PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+use libnvme::{
+    controller::{Controller, NvmeControllerError, WriteLockedController},
+    selector::{ControllerSelector, FindError},
+    Nvme, NvmeError,
+};
+use thiserror::Error;
+
+mod firmware;
+mod format;
+mod info;
+mod logpage;
+pub mod output;
+
+use output::{Output, OutputMode};
+
+#[derive(Debug, Parser)]
+#[command(name = "nvmectl", about = "Inspect and manage NVMe controllers")]
+pub struct Cli {
+    /// Print results as JSON.
+    #[arg(long, global = true, conflicts_with = "parsable")]
+    pub json: bool,
+    /// Print results as `:` separated fields without a header.
+    #[arg(short, long, global = true)]
+    pub parsable: bool,
+    /// Show what a command that changes a controller would do, without
+    /// doing it.
+    #[arg(short = 'n', long, global = true)]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// A controller selector, as accepted by `ControllerSelector::from_str`:
+/// `nvme<N>`, a `/devices` path, `serial=`, `model=<glob>`, `vid=` or `nqn=`.
+#[derive(Debug, Args)]
+pub struct Target {
+    /// The controller to operate on.
+    #[arg(value_name = "CONTROLLER")]
+    pub selector: ControllerSelector,
+}
+
+/// As [`Target`], but every controller when no selector is given.
+#[derive(Debug, Args)]
+pub struct Targets {
+    /// The controllers to show; all of them if not given.
+    #[arg(value_name = "CONTROLLER")]
+    pub selector: Option<ControllerSelector>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List controllers.
+    List(Targets),
+    /// Show identify information of controllers or their namespaces.
+    Identify {
+        #[command(flatten)]
+        targets: Targets,
+        /// Show every namespace instead of the controller.
+        #[arg(long)]
+        ns: bool,
+    },
+    /// Read a log page.
+    Logpage(logpage::LogpageArgs),
+    /// Read features.
+    #[command(subcommand)]
+    Features(logpage::FeaturesCommand),
+    /// Show and update firmware.
+    #[command(subcommand)]
+    Firmware(firmware::FirmwareCommand),
+    /// Format namespaces with a new LBA format, erasing them.
+    Format(format::FormatArgs),
+    /// Manage namespaces and their blkdev attachment.
+    #[command(subcommand)]
+    Namespace(format::NamespaceCommand),
+    /// Show the progress and results of device self-tests.
+    Selftest(Targets),
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Nvme(#[from] NvmeError),
+    #[error(transparent)]
+    Controller(#[from] NvmeControllerError),
+    #[error(transparent)]
+    Find(#[from] FindError),
+    #[error("no controller matches {0:?}")]
+    NoController(ControllerSelector),
+    #[error("{selector:?} matches {count} controllers but must match one")]
+    Ambiguous { selector: ControllerSelector, count: usize },
+    #[error("nvme{instance}: {error}")]
+    Failed { instance: i32, error: Box<dyn std::error::Error> },
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0} is not supported by libnvme")]
+    Unsupported(&'static str),
+    #[error("failed to read {}: {error}", path.display())]
+    Read { path: PathBuf, error: std::io::Error },
+    #[error("failed to write output: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to write JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl Error {
+    /// An error from operating on the controller with driver `instance`.
+    fn on<E>(instance: i32) -> impl FnOnce(E) -> Self
+    where
+        E: std::error::Error + 'static,
+    {
+        move |error| Self::Failed { instance, error: Box::new(error) }
+    }
+}
+
+/// Run `cli` against the controllers of `nvme`, writing results to `out`.
+pub fn run(nvme: &Nvme, cli: &Cli, out: &mut dyn Write) -> Result<(), Error> {
+    let mode = if cli.json {
+        OutputMode::Json
+    } else if cli.parsable {
+        OutputMode::Parsable
+    } else {
+        OutputMode::Human
+    };
+    let mut out = Output::new(out, mode);
+    let ctx = Context { nvme, dry_run: cli.dry_run };
+
+    match &cli.command {
+        Command::List(targets) => info::list(&ctx, &mut out, targets),
+        Command::Identify { targets, ns: false } => {
+            info::identify(&ctx, &mut out, targets)
+        }
+        Command::Identify { targets, ns: true } => {
+            info::identify_namespaces(&ctx, &mut out, targets)
+        }
+        Command::Logpage(args) => logpage::logpage(&ctx, &mut out, args),
+        Command::Features(cmd) => logpage::features(&ctx, &mut out, cmd),
+        Command::Firmware(cmd) => firmware::firmware(&ctx, &mut out, cmd),
+        Command::Format(args) => format::format(&ctx, &mut out, args),
+        Command::Namespace(cmd) => format::namespace(&ctx, &mut out, cmd),
+        Command::Selftest(targets) => info::selftest(&ctx, &mut out, targets),
+    }
+}
+
+/// What every command needs besides its own arguments.
+struct Context<'a> {
+    nvme: &'a Nvme,
+    dry_run: bool,
+}
+
+impl<'a> Context<'a> {
+    /// The controllers `targets` selects, in discovery order.
+    fn controllers(
+        &self,
+        targets: &Targets,
+    ) -> Result<Vec<Controller<'a>>, Error> {
+        match &targets.selector {
+            Some(selector) => Ok(self
+                .nvme
+                .find_controllers(selector)?
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?),
+            None => Ok(self
+                .nvme
+                .controller_discovery()?
+                .collect::<Result<Vec<_>, _>>()?),
+        }
+    }
+
+    /// The single controller `target` selects.
+    fn controller(&self, target: &Target) -> Result<Controller<'a>, Error> {
+        let selector = &target.selector;
+        let mut controllers = self
+            .nvme
+            .find_controllers(selector)?
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        match controllers.len() {
+            0 => Err(Error::NoController(selector.clone())),
+            1 => Ok(controllers.remove(0)),
+            count => {
+                Err(Error::Ambiguous { selector: selector.clone(), count })
+            }
+        }
+    }
+
+    /// The single controller `target` selects, write locked unless this is
+    /// a dry run.
+    fn changing(&self, target: &Target) -> Result<Changing<'a>, Error> {
+        let controller = self.controller(target)?;
+        if self.dry_run {
+            return Ok(Changing::DryRun(controller));
+        }
+        let instance = instance(&controller)?;
+        let locked = controller
+            .write_lock()
+            .map_err(|(_, error)| Error::on(instance)(error))?;
+        Ok(Changing::Locked(locked))
+    }
+}
+
+/// A controller that a command is about to change.
+enum Changing<'a> {
+    /// Left unlocked, so that nothing can be changed.
+    DryRun(Controller<'a>),
+    Locked(WriteLockedController<'a>),
+}
+
+impl<'a> Changing<'a> {
+    /// The locked controller, or `None` in a dry run.
+    fn locked(&self) -> Option<&WriteLockedController<'a>> {
+        match self {
+            Changing::DryRun(_) => None,
+            Changing::Locked(locked) => Some(locked),
+        }
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.locked().is_none()
+    }
+}
+
+impl<'a> Deref for Changing<'a> {
+    type Target = Controller<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Changing::DryRun(controller) => controller,
+            Changing::Locked(locked) => locked,
+        }
+    }
+}
+
+/// Parse a number given in decimal or, with a `0x` prefix, hexadecimal.
+fn parse_number<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("invalid number {s:?}"))
+}
+
+/// The driver instance of `controller`, for labelling output.
+fn instance(controller: &Controller<'_>) -> Result<i32, Error> {
+    Ok(controller.instance()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libnvme::{
+        sim::{Sim, SimController, SimError, SimNamespace, SimOp},
+        NvmeErrorCode,
+    };
+
+    fn sim() -> Sim {
+        Sim::new().with_controller(SimController::new(0)).with_controller(
+            SimController::new(1).with_model("OTHER NVMe").with_namespaces(
+                vec![SimNamespace::new(1), SimNamespace::new(2)],
+            ),
+        )
+    }
+
+    fn nvmectl(sim: &Sim, args: &[&str]) -> Result<String, Error> {
+        let cli = Cli::try_parse_from(
+            std::iter::once("nvmectl").chain(args.iter().copied()),
+        )
+        .expect("invalid arguments");
+        let nvme = Nvme::with_backend(sim.clone());
+        let mut out = Vec::new();
+        run(&nvme, &cli, &mut out)?;
+        Ok(String::from_utf8(out).expect("output is not UTF-8"))
+    }
+
+    #[test]
+    fn list() {
+        let sim = sim();
+        let out = nvmectl(&sim, &["list", "--parsable"]).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("nvme0:SIM NVMe:SIM-0:SIM1.0:1:"));
+        assert!(lines[1].starts_with("nvme1:OTHER NVMe:SIM-1:SIM1.0:2:"));
+
+        let out = nvmectl(&sim, &["list", "--json", "model=OTHER*"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["instance"], 1);
+    }
+
+    #[test]
+    fn identify() {
+        let sim = sim();
+        let out = nvmectl(&sim, &["identify", "serial=SIM-0"]).unwrap();
+        assert!(out.starts_with("INSTANCE"));
+        assert!(out.contains("SIM-0"));
+        assert!(!out.contains("SIM-1"));
+
+        let out = nvmectl(&sim, &["identify", "--ns", "--parsable", "nvme1"])
+            .unwrap();
+        assert_eq!(out.lines().count(), 2);
+    }
+
+    #[test]
+    fn logpage() {
+        let sim = sim();
+        let out = nvmectl(&sim, &["logpage", "nvme0", "smart"]).unwrap();
+        assert!(out.starts_with("nvme0: log page 0x02, 512 bytes\n"));
+        assert!(out.contains("SmartLog"));
+
+        let raw =
+            nvmectl(&sim, &["logpage", "nvme0", "smart", "--raw"]).unwrap();
+        assert_eq!(raw.len(), 512);
+        assert_eq!(raw.as_bytes()[3], 100);
+
+        let out =
+            nvmectl(&sim, &["logpage", "nvme0", "0x2", "--json"]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["lid"], 2);
+        assert!(json["data"].as_str().unwrap().starts_with("002c0164"));
+
+        let err = nvmectl(&sim, &["logpage", "nvme0", "bogus"]).unwrap_err();
+        assert!(matches!(err, Error::Invalid(_)));
+    }
+
+    #[test]
+    fn features() {
+        let sim = Sim::new().with_controller(
+            SimController::new(0).with_feature(0x07, 0x003f_003f, Vec::new()),
+        );
+        let out = nvmectl(
+            &sim,
+            &["features", "get", "nvme0", "num-queues", "--parsable"],
+        )
+        .unwrap();
+        assert_eq!(out, "nvme0:0x07:0x003f003f:\n");
+    }
+
+    #[test]
+    fn ambiguous_target() {
+        let sim = sim();
+        let err = nvmectl(&sim, &["logpage", "model=*", "smart"]).unwrap_err();
+        assert!(matches!(err, Error::Ambiguous { count: 2, .. }));
+        let err = nvmectl(&sim, &["logpage", "nvme7", "smart"]).unwrap_err();
+        assert!(matches!(err, Error::NoController(_)));
+    }
+
+    #[test]
+    fn firmware_update() {
+        let sim = sim();
+        let image = std::env::temp_dir()
+            .join(format!("nvmectl-test-{}.bin", std::process::id()));
+        std::fs::write(&image, b"SIM2.0\0\0").unwrap();
+        let image = image.to_str().unwrap();
+
+        let args = ["firmware", "update", "nvme0", image, "--slot", "2"];
+        let mut dry_run = vec!["--dry-run"];
+        dry_run.extend(args);
+        let out = nvmectl(&sim, &dry_run).unwrap();
+        assert!(out.contains("dry-run"));
+        assert_eq!(sim.controller(0, |c| c.next_active_slot()), Some(None));
+
+        nvmectl(&sim, &args).unwrap();
+        std::fs::remove_file(image).unwrap();
+        sim.controller(0, |c| {
+            assert_eq!(c.firmware_slot(2), Some("SIM2.0"));
+            assert_eq!(c.next_active_slot(), Some(2));
+        });
+
+        let out = nvmectl(&sim, &["firmware", "show", "nvme0", "--parsable"])
+            .unwrap();
+        assert_eq!(out, "nvme0:1:SIM1.0:active\nnvme0:2:SIM2.0:next\n");
+
+        let err =
+            nvmectl(&sim, &["firmware", "commit", "nvme0", "--slot", "3"])
+                .unwrap_err();
+        assert!(matches!(err, Error::Invalid(_)));
+    }
+
+    #[test]
+    fn format() {
+        let sim = sim();
+        nvmectl(&sim, &["--dry-run", "format", "nvme1", "--lbaf", "1"])
+            .unwrap();
+        assert_eq!(
+            sim.controller(1, |c| c.namespace(1).unwrap().lbaf),
+            Some(0)
+        );
+
+        let out = nvmectl(
+            &sim,
+            &["format", "nvme1", "--lbaf", "1", "--nsid", "2", "--parsable"],
+        )
+        .unwrap();
+        assert_eq!(out.lines().count(), 3);
+        sim.controller(1, |c| {
+            assert_eq!(c.namespace(1).unwrap().lbaf, 0);
+            assert_eq!(c.namespace(2).unwrap().lbaf, 1);
+            assert!(c.namespace(2).unwrap().blkdev);
+        });
+
+        let err =
+            nvmectl(&sim, &["format", "nvme1", "--lbaf", "9"]).unwrap_err();
+        assert!(matches!(err, Error::Invalid(_)));
+    }
+
+    #[test]
+    fn failed_format_reattaches_blkdev() {
+        let sim = Sim::new().with_controller(
+            SimController::new(0)
+                .with_namespaces(vec![
+                    SimNamespace::new(1),
+                    SimNamespace::new(2),
+                ])
+                .with_error(
+                    SimOp::Format,
+                    SimError::Library(NvmeErrorCode::FormatUnsupByDev),
+                ),
+        );
+        let err =
+            nvmectl(&sim, &["format", "nvme0", "--lbaf", "1"]).unwrap_err();
+        assert!(matches!(err, Error::Failed { instance: 0, .. }));
+        sim.controller(0, |c| {
+            for nsid in [1, 2] {
+                let ns = c.namespace(nsid).unwrap();
+                assert_eq!(ns.lbaf, 0);
+                assert!(ns.blkdev);
+            }
+        });
+    }
+
+    #[test]
+    fn namespace() {
+        let sim = sim();
+        nvmectl(&sim, &["namespace", "detach", "nvme1", "2"]).unwrap();
+        assert_eq!(
+            sim.controller(1, |c| c.namespace(2).unwrap().blkdev),
+            Some(false)
+        );
+        nvmectl(&sim, &["namespace", "attach", "nvme1", "2"]).unwrap();
+        assert_eq!(
+            sim.controller(1, |c| c.namespace(2).unwrap().blkdev),
+            Some(true)
+        );
+
+        let err = nvmectl(
+            &sim,
+            &[
+                "namespace",
+                "create",
+                "nvme1",
+                "--blocks",
+                "1024",
+                "--lbaf",
+                "0",
+            ],
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Unsupported("namespace creation")));
+        assert_eq!(
+            err.to_string(),
+            "namespace creation is not supported by libnvme"
+        );
+        let err =
+            nvmectl(&sim, &["namespace", "delete", "nvme1", "2"]).unwrap_err();
+        assert!(matches!(err, Error::Unsupported("namespace deletion")));
+        assert!(sim.controller(1, |c| c.namespace(2).is_some()).unwrap());
+    }
+
+    #[test]
+    fn selftest() {
+        let mut log = vec![0u8; 564];
+        for entry in log[4..].chunks_exact_mut(28) {
+            entry[0] = 0xf;
+        }
+        log[4] = 0x10;
+        log[8..16].copy_from_slice(&1234u64.to_le_bytes());
+        let sim = Sim::new()
+            .with_controller(SimController::new(0).with_log_page(0x06, log));
+        let out = nvmectl(&sim, &["selftest", "--parsable"]).unwrap();
+        assert_eq!(out, "nvme0:short:passed:1234:-:-\n");
+    }
+}
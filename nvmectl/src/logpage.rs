@@ -0,0 +1,316 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading log pages and features by name or by identifier.
+
+use clap::{Args, Subcommand, ValueEnum};
+use libnvme::{
+    feature::FeatureSelect,
+    nvmespec::{
+        async_event::{ChangedNamespaces, CHANGED_NS_LEN, CHANGED_NS_LID},
+        command_effects::{
+            CommandEffectsLog, COMMAND_EFFECTS_LEN, COMMAND_EFFECTS_LID,
+        },
+        ocp::{OcpSmartLog, OCP_SMART_LOG_LEN, OCP_SMART_LOG_LID},
+        self_test::{SelfTestLog, SELF_TEST_LEN, SELF_TEST_LID},
+        smart::{SmartLog, SMART_LEN, SMART_LID},
+        supported_log_pages::{
+            SupportedLogPages, SUPPORTED_LOG_PAGES_LEN, SUPPORTED_LOG_PAGES_LID,
+        },
+        DecodeError,
+    },
+};
+use serde::Serialize;
+
+use crate::{
+    instance,
+    output::{hex, Output, OutputMode, Table},
+    parse_number, Context, Error, Target,
+};
+
+#[derive(Debug, Args)]
+pub struct LogpageArgs {
+    #[command(flatten)]
+    target: Target,
+    /// A log page name, such as `smart`, or a Log Page Identifier.
+    #[arg(value_name = "NAME|LID")]
+    page: String,
+    /// Write the log page to stdout exactly as read.
+    #[arg(long)]
+    raw: bool,
+    /// How many bytes of the log page to read. Defaults to the size of a
+    /// named log page, and 512 bytes otherwise.
+    #[arg(long, value_parser = parse_number::<usize>)]
+    len: Option<usize>,
+    /// The namespace to read the log page for.
+    #[arg(long, value_parser = parse_number::<u32>)]
+    nsid: Option<u32>,
+    /// The Log Specific Parameter.
+    #[arg(long, value_parser = parse_number::<u32>)]
+    lsp: Option<u32>,
+}
+
+/// Decode a log page into a form fit for display.
+type Decoder = fn(&[u8]) -> Result<String, DecodeError>;
+
+/// A log page that can be asked for by name.
+struct NamedLog {
+    name: &'static str,
+    lid: u32,
+    len: usize,
+    /// Decode the log page for display, if there is a decoder for it.
+    decode: Option<Decoder>,
+}
+
+const NAMED_LOGS: &[NamedLog] = &[
+    NamedLog {
+        name: "supported",
+        lid: SUPPORTED_LOG_PAGES_LID,
+        len: SUPPORTED_LOG_PAGES_LEN,
+        decode: Some(|buf| {
+            Ok(format!("{:#?}", SupportedLogPages::decode(buf)?))
+        }),
+    },
+    NamedLog {
+        name: "smart",
+        lid: SMART_LID,
+        len: SMART_LEN,
+        decode: Some(|buf| Ok(format!("{:#?}", SmartLog::decode(buf)?))),
+    },
+    NamedLog { name: "firmware", lid: 0x03, len: 512, decode: None },
+    NamedLog {
+        name: "changed-ns",
+        lid: CHANGED_NS_LID,
+        len: CHANGED_NS_LEN,
+        decode: Some(|buf| {
+            Ok(format!("{:#?}", ChangedNamespaces::decode(buf)?))
+        }),
+    },
+    NamedLog {
+        name: "effects",
+        lid: COMMAND_EFFECTS_LID,
+        len: COMMAND_EFFECTS_LEN,
+        decode: Some(|buf| {
+            Ok(format!("{:#?}", CommandEffectsLog::decode(buf)?))
+        }),
+    },
+    NamedLog {
+        name: "selftest",
+        lid: SELF_TEST_LID,
+        len: SELF_TEST_LEN,
+        decode: Some(|buf| Ok(format!("{:#?}", SelfTestLog::decode(buf)?))),
+    },
+    NamedLog {
+        name: "ocp-smart",
+        lid: OCP_SMART_LOG_LID,
+        len: OCP_SMART_LOG_LEN,
+        decode: Some(|buf| Ok(format!("{:#?}", OcpSmartLog::decode(buf)?))),
+    },
+];
+
+/// The size of a log page read by identifier without `--len`.
+const DEFAULT_LOG_LEN: usize = 512;
+
+#[derive(Debug, Serialize)]
+struct LogPage {
+    instance: i32,
+    lid: u32,
+    /// The contents of the log page in hexadecimal.
+    data: String,
+}
+
+pub(crate) fn logpage(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    args: &LogpageArgs,
+) -> Result<(), Error> {
+    let named = NAMED_LOGS.iter().find(|log| log.name == args.page);
+    let lid = match named {
+        Some(log) => log.lid,
+        None => parse_number::<u32>(&args.page).map_err(|_| {
+            let names = NAMED_LOGS.iter().map(|log| log.name);
+            Error::Invalid(format!(
+                "unknown log page {:?}; expected a LID or one of: {}",
+                args.page,
+                names.collect::<Vec<_>>().join(", ")
+            ))
+        })?,
+    };
+    let len = args.len.or(named.map(|log| log.len)).unwrap_or(DEFAULT_LOG_LEN);
+
+    let controller = ctx.controller(&args.target)?;
+    let instance = instance(&controller)?;
+    let mut req = controller.log_request()?.set_lid(lid)?;
+    if let Some(nsid) = args.nsid {
+        req = req.set_nsid(nsid)?;
+    }
+    if let Some(lsp) = args.lsp {
+        req = req.set_lsp(lsp)?;
+    }
+    let mut buf = vec![0u8; len];
+    req.execute(&mut buf).map_err(Error::on(instance))?;
+
+    if args.raw {
+        return out.raw(&buf);
+    }
+    if out.mode() == OutputMode::Human {
+        let decoded = match named.and_then(|log| log.decode) {
+            Some(decode) => decode(&buf).map_err(Error::on(instance))?,
+            None => hexdump(&buf),
+        };
+        return out.text(&format!(
+            "nvme{instance}: log page {lid:#04x}, {len} bytes\n{decoded}"
+        ));
+    }
+
+    let page = LogPage { instance, lid, data: hex(&buf) };
+    let mut table = Table::new(&["INSTANCE", "LID", "DATA"]);
+    table.row(vec![
+        format!("nvme{instance}"),
+        format!("{lid:#04x}"),
+        page.data.clone(),
+    ]);
+    out.emit(&table, &page)
+}
+
+/// Offsets and bytes in hexadecimal, sixteen bytes to a line.
+fn hexdump(data: &[u8]) -> String {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let bytes = chunk
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("{:08x}  {bytes}", i * 16)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FeaturesCommand {
+    /// Get the value of a feature.
+    Get {
+        #[command(flatten)]
+        target: Target,
+        /// A feature name, such as `num-queues`, or a Feature Identifier.
+        #[arg(value_name = "NAME|FID")]
+        feature: String,
+        /// Which value of the feature to get.
+        #[arg(long, value_enum, default_value_t = Select::Current)]
+        sel: Select,
+        /// The feature specific Command Dword 11.
+        #[arg(long, value_parser = parse_number::<u32>)]
+        cdw11: Option<u32>,
+        /// The namespace for namespace specific features.
+        #[arg(long, value_parser = parse_number::<u32>)]
+        nsid: Option<u32>,
+        /// The size of the data structure the feature returns, if any.
+        #[arg(long, value_parser = parse_number::<usize>)]
+        data_len: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Select {
+    Current,
+    Default,
+    Saved,
+    Supported,
+}
+
+impl From<Select> for FeatureSelect {
+    fn from(sel: Select) -> Self {
+        match sel {
+            Select::Current => FeatureSelect::Current,
+            Select::Default => FeatureSelect::Default,
+            Select::Saved => FeatureSelect::Saved,
+            Select::Supported => FeatureSelect::SupportedCapabilities,
+        }
+    }
+}
+
+/// Features that can be asked for by name, with their identifiers.
+const NAMED_FEATURES: &[(&str, u32)] = &[
+    ("arbitration", 0x01),
+    ("power-mgmt", 0x02),
+    ("lba-range", 0x03),
+    ("temp-threshold", 0x04),
+    ("error-recovery", 0x05),
+    ("write-cache", 0x06),
+    ("num-queues", 0x07),
+    ("intr-coalesce", 0x08),
+    ("intr-vector", 0x09),
+    ("write-atomicity", 0x0a),
+    ("async-event", 0x0b),
+    ("apst", 0x0c),
+    ("timestamp", 0x0e),
+    ("hctm", 0x10),
+    ("plm-config", 0x13),
+    ("plm-window", 0x14),
+];
+
+#[derive(Debug, Serialize)]
+struct Feature {
+    instance: i32,
+    fid: u32,
+    /// Dword 0 of the completion queue entry.
+    cdw0: u32,
+    /// The data structure returned by the feature in hexadecimal, if any.
+    data: Option<String>,
+}
+
+pub(crate) fn features(
+    ctx: &Context<'_>,
+    out: &mut Output<'_>,
+    cmd: &FeaturesCommand,
+) -> Result<(), Error> {
+    let FeaturesCommand::Get { target, feature, sel, cdw11, nsid, data_len } =
+        cmd;
+    let fid = match NAMED_FEATURES.iter().find(|(name, _)| name == feature) {
+        Some(&(_, fid)) => fid,
+        None => parse_number::<u32>(feature).map_err(|_| {
+            let names = NAMED_FEATURES.iter().map(|(name, _)| *name);
+            Error::Invalid(format!(
+                "unknown feature {feature:?}; expected a FID or one of: {}",
+                names.collect::<Vec<_>>().join(", ")
+            ))
+        })?,
+    };
+
+    let controller = ctx.controller(target)?;
+    let instance = instance(&controller)?;
+    let mut req = controller
+        .get_feature_request()?
+        .set_fid(fid)?
+        .set_select((*sel).into())?;
+    if let Some(cdw11) = cdw11 {
+        req = req.set_cdw11(*cdw11)?;
+    }
+    if let Some(nsid) = nsid {
+        req = req.set_nsid(*nsid)?;
+    }
+    let (cdw0, data) = match data_len {
+        Some(len) => {
+            let mut buf = vec![0u8; *len];
+            let cdw0 = req
+                .execute_with_output(&mut buf)
+                .map_err(Error::on(instance))?;
+            (cdw0, Some(hex(&buf)))
+        }
+        None => (req.execute().map_err(Error::on(instance))?, None),
+    };
+
+    let feature = Feature { instance, fid, cdw0, data };
+    let mut table = Table::new(&["INSTANCE", "FID", "CDW0", "DATA"]);
+    table.row(vec![
+        format!("nvme{instance}"),
+        format!("{fid:#04x}"),
+        format!("{cdw0:#010x}"),
+        feature.data.clone().unwrap_or_default(),
+    ]);
+    out.emit(&table, &feature)
+}
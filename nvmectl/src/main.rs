@@ -0,0 +1,24 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use libnvme::Nvme;
+use nvmectl::Cli;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = Nvme::new().map_err(|e| e.to_string()).and_then(|nvme| {
+        nvmectl::run(&nvme, &cli, &mut std::io::stdout().lock())
+            .map_err(|e| e.to_string())
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nvmectl: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Rendering command results for people, scripts and other programs.
+
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::Error;
+
+/// How results are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Aligned columns with a header.
+    Human,
+    /// One line per row with fields separated by `:`, as `nvmeadm -p`
+    /// prints them. A `:` or `\` within a field is escaped with `\`.
+    Parsable,
+    /// A single JSON document.
+    Json,
+}
+
+/// Rows of fields, printed in human and parsable modes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: &[&'static str]) -> Self {
+        Self { columns: columns.to_vec(), rows: Vec::new() }
+    }
+
+    pub fn row(&mut self, fields: Vec<String>) {
+        assert_eq!(fields.len(), self.columns.len(), "table row width");
+        self.rows.push(fields);
+    }
+
+    fn write_human(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let mut widths =
+            self.columns.iter().map(|c| c.len()).collect::<Vec<_>>();
+        for row in &self.rows {
+            for (width, field) in widths.iter_mut().zip(row) {
+                *width = (*width).max(field.len());
+            }
+        }
+        let line = |out: &mut dyn Write, fields: Vec<&str>| {
+            let last = fields.len() - 1;
+            let mut line = String::new();
+            for (i, (field, width)) in fields.iter().zip(&widths).enumerate() {
+                if i == last {
+                    line.push_str(field);
+                } else {
+                    line.push_str(&format!("{field:width$}  "));
+                }
+            }
+            writeln!(out, "{}", line.trim_end())
+        };
+        line(out, self.columns.clone())?;
+        for row in &self.rows {
+            line(out, row.iter().map(String::as_str).collect())?;
+        }
+        Ok(())
+    }
+
+    fn write_parsable(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        for row in &self.rows {
+            let fields = row
+                .iter()
+                .map(|f| f.replace('\\', "\\\\").replace(':', "\\:"))
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", fields.join(":"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where command results are written, and how.
+pub struct Output<'a> {
+    out: &'a mut dyn Write,
+    mode: OutputMode,
+}
+
+impl<'a> Output<'a> {
+    pub fn new(out: &'a mut dyn Write, mode: OutputMode) -> Self {
+        Self { out, mode }
+    }
+
+    pub fn mode(&self) -> OutputMode {
+        self.mode
+    }
+
+    /// Write a result that is shown as `table`, or as `json` in JSON mode.
+    pub fn emit<T: Serialize>(
+        &mut self,
+        table: &Table,
+        json: &T,
+    ) -> Result<(), Error> {
+        match self.mode {
+            OutputMode::Human => table.write_human(self.out)?,
+            OutputMode::Parsable => table.write_parsable(self.out)?,
+            OutputMode::Json => {
+                serde_json::to_writer_pretty(&mut *self.out, json)?;
+                writeln!(self.out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write free-form text, which is only shown in human mode.
+    pub fn text(&mut self, text: &str) -> Result<(), Error> {
+        if self.mode == OutputMode::Human {
+            writeln!(self.out, "{text}")?;
+        }
+        Ok(())
+    }
+
+    /// Write bytes exactly as given, whatever the mode.
+    pub fn raw(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.out.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// A result labelled with the controller it came from.
+#[derive(Debug, Serialize)]
+pub(crate) struct Labelled<T> {
+    pub instance: i32,
+    #[serde(flatten)]
+    pub item: T,
+}
+
+/// Something done, or with `--dry-run` that would have been done, to a
+/// controller.
+#[derive(Debug, Serialize)]
+pub(crate) struct Action {
+    pub instance: i32,
+    pub action: String,
+    pub dry_run: bool,
+}
+
+impl Output<'_> {
+    /// Report the actions taken by a command.
+    pub(crate) fn actions(&mut self, actions: &[Action]) -> Result<(), Error> {
+        let mut table = Table::new(&["INSTANCE", "ACTION", "STATUS"]);
+        for Action { instance, action, dry_run } in actions {
+            let status = if *dry_run { "dry-run" } else { "done" };
+            table.row(vec![
+                format!("nvme{instance}"),
+                action.clone(),
+                status.to_string(),
+            ]);
+        }
+        self.emit(&table, &actions)
+    }
+}
+
+/// Shown in place of a value that could not be read.
+const MISSING: &str = "-";
+
+pub(crate) fn or_missing<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| MISSING.to_string(), |v| v.to_string())
+}
+
+/// Format bytes as lowercase hexadecimal with no separators.
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(mode: OutputMode, table: &Table) -> String {
+        let mut buf = Vec::new();
+        Output::new(&mut buf, mode).emit(table, &()).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn tables() {
+        let mut table = Table::new(&["INSTANCE", "MODEL", "NQN"]);
+        table.row(vec!["nvme0".into(), "A".into(), "nqn.a:b".into()]);
+        table.row(vec!["nvme12".into(), "Long model".into(), "c\\d".into()]);
+
+        assert_eq!(
+            render(OutputMode::Human, &table),
+            "INSTANCE  MODEL       NQN\n\
+             nvme0     A           nqn.a:b\n\
+             nvme12    Long model  c\\d\n"
+        );
+        assert_eq!(
+            render(OutputMode::Parsable, &table),
+            "nvme0:A:nqn.a\\:b\nnvme12:Long model:c\\\\d\n"
+        );
+    }
+}